@@ -9,10 +9,14 @@
 //!    -V, --version    Prints version information
 //!
 //! SUBCOMMANDS:
-//!    cp      Multithreaded directory copy
-//!    help    Prints this message or the help of the given subcommand(s)
-//!    rm      Multithreaded directory remove
-//!    sync    Multithreaded directory synchronization [aliases: s]
+//!    checksum  Multithreaded listing of the checksums of all files in a directory, sorted by path
+//!    cp        Multithreaded directory copy
+//!    diff      Multithreaded list of the differences between two directories, without modifying them
+//!    help      Prints this message or the help of the given subcommand(s)
+//!    mv        Multithreaded directory move, even across file systems
+//!    rm        Multithreaded directory remove
+//!    sync      Multithreaded directory synchronization [aliases: s]
+//!    verify    Multithreaded check that two directories match, without modifying them
 //! ```
 
 mod lumins;
@@ -0,0 +1,272 @@
+//! Keeps the hashes of files across runs, so that files that did not change are not hashed again
+
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::lumins::parse::HashAlgo;
+
+/// Name of the cache file that is kept in the destination, unless another one is given
+pub const CACHE_FILE_NAME: &str = ".lms-cache";
+
+lazy_static! {
+    /// Holds the hashes of files once the cache is loaded by `cache_init`
+    static ref CACHE: Mutex<Option<HashCache>> = Mutex::new(None);
+}
+
+/// A hash of a file, which is only valid while the file has the same size and modification time
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Absolute path of the file
+    path: PathBuf,
+    /// Hash function that the hash was generated with
+    algo: HashAlgo,
+    size: u64,
+    modified: SystemTime,
+    hash: Vec<u8>,
+}
+
+/// Hashes of files, along with where they are saved
+struct HashCache {
+    path: PathBuf,
+    entries: HashMap<(PathBuf, HashAlgo), CacheEntry>,
+    changed: bool,
+}
+
+impl HashCache {
+    /// Loads the cache at `path`, which starts out empty if it is missing or unreadable
+    fn load(path: &Path) -> Self {
+        let entries: Vec<CacheEntry> = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Warning -- Cache {:?} is invalid and will be replaced: {}",
+                    path, e
+                );
+                Vec::new()
+            }),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(
+                    "Warning -- Cache {:?} cannot be read and will be replaced: {}",
+                    path, e
+                );
+                Vec::new()
+            }
+        };
+
+        HashCache {
+            path: path.to_path_buf(),
+            entries: entries
+                .into_iter()
+                .map(|entry| ((entry.path.clone(), entry.algo), entry))
+                .collect(),
+            changed: false,
+        }
+    }
+
+    /// Looks up the hash of the file at `path` made with `algo`, if the file has the same size
+    /// and modification time in `metadata` as when it was hashed
+    fn get(&self, path: &Path, algo: HashAlgo, metadata: &fs::Metadata) -> Option<Vec<u8>> {
+        let path = path::absolute(path).ok()?;
+        let modified = metadata.modified().ok()?;
+
+        let entry = self.entries.get(&(path, algo))?;
+        if entry.size == metadata.len() && entry.modified == modified {
+            Some(entry.hash.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Adds the hash of the file at `path` made with `algo`, with the size and modification time
+    /// in `metadata`, replacing any older hash of it
+    fn insert(&mut self, path: &Path, algo: HashAlgo, metadata: &fs::Metadata, hash: &[u8]) {
+        let (path, modified) = match (path::absolute(path), metadata.modified()) {
+            (Ok(path), Ok(modified)) => (path, modified),
+            _ => return,
+        };
+
+        let entry = CacheEntry {
+            path: path.clone(),
+            algo,
+            size: metadata.len(),
+            modified,
+            hash: hash.to_vec(),
+        };
+        self.entries.insert((path, algo), entry);
+        self.changed = true;
+    }
+
+    /// Saves the cache to a temporary file, which then replaces the file it was loaded from,
+    /// if anything was added to it
+    fn save(&self) -> Result<(), io::Error> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let mut entries: Vec<&CacheEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let contents = serde_json::to_vec(&entries)?;
+
+        let mut temp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp = self.path.with_file_name(temp_name);
+        fs::write(&temp, contents)
+            .and_then(|_| fs::rename(&temp, &self.path))
+            .inspect_err(|_| {
+                fs::remove_file(&temp).ok();
+            })
+    }
+}
+
+/// Loads the cache at `path`, so that hashes are looked up in and added to it
+///
+/// A missing cache starts out empty, and so does an unreadable one, which is then replaced
+/// # Arguments
+/// * `path`: Path of the cache file
+pub fn cache_init(path: &Path) {
+    let hash_cache = HashCache::load(path);
+    if let Ok(mut cache) = CACHE.lock() {
+        *cache = Some(hash_cache);
+    }
+}
+
+/// Checks whether the cache was loaded by `cache_init`
+pub fn cache_enabled() -> bool {
+    CACHE.lock().is_ok_and(|cache| cache.is_some())
+}
+
+/// Looks up the hash of the file at `path`, made with `algo`
+/// # Arguments
+/// * `path`: Path of the file
+/// * `algo`: Hash function of the hash
+/// * `metadata`: Current metadata of the file
+///
+/// # Returns
+/// * Some: The hash, if the file has the same size and modification time as when it was hashed
+/// * None: If the cache is not loaded, the file is not in it or changed since it was hashed,
+///   or its modification time is not available
+pub fn cache_get(path: &Path, algo: HashAlgo, metadata: &fs::Metadata) -> Option<Vec<u8>> {
+    CACHE.lock().ok()?.as_ref()?.get(path, algo, metadata)
+}
+
+/// Adds the hash of the file at `path`, made with `algo`, if the cache is loaded
+/// # Arguments
+/// * `path`: Path of the file
+/// * `algo`: Hash function of the hash
+/// * `metadata`: Metadata of the file from before it was hashed, so that the hash is invalid
+///   if the file changed while it was hashed
+/// * `hash`: Hash of the file
+pub fn cache_insert(path: &Path, algo: HashAlgo, metadata: &fs::Metadata, hash: &[u8]) {
+    if let Ok(mut cache) = CACHE.lock() {
+        if let Some(cache) = cache.as_mut() {
+            cache.insert(path, algo, metadata, hash);
+        }
+    }
+}
+
+/// Saves the cache to the file it was loaded from, if it is loaded and anything was added to it
+///
+/// # Errors
+/// If the cache cannot be written
+pub fn cache_save() -> Result<(), io::Error> {
+    match CACHE.lock() {
+        Ok(cache) => cache.as_ref().map_or(Ok(()), HashCache::save),
+        Err(_) => Ok(()),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_hash_cache {
+    use super::*;
+
+    #[test]
+    fn get_and_insert() {
+        const TEST_DIR: &str = "test_hash_cache_get_and_insert";
+        let file = Path::new(TEST_DIR).join("a.txt");
+        let cache_file = Path::new(TEST_DIR).join(CACHE_FILE_NAME);
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&file, b"abc").unwrap();
+        let mut cache = HashCache::load(&cache_file);
+
+        let metadata = fs::metadata(&file).unwrap();
+        assert_eq!(cache.get(&file, HashAlgo::Seahash, &metadata), None);
+
+        cache.insert(&file, HashAlgo::Seahash, &metadata, &[1, 2, 3]);
+        assert_eq!(
+            cache.get(&file, HashAlgo::Seahash, &metadata),
+            Some(vec![1, 2, 3])
+        );
+        // Hashes of other hash functions are kept apart
+        assert_eq!(cache.get(&file, HashAlgo::Blake2, &metadata), None);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn save_and_load() {
+        const TEST_DIR: &str = "test_hash_cache_save_and_load";
+        let file = Path::new(TEST_DIR).join("a.txt");
+        let cache_file = Path::new(TEST_DIR).join(CACHE_FILE_NAME);
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&file, b"abc").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+
+        let mut cache = HashCache::load(&cache_file);
+        cache.insert(&file, HashAlgo::Blake2, &metadata, &[1, 2, 3]);
+        cache.save().unwrap();
+
+        let cache = HashCache::load(&cache_file);
+        assert_eq!(
+            cache.get(&file, HashAlgo::Blake2, &metadata),
+            Some(vec![1, 2, 3])
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn changed_file() {
+        const TEST_DIR: &str = "test_hash_cache_changed_file";
+        let file = Path::new(TEST_DIR).join("a.txt");
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&file, b"abc").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+
+        let mut cache = HashCache::load(&Path::new(TEST_DIR).join(CACHE_FILE_NAME));
+        cache.insert(&file, HashAlgo::Seahash, &metadata, &[1, 2, 3]);
+
+        fs::write(&file, b"abcd").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        assert_eq!(cache.get(&file, HashAlgo::Seahash, &metadata), None);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn invalid_cache() {
+        const TEST_DIR: &str = "test_hash_cache_invalid_cache";
+        let cache_file = Path::new(TEST_DIR).join(CACHE_FILE_NAME);
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&cache_file, b"not a cache").unwrap();
+
+        assert!(HashCache::load(&cache_file).entries.is_empty());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+//! Changes the permissions of copied files and directories with rules like those of rsync's
+//! --chmod, such as F644,D755 or go-w
+
+/// Permission bits that each class of users can be given, including setuid, setgid, and sticky
+const USER_BITS: u32 = 0o4700;
+const GROUP_BITS: u32 = 0o2070;
+const OTHER_BITS: u32 = 0o1007;
+const ALL_BITS: u32 = 0o7777;
+
+/// Which paths a rule applies to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Target {
+    All,
+    Files,
+    Dirs,
+}
+
+/// Change that a rule makes to a mode
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Change {
+    /// Sets the mode to an octal mode
+    Octal(u32),
+    /// Adds, removes, or sets permissions for the users in `who`,
+    /// as a list of operators `+`, `-`, or `=` and their permissions
+    Symbolic { who: u32, ops: Vec<(char, String)> },
+}
+
+/// Rule that changes the mode of the paths it applies to
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Rule {
+    target: Target,
+    change: Change,
+}
+
+/// Changes the permissions of files and directories by applying all rules in order
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chmod {
+    rules: Vec<Rule>,
+}
+
+impl Chmod {
+    /// Parses a comma-separated list of rules, each of which is an octal mode like 644,
+    /// or a symbolic mode like go-w or u=rwX,o-rwx, and applies only to files if prefixed with F,
+    /// or only to directories if prefixed with D
+    ///
+    /// A symbolic mode without users, such as +x, applies to all users
+    ///
+    /// # Errors
+    /// If any rule is not an octal or symbolic mode
+    pub fn new(spec: &str) -> Result<Self, String> {
+        let rules = spec
+            .split(',')
+            .map(|rule| parse_rule(rule).ok_or_else(|| format!("{} is not a valid mode", rule)))
+            .collect::<Result<_, _>>()?;
+        Ok(Chmod { rules })
+    }
+
+    /// Applies the rules to the permission bits of `mode`, for a directory if `is_dir` is set
+    ///
+    /// # Returns
+    /// The changed permission bits
+    pub fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+        self.rules
+            .iter()
+            .filter(|rule| match rule.target {
+                Target::All => true,
+                Target::Files => !is_dir,
+                Target::Dirs => is_dir,
+            })
+            .fold(mode & ALL_BITS, |mode, rule| match &rule.change {
+                Change::Octal(octal) => *octal,
+                Change::Symbolic { who, ops } => ops.iter().fold(mode, |mode, (op, perms)| {
+                    let bits = perm_bits(perms, mode, is_dir) & who;
+                    match op {
+                        '+' => mode | bits,
+                        '-' => mode & !bits,
+                        _ => (mode & !who) | bits,
+                    }
+                }),
+            })
+    }
+}
+
+/// Parses a single rule, which is an octal or symbolic mode optionally prefixed with F or D
+///
+/// # Returns
+/// * Some: The parsed rule
+/// * None: If `rule` is not a valid mode
+fn parse_rule(rule: &str) -> Option<Rule> {
+    let (target, mode) = match rule.chars().next()? {
+        'F' => (Target::Files, &rule[1..]),
+        'D' => (Target::Dirs, &rule[1..]),
+        _ => (Target::All, rule),
+    };
+
+    let change = if !mode.is_empty() && mode.len() <= 4 && mode.chars().all(|c| c.is_digit(8)) {
+        Change::Octal(u32::from_str_radix(mode, 8).ok()?)
+    } else {
+        parse_symbolic(mode)?
+    };
+
+    Some(Rule { target, change })
+}
+
+/// Parses a symbolic mode, which is any of the users u, g, o, or a,
+/// followed by one or more operators +, -, or = with any of the permissions r, w, x, X, s, or t
+fn parse_symbolic(mode: &str) -> Option<Change> {
+    let ops_start = mode.find(|c| "+-=".contains(c))?;
+    let who = mode[..ops_start].chars().try_fold(0, |who, c| match c {
+        'u' => Some(who | USER_BITS),
+        'g' => Some(who | GROUP_BITS),
+        'o' => Some(who | OTHER_BITS),
+        'a' => Some(who | ALL_BITS),
+        _ => None,
+    })?;
+
+    let mut ops = Vec::new();
+    for c in mode[ops_start..].chars() {
+        match c {
+            '+' | '-' | '=' => ops.push((c, String::new())),
+            'r' | 'w' | 'x' | 'X' | 's' | 't' => ops.last_mut()?.1.push(c),
+            _ => return None,
+        }
+    }
+
+    Some(Change::Symbolic {
+        who: if who == 0 { ALL_BITS } else { who },
+        ops,
+    })
+}
+
+/// Gets the bits of the permissions `perms` for all users, where X is only execute permission
+/// for directories or if `mode` already has execute permission for anyone
+fn perm_bits(perms: &str, mode: u32, is_dir: bool) -> u32 {
+    perms.chars().fold(0, |bits, c| match c {
+        'r' => bits | 0o444,
+        'w' => bits | 0o222,
+        'x' => bits | 0o111,
+        'X' if is_dir || mode & 0o111 != 0 => bits | 0o111,
+        's' => bits | 0o6000,
+        't' => bits | 0o1000,
+        _ => bits,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_chmod {
+    use super::*;
+
+    #[test]
+    fn octal() {
+        let chmod = Chmod::new("F644,D755").unwrap();
+        assert_eq!(chmod.apply(0o777, false), 0o644);
+        assert_eq!(chmod.apply(0o700, true), 0o755);
+
+        let chmod = Chmod::new("600").unwrap();
+        assert_eq!(chmod.apply(0o755, false), 0o600);
+        assert_eq!(chmod.apply(0o755, true), 0o600);
+    }
+
+    #[test]
+    fn symbolic() {
+        let matrix = [
+            ("go-w", 0o777, false, 0o755),
+            ("go-w", 0o777, true, 0o755),
+            ("u+x", 0o644, false, 0o744),
+            ("+x", 0o644, false, 0o755),
+            ("a=r", 0o777, false, 0o444),
+            ("u=rw,go=r", 0o700, false, 0o644),
+            ("o-rwx,g+r", 0o705, false, 0o740),
+            ("u+rw-x", 0o100, false, 0o600),
+            ("g+s", 0o755, true, 0o2755),
+            ("+t", 0o777, true, 0o1777),
+            ("go=", 0o777, false, 0o700),
+        ];
+        for &(spec, mode, is_dir, expected) in &matrix {
+            assert_eq!(Chmod::new(spec).unwrap().apply(mode, is_dir), expected);
+        }
+    }
+
+    #[test]
+    fn conditional_execute() {
+        let chmod = Chmod::new("a+X").unwrap();
+        assert_eq!(chmod.apply(0o644, false), 0o644);
+        assert_eq!(chmod.apply(0o744, false), 0o755);
+        assert_eq!(chmod.apply(0o644, true), 0o755);
+    }
+
+    #[test]
+    fn targets() {
+        let chmod = Chmod::new("Fgo-w,Du=rwx,Do=").unwrap();
+        assert_eq!(chmod.apply(0o666, false), 0o644);
+        assert_eq!(chmod.apply(0o555, true), 0o750);
+    }
+
+    #[test]
+    fn invalid() {
+        for spec in &["", "F", "8", "77777", "u", "z+r", "u+q", "F644,", "u+r,,"] {
+            assert!(Chmod::new(spec).is_err());
+        }
+    }
+}
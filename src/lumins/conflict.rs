@@ -0,0 +1,190 @@
+//! Lets callers decide what happens to files that differ between the source and destination
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// A file that differs between the source and destination, which would be overwritten
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConflictInfo {
+    /// Path relative to the source and destination
+    pub path: PathBuf,
+    pub src_size: u64,
+    pub dest_size: u64,
+    /// Hash of the source file, which is only found if both files have the same size
+    pub src_hash: Option<Vec<u8>>,
+    /// Hash of the destination file, which is only found if both files have the same size
+    pub dest_hash: Option<Vec<u8>>,
+}
+
+/// What to do with a file that differs between the source and destination
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConflictAction {
+    /// Copy the source file over the destination file
+    Overwrite,
+    /// Keep the destination file as it is
+    Skip,
+    /// Stop synchronizing, without copying any more files
+    Abort,
+}
+
+/// Decides what happens to each file that differs between the source and destination,
+/// which may be called from several threads at once
+#[derive(Clone)]
+pub struct ConflictHandler(Arc<dyn Fn(&ConflictInfo) -> ConflictAction + Send + Sync>);
+
+impl ConflictHandler {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&ConflictInfo) -> ConflictAction + Send + Sync + 'static,
+    {
+        ConflictHandler(Arc::new(handler))
+    }
+
+    /// Decides what happens to the file described by `info`
+    pub fn resolve(&self, info: &ConflictInfo) -> ConflictAction {
+        (self.0)(info)
+    }
+}
+
+impl fmt::Debug for ConflictHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConflictHandler")
+    }
+}
+
+/// Creates a handler that asks on stdin what happens to each file that differs,
+/// one file at a time, and aborts without asking again once any answer is to abort
+pub fn prompt_handler() -> ConflictHandler {
+    let aborted = Mutex::new(false);
+
+    ConflictHandler::new(move |info| {
+        let mut aborted = match aborted.lock() {
+            Ok(aborted) => aborted,
+            Err(_) => return ConflictAction::Abort,
+        };
+        if *aborted {
+            return ConflictAction::Abort;
+        }
+
+        let stdin = io::stdin();
+        let action = prompt(info, &mut stdin.lock(), &mut io::stderr());
+        *aborted = action == ConflictAction::Abort;
+        action
+    })
+}
+
+/// Asks what happens to the file described by `info`, until the answer is understood
+///
+/// # Arguments
+/// * `info`: file that differs
+/// * `input`: where the answer is read from
+/// * `output`: where the question is written to
+///
+/// # Returns
+/// The answer, which is to abort if `input` ends or cannot be read
+fn prompt<R, W>(info: &ConflictInfo, input: &mut R, output: &mut W) -> ConflictAction
+where
+    R: BufRead,
+    W: Write,
+{
+    loop {
+        let asked = write!(
+            output,
+            "{} differs ({} bytes in source, {} bytes in destination), overwrite? [y/n/a] ",
+            info.path.display(),
+            info.src_size,
+            info.dest_size
+        )
+        .and_then(|_| output.flush());
+        if asked.is_err() {
+            return ConflictAction::Abort;
+        }
+
+        let mut answer = String::new();
+        match input.read_line(&mut answer) {
+            Ok(0) | Err(_) => return ConflictAction::Abort,
+            Ok(_) => (),
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return ConflictAction::Overwrite,
+            "n" | "no" => return ConflictAction::Skip,
+            "a" | "abort" => return ConflictAction::Abort,
+            _ => (),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_prompt {
+    use super::*;
+
+    fn conflict_info() -> ConflictInfo {
+        ConflictInfo {
+            path: PathBuf::from("a/b.txt"),
+            src_size: 3,
+            dest_size: 4,
+            src_hash: None,
+            dest_hash: None,
+        }
+    }
+
+    fn prompt_with(input: &str) -> (ConflictAction, String) {
+        let mut output = Vec::new();
+        let action = prompt(&conflict_info(), &mut input.as_bytes(), &mut output);
+        (action, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn answers() {
+        assert_eq!(prompt_with("y\n").0, ConflictAction::Overwrite);
+        assert_eq!(prompt_with("Yes\n").0, ConflictAction::Overwrite);
+        assert_eq!(prompt_with("n\n").0, ConflictAction::Skip);
+        assert_eq!(prompt_with("a\n").0, ConflictAction::Abort);
+    }
+
+    #[test]
+    fn asks_again() {
+        let (action, output) = prompt_with("maybe\nn\n");
+        assert_eq!(action, ConflictAction::Skip);
+        assert_eq!(output.matches("a/b.txt differs").count(), 2);
+    }
+
+    #[test]
+    fn no_answer() {
+        assert_eq!(prompt_with("").0, ConflictAction::Abort);
+    }
+}
+
+#[cfg(test)]
+mod test_conflict_handler {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let handler = ConflictHandler::new(|info| {
+            if info.src_hash == info.dest_hash {
+                ConflictAction::Skip
+            } else {
+                ConflictAction::Overwrite
+            }
+        });
+        let mut info = ConflictInfo {
+            path: PathBuf::from("a.txt"),
+            src_size: 3,
+            dest_size: 3,
+            src_hash: Some(vec![1]),
+            dest_hash: Some(vec![1]),
+        };
+
+        assert_eq!(handler.resolve(&info), ConflictAction::Skip);
+        info.dest_hash = Some(vec![2]);
+        assert_eq!(handler.clone().resolve(&info), ConflictAction::Overwrite);
+    }
+}
@@ -1,10 +1,19 @@
 //! Contains core copy, remove, synchronize functions
 
-use std::io;
+use std::path::{self, Path, PathBuf};
+use std::{fs, io};
 
+use hashbrown::{HashMap, HashSet};
 use rayon::prelude::*;
 
-use crate::lumins::{file_ops, file_ops::Dir, parse::Flag};
+use crate::lumins::{
+    file_ops,
+    file_ops::{Dir, File, FileOps, FileSets, FollowSymlinks, OneFileSystem, Symlink},
+    filter::Filter,
+    interrupt,
+    parse::{Flag, Options},
+    stats::SyncStats,
+};
 use crate::progress::{self, PROGRESS_BAR};
 
 /// Synchronizes all files, directories, and symlinks in `dest` with `src`
@@ -13,25 +22,96 @@ use crate::progress::{self, PROGRESS_BAR};
 /// * `src`: Source directory
 /// * `dest`: Destination directory
 /// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of everything that was copied, updated, and deleted
 ///
 /// # Errors
 /// This function will return an error in the following situations,
 /// but is not limited to just these cases:
 /// * `src` is an invalid directory
 /// * `dest` is an invalid directory
-pub fn synchronize(src: &str, dest: &str, flags: Flag) -> Result<(), io::Error> {
+/// * `src` and `dest` overlap
+pub fn synchronize(
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> Result<SyncStats, io::Error> {
+    check_overlap(src, dest)?;
+    let backup_path = match &options.backup_dir {
+        Some(backup_dir) => backup_dir_in_dest(src, dest, backup_dir)?,
+        None => None,
+    };
+
+    let delete_excluded = flags.contains(Flag::DELETE_EXCLUDED);
+    let ignore_existing = flags.contains(Flag::IGNORE_EXISTING);
+    let update = flags.contains(Flag::UPDATE);
+
     // Retrieve data from src directory about files, dirs, symlinks
-    let src_file_sets = file_ops::get_all_files(&src)?;
+    let mut src_file_sets = match &options.files {
+        Some(files) => file_ops::get_listed_files(src, files, &options.filter)?,
+        None => get_all_files_with(src, &options.filter, flags)?,
+    };
+    let out_of_range = src_file_sets.retain_files(|file| in_range(src, file, options));
+    let pruned_dirs = if flags.contains(Flag::PRUNE_EMPTY_DIRS) {
+        src_file_sets.prune_empty_dirs()
+    } else {
+        HashSet::new()
+    };
     let src_files = src_file_sets.files();
     let src_dirs = src_file_sets.dirs();
     let src_symlinks = src_file_sets.symlinks();
 
-    // Retrieve data from dest directory about files, dirs, symlinks
-    let dest_file_sets = file_ops::get_all_files(&dest)?;
+    // Retrieve data from dest directory about files, dirs, symlinks.
+    // Symlinks in dest are never followed, so that nothing outside of dest is deleted
+    // Estimates are also made for a dest that doesn't exist yet, which is then empty
+    let dest_flags = flags - Flag::FOLLOW_SYMLINKS;
+    let missing_dest = flags.contains(Flag::STATS_ONLY) && fs::metadata(dest).is_err();
+    let mut dest_file_sets = if missing_dest {
+        FileSets::default()
+    } else {
+        get_all_files_with(dest, &options.filter, dest_flags)?
+    };
+    // The backup dir is only in dest to keep what was replaced, so it is never synchronized
+    if let Some(backup_path) = &backup_path {
+        dest_file_sets.remove_within(backup_path);
+    }
+
+    // Temporary files left in dest by copies that were stopped are removed first,
+    // unless there is a file of the same name in src, or they are partial copies of a file
+    // in src that are kept to resume copying it. Nothing is removed if nothing is deleted,
+    // since a file that only looks like a temporary file may belong to the user
+    if !flags.contains(Flag::STATS_ONLY) && !flags.contains(Flag::NO_DELETE) {
+        let src_file_paths: HashSet<&PathBuf> = src_files.par_iter().map(FileOps::path).collect();
+        let temp_files = dest_file_sets.retain_files(|file| {
+            !File::is_temp_path(file.path()) || src_file_paths.contains(file.path())
+        });
+        let stale_temp_files = temp_files.par_iter().filter(|file| {
+            !flags.contains(Flag::PARTIAL)
+                || !File::temp_target(file.path())
+                    .is_some_and(|target| src_file_paths.contains(&target))
+        });
+        file_ops::remove_temp_files(stale_temp_files, dest);
+    }
     let dest_files = dest_file_sets.files();
     let dest_dirs = dest_file_sets.dirs();
     let dest_symlinks = dest_file_sets.symlinks();
 
+    // Split off the excluded entries of dest if they should be deleted,
+    // which are those that are only found without the filter
+    let excluded_file_sets = if delete_excluded && !missing_dest {
+        let mut excluded_file_sets = get_all_files_with(dest, &Filter::default(), dest_flags)?;
+        excluded_file_sets.remove_all(&dest_file_sets);
+        if let Some(backup_path) = &backup_path {
+            excluded_file_sets.remove_within(backup_path);
+        }
+        excluded_file_sets
+    } else {
+        FileSets::default()
+    };
+
     // Initialize progress bar
     progress::progress_init(
         (src_files.len()
@@ -39,97 +119,919 @@ pub fn synchronize(src: &str, dest: &str, flags: Flag) -> Result<(), io::Error>
             + src_symlinks.len()
             + dest_files.len()
             + dest_dirs.len()
-            + dest_symlinks.len()) as u64,
+            + dest_symlinks.len()
+            + excluded_file_sets.len()) as u64,
+        src_files.par_iter().map(FileOps::size).sum(),
     );
 
-    // Determine whether or not to delete
+    // Determine whether or not to delete, and when
     let delete = !flags.contains(Flag::NO_DELETE);
+    let delete_before = delete && flags.contains(Flag::DELETE_BEFORE);
+    let delete_after = delete && flags.contains(Flag::DELETE_AFTER);
+
+    // Files in both src and dest whose size changed are not equal, but are still compared and
+    // overwritten like any other file in both, rather than deleted from dest and copied again
+    let dest_file_paths: HashSet<&PathBuf> = dest_files.par_iter().map(FileOps::path).collect();
+    let changed_files: Vec<&File> = src_files
+        .par_difference(dest_files)
+        .filter(|file| dest_file_paths.contains(file.path()))
+        .collect();
+    let changed_paths: HashSet<&PathBuf> = changed_files.iter().map(|file| file.path()).collect();
+
+    let mut symlinks_to_delete: Vec<&Symlink> = Vec::new();
+    let mut files_to_delete: Vec<&File> = Vec::new();
+    let mut dirs_to_delete: Vec<&Dir> = Vec::new();
 
-    // Delete files and symlinks
     if delete {
-        let symlinks_to_delete = dest_symlinks.par_difference(&src_symlinks);
-        let files_to_delete = dest_files.par_difference(&src_files);
+        symlinks_to_delete = dest_symlinks.par_difference(src_symlinks).collect();
+        files_to_delete = dest_files
+            .par_difference(src_files)
+            .filter(|file| !changed_paths.contains(file.path()))
+            .collect();
+        dirs_to_delete = dest_dirs.par_difference(src_dirs).collect();
+    }
 
-        file_ops::delete_files(symlinks_to_delete, &dest);
-        file_ops::delete_files(files_to_delete, &dest);
+    // Files that are only left out of src due to their size or time are kept in dest,
+    // unless excluded entries are deleted
+    let out_of_range_paths: HashSet<&PathBuf> = out_of_range.iter().map(FileOps::path).collect();
+    if !delete_excluded {
+        files_to_delete.retain(|file| !out_of_range_paths.contains(file.path()));
+    } else if !delete {
+        files_to_delete = dest_files
+            .par_iter()
+            .filter(|file| out_of_range_paths.contains(file.path()))
+            .collect();
     }
 
-    let dirs_to_copy = src_dirs.par_difference(&dest_dirs);
-    let symlinks_to_copy = src_symlinks.par_difference(&dest_symlinks);
-    let files_to_copy = src_files.par_difference(&dest_files);
-    let files_to_compare = src_files.par_intersection(&dest_files);
+    // Backups of overwritten files are only in dest, but are kept rather than deleted
+    if let Some(suffix) = &options.backup_suffix {
+        files_to_delete.retain(|file| !file.path().as_os_str().to_string_lossy().ends_with(suffix));
+    }
+
+    // Files in dest that are never touched, so they are kept even if replaced by a src file
+    // of the same path, which are either all existing files, or those that are newer than in src
+    let (kept_paths, skip_reason): (HashSet<&PathBuf>, &str) = if ignore_existing {
+        (dest_file_paths.clone(), "exists")
+    } else if update {
+        let src_files_by_path: HashMap<&PathBuf, &File> = src_files
+            .par_iter()
+            .map(|file| (file.path(), file))
+            .collect();
+        let newer_paths = dest_files
+            .par_iter()
+            .filter(|dest_file| match src_files_by_path.get(dest_file.path()) {
+                Some(src_file) => file_ops::is_newer_in_dest(
+                    *src_file,
+                    src,
+                    *dest_file,
+                    dest,
+                    options.modify_window,
+                ),
+                None => false,
+            })
+            .map(FileOps::path)
+            .collect();
+        (newer_paths, "newer in destination")
+    } else {
+        (HashSet::new(), "")
+    };
+
+    // Excluded entries are deleted even with Flag::NO_DELETE
+    symlinks_to_delete.par_extend(excluded_file_sets.symlinks().par_iter());
+    files_to_delete.par_extend(excluded_file_sets.files().par_iter());
+    dirs_to_delete.par_extend(excluded_file_sets.dirs().par_iter());
+    let mut dirs_to_delete = file_ops::sort_files(dirs_to_delete.into_par_iter());
+
+    // Entries replaced by a src entry of the same path must always be deleted before copying
+    let mut symlinks_to_delete_after: Vec<&Symlink> = Vec::new();
+    let mut files_to_delete_after: Vec<&File> = Vec::new();
+
+    if delete_after {
+        let src_paths = src_file_sets.paths();
+        let (replaced, removed) = partition_replaced(symlinks_to_delete, &src_paths);
+        symlinks_to_delete = replaced;
+        symlinks_to_delete_after = removed;
+        let (replaced, removed) = partition_replaced(files_to_delete, &src_paths);
+        files_to_delete = replaced;
+        files_to_delete_after = removed;
+    }
+
+    // Pruned dirs may still hold entries that are kept in dest, so they are only deleted if empty
+    let dest_path = Path::new(dest);
+    let is_deletable =
+        |dir: &&Dir| !pruned_dirs.contains(*dir) || is_empty_dir(&dest_path.join(dir.path()));
+
+    let dirs_to_copy: Vec<&Dir> = src_dirs.par_difference(dest_dirs).collect();
+    let symlinks_to_copy: Vec<&Symlink> = src_symlinks.par_difference(dest_symlinks).collect();
+    let files_to_copy: Vec<&File> = src_files
+        .par_difference(dest_files)
+        .filter(|file| !changed_paths.contains(file.path()) && !kept_paths.contains(file.path()))
+        .collect();
+
+    // Only count what would be done, without touching anything. Files in both that are
+    // the same size would have to be hashed to know if they differ, so they are not counted
+    if flags.contains(Flag::STATS_ONLY) {
+        let files_to_update: Vec<&File> = changed_files
+            .into_par_iter()
+            .filter(|file| !kept_paths.contains(file.path()))
+            .collect();
+        return Ok(SyncStats {
+            files_copied: files_to_copy.len() as u64,
+            files_updated: files_to_update.len() as u64,
+            files_deleted: (files_to_delete.len() + files_to_delete_after.len()) as u64,
+            dirs_created: dirs_to_copy.len() as u64,
+            dirs_deleted: dirs_to_delete.iter().copied().filter(is_deletable).count() as u64,
+            symlinks_copied: symlinks_to_copy.len() as u64,
+            symlinks_deleted: (symlinks_to_delete.len() + symlinks_to_delete_after.len()) as u64,
+            bytes_copied: files_to_copy
+                .par_iter()
+                .chain(files_to_update.par_iter())
+                .map(|file| file.size())
+                .sum(),
+            ..SyncStats::default()
+        });
+    }
+
+    let (files_to_copy, duplicates) = partition_links(files_to_copy, src, flags, options);
 
-    file_ops::copy_files(dirs_to_copy, &src, &dest);
-    file_ops::copy_files(symlinks_to_copy, &src, &dest);
-    file_ops::copy_files(files_to_copy, &src, &dest);
-    file_ops::compare_and_copy_files(files_to_compare, &src, &dest, flags);
+    let mut stats = SyncStats::default();
+
+    // Delete files and symlinks
+    stats += file_ops::delete_files(symlinks_to_delete.into_par_iter(), dest, flags, options);
+    stats += file_ops::delete_files(files_to_delete.into_par_iter(), dest, flags, options);
 
     // Delete dirs in the correct order
-    if delete {
-        let dirs_to_delete = dest_dirs.par_difference(&src_dirs);
-        let dirs_to_delete: Vec<&file_ops::Dir> = file_ops::sort_files(dirs_to_delete);
-        file_ops::delete_files_sequential(dirs_to_delete, &dest);
+    if delete_before {
+        stats += file_ops::delete_files_sequential(
+            dirs_to_delete.drain(..).filter(is_deletable),
+            dest,
+            flags,
+            options,
+        );
+    }
+
+    let files_to_compare = src_files
+        .par_intersection(dest_files)
+        .chain(changed_files.into_par_iter())
+        .filter(|file| !kept_paths.contains(file.path()));
+
+    // The bandwidth limit is counted from when copying starts
+    options.throttle.start(options.bwlimit);
+    stats += file_ops::copy_files(dirs_to_copy.into_par_iter(), src, dest, flags, options);
+    stats += file_ops::copy_files(symlinks_to_copy.into_par_iter(), src, dest, flags, options);
+    stats += if options.link_dest.is_empty() {
+        file_ops::copy_files(files_to_copy.into_par_iter(), src, dest, flags, options)
+    } else {
+        file_ops::link_or_copy_files(files_to_copy.into_par_iter(), src, dest, flags, options)
+    };
+    stats += file_ops::link_duplicates(duplicates.into_par_iter(), src, dest, flags, options);
+    stats += file_ops::compare_and_copy_files(files_to_compare, src, dest, flags, options)?;
+    file_ops::skip_files(
+        src_files
+            .par_iter()
+            .filter(|file| kept_paths.contains(file.path())),
+        skip_reason,
+    );
+
+    // Delete the remaining files and symlinks, and then dirs in the correct order
+    stats += file_ops::delete_files(
+        symlinks_to_delete_after.into_par_iter(),
+        dest,
+        flags,
+        options,
+    );
+    stats += file_ops::delete_files(files_to_delete_after.into_par_iter(), dest, flags, options);
+    stats += file_ops::delete_files_sequential(
+        dirs_to_delete.into_iter().filter(is_deletable),
+        dest,
+        flags,
+        options,
+    );
+
+    // Owners are set before permissions, since changing the owner may clear setuid bits.
+    // Files that are kept as they are in dest are not touched
+    if flags.contains(Flag::PRESERVE_OWNER) {
+        file_ops::copy_owners(
+            src_files
+                .par_iter()
+                .filter(|file| !kept_paths.contains(file.path())),
+            src,
+            dest,
+        );
+        file_ops::copy_owners(src_symlinks.par_iter(), src, dest);
+        file_ops::copy_owners(src_dirs.par_iter(), src, dest);
+    }
+
+    // Copied files already have the extended attributes of src, but dirs need them set,
+    // which is done before permissions in case those make the dirs read-only
+    if flags.contains(Flag::PRESERVE_XATTRS) {
+        file_ops::copy_all_xattrs(src_dirs.par_iter(), src, dest);
+    }
+
+    // Copied files already have the permissions of src, but dirs are created with the defaults,
+    // so that private dirs would be opened up. They are only set at the end, including those
+    // that already existed, so that restricted dirs can still be copied into
+    file_ops::copy_permissions(
+        file_ops::sort_files(src_dirs.par_iter()),
+        src,
+        dest,
+        options,
+    );
+
+    Ok(stats)
+}
+
+/// Checks that `src` and `dest` are neither the same directory nor inside one another,
+/// since `dest` would then be traversed while copying into it, or `src` be overwritten or deleted
+///
+/// # Errors
+/// If `src` and `dest` overlap, or `src` cannot be found
+fn check_overlap(src: &str, dest: &str) -> Result<(), io::Error> {
+    let src_path = fs::canonicalize(src)?;
+    let dest_path = canonicalize_existing(Path::new(dest))?;
+
+    let message = if src_path == dest_path {
+        format!(
+            "Destination Error -- {} is the same directory as {}",
+            dest, src
+        )
+    } else if dest_path.starts_with(&src_path) {
+        format!(
+            "Destination Error -- {} is inside {}, so it would be copied into itself",
+            dest, src
+        )
+    } else if src_path.starts_with(&dest_path) {
+        format!(
+            "Source Error -- {} is inside {}, so it would be overwritten or deleted",
+            src, dest
+        )
+    } else {
+        return Ok(());
+    };
+
+    Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+/// Finds where the backup dir `backup_dir` is in `dest`, so that it can be left out of `dest`
+///
+/// # Returns
+/// * Some: The path of `backup_dir` relative to `dest`, if it is inside `dest`
+/// * None: If `backup_dir` is outside of `dest`
+///
+/// # Errors
+/// If `backup_dir` is inside `src`, where it would be synchronized too,
+/// or `dest` is inside `backup_dir`, or either cannot be found
+fn backup_dir_in_dest(
+    src: &str,
+    dest: &str,
+    backup_dir: &Path,
+) -> Result<Option<PathBuf>, io::Error> {
+    let src_path = fs::canonicalize(src)?;
+    let dest_path = canonicalize_existing(Path::new(dest))?;
+    let backup_path = canonicalize_existing(backup_dir)?;
+
+    let message = if backup_path.starts_with(&src_path) {
+        format!(
+            "Argument Error -- backup-dir {} is inside {}, so it would be synchronized too",
+            backup_dir.display(),
+            src
+        )
+    } else if dest_path.starts_with(&backup_path) {
+        format!(
+            "Argument Error -- {} is inside backup-dir {}",
+            dest,
+            backup_dir.display()
+        )
+    } else {
+        return Ok(backup_path
+            .strip_prefix(&dest_path)
+            .ok()
+            .map(Path::to_path_buf));
+    };
+
+    Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+/// Canonicalizes the longest part of `path` that exists, keeping the rest of `path` as it is,
+/// so that paths that are not created yet can still be compared
+fn canonicalize_existing(path: &Path) -> Result<PathBuf, io::Error> {
+    let path = path::absolute(path)?;
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = fs::canonicalize(ancestor) {
+            // This is safe to unwrap, since `ancestor` is an ancestor of `path`
+            return Ok(canonical.join(path.strip_prefix(ancestor).unwrap()));
+        }
+    }
+    Ok(path)
+}
+
+/// Traverses `src` with `filter`, without leaving the file system of `src`
+/// if `Flag::ONE_FILE_SYSTEM` is set, and following symlinks if `Flag::FOLLOW_SYMLINKS` is set
+///
+/// # Arguments
+/// * `src`: directory to traverse
+/// * `filter`: rules for leaving out paths
+/// * `flags`: set for Flag's
+///
+/// # Returns
+/// * Ok: A `FileSets` containing all files, directories, and symlinks that were not left out
+/// * Error: If `src` is an invalid directory
+fn get_all_files_with(src: &str, filter: &Filter, flags: Flag) -> Result<FileSets, io::Error> {
+    let one_file_system = flags.contains(Flag::ONE_FILE_SYSTEM);
+    let follow_symlinks = flags.contains(Flag::FOLLOW_SYMLINKS);
+
+    match (one_file_system, follow_symlinks) {
+        (false, false) => file_ops::get_all_files_with(src, filter),
+        (true, false) => {
+            file_ops::get_all_files_with(src, &OneFileSystem::new(src, filter.clone())?)
+        }
+        (false, true) => file_ops::get_all_files_with(src, &FollowSymlinks(filter.clone())),
+        (true, true) => file_ops::get_all_files_with(
+            src,
+            &OneFileSystem::new(src, FollowSymlinks(filter.clone()))?,
+        ),
+    }
+}
+
+/// Checks whether `file` in `src` is within the size and time range of `options`
+///
+/// The modification time of `file` is only read if there is a time range,
+/// and files whose modification time cannot be read are always in range
+///
+/// # Arguments
+/// * `src`: base directory of `file`
+/// * `file`: file to check
+/// * `options`: values of options
+fn in_range(src: &str, file: &File, options: &Options) -> bool {
+    options.in_size_range(file.size()) && in_time_range(src, file.path(), options)
+}
+
+/// Checks whether `path` in `base` was modified within the time range of `options`
+///
+/// The modification time of `path`, and not of what it links to, is only read
+/// if there is a time range, and paths whose modification time cannot be read are always in range
+///
+/// # Arguments
+/// * `base`: base directory of `path`
+/// * `path`: path to check
+/// * `options`: values of options
+fn in_time_range(base: &str, path: &Path, options: &Options) -> bool {
+    if !options.has_time_range() {
+        return true;
+    }
+
+    match fs::symlink_metadata(Path::new(base).join(path)).and_then(|m| m.modified()) {
+        Ok(modified) => options.in_time_range(modified),
+        Err(_) => true,
+    }
+}
+
+/// Checks whether `path` is a directory with nothing in it
+///
+/// # Arguments
+/// * `path`: path to check
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(false)
+}
+
+/// Splits `files_to_delete` into the files whose path is also in `src_paths`,
+/// and the files whose path is not
+///
+/// # Arguments
+/// * `files_to_delete`: files to split
+/// * `src_paths`: all paths in the source directory
+///
+/// # Returns
+/// The files that are replaced by a source file, and the files that are removed
+fn partition_replaced<'a, S>(
+    files_to_delete: Vec<&'a S>,
+    src_paths: &HashSet<&PathBuf>,
+) -> (Vec<&'a S>, Vec<&'a S>)
+where
+    S: FileOps + Sync,
+{
+    files_to_delete
+        .into_par_iter()
+        .partition(|file| src_paths.contains(file.path()))
+}
+
+/// Splits `files_to_copy` into the files that are copied, and the files that are only linked to
+/// the copy of one of those, which are those hard linked to it in `src` if `Flag::HARD_LINKS`
+/// is set, and those with the same contents if `Flag::DEDUP` is set
+///
+/// # Arguments
+/// * `files_to_copy`: files to split
+/// * `src`: base directory of the files
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The files that are copied, and the other files along with the copied file they are linked to
+fn partition_links<'a>(
+    files_to_copy: Vec<&'a File>,
+    src: &str,
+    flags: Flag,
+    options: &Options,
+) -> (Vec<&'a File>, Vec<(&'a File, &'a File)>) {
+    let (files_to_copy, mut links) = if flags.contains(Flag::HARD_LINKS) {
+        partition_hard_links(files_to_copy)
+    } else {
+        (files_to_copy, Vec::new())
+    };
+    if !flags.contains(Flag::DEDUP) {
+        return (files_to_copy, links);
+    }
+
+    let (files_to_copy, duplicates) = partition_duplicates(files_to_copy, src, flags, options);
+    links.extend(duplicates);
+    (files_to_copy, links)
+}
+
+/// Splits `files_to_copy` into the files that are copied, and the files hard linked to one of
+/// those in the source, which are only linked to its copy
+///
+/// Of each set of files hard linked together, the one with the first path is copied,
+/// so files linked to a file that is not copied, such as one that is only updated, are copied
+///
+/// # Arguments
+/// * `files_to_copy`: files to split
+///
+/// # Returns
+/// The files that are copied, and the other files along with the copied file they are linked to
+fn partition_hard_links(mut files_to_copy: Vec<&File>) -> (Vec<&File>, Vec<(&File, &File)>) {
+    files_to_copy.par_sort_unstable_by(|a, b| a.path().cmp(b.path()));
+
+    let mut originals: HashMap<(u64, u64), &File> = HashMap::new();
+    let mut copied = Vec::new();
+    let mut links = Vec::new();
+    for file in files_to_copy {
+        match file.hard_link() {
+            Some(link) => match originals.get(&link) {
+                Some(original) => links.push((file, *original)),
+                None => {
+                    originals.insert(link, file);
+                    copied.push(file);
+                }
+            },
+            None => copied.push(file),
+        }
+    }
+    (copied, links)
+}
+
+/// Splits `files_to_copy` into the files that are copied, and the files with the same contents
+/// as one of those, which are only linked to its copy
+///
+/// Of each set of files with the same contents, the one with the first path is copied,
+/// and files that cannot be hashed or compared are always copied. Files with the same size and
+/// hash are also compared byte by byte, so that a hash collision never links different files
+///
+/// # Arguments
+/// * `files_to_copy`: files to split
+/// * `src`: base directory of the files
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The files that are copied, and the other files along with the copied file they are the same as
+fn partition_duplicates<'a>(
+    mut files_to_copy: Vec<&'a File>,
+    src: &str,
+    flags: Flag,
+    options: &Options,
+) -> (Vec<&'a File>, Vec<(&'a File, &'a File)>) {
+    // Only files that share their size with another file can be duplicates, so only those
+    // are hashed. Empty files are always copied, since there is nothing to save by linking them
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for file in &files_to_copy {
+        *size_counts.entry(file.size()).or_default() += 1;
+    }
+    files_to_copy.par_sort_unstable_by(|a, b| a.path().cmp(b.path()));
+    let hashes: Vec<Option<Vec<u8>>> = files_to_copy
+        .par_iter()
+        .map(|file| match size_counts[&file.size()] {
+            count if count > 1 && file.size() > 0 => {
+                file_ops::hash_file(*file, src, flags, options.hash, options.retries)
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut originals: HashMap<(u64, Vec<u8>), &File> = HashMap::new();
+    let mut copied = Vec::new();
+    let mut same_hashes = Vec::new();
+    for (file, file_hash) in files_to_copy.into_iter().zip(hashes) {
+        match file_hash {
+            Some(file_hash) => match originals.get(&(file.size(), file_hash.clone())) {
+                Some(original) => same_hashes.push((file, *original)),
+                None => {
+                    originals.insert((file.size(), file_hash), file);
+                    copied.push(file);
+                }
+            },
+            None => copied.push(file),
+        }
     }
 
-    Ok(())
+    let (duplicates, collisions): (Vec<_>, Vec<_>) =
+        same_hashes.into_par_iter().partition(|(file, original)| {
+            file_ops::files_identical(
+                &Path::new(src).join(file.path()),
+                &Path::new(src).join(original.path()),
+            )
+            .unwrap_or(false)
+        });
+    copied.extend(collisions.into_iter().map(|(file, _)| file));
+    (copied, duplicates)
 }
 
 /// Copies all files, directories, and symlinks in `src` to `dest`
 ///
+/// If `options.files` is set, only those paths in `src` are copied,
+/// and paths excluded by `options.filter`, or files out of the size or time range of
+/// `options`, are never copied
+///
+/// If `options.link_dest` is not empty, files that are the same in one of its directories
+/// as in `src` are hard linked from the first such directory instead,
+/// which falls back to copying if they cannot be linked
+///
+/// If `Flag::RESUME` is set, files already in `dest` with the same size and modification time
+/// are not copied again
+///
 /// # Arguments
 /// * `src`: Source directory
 /// * `dest`: Destination directory
 /// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of everything that was copied
 ///
 /// # Errors
 /// This function will return an error in the following situations,
 /// but is not limited to just these cases:
 /// * `src` is an invalid directory
 /// * `dest` is an invalid directory
-pub fn copy(src: &str, dest: &str, _flags: Flag) -> Result<(), io::Error> {
+/// * `src` and `dest` overlap
+pub fn copy(src: &str, dest: &str, flags: Flag, options: &Options) -> Result<SyncStats, io::Error> {
+    check_overlap(src, dest)?;
+
     // Retrieve data from src directory about files, dirs, symlinks
-    let src_file_sets = file_ops::get_all_files(&src)?;
+    let mut src_file_sets = match &options.files {
+        Some(files) => file_ops::get_listed_files(src, files, &options.filter)?,
+        None => get_all_files_with(src, &options.filter, flags)?,
+    };
+    src_file_sets.retain_files(|file| in_range(src, file, options));
+    if flags.contains(Flag::PRUNE_EMPTY_DIRS) {
+        src_file_sets.prune_empty_dirs();
+    }
     let src_files = src_file_sets.files();
     let src_dirs = src_file_sets.dirs();
     let src_symlinks = src_file_sets.symlinks();
 
     // Initialize progress bar
-    progress::progress_init((src_files.len() + src_dirs.len() + src_symlinks.len()) as u64);
+    progress::progress_init(
+        (src_files.len() + src_dirs.len() + src_symlinks.len()) as u64,
+        src_files.par_iter().map(FileOps::size).sum(),
+    );
+
+    // Files already copied to dest are kept, so that interrupted copies resume
+    let (files_to_copy, copied_files): (Vec<&File>, Vec<&File>) = if flags.contains(Flag::RESUME) {
+        src_files
+            .par_iter()
+            .partition(|file| !file_ops::is_copied(*file, src, dest))
+    } else {
+        (src_files.par_iter().collect(), Vec::new())
+    };
+    file_ops::skip_files(copied_files.into_par_iter(), "already copied");
+    let (files_to_copy, duplicates) = partition_links(files_to_copy, src, flags, options);
+
+    // Copy everything, and then link duplicates to the files that were copied,
+    // counting the bandwidth limit from when copying starts
+    options.throttle.start(options.bwlimit);
+    let mut stats = file_ops::copy_files(src_dirs.into_par_iter(), src, dest, flags, options);
+    stats += if options.link_dest.is_empty() {
+        file_ops::copy_files(files_to_copy.into_par_iter(), src, dest, flags, options)
+    } else {
+        file_ops::link_or_copy_files(files_to_copy.into_par_iter(), src, dest, flags, options)
+    };
+    stats += file_ops::link_duplicates(duplicates.into_par_iter(), src, dest, flags, options);
+    stats += file_ops::copy_files(src_symlinks.into_par_iter(), src, dest, flags, options);
+
+    // Owners are set before permissions, since changing the owner may clear setuid bits
+    if flags.contains(Flag::PRESERVE_OWNER) {
+        file_ops::copy_owners(src_files.par_iter(), src, dest);
+        file_ops::copy_owners(src_symlinks.par_iter(), src, dest);
+        file_ops::copy_owners(src_dirs.par_iter(), src, dest);
+    }
+
+    // Copied files already have the extended attributes of src, but dirs need them set,
+    // which is done before permissions in case those make the dirs read-only
+    if flags.contains(Flag::PRESERVE_XATTRS) {
+        file_ops::copy_all_xattrs(src_dirs.par_iter(), src, dest);
+    }
+
+    // Copied files already have the permissions of src, but dirs are created with the defaults,
+    // so that private dirs would be opened up. They are only set at the end, including those
+    // that already existed, so that restricted dirs can still be copied into
+    file_ops::copy_permissions(
+        file_ops::sort_files(src_dirs.par_iter()),
+        src,
+        dest,
+        options,
+    );
+
+    Ok(stats)
+}
+
+/// Moves all files, directories, and symlinks in `src` to `dest`, which works across
+/// file systems, by copying everything and then deleting `src`
+///
+/// `src` is only deleted if everything was copied without errors, so that nothing is lost
+///
+/// # Arguments
+/// * `src`: Source directory
+/// * `dest`: Destination directory, which must not be inside `src`
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of everything that was copied and deleted
+///
+/// # Errors
+/// This function will return an error in the following situations,
+/// but is not limited to just these cases:
+/// * `src` is an invalid directory
+/// * `dest` is an invalid directory
+/// * `dest` is inside `src`
+/// * Anything failed to be copied
+pub fn move_dir(
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> Result<SyncStats, io::Error> {
+    // Deleting src would also delete everything copied into dest
+    if fs::canonicalize(dest)?.starts_with(fs::canonicalize(src)?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Destination Error -- {} is inside {}", dest, src),
+        ));
+    }
+
+    let stats = copy(src, dest, flags, options)?;
+    if interrupt::is_interrupted() {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!(
+                "Move Error -- Interrupted while copying, so {} was not deleted",
+                src
+            ),
+        ));
+    }
+    if stats.errors > 0 {
+        return Err(io::Error::other(format!(
+            "Move Error -- {} errors while copying, so {} was not deleted",
+            stats.errors, src
+        )));
+    }
+
+    Ok(stats + remove(src, flags, options)?)
+}
+
+/// Paths that differ between a source and destination directory
+#[derive(Default, Debug, Eq, PartialEq)]
+pub struct Differences {
+    /// Paths in the source that are not in the destination
+    pub missing: Vec<PathBuf>,
+    /// Paths in the destination that are not in the source
+    pub extra: Vec<PathBuf>,
+    /// Paths in both, but with different types, contents, or symlink targets
+    pub different: Vec<PathBuf>,
+    /// Files in both with the same contents
+    pub identical: Vec<PathBuf>,
+}
+
+impl Differences {
+    /// Checks whether there are no differences
+    ///
+    /// # Returns
+    /// Whether the directories match
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+}
+
+/// Checks whether all files, directories, and symlinks in `dest` match `src`,
+/// without modifying either of them
+///
+/// # Arguments
+/// * `src`: Source directory
+/// * `dest`: Destination directory
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The sorted paths that are missing, extra, or different in `dest`,
+/// along with the files that are identical
+///
+/// # Errors
+/// This function will return an error in the following situations,
+/// but is not limited to just these cases:
+/// * `src` is an invalid directory
+/// * `dest` is an invalid directory
+pub fn verify(
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> Result<Differences, io::Error> {
+    // Retrieve data from src and dest directories about files, dirs, symlinks
+    // The hash cache is left out of dest if it is kept there
+    let mut dest_filter = Filter::default();
+    if let Some(pattern) = options.cache_file_pattern(dest) {
+        // This is safe to unwrap, since the path of the cache file is escaped
+        dest_filter.exclude(&pattern).unwrap();
+    }
+
+    let src_file_sets = file_ops::get_all_files(src)?;
+    let dest_file_sets = file_ops::get_all_files_with(dest, &dest_filter)?;
+    let src_files = src_file_sets.files();
+    let dest_files = dest_file_sets.files();
+    let src_paths = src_file_sets.paths();
+    let dest_paths = dest_file_sets.paths();
+
+    let files_to_compare: Vec<&File> = src_files.par_intersection(dest_files).collect();
+
+    // Initialize progress bar
+    progress::progress_init(files_to_compare.len() as u64, 0);
+
+    let (identical, different): (Vec<&File>, Vec<&File>) =
+        files_to_compare.into_par_iter().partition(|file| {
+            let is_same = file_ops::is_same_file(*file, src, dest, flags, options);
+            progress::progress_inc(1);
+            is_same
+        });
+
+    let mut differences = Differences {
+        missing: src_paths
+            .par_difference(&dest_paths)
+            .map(|&path| path.clone())
+            .collect(),
+        extra: dest_paths
+            .par_difference(&src_paths)
+            .map(|&path| path.clone())
+            .collect(),
+        different: different
+            .into_par_iter()
+            .map(|file| file.path().clone())
+            .collect(),
+        identical: identical
+            .into_par_iter()
+            .map(|file| file.path().clone())
+            .collect(),
+    };
+
+    // Entries with the same path that are not equal have a different type, size, or target
+    let changed_paths = src_files
+        .par_difference(dest_files)
+        .map(FileOps::path)
+        .chain(
+            src_file_sets
+                .dirs()
+                .par_difference(dest_file_sets.dirs())
+                .map(FileOps::path),
+        )
+        .chain(
+            src_file_sets
+                .symlinks()
+                .par_difference(dest_file_sets.symlinks())
+                .map(FileOps::path),
+        )
+        .filter(|path| dest_paths.contains(path))
+        .cloned();
+    differences.different.par_extend(changed_paths);
+
+    differences.missing.par_sort();
+    differences.extra.par_sort();
+    differences.different.par_sort();
+    differences.identical.par_sort();
+
+    Ok(differences)
+}
+
+/// Generates checksums of all files in `src`, without modifying them
+///
+/// # Arguments
+/// * `src`: Source directory
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The paths of all files that could be hashed along with their checksums, sorted by path.
+/// Files that can't be hashed are recorded as failures
+///
+/// # Errors
+/// This function will return an error in the following situations,
+/// but is not limited to just these cases:
+/// * `src` is an invalid directory
+pub fn checksums(
+    src: &str,
+    flags: Flag,
+    options: &Options,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, io::Error> {
+    // Retrieve data from src directory about files
+    let src_file_sets = get_all_files_with(src, &options.filter, flags)?;
+    let src_files = src_file_sets.files();
+
+    // Initialize progress bar
+    progress::progress_init(src_files.len() as u64, 0);
+
+    let mut checksums: Vec<(PathBuf, Vec<u8>)> = src_files
+        .par_iter()
+        .filter_map(|file| {
+            let hash = file_ops::hash_file(file, src, flags, options.hash, options.retries);
+            progress::progress_inc(1);
+            hash.map(|hash| (file.path().clone(), hash))
+        })
+        .collect();
 
-    // Copy everything
-    file_ops::copy_files(src_dirs.into_par_iter(), &src, &dest);
-    file_ops::copy_files(src_files.into_par_iter(), &src, &dest);
-    file_ops::copy_files(src_symlinks.into_par_iter(), &src, &dest);
+    checksums.par_sort();
 
-    Ok(())
+    Ok(checksums)
 }
 
 /// Deletes directory `target`
 ///
+/// If `options` has a time range, only the files and symlinks modified within it are deleted,
+/// along with the directories that become empty as a result.
+/// `target` itself is kept if anything is left in it
+///
+/// If `options` has trash set, everything is moved to the trash instead of deleted
+///
 /// # Arguments
 /// * `target`: Target directory
 /// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of everything that was deleted
 ///
 /// # Errors
 /// This function will return an error in the following situations,
 /// but is not limited to just these cases:
 /// * `target` is an invalid directory
-pub fn remove(target: &str, _flags: Flag) -> Result<(), io::Error> {
+pub fn remove(target: &str, flags: Flag, options: &Options) -> Result<SyncStats, io::Error> {
     // Retrieve data from target directory about files, dirs, symlinks
     let target_file_sets = file_ops::get_all_files(&target)?;
-    let target_files = target_file_sets.files();
+    let target_files: Vec<&File> = target_file_sets
+        .files()
+        .par_iter()
+        .filter(|file| in_time_range(target, file.path(), options))
+        .collect();
     let target_dirs = target_file_sets.dirs();
-    let target_symlinks = target_file_sets.symlinks();
+    let target_symlinks: Vec<&Symlink> = target_file_sets
+        .symlinks()
+        .par_iter()
+        .filter(|symlink| in_time_range(target, symlink.path(), options))
+        .collect();
 
     // Initialize progress bar
     progress::progress_init(
         (target_files.len() + target_dirs.len() + target_symlinks.len()) as u64,
+        0,
     );
     PROGRESS_BAR.enable_steady_tick(1);
 
+    // The whole target is moved to the trash at once, so that it can be restored as it was,
+    // instead of as each of its entries on their own
+    if flags.contains(Flag::TRASH) && !options.has_time_range() {
+        let mut stats = Dir::from("").remove(&PathBuf::from(target), flags, options);
+        if stats.errors == 0 {
+            stats.files_deleted += target_files.len() as u64;
+            stats.dirs_deleted += target_dirs.len() as u64;
+            stats.symlinks_deleted += target_symlinks.len() as u64;
+        }
+        progress::progress_inc(
+            (target_files.len() + target_dirs.len() + target_symlinks.len()) as u64,
+        );
+        return Ok(stats);
+    }
+
+    // Directories that had something in them deleted, which are the only ones
+    // that may be deleted for a time range, so that those that were already empty are kept
+    let mut emptied_dirs: HashSet<PathBuf> = target_files
+        .iter()
+        .map(|file| file.path())
+        .chain(target_symlinks.iter().map(|symlink| symlink.path()))
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+
     // Delete everything
-    file_ops::delete_files(target_files.into_par_iter(), &target);
-    file_ops::delete_files(target_symlinks.into_par_iter(), &target);
+    let mut stats = file_ops::delete_files(target_files.into_par_iter(), target, flags, options);
+    stats += file_ops::delete_files(target_symlinks.into_par_iter(), target, flags, options);
 
     // Directories must always be deleted sequentially so that they are deleted in the correct order
     let mut target_dirs: Vec<&file_ops::Dir> = file_ops::sort_files(target_dirs.into_par_iter());
@@ -138,9 +1040,27 @@ pub fn remove(target: &str, _flags: Flag) -> Result<(), io::Error> {
     let root_dir = Dir::from("");
     target_dirs.push(&root_dir);
 
-    file_ops::delete_files_sequential(target_dirs.into_iter(), &target);
+    if options.has_time_range() {
+        // Only delete the directories that were emptied. Each one is checked right before it
+        // would be deleted, after all of its subdirectories, and its parent is then emptied too
+        stats += file_ops::delete_files_sequential(
+            target_dirs.into_iter().filter(|dir| {
+                let emptied = emptied_dirs.contains(dir.path())
+                    && is_empty_dir(&Path::new(target).join(dir.path()));
+                if let (true, Some(parent)) = (emptied, dir.path().parent()) {
+                    emptied_dirs.insert(parent.to_path_buf());
+                }
+                emptied
+            }),
+            target,
+            flags,
+            options,
+        );
+    } else {
+        stats += file_ops::delete_files_sequential(target_dirs, target, flags, options);
+    }
 
-    Ok(())
+    Ok(stats)
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
@@ -150,8 +1070,10 @@ pub fn remove(target: &str, _flags: Flag) -> Result<(), io::Error> {
 #[cfg(test)]
 mod test_synchronize {
     use super::*;
+    use crate::lumins::parse::HashAlgo;
     use std::fs;
     use std::process::Command;
+    use std::time::{Duration, SystemTime};
 
     #[cfg(debug_assertions)]
     const BUILD_DIR: &str = "target/debug";
@@ -161,12 +1083,35 @@ mod test_synchronize {
 
     #[test]
     fn invalid_src() {
-        assert_eq!(synchronize("/?", "src", Flag::empty()).is_err(), true);
+        assert!(synchronize("/?", "src", Flag::empty(), &Options::default()).is_err());
     }
 
     #[test]
     fn invalid_dest() {
-        assert_eq!(synchronize("src", "/?", Flag::empty()).is_err(), true);
+        assert!(synchronize("src", "/?", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn overlap() {
+        const TEST_DIR: &str = "test_synchronize_overlap";
+        let sub = [TEST_DIR, "sub"].join("/");
+        let new = [TEST_DIR, "new"].join("/");
+
+        fs::create_dir_all(&sub).unwrap();
+
+        for &(src, dest) in &[
+            (TEST_DIR, TEST_DIR),
+            (TEST_DIR, sub.as_str()),
+            (sub.as_str(), TEST_DIR),
+            (TEST_DIR, new.as_str()),
+        ] {
+            let result = synchronize(src, dest, Flag::empty(), &Options::default());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(fs::metadata(&sub).is_ok());
+        assert!(fs::metadata(&new).is_err());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
     }
 
     #[cfg(target_family = "unix")]
@@ -175,7 +1120,7 @@ mod test_synchronize {
         const TEST_DIR: &str = "test_synchronize_dir1";
         fs::create_dir_all(TEST_DIR).unwrap();
 
-        assert_eq!(synchronize("src", TEST_DIR, Flag::empty()).is_ok(), true);
+        assert!(synchronize("src", TEST_DIR, Flag::empty(), &Options::default()).is_ok());
 
         let diff = Command::new("diff")
             .args(&["-r", "src", TEST_DIR])
@@ -194,7 +1139,7 @@ mod test_synchronize {
         fs::create_dir_all(TEST_DIR).unwrap();
 
         assert_eq!(
-            synchronize(BUILD_DIR, TEST_DIR, Flag::empty()).is_ok(),
+            synchronize(BUILD_DIR, TEST_DIR, Flag::empty(), &Options::default()).is_ok(),
             true
         );
 
@@ -216,7 +1161,7 @@ mod test_synchronize {
         assert_eq!(diff.status.success(), false);
 
         assert_eq!(
-            synchronize(BUILD_DIR, TEST_DIR, Flag::empty()).is_ok(),
+            synchronize(BUILD_DIR, TEST_DIR, Flag::empty(), &Options::default()).is_ok(),
             true
         );
 
@@ -251,7 +1196,7 @@ mod test_synchronize {
         assert_eq!(diff.status.success(), false);
 
         assert_eq!(
-            synchronize(TEST_SRC, TEST_DEST, Flag::empty()).is_ok(),
+            synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_ok(),
             true
         );
 
@@ -283,7 +1228,7 @@ mod test_synchronize {
         fs::File::create([TEST_DIR_EXPECTED, TEST_FILES[1]].join("/")).unwrap();
 
         assert_eq!(
-            synchronize(TEST_DIR, TEST_DIR_OUT, Flag::empty()).is_ok(),
+            synchronize(TEST_DIR, TEST_DIR_OUT, Flag::empty(), &Options::default()).is_ok(),
             true
         );
 
@@ -292,10 +1237,14 @@ mod test_synchronize {
         let mut flags = Flag::empty();
         flags.insert(Flag::VERBOSE);
         flags.insert(Flag::NO_DELETE);
-        flags.insert(Flag::SECURE);
         flags.insert(Flag::SEQUENTIAL);
 
-        assert_eq!(synchronize(TEST_DIR, TEST_DIR_OUT, flags).is_ok(), true);
+        let options = Options {
+            hash: HashAlgo::Blake2,
+            ..Options::default()
+        };
+
+        assert!(synchronize(TEST_DIR, TEST_DIR_OUT, flags, &options).is_ok());
 
         let diff = Command::new("diff")
             .args(&["-r", TEST_DIR_OUT, TEST_DIR_EXPECTED])
@@ -308,106 +1257,1672 @@ mod test_synchronize {
         fs::remove_dir_all(TEST_DIR_OUT).unwrap();
         fs::remove_dir_all(TEST_DIR_EXPECTED).unwrap();
     }
-}
-
-#[cfg(test)]
-mod test_copy {
-    use super::*;
-    use std::fs;
-    use std::process::Command;
-
-    #[test]
-    fn invalid_src() {
-        assert_eq!(copy("/?", "src", Flag::empty()).is_err(), true);
-    }
 
-    #[test]
-    fn invalid_dest() {
-        const TEST_DIR: &str = "test_copy_invalid_dest";
-        assert_eq!(copy("src", TEST_DIR, Flag::empty()).is_ok(), true);
-        fs::remove_dir_all(TEST_DIR).unwrap();
+    /// Creates a source and a destination where the destination has
+    /// changed, removed, and replaced files and directories
+    #[cfg(target_family = "unix")]
+    fn create_delete_order_dirs(src: &str, dest: &str) {
+        fs::create_dir_all([src, "a"].join("/")).unwrap();
+        fs::create_dir_all([dest, "e/f"].join("/")).unwrap();
+
+        fs::write([src, "a/x.txt"].join("/"), b"x").unwrap();
+        fs::write([src, "c.txt"].join("/"), b"new contents").unwrap();
+        fs::write([dest, "a"].join("/"), b"file replaced by dir").unwrap();
+        fs::write([dest, "c.txt"].join("/"), b"old").unwrap();
+        fs::write([dest, "d.txt"].join("/"), b"removed").unwrap();
+        fs::write([dest, "e/f/g.txt"].join("/"), b"removed").unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn dir1() {
-        const TEST_DIR: &str = "test_copy_dir1";
-        fs::create_dir_all(TEST_DIR).unwrap();
-
-        assert_eq!(copy("src", TEST_DIR, Flag::empty()).is_ok(), true);
+    fn delete_before() {
+        const TEST_SRC: &str = "test_synchronize_delete_before_src";
+        const TEST_DEST: &str = "test_synchronize_delete_before_dest";
+        create_delete_order_dirs(TEST_SRC, TEST_DEST);
+
+        // Directories can only be replaced by files when deleting before copying
+        fs::create_dir_all([TEST_DEST, "b"].join("/")).unwrap();
+        fs::write([TEST_DEST, "b/y.txt"].join("/"), b"y").unwrap();
+        fs::write([TEST_SRC, "b"].join("/"), b"dir replaced by file").unwrap();
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::DELETE_BEFORE,
+            &Options::default()
+        )
+        .is_ok());
 
         let diff = Command::new("diff")
-            .args(&["-r", "src", TEST_DIR])
+            .args(["-r", TEST_SRC, TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
+        assert!(diff.status.success());
 
-        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn flags() {
-        const TEST_DIR: &str = "test_copy_flags";
-        fs::create_dir_all(TEST_DIR).unwrap();
-
-        let mut flags = Flag::empty();
-        flags.insert(Flag::SEQUENTIAL);
-
-        assert_eq!(copy("src", TEST_DIR, flags).is_ok(), true);
+    fn delete_during() {
+        const TEST_SRC: &str = "test_synchronize_delete_during_src";
+        const TEST_DEST: &str = "test_synchronize_delete_during_dest";
+        create_delete_order_dirs(TEST_SRC, TEST_DEST);
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::DELETE_DURING,
+            &Options::default()
+        )
+        .is_ok());
 
         let diff = Command::new("diff")
-            .args(&["-r", "src", TEST_DIR])
+            .args(["-r", TEST_SRC, TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
-
-        fs::remove_dir_all(TEST_DIR).unwrap();
-    }
-}
-
-#[cfg(test)]
-mod test_remove {
-    use super::*;
-    use std::fs;
-    use std::process::Command;
-
-    #[cfg(debug_assertions)]
-    const BUILD_DIR: &str = "target/debug";
-
-    #[cfg(not(debug_assertions))]
-    const BUILD_DIR: &str = "target/release";
+        assert!(diff.status.success());
 
-    #[test]
-    fn invalid_target() {
-        assert_eq!(remove("/?", Flag::empty()).is_err(), true);
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn dir1() {
-        const TEST_DIR: &str = "test_remove_dir1";
-        fs::create_dir_all(TEST_DIR).unwrap();
+    fn delete_after() {
+        const TEST_SRC: &str = "test_synchronize_delete_after_src";
+        const TEST_DEST: &str = "test_synchronize_delete_after_dest";
+        create_delete_order_dirs(TEST_SRC, TEST_DEST);
 
-        Command::new("cp")
-            .args(&["-r", BUILD_DIR, TEST_DIR])
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::DELETE_AFTER, &Options::default()).is_ok());
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SRC, TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(remove(TEST_DIR, Flag::empty()).is_ok(), true);
+        assert!(diff.status.success());
 
-        assert_eq!(fs::read_dir(TEST_DIR).is_err(), true);
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn flags() {
-        const TEST_DIR: &str = "test_remove_flags";
-        fs::create_dir_all(TEST_DIR).unwrap();
+    fn delete_order() {
+        const TEST_SRC: &str = "test_synchronize_delete_order_src";
+        const TEST_DEST: &str = "test_synchronize_delete_order_dest";
+
+        for &(delete_flag, linked, replaced_dir) in &[
+            (Flag::DELETE_BEFORE, false, true),
+            (Flag::DELETE_DURING, false, false),
+            (Flag::DELETE_AFTER, true, false),
+        ] {
+            create_delete_order_dirs(TEST_SRC, TEST_DEST);
+            fs::create_dir_all([TEST_SRC, "f"].join("/")).unwrap();
+            fs::write([TEST_SRC, "f/g.txt"].join("/"), b"removed").unwrap();
+            fs::create_dir_all([TEST_DEST, "b"].join("/")).unwrap();
+            fs::write([TEST_DEST, "b/y.txt"].join("/"), b"y").unwrap();
+            fs::write([TEST_SRC, "b"].join("/"), b"dir replaced by file").unwrap();
+
+            // A file that is deleted from dest can only be linked from while it still exists,
+            // so it is only linked if files are deleted after copying
+            let options = Options {
+                link_dest: vec![[TEST_DEST, "e"].join("/")],
+                ..Options::default()
+            };
+            let stats = synchronize(TEST_SRC, TEST_DEST, delete_flag, &options).unwrap();
+
+            assert_eq!(stats.files_linked == 1, linked);
+            assert_eq!(
+                fs::read([TEST_DEST, "f/g.txt"].join("/")).unwrap(),
+                b"removed"
+            );
+            assert!(fs::metadata([TEST_DEST, "e"].join("/")).is_err());
+
+            // A directory in the way of a file is only gone before copying if it is deleted
+            // before, and otherwise it is still deleted after copying the other files
+            assert_eq!(stats.errors == 0, replaced_dir);
+            assert_eq!(fs::read([TEST_DEST, "b"].join("/")).is_ok(), replaced_dir);
+            assert_eq!(
+                fs::metadata([TEST_DEST, "b"].join("/")).is_ok(),
+                replaced_dir
+            );
+
+            fs::remove_dir_all(TEST_SRC).unwrap();
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+    }
 
-        let mut flags = Flag::empty();
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn delete_after_no_delete() {
+        const TEST_SRC: &str = "test_synchronize_delete_after_no_delete_src";
+        const TEST_DEST: &str = "test_synchronize_delete_after_no_delete_dest";
+        create_delete_order_dirs(TEST_SRC, TEST_DEST);
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::DELETE_AFTER | Flag::NO_DELETE,
+            &Options::default()
+        )
+        .is_ok());
+
+        assert!(fs::read([TEST_DEST, "d.txt"].join("/")).is_ok());
+        assert!(fs::read([TEST_DEST, "e/f/g.txt"].join("/")).is_ok());
+        assert_eq!(
+            fs::read([TEST_DEST, "c.txt"].join("/")).unwrap(),
+            b"new contents"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn exclude() {
+        const TEST_SRC: &str = "test_synchronize_exclude_src";
+        const TEST_DEST: &str = "test_synchronize_exclude_dest";
+
+        fs::create_dir_all([TEST_SRC, "a/target/b"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "a/target/b/c.txt"].join("/"), b"c").unwrap();
+        fs::write([TEST_SRC, "a/d.txt"].join("/"), b"d").unwrap();
+        fs::write([TEST_SRC, "e.iso"].join("/"), b"e").unwrap();
+        fs::write([TEST_DEST, "f.iso"].join("/"), b"f").unwrap();
+
+        let mut options = Options::default();
+        options.filter.exclude("target/").unwrap();
+        options.filter.exclude("*.iso").unwrap();
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "a/d.txt"].join("/")).unwrap(), b"d");
+        assert_eq!(fs::read([TEST_DEST, "f.iso"].join("/")).unwrap(), b"f");
+        assert!(fs::metadata([TEST_DEST, "a/target"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "e.iso"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn include() {
+        const TEST_SRC: &str = "test_synchronize_include_src";
+        const TEST_DEST: &str = "test_synchronize_include_dest";
+
+        fs::create_dir_all([TEST_SRC, "build/a"].join("/")).unwrap();
+        fs::create_dir_all([TEST_SRC, "build/b"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "build/a/c.json"].join("/"), b"c").unwrap();
+        fs::write([TEST_SRC, "build/a/d.txt"].join("/"), b"d").unwrap();
+        fs::write([TEST_SRC, "build/b/e.txt"].join("/"), b"e").unwrap();
+        fs::write([TEST_SRC, "f.txt"].join("/"), b"f").unwrap();
+
+        let mut options = Options::default();
+        options.filter.include("*.json").unwrap();
+        options.filter.exclude("build/").unwrap();
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(
+            fs::read([TEST_DEST, "build/a/c.json"].join("/")).unwrap(),
+            b"c"
+        );
+        assert_eq!(fs::read([TEST_DEST, "f.txt"].join("/")).unwrap(), b"f");
+        assert!(fs::metadata([TEST_DEST, "build/a/d.txt"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "build/b"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn exclude_dir() {
+        const TEST_SRC: &str = "test_synchronize_exclude_dir_src";
+        const TEST_DEST: &str = "test_synchronize_exclude_dir_dest";
+
+        fs::create_dir_all([TEST_SRC, "a/node_modules/b"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DEST, "node_modules"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/node_modules/b/c.json"].join("/"), b"c").unwrap();
+        fs::write([TEST_SRC, "a/d.json"].join("/"), b"d").unwrap();
+        fs::write([TEST_DEST, "node_modules/e.json"].join("/"), b"e").unwrap();
+
+        let mut options = Options::default();
+        options.filter.include("*.json").unwrap();
+        options.filter.exclude_dir("node_modules");
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "a/d.json"].join("/")).unwrap(), b"d");
+        assert!(fs::metadata([TEST_DEST, "a/node_modules"].join("/")).is_err());
+        assert_eq!(
+            fs::read([TEST_DEST, "node_modules/e.json"].join("/")).unwrap(),
+            b"e"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn ignore_files() {
+        const TEST_SRC: &str = "test_synchronize_ignore_files_src";
+        const TEST_DEST: &str = "test_synchronize_ignore_files_dest";
+
+        fs::create_dir_all([TEST_SRC, "a"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DEST, "a"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/.lmsignore"].join("/"), b"*.log\n!keep.log\n").unwrap();
+        fs::write([TEST_SRC, "a/b.log"].join("/"), b"b").unwrap();
+        fs::write([TEST_SRC, "a/keep.log"].join("/"), b"keep").unwrap();
+        fs::write([TEST_SRC, "c.log"].join("/"), b"c").unwrap();
+        fs::write([TEST_DEST, "a/d.log"].join("/"), b"d").unwrap();
+
+        let mut options = Options::default();
+        options.filter.use_ignore_files(Path::new(TEST_SRC));
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(
+            fs::read([TEST_DEST, "a/keep.log"].join("/")).unwrap(),
+            b"keep"
+        );
+        assert_eq!(fs::read([TEST_DEST, "c.log"].join("/")).unwrap(), b"c");
+        assert_eq!(fs::read([TEST_DEST, "a/d.log"].join("/")).unwrap(), b"d");
+        assert!(fs::metadata([TEST_DEST, "a/b.log"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        const TEST_SRC: &str = "test_synchronize_stats_src";
+        const TEST_DEST: &str = "test_synchronize_stats_dest";
+
+        fs::create_dir_all([TEST_SRC, "a"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DEST, "b"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/c.txt"].join("/"), b"ccc").unwrap();
+        fs::write([TEST_SRC, "d.txt"].join("/"), b"dd").unwrap();
+        fs::write([TEST_SRC, "e.txt"].join("/"), b"e").unwrap();
+        fs::write([TEST_DEST, "d.txt"].join("/"), b"xx").unwrap();
+        fs::write([TEST_DEST, "e.txt"].join("/"), b"e").unwrap();
+        fs::write([TEST_DEST, "b/f.txt"].join("/"), b"f").unwrap();
+
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).unwrap();
+
+        assert_eq!(
+            stats,
+            SyncStats {
+                files_copied: 1,
+                files_updated: 1,
+                files_deleted: 1,
+                dirs_created: 1,
+                dirs_deleted: 1,
+                bytes_copied: 5,
+                ..SyncStats::default()
+            }
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn changed_size() {
+        const TEST_SRC: &str = "test_synchronize_changed_size_src";
+        const TEST_DEST: &str = "test_synchronize_changed_size_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"aaa").unwrap();
+        fs::write([TEST_DEST, "a.txt"].join("/"), b"a").unwrap();
+
+        // The file is overwritten, rather than deleted and then copied
+        for &flags in &[Flag::empty(), Flag::DELETE_AFTER] {
+            fs::write([TEST_DEST, "a.txt"].join("/"), b"a").unwrap();
+
+            let stats = synchronize(TEST_SRC, TEST_DEST, flags, &Options::default()).unwrap();
+
+            assert_eq!(
+                stats,
+                SyncStats {
+                    files_updated: 1,
+                    bytes_copied: 3,
+                    ..SyncStats::default()
+                }
+            );
+            assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"aaa");
+        }
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    fn create_size_dirs(src: &str, dest: &str) {
+        fs::create_dir_all(src).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        fs::write([src, "big.txt"].join("/"), b"0123456789").unwrap();
+        fs::write([src, "small.txt"].join("/"), b"0").unwrap();
+        fs::write([src, "c.iso"].join("/"), b"c").unwrap();
+        fs::write([dest, "big.txt"].join("/"), b"old").unwrap();
+        fs::write([dest, "d.iso"].join("/"), b"d").unwrap();
+        fs::write([dest, "e.txt"].join("/"), b"e").unwrap();
+    }
+
+    #[test]
+    fn max_size() {
+        const TEST_SRC: &str = "test_synchronize_max_size_src";
+        const TEST_DEST: &str = "test_synchronize_max_size_dest";
+
+        create_size_dirs(TEST_SRC, TEST_DEST);
+
+        let options = Options {
+            max_size: Some(5),
+            ..Options::default()
+        };
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "big.txt"].join("/")).unwrap(), b"old");
+        assert_eq!(fs::read([TEST_DEST, "small.txt"].join("/")).unwrap(), b"0");
+        assert!(fs::metadata([TEST_DEST, "e.txt"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn delete_excluded() {
+        const TEST_SRC: &str = "test_synchronize_delete_excluded_src";
+        const TEST_DEST: &str = "test_synchronize_delete_excluded_dest";
+
+        create_size_dirs(TEST_SRC, TEST_DEST);
+
+        let mut options = Options {
+            min_size: Some(1),
+            max_size: Some(5),
+            ..Options::default()
+        };
+        options.filter.exclude("*.iso").unwrap();
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::DELETE_EXCLUDED, &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "small.txt"].join("/")).unwrap(), b"0");
+        assert!(fs::metadata([TEST_DEST, "big.txt"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "c.iso"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "d.iso"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn prune_empty_dirs() {
+        const TEST_SRC: &str = "test_synchronize_prune_empty_dirs_src";
+        const TEST_DEST: &str = "test_synchronize_prune_empty_dirs_dest";
+
+        fs::create_dir_all([TEST_SRC, "a/b"].join("/")).unwrap();
+        fs::create_dir_all([TEST_SRC, "c/d"].join("/")).unwrap();
+        fs::create_dir_all([TEST_SRC, "e"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/b/f.txt"].join("/"), b"f").unwrap();
+        fs::write([TEST_SRC, "c/d/g.iso"].join("/"), b"g").unwrap();
+        fs::write([TEST_SRC, "e/h.iso"].join("/"), b"h").unwrap();
+        fs::create_dir_all([TEST_DEST, "c/d"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DEST, "e"].join("/")).unwrap();
+        fs::write([TEST_DEST, "e/i.iso"].join("/"), b"i").unwrap();
+
+        let mut options = Options::default();
+        options.filter.exclude("*.iso").unwrap();
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::PRUNE_EMPTY_DIRS, &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "a/b/f.txt"].join("/")).unwrap(), b"f");
+        assert!(fs::metadata([TEST_DEST, "c"].join("/")).is_err());
+        assert_eq!(fs::read([TEST_DEST, "e/i.iso"].join("/")).unwrap(), b"i");
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn delete_excluded_nodelete() {
+        const TEST_SRC: &str = "test_synchronize_delete_excluded_nodelete_src";
+        const TEST_DEST: &str = "test_synchronize_delete_excluded_nodelete_dest";
+
+        create_size_dirs(TEST_SRC, TEST_DEST);
+        fs::create_dir_all([TEST_DEST, "junk.iso"].join("/")).unwrap();
+        fs::write([TEST_DEST, "junk.iso/file.txt"].join("/"), b"junk").unwrap();
+
+        let mut options = Options {
+            min_size: Some(1),
+            max_size: Some(5),
+            ..Options::default()
+        };
+        options.filter.exclude("*.iso").unwrap();
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::DELETE_EXCLUDED | Flag::NO_DELETE,
+            &options
+        )
+        .is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "small.txt"].join("/")).unwrap(), b"0");
+        assert_eq!(fs::read([TEST_DEST, "e.txt"].join("/")).unwrap(), b"e");
+        assert!(fs::metadata([TEST_DEST, "big.txt"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "c.iso"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "d.iso"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "junk.iso"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn newer_than() {
+        const TEST_SRC: &str = "test_synchronize_newer_than_src";
+        const TEST_DEST: &str = "test_synchronize_newer_than_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "old.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_SRC, "new.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DEST, "old.txt"].join("/"), b"dest").unwrap();
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open([TEST_SRC, "old.txt"].join("/"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let options = Options {
+            newer_than: Some(SystemTime::now() - Duration::from_secs(60 * 60)),
+            ..Options::default()
+        };
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "new.txt"].join("/")).unwrap(), b"new");
+        assert_eq!(fs::read([TEST_DEST, "old.txt"].join("/")).unwrap(), b"dest");
+
+        let options = Options {
+            older_than: Some(SystemTime::now() - Duration::from_secs(60 * 60)),
+            ..Options::default()
+        };
+
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_ok());
+
+        assert_eq!(fs::read([TEST_DEST, "new.txt"].join("/")).unwrap(), b"new");
+        assert_eq!(fs::read([TEST_DEST, "old.txt"].join("/")).unwrap(), b"old");
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn copy_links() {
+        use std::os::unix::fs::symlink;
+
+        const TEST_SRC: &str = "test_synchronize_copy_links_src";
+        const TEST_DEST: &str = "test_synchronize_copy_links_dest";
+
+        fs::create_dir_all([TEST_SRC, "a"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/file.txt"].join("/"), b"file").unwrap();
+        symlink("a", [TEST_SRC, "dir_link"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::FOLLOW_SYMLINKS,
+            &Options::default()
+        )
+        .is_ok());
+
+        let dir_link = [TEST_DEST, "dir_link"].join("/");
+        assert!(fs::symlink_metadata(&dir_link)
+            .unwrap()
+            .file_type()
+            .is_dir());
+        assert_eq!(
+            fs::read([&dir_link, "file.txt"].join("/")).unwrap(),
+            b"file"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn preserve_perms() {
+        use std::os::unix::fs::PermissionsExt;
+
+        const TEST_SRC: &str = "test_synchronize_preserve_perms_src";
+        const TEST_DEST: &str = "test_synchronize_preserve_perms_dest";
+
+        fs::create_dir_all([TEST_SRC, "a/b"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/b/file.txt"].join("/"), b"file").unwrap();
+        fs::set_permissions(
+            [TEST_SRC, "a/b/file.txt"].join("/"),
+            fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        fs::set_permissions(
+            [TEST_SRC, "a/b"].join("/"),
+            fs::Permissions::from_mode(0o500),
+        )
+        .unwrap();
+        fs::set_permissions([TEST_SRC, "a"].join("/"), fs::Permissions::from_mode(0o750)).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::PRESERVE_PERMS,
+            &Options::default()
+        )
+        .is_ok());
+
+        let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&[TEST_DEST, "a"].join("/")), 0o750);
+        assert_eq!(mode(&[TEST_DEST, "a/b"].join("/")), 0o500);
+        assert_eq!(mode(&[TEST_DEST, "a/b/file.txt"].join("/")), 0o640);
+
+        // Permissions that drifted in dest are updated without copying the files again
+        fs::set_permissions(
+            [TEST_DEST, "a/b/file.txt"].join("/"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        fs::set_permissions(
+            [TEST_DEST, "a"].join("/"),
+            fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+
+        let stats = synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::PRESERVE_PERMS,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(stats.perms_updated, 1);
+        assert_eq!(stats.files_updated, 0);
+        assert_eq!(mode(&[TEST_DEST, "a"].join("/")), 0o750);
+        assert_eq!(mode(&[TEST_DEST, "a/b/file.txt"].join("/")), 0o640);
+
+        for base in &[TEST_SRC, TEST_DEST] {
+            fs::set_permissions([base, "a/b"].join("/"), fs::Permissions::from_mode(0o755))
+                .unwrap();
+            fs::remove_dir_all(base).unwrap();
+        }
+    }
+
+    #[test]
+    fn ignore_existing() {
+        const TEST_SRC: &str = "test_synchronize_ignore_existing_src";
+        const TEST_DEST: &str = "test_synchronize_ignore_existing_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "new.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_SRC, "same_size.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_DEST, "same_size.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_SRC, "other_size.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_DEST, "other_size.txt"].join("/"), b"archived").unwrap();
+        fs::write([TEST_DEST, "removed.txt"].join("/"), b"removed").unwrap();
+
+        let stats = synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::IGNORE_EXISTING,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(stats.files_updated, 0);
+        assert_eq!(stats.files_deleted, 1);
+        assert_eq!(fs::read([TEST_DEST, "new.txt"].join("/")).unwrap(), b"new");
+        assert_eq!(
+            fs::read([TEST_DEST, "same_size.txt"].join("/")).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "other_size.txt"].join("/")).unwrap(),
+            b"archived"
+        );
+        assert!(fs::metadata([TEST_DEST, "removed.txt"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn ignore_existing_no_delete() {
+        const TEST_SRC: &str = "test_synchronize_ignore_existing_no_delete_src";
+        const TEST_DEST: &str = "test_synchronize_ignore_existing_no_delete_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "new.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_SRC, "existing.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_DEST, "existing.txt"].join("/"), b"dest").unwrap();
+        fs::write([TEST_DEST, "kept.txt"].join("/"), b"kept").unwrap();
+
+        // Only new files are added, and nothing in dest is changed
+        let stats = synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::IGNORE_EXISTING | Flag::NO_DELETE,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(stats.files_updated, 0);
+        assert_eq!(stats.files_deleted, 0);
+        assert_eq!(fs::read([TEST_DEST, "new.txt"].join("/")).unwrap(), b"new");
+        assert_eq!(
+            fs::read([TEST_DEST, "existing.txt"].join("/")).unwrap(),
+            b"dest"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "kept.txt"].join("/")).unwrap(),
+            b"kept"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn update() {
+        use std::fs::OpenOptions;
+
+        const TEST_SRC: &str = "test_synchronize_update_src";
+        const TEST_DEST: &str = "test_synchronize_update_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        for name in &[
+            "newer.txt",
+            "newer_other_size.txt",
+            "older.txt",
+            "in_window.txt",
+        ] {
+            fs::write([TEST_SRC, name].join("/"), b"src").unwrap();
+        }
+        fs::write([TEST_DEST, "newer.txt"].join("/"), b"dst").unwrap();
+        fs::write([TEST_DEST, "newer_other_size.txt"].join("/"), b"edited").unwrap();
+        fs::write([TEST_DEST, "older.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_DEST, "in_window.txt"].join("/"), b"dst").unwrap();
+
+        let now = SystemTime::now();
+        let set_modified = |base: &str, name: &str, modified: SystemTime| {
+            OpenOptions::new()
+                .write(true)
+                .open([base, name].join("/"))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        for name in &[
+            "newer.txt",
+            "newer_other_size.txt",
+            "older.txt",
+            "in_window.txt",
+        ] {
+            set_modified(TEST_SRC, name, now - Duration::from_secs(60));
+        }
+        set_modified(TEST_DEST, "newer.txt", now);
+        set_modified(TEST_DEST, "newer_other_size.txt", now);
+        set_modified(TEST_DEST, "older.txt", now - Duration::from_secs(120));
+        set_modified(TEST_DEST, "in_window.txt", now - Duration::from_secs(59));
+
+        let options = Options {
+            modify_window: Duration::from_secs(2),
+            ..Options::default()
+        };
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::UPDATE, &options).unwrap();
+
+        assert_eq!(stats.files_updated, 2);
+        assert_eq!(stats.files_deleted, 0);
+        assert_eq!(
+            fs::read([TEST_DEST, "newer.txt"].join("/")).unwrap(),
+            b"dst"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "newer_other_size.txt"].join("/")).unwrap(),
+            b"edited"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "older.txt"].join("/")).unwrap(),
+            b"src"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "in_window.txt"].join("/")).unwrap(),
+            b"src"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn preserve_owner() {
+        use std::os::unix::fs::{chown, lchown, symlink, MetadataExt};
+
+        const TEST_SRC: &str = "test_synchronize_preserve_owner_src";
+        const TEST_DEST: &str = "test_synchronize_preserve_owner_dest";
+
+        fs::create_dir_all([TEST_SRC, "a"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a/file.txt"].join("/"), b"file").unwrap();
+        symlink("file.txt", [TEST_SRC, "a/link"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        // Owners can only be changed by root
+        if chown([TEST_SRC, "a"].join("/"), Some(1234), Some(2345)).is_err() {
+            fs::remove_dir_all(TEST_SRC).unwrap();
+            fs::remove_dir_all(TEST_DEST).unwrap();
+            return;
+        }
+        chown([TEST_SRC, "a/file.txt"].join("/"), Some(3456), Some(4567)).unwrap();
+        lchown([TEST_SRC, "a/link"].join("/"), Some(5678), Some(6789)).unwrap();
+
+        assert!(synchronize(
+            TEST_SRC,
+            TEST_DEST,
+            Flag::PRESERVE_OWNER,
+            &Options::default()
+        )
+        .is_ok());
+
+        let owner = |path: &str| {
+            let metadata = fs::symlink_metadata(path).unwrap();
+            (metadata.uid(), metadata.gid())
+        };
+        assert_eq!(owner(&[TEST_DEST, "a"].join("/")), (1234, 2345));
+        assert_eq!(owner(&[TEST_DEST, "a/file.txt"].join("/")), (3456, 4567));
+        assert_eq!(owner(&[TEST_DEST, "a/link"].join("/")), (5678, 6789));
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn link_dest() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_SRC: &str = "test_synchronize_link_dest_src";
+        const TEST_LINK_DEST: &str = "test_synchronize_link_dest_link";
+        const TEST_DEST: &str = "test_synchronize_link_dest_dest";
+
+        for base in &[TEST_SRC, TEST_LINK_DEST] {
+            fs::create_dir_all([base, "dir"].join("/")).unwrap();
+            fs::write([base, "dir/same.txt"].join("/"), b"same").unwrap();
+        }
+        fs::write([TEST_SRC, "changed.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_LINK_DEST, "changed.txt"].join("/"), b"old").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let options = Options {
+            link_dest: vec![TEST_LINK_DEST.to_string()],
+            ..Options::default()
+        };
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).unwrap();
+        assert_eq!(stats.files_linked, 1);
+
+        let inode = |path: &str| fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(&[TEST_DEST, "dir/same.txt"].join("/")),
+            inode(&[TEST_LINK_DEST, "dir/same.txt"].join("/"))
+        );
+        assert_ne!(
+            inode(&[TEST_DEST, "changed.txt"].join("/")),
+            inode(&[TEST_LINK_DEST, "changed.txt"].join("/"))
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "changed.txt"].join("/")).unwrap(),
+            b"new"
+        );
+
+        // Updating a linked file must not change the file it is linked to
+        fs::write([TEST_SRC, "dir/same.txt"].join("/"), b"updated").unwrap();
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_ok());
+        assert_eq!(
+            fs::read([TEST_DEST, "dir/same.txt"].join("/")).unwrap(),
+            b"updated"
+        );
+        assert_eq!(
+            fs::read([TEST_LINK_DEST, "dir/same.txt"].join("/")).unwrap(),
+            b"same"
+        );
+
+        for base in &[TEST_SRC, TEST_LINK_DEST, TEST_DEST] {
+            fs::remove_dir_all(base).unwrap();
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn link_dest_multiple() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_SRC: &str = "test_synchronize_link_dest_multiple_src";
+        const TEST_LINK_DEST_1: &str = "test_synchronize_link_dest_multiple_link_1";
+        const TEST_LINK_DEST_2: &str = "test_synchronize_link_dest_multiple_link_2";
+        const TEST_DEST: &str = "test_synchronize_link_dest_multiple_dest";
+
+        for base in &[TEST_SRC, TEST_LINK_DEST_1, TEST_LINK_DEST_2] {
+            fs::create_dir_all(base).unwrap();
+            fs::write([base, "both.txt"].join("/"), b"both").unwrap();
+        }
+        fs::write([TEST_SRC, "second.txt"].join("/"), b"second").unwrap();
+        fs::write([TEST_LINK_DEST_1, "second.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_LINK_DEST_2, "second.txt"].join("/"), b"second").unwrap();
+        fs::write([TEST_SRC, "neither.txt"].join("/"), b"neither").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let options = Options {
+            link_dest: vec![TEST_LINK_DEST_1.to_string(), TEST_LINK_DEST_2.to_string()],
+            ..Options::default()
+        };
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).unwrap();
+        assert_eq!(stats.files_linked, 2);
+        assert_eq!(stats.files_copied, 1);
+
+        let inode = |path: &str| fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(&[TEST_DEST, "both.txt"].join("/")),
+            inode(&[TEST_LINK_DEST_1, "both.txt"].join("/"))
+        );
+        assert_eq!(
+            inode(&[TEST_DEST, "second.txt"].join("/")),
+            inode(&[TEST_LINK_DEST_2, "second.txt"].join("/"))
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "neither.txt"].join("/")).unwrap(),
+            b"neither"
+        );
+
+        for base in &[TEST_SRC, TEST_LINK_DEST_1, TEST_LINK_DEST_2, TEST_DEST] {
+            fs::remove_dir_all(base).unwrap();
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dedup() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_SRC: &str = "test_synchronize_dedup_src";
+        const TEST_DEST: &str = "test_synchronize_dedup_dest";
+
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"same").unwrap();
+        fs::write([TEST_SRC, "dir/b.txt"].join("/"), b"same").unwrap();
+        fs::write([TEST_SRC, "c.txt"].join("/"), b"diff").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::DEDUP, &Options::default()).unwrap();
+        assert_eq!(stats.files_copied, 2);
+        assert_eq!(stats.files_linked, 1);
+
+        let inode = |path: &str| fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(&[TEST_DEST, "a.txt"].join("/")),
+            inode(&[TEST_DEST, "dir/b.txt"].join("/"))
+        );
+        assert_ne!(
+            inode(&[TEST_DEST, "a.txt"].join("/")),
+            inode(&[TEST_DEST, "c.txt"].join("/"))
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "dir/b.txt"].join("/")).unwrap(),
+            b"same"
+        );
+
+        // Updating a linked file must not change the file it is linked to
+        fs::write([TEST_SRC, "dir/b.txt"].join("/"), b"new!").unwrap();
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_ok());
+        assert_eq!(
+            fs::read([TEST_DEST, "dir/b.txt"].join("/")).unwrap(),
+            b"new!"
+        );
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"same");
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dedup_hash_collision() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_SRC: &str = "test_synchronize_dedup_hash_collision_src";
+        const TEST_DEST: &str = "test_synchronize_dedup_hash_collision_dest";
+
+        // These have the same CRC-32, but different contents
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"plumless").unwrap();
+        fs::write([TEST_SRC, "b.txt"].join("/"), b"buckeroo").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let options = Options {
+            hash: HashAlgo::Crc32,
+            ..Options::default()
+        };
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::DEDUP, &options).unwrap();
+        assert_eq!(stats.files_copied, 2);
+        assert_eq!(stats.files_linked, 0);
+
+        let inode = |path: &str| fs::metadata(path).unwrap().ino();
+        assert_ne!(
+            inode(&[TEST_DEST, "a.txt"].join("/")),
+            inode(&[TEST_DEST, "b.txt"].join("/"))
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "b.txt"].join("/")).unwrap(),
+            b"buckeroo"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn hard_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_SRC: &str = "test_synchronize_hard_links_src";
+        const TEST_DEST: &str = "test_synchronize_hard_links_dest";
+
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"linked").unwrap();
+        fs::hard_link(
+            [TEST_SRC, "a.txt"].join("/"),
+            [TEST_SRC, "dir/b.txt"].join("/"),
+        )
+        .unwrap();
+        fs::write([TEST_SRC, "c.txt"].join("/"), b"linked").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let stats =
+            synchronize(TEST_SRC, TEST_DEST, Flag::HARD_LINKS, &Options::default()).unwrap();
+        assert_eq!(stats.files_copied, 2);
+        assert_eq!(stats.files_linked, 1);
+
+        let inode = |path: &str| fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(&[TEST_DEST, "a.txt"].join("/")),
+            inode(&[TEST_DEST, "dir/b.txt"].join("/"))
+        );
+        assert_ne!(
+            inode(&[TEST_DEST, "a.txt"].join("/")),
+            inode(&[TEST_DEST, "c.txt"].join("/"))
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "dir/b.txt"].join("/")).unwrap(),
+            b"linked"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn backup_dir() {
+        const TEST_SRC: &str = "test_synchronize_backup_dir_src";
+        const TEST_DEST: &str = "test_synchronize_backup_dir_dest";
+        const TEST_BACKUP_DIR: &str = "test_synchronize_backup_dir_dest/backup";
+
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "dir/changed.txt"].join("/"), b"new").unwrap();
+        fs::create_dir_all([TEST_DEST, "dir/extra"].join("/")).unwrap();
+        fs::write([TEST_DEST, "dir/changed.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_DEST, "dir/extra/deleted.txt"].join("/"), b"deleted").unwrap();
+
+        let mut options = Options {
+            backup_dir: Some(PathBuf::from(TEST_BACKUP_DIR)),
+            ..Options::default()
+        };
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).unwrap();
+        assert_eq!(stats.files_updated, 1);
+        assert_eq!(stats.files_deleted, 1);
+
+        assert_eq!(
+            fs::read([TEST_DEST, "dir/changed.txt"].join("/")).unwrap(),
+            b"new"
+        );
+        assert!(fs::metadata([TEST_DEST, "dir/extra"].join("/")).is_err());
+        assert_eq!(
+            fs::read([TEST_BACKUP_DIR, "dir/changed.txt"].join("/")).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            fs::read([TEST_BACKUP_DIR, "dir/extra/deleted.txt"].join("/")).unwrap(),
+            b"deleted"
+        );
+
+        // The backup dir is neither synchronized nor deleted on later runs
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).unwrap();
+        assert_eq!(stats.files_deleted, 0);
+        assert_eq!(
+            fs::read([TEST_BACKUP_DIR, "dir/changed.txt"].join("/")).unwrap(),
+            b"old"
+        );
+
+        // The backup dir must not be where it would be synchronized too
+        options.backup_dir = Some(PathBuf::from([TEST_SRC, "backup"].join("/")));
+        assert!(synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &options).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn stale_temp_files() {
+        const TEST_SRC: &str = "test_synchronize_stale_temp_files_src";
+        const TEST_DEST: &str = "test_synchronize_stale_temp_files_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all([TEST_DEST, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "file.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_SRC, "kept.txt.lms-tmp.1.0"].join("/"), b"src").unwrap();
+        fs::write([TEST_DEST, "file.txt.lms-tmp.1.0"].join("/"), b"partial").unwrap();
+        fs::write(
+            [TEST_DEST, "dir/other.txt.lms-tmp.1.0"].join("/"),
+            b"partial",
+        )
+        .unwrap();
+        fs::write([TEST_DEST, "kept.txt.lms-tmp.1.0"].join("/"), b"dest").unwrap();
+        fs::write([TEST_DEST, "notes.lms-tmp"].join("/"), b"dest").unwrap();
+
+        // Temporary files are kept if nothing is deleted
+        synchronize(TEST_SRC, TEST_DEST, Flag::NO_DELETE, &Options::default()).unwrap();
+
+        assert!(Path::new(&[TEST_DEST, "dir/other.txt.lms-tmp.1.0"].join("/")).exists());
+
+        // Temporary files are removed without counting them as deleted,
+        // but not if they are in src, while files only named like them are deleted
+        fs::write([TEST_DEST, "file.txt.lms-tmp.1.0"].join("/"), b"partial").unwrap();
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).unwrap();
+
+        assert_eq!(stats.files_deleted, 1);
+        assert!(!Path::new(&[TEST_DEST, "notes.lms-tmp"].join("/")).exists());
+        assert_eq!(fs::read([TEST_DEST, "file.txt"].join("/")).unwrap(), b"src");
+        assert!(!Path::new(&[TEST_DEST, "file.txt.lms-tmp.1.0"].join("/")).exists());
+        assert!(!Path::new(&[TEST_DEST, "dir/other.txt.lms-tmp.1.0"].join("/")).exists());
+        assert_eq!(
+            fs::read([TEST_DEST, "kept.txt.lms-tmp.1.0"].join("/")).unwrap(),
+            b"src"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn newer() {
+        use std::fs::OpenOptions;
+
+        const TEST_SRC: &str = "test_synchronize_newer_src";
+        const TEST_DEST: &str = "test_synchronize_newer_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        for name in &["newer_in_src.txt", "newer_in_dest.txt", "same_time.txt"] {
+            fs::write([TEST_SRC, name].join("/"), b"src").unwrap();
+            fs::write([TEST_DEST, name].join("/"), b"destination").unwrap();
+        }
+
+        let now = SystemTime::now();
+        let set_modified = |base: &str, name: &str, modified: SystemTime| {
+            OpenOptions::new()
+                .write(true)
+                .open([base, name].join("/"))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        set_modified(TEST_SRC, "newer_in_src.txt", now);
+        set_modified(TEST_DEST, "newer_in_src.txt", now - Duration::from_secs(60));
+        set_modified(TEST_SRC, "newer_in_dest.txt", now - Duration::from_secs(60));
+        set_modified(TEST_DEST, "newer_in_dest.txt", now);
+        set_modified(TEST_SRC, "same_time.txt", now);
+        set_modified(TEST_DEST, "same_time.txt", now);
+
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::NEWER, &Options::default()).unwrap();
+
+        assert_eq!(stats.files_updated, 1);
+        assert_eq!(
+            fs::read([TEST_DEST, "newer_in_src.txt"].join("/")).unwrap(),
+            b"src"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "newer_in_dest.txt"].join("/")).unwrap(),
+            b"destination"
+        );
+        assert_eq!(
+            fs::read([TEST_DEST, "same_time.txt"].join("/")).unwrap(),
+            b"destination"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn partial_temp_files() {
+        const TEST_SRC: &str = "test_synchronize_partial_temp_files_src";
+        const TEST_DEST: &str = "test_synchronize_partial_temp_files_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "file.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_DEST, "file.txt.lms-tmp.1.0"].join("/"), b"sr").unwrap();
+        fs::write([TEST_DEST, "gone.txt.lms-tmp.1.0"].join("/"), b"partial").unwrap();
+
+        // Partial copies of files in src are neither removed nor deleted, but copied into,
+        // while those of files that are no longer in src are removed
+        let stats = synchronize(TEST_SRC, TEST_DEST, Flag::PARTIAL, &Options::default()).unwrap();
+
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(stats.files_deleted, 0);
+        assert_eq!(fs::read([TEST_DEST, "file.txt"].join("/")).unwrap(), b"src");
+        assert!(!Path::new(&[TEST_DEST, "file.txt.lms-tmp.1.0"].join("/")).exists());
+        assert!(!Path::new(&[TEST_DEST, "gone.txt.lms-tmp.1.0"].join("/")).exists());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn stats_only() {
+        const TEST_SRC: &str = "test_synchronize_stats_only_src";
+        const TEST_DEST: &str = "test_synchronize_stats_only_dest";
+
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DEST, "extra_dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "dir/new.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_SRC, "same.txt"].join("/"), b"same").unwrap();
+        fs::write([TEST_SRC, "changed.txt"].join("/"), b"longer").unwrap();
+        fs::write([TEST_DEST, "same.txt"].join("/"), b"same").unwrap();
+        fs::write([TEST_DEST, "changed.txt"].join("/"), b"short").unwrap();
+        fs::write([TEST_DEST, "extra.txt"].join("/"), b"extra").unwrap();
+
+        let stats =
+            synchronize(TEST_SRC, TEST_DEST, Flag::STATS_ONLY, &Options::default()).unwrap();
+
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(stats.files_updated, 1);
+        assert_eq!(stats.files_deleted, 1);
+        assert_eq!(stats.dirs_created, 1);
+        assert_eq!(stats.dirs_deleted, 1);
+        assert_eq!(stats.bytes_copied, 9);
+
+        // Nothing is changed
+        assert!(!Path::new(&[TEST_DEST, "dir"].join("/")).exists());
+        assert_eq!(
+            fs::read([TEST_DEST, "changed.txt"].join("/")).unwrap(),
+            b"short"
+        );
+        assert!(Path::new(&[TEST_DEST, "extra.txt"].join("/")).exists());
+
+        // A dest that doesn't exist is not created
+        fs::remove_dir_all(TEST_DEST).unwrap();
+        let stats =
+            synchronize(TEST_SRC, TEST_DEST, Flag::STATS_ONLY, &Options::default()).unwrap();
+
+        assert_eq!(stats.files_copied, 3);
+        assert!(!Path::new(TEST_DEST).exists());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_copy {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn invalid_src() {
+        assert!(copy("/?", "src", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn invalid_dest() {
+        const TEST_DIR: &str = "test_copy_invalid_dest";
+        assert!(copy("src", TEST_DIR, Flag::empty(), &Options::default()).is_ok());
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dir1() {
+        const TEST_DIR: &str = "test_copy_dir1";
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        assert!(copy("src", TEST_DIR, Flag::empty(), &Options::default()).is_ok());
+
+        let diff = Command::new("diff")
+            .args(&["-r", "src", TEST_DIR])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn flags() {
+        const TEST_DIR: &str = "test_copy_flags";
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        let mut flags = Flag::empty();
+        flags.insert(Flag::SEQUENTIAL);
+
+        assert!(copy("src", TEST_DIR, flags, &Options::default()).is_ok());
+
+        let diff = Command::new("diff")
+            .args(&["-r", "src", TEST_DIR])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dir_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        const TEST_SRC: &str = "test_copy_dir_permissions_src";
+        const TEST_DEST: &str = "test_copy_dir_permissions_dest";
+
+        fs::create_dir_all([TEST_SRC, "private/readonly"].join("/")).unwrap();
+        fs::write([TEST_SRC, "private/readonly/file.txt"].join("/"), b"file").unwrap();
+        fs::set_permissions(
+            [TEST_SRC, "private/readonly"].join("/"),
+            fs::Permissions::from_mode(0o500),
+        )
+        .unwrap();
+        fs::set_permissions(
+            [TEST_SRC, "private"].join("/"),
+            fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+        // Dirs that already exist in dest are also restricted
+        fs::create_dir_all([TEST_DEST, "private"].join("/")).unwrap();
+        fs::set_permissions(
+            [TEST_DEST, "private"].join("/"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        assert!(copy(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_ok());
+
+        let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&[TEST_DEST, "private"].join("/")), 0o700);
+        assert_eq!(mode(&[TEST_DEST, "private/readonly"].join("/")), 0o500);
+        assert_eq!(
+            fs::read([TEST_DEST, "private/readonly/file.txt"].join("/")).unwrap(),
+            b"file"
+        );
+
+        for base in &[TEST_SRC, TEST_DEST] {
+            fs::set_permissions(
+                [base, "private/readonly"].join("/"),
+                fs::Permissions::from_mode(0o755),
+            )
+            .unwrap();
+            fs::remove_dir_all(base).unwrap();
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn files() {
+        const TEST_DIR: &str = "test_copy_files";
+        const TEST_DIR_EXPECTED: &str = "test_copy_files_expected";
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all([TEST_DIR_EXPECTED, "lumins"].join("/")).unwrap();
+
+        fs::copy("src/main.rs", [TEST_DIR_EXPECTED, "main.rs"].join("/")).unwrap();
+        fs::copy(
+            "src/lumins/core.rs",
+            [TEST_DIR_EXPECTED, "lumins/core.rs"].join("/"),
+        )
+        .unwrap();
+
+        let options = Options {
+            files: Some(vec![
+                PathBuf::from("main.rs"),
+                PathBuf::from("lumins/core.rs"),
+            ]),
+            ..Options::default()
+        };
+
+        assert!(copy("src", TEST_DIR, Flag::empty(), &options).is_ok());
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_DIR, TEST_DIR_EXPECTED])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_EXPECTED).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn exclude() {
+        const TEST_DIR: &str = "test_copy_exclude";
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        let mut options = Options::default();
+        options.filter.exclude("lumins/").unwrap();
+
+        assert!(copy("src", TEST_DIR, Flag::empty(), &options).is_ok());
+
+        assert!(fs::metadata([TEST_DIR, "main.rs"].join("/")).is_ok());
+        assert!(fs::metadata([TEST_DIR, "lumins"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn resume() {
+        use std::fs::OpenOptions;
+
+        const TEST_SRC: &str = "test_copy_resume";
+        const TEST_DEST: &str = "test_copy_resume_out";
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "done.txt"].join("/"), b"src").unwrap();
+        fs::write([TEST_SRC, "partial.txt"].join("/"), b"src").unwrap();
+
+        copy(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).unwrap();
+
+        // A finished file that was copied with the same size and modification time is kept,
+        // even if its contents are different
+        let modified = fs::metadata([TEST_SRC, "done.txt"].join("/"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        fs::write([TEST_DEST, "done.txt"].join("/"), b"dst").unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open([TEST_DEST, "done.txt"].join("/"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::write([TEST_DEST, "partial.txt"].join("/"), b"s").unwrap();
+
+        let stats = copy(TEST_SRC, TEST_DEST, Flag::RESUME, &Options::default()).unwrap();
+
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(fs::read([TEST_DEST, "done.txt"].join("/")).unwrap(), b"dst");
+        assert_eq!(
+            fs::read([TEST_DEST, "partial.txt"].join("/")).unwrap(),
+            b"src"
+        );
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_move_dir {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn invalid_src() {
+        assert!(move_dir("/?", "src", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dir1() {
+        const TEST_SRC: &str = "test_move_dir_dir1_src";
+        const TEST_DEST: &str = "test_move_dir_dir1_dest";
+
+        Command::new("cp")
+            .args(["-r", "src", TEST_SRC])
+            .output()
+            .unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        assert!(move_dir(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_ok());
+
+        let diff = Command::new("diff")
+            .args(["-r", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+        assert!(fs::metadata(TEST_SRC).is_err());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn dest_inside_src() {
+        const TEST_SRC: &str = "test_move_dir_dest_inside_src";
+
+        fs::create_dir_all([TEST_SRC, "dest"].join("/")).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"a").unwrap();
+
+        assert!(move_dir(
+            TEST_SRC,
+            &[TEST_SRC, "dest"].join("/"),
+            Flag::empty(),
+            &Options::default()
+        )
+        .is_err());
+        assert_eq!(fs::read([TEST_SRC, "a.txt"].join("/")).unwrap(), b"a");
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+    }
+
+    #[test]
+    fn copy_error() {
+        const TEST_SRC: &str = "test_move_dir_copy_error_src";
+        const TEST_DEST: &str = "test_move_dir_copy_error_dest";
+
+        fs::create_dir_all(TEST_SRC).unwrap();
+        fs::write([TEST_SRC, "a.txt"].join("/"), b"a").unwrap();
+        fs::write([TEST_SRC, "b.txt"].join("/"), b"b").unwrap();
+        // A directory in the way of a file cannot be copied over
+        fs::create_dir_all([TEST_DEST, "b.txt"].join("/")).unwrap();
+
+        assert!(move_dir(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).is_err());
+        assert_eq!(fs::read([TEST_SRC, "a.txt"].join("/")).unwrap(), b"a");
+        assert_eq!(fs::read([TEST_SRC, "b.txt"].join("/")).unwrap(), b"b");
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_verify {
+    use super::*;
+    use crate::lumins::parse::HashAlgo;
+    use std::fs;
+
+    #[test]
+    fn invalid_src() {
+        assert!(verify("/?", "src", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn same() {
+        assert!(verify("src", "src", Flag::empty(), &Options::default())
+            .unwrap()
+            .is_empty());
+        for &hash in &[
+            HashAlgo::Blake2,
+            HashAlgo::Sha256,
+            HashAlgo::Xxhash,
+            HashAlgo::Blake3,
+            HashAlgo::Crc32,
+        ] {
+            let options = Options {
+                hash,
+                ..Options::default()
+            };
+            assert!(verify("src", "src", Flag::empty(), &options)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn differences() {
+        const TEST_SRC: &str = "test_verify_differences_src";
+        const TEST_DEST: &str = "test_verify_differences_dest";
+
+        fs::create_dir_all([TEST_SRC, "a"].join("/")).unwrap();
+        fs::create_dir_all([TEST_SRC, "b"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SRC, "a/c.txt"].join("/"), b"c").unwrap();
+        fs::write([TEST_SRC, "d.txt"].join("/"), b"dd").unwrap();
+        fs::write([TEST_SRC, "e.txt"].join("/"), b"e").unwrap();
+        fs::write([TEST_DEST, "b"].join("/"), b"b").unwrap();
+        fs::write([TEST_DEST, "d.txt"].join("/"), b"xx").unwrap();
+        fs::write([TEST_DEST, "e.txt"].join("/"), b"eee").unwrap();
+        fs::write([TEST_DEST, "f.txt"].join("/"), b"f").unwrap();
+        fs::write([TEST_SRC, "g.txt"].join("/"), b"g").unwrap();
+        fs::write([TEST_DEST, "g.txt"].join("/"), b"g").unwrap();
+
+        let differences = verify(TEST_SRC, TEST_DEST, Flag::empty(), &Options::default()).unwrap();
+
+        assert_eq!(
+            differences,
+            Differences {
+                missing: vec![PathBuf::from("a"), PathBuf::from("a/c.txt")],
+                extra: vec![PathBuf::from("f.txt")],
+                different: vec![
+                    PathBuf::from("b"),
+                    PathBuf::from("d.txt"),
+                    PathBuf::from("e.txt")
+                ],
+                identical: vec![PathBuf::from("g.txt")],
+            }
+        );
+
+        // Nothing is modified
+        assert_eq!(fs::read([TEST_DEST, "d.txt"].join("/")).unwrap(), b"xx");
+        assert!(fs::metadata([TEST_DEST, "a"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_checksums {
+    use super::*;
+    use crate::lumins::parse::HashAlgo;
+    use std::fs;
+
+    #[test]
+    fn invalid_src() {
+        assert!(checksums("/?", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn sorted() {
+        const TEST_DIR: &str = "test_checksums_sorted";
+
+        fs::create_dir_all([TEST_DIR, "b"].join("/")).unwrap();
+        fs::write([TEST_DIR, "c.txt"].join("/"), "123456789").unwrap();
+        fs::write([TEST_DIR, "b/a.txt"].join("/"), "").unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), "123456789").unwrap();
+
+        let options = Options {
+            hash: HashAlgo::Crc32,
+            ..Options::default()
+        };
+        assert_eq!(
+            checksums(TEST_DIR, Flag::empty(), &options).unwrap(),
+            vec![
+                (PathBuf::from("a.txt"), vec![0xcb, 0xf4, 0x39, 0x26]),
+                (PathBuf::from("b/a.txt"), vec![0, 0, 0, 0]),
+                (PathBuf::from("c.txt"), vec![0xcb, 0xf4, 0x39, 0x26]),
+            ]
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_remove {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+    use std::time::{Duration, SystemTime};
+
+    #[cfg(debug_assertions)]
+    const BUILD_DIR: &str = "target/debug";
+
+    #[cfg(not(debug_assertions))]
+    const BUILD_DIR: &str = "target/release";
+
+    #[test]
+    fn invalid_target() {
+        assert!(remove("/?", Flag::empty(), &Options::default()).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dir1() {
+        const TEST_DIR: &str = "test_remove_dir1";
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        Command::new("cp")
+            .args(&["-r", BUILD_DIR, TEST_DIR])
+            .output()
+            .unwrap();
+
+        assert!(remove(TEST_DIR, Flag::empty(), &Options::default()).is_ok());
+
+        assert_eq!(fs::read_dir(TEST_DIR).is_err(), true);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn flags() {
+        const TEST_DIR: &str = "test_remove_flags";
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        let mut flags = Flag::empty();
         flags.insert(Flag::SEQUENTIAL);
 
         Command::new("cp")
@@ -415,8 +2930,73 @@ mod test_remove {
             .output()
             .unwrap();
 
-        assert_eq!(remove(TEST_DIR, flags).is_ok(), true);
+        assert!(remove(TEST_DIR, flags, &Options::default()).is_ok());
 
         assert_eq!(fs::read_dir(TEST_DIR).is_err(), true);
     }
+
+    #[test]
+    fn older_than() {
+        const TEST_DIR: &str = "test_remove_older_than";
+        fs::create_dir_all([TEST_DIR, "old"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR, "mixed"].join("/")).unwrap();
+        fs::write([TEST_DIR, "old/old.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_DIR, "mixed/old.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_DIR, "mixed/new.txt"].join("/"), b"new").unwrap();
+
+        for path in &["old/old.txt", "mixed/old.txt"] {
+            fs::OpenOptions::new()
+                .write(true)
+                .open([TEST_DIR, path].join("/"))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap();
+        }
+
+        let options = Options {
+            older_than: Some(SystemTime::now() - Duration::from_secs(60 * 60)),
+            ..Options::default()
+        };
+
+        let stats = remove(TEST_DIR, Flag::empty(), &options).unwrap();
+
+        assert_eq!(stats.files_deleted, 2);
+        assert_eq!(stats.dirs_deleted, 1);
+        assert!(fs::metadata([TEST_DIR, "old"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DIR, "mixed/old.txt"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DIR, "mixed/new.txt"].join("/")).is_ok());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn older_than_empty_dirs() {
+        const TEST_DIR: &str = "test_remove_older_than_empty_dirs";
+        fs::create_dir_all([TEST_DIR, "empty"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR, "outer/inner"].join("/")).unwrap();
+        fs::write([TEST_DIR, "new.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DIR, "outer/inner/old.txt"].join("/"), b"old").unwrap();
+        fs::OpenOptions::new()
+            .write(true)
+            .open([TEST_DIR, "outer/inner/old.txt"].join("/"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let options = Options {
+            older_than: Some(SystemTime::now() - Duration::from_secs(60 * 60)),
+            ..Options::default()
+        };
+
+        // Directories that were emptied are deleted along with their parents,
+        // but those that were already empty are kept
+        let stats = remove(TEST_DIR, Flag::empty(), &options).unwrap();
+
+        assert_eq!(stats.files_deleted, 1);
+        assert_eq!(stats.dirs_deleted, 2);
+        assert!(fs::metadata([TEST_DIR, "outer"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DIR, "empty"].join("/")).is_ok());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
 }
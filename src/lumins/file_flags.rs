@@ -0,0 +1,243 @@
+//! Preserves the BSD flags and Finder metadata of files, such as whether they are hidden or
+//! locked and their color tags, which only exist on macOS
+
+pub use platform::{copy_file_flags, copy_finder_info, unlock};
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::macos::fs::MetadataExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    /// Extended attributes that hold the Finder info and color tags of files
+    const FINDER_XATTRS: [&str; 2] = [
+        "com.apple.FinderInfo",
+        "com.apple.metadata:_kMDItemUserTags",
+    ];
+
+    /// Flags that the owner of a file can set, from sys/stat.h
+    const UF_SETTABLE: u32 = 0x0000_ffff;
+    /// Flags that prevent a file from being replaced, from sys/stat.h
+    const UF_IMMUTABLE: u32 = 0x0000_0002;
+    const UF_APPEND: u32 = 0x0000_0004;
+
+    fn c_string(bytes: &[u8]) -> Result<CString, io::Error> {
+        CString::new(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn c_path(path: &Path) -> Result<CString, io::Error> {
+        c_string(path.as_os_str().as_bytes())
+    }
+
+    /// Sets the flags of `path`, failing with the error set by `chflags`
+    fn chflags(path: &Path, flags: u32) -> Result<(), io::Error> {
+        let path = c_path(path)?;
+        if unsafe { libc::chflags(path.as_ptr(), flags as libc::c_uint) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Reads the extended attribute `name` of `path`
+    ///
+    /// # Returns
+    /// * Ok: The value of the attribute, or None if `path` does not have it
+    /// * Err: If the attribute cannot be read
+    fn get_xattr(path: &CString, name: &CString) -> Result<Option<Vec<u8>>, io::Error> {
+        loop {
+            let size = unsafe {
+                libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    ptr::null_mut(),
+                    0,
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if size < 0 {
+                return missing_xattr();
+            }
+
+            let mut value = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_mut_ptr() as *mut libc::c_void,
+                    value.len(),
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if read >= 0 {
+                value.truncate(read as usize);
+                return Ok(Some(value));
+            }
+            // The attribute grew after its size was read, so it is read again
+            if io::Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+                return missing_xattr();
+            }
+        }
+    }
+
+    /// Turns the error set by `getxattr` into None if the attribute does not exist
+    fn missing_xattr() -> Result<Option<Vec<u8>>, io::Error> {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::ENOATTR) {
+            Ok(None)
+        } else {
+            Err(e)
+        }
+    }
+
+    /// Copies the Finder info and color tags of `src` to `dest`
+    ///
+    /// # Errors
+    /// If they cannot be read from `src` or written to `dest`
+    pub fn copy_finder_info(src: &Path, dest: &Path) -> Result<(), io::Error> {
+        let src = c_path(src)?;
+        let dest = c_path(dest)?;
+
+        for name in FINDER_XATTRS.iter() {
+            let name = c_string(name.as_bytes())?;
+            if let Some(value) = get_xattr(&src, &name)? {
+                let set = unsafe {
+                    libc::setxattr(
+                        dest.as_ptr(),
+                        name.as_ptr(),
+                        value.as_ptr() as *const libc::c_void,
+                        value.len(),
+                        0,
+                        libc::XATTR_NOFOLLOW,
+                    )
+                };
+                if set != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the flags of `dest` that its owner can set to those of `src`, if they differ
+    ///
+    /// # Errors
+    /// If the flags cannot be read or set
+    pub fn copy_file_flags(src: &Path, dest: &Path) -> Result<(), io::Error> {
+        let flags = fs::metadata(src)?.st_flags() & UF_SETTABLE;
+        if fs::metadata(dest)?.st_flags() & UF_SETTABLE == flags {
+            return Ok(());
+        }
+        chflags(dest, flags)
+    }
+
+    /// Clears the flags of `path` that prevent it from being replaced, such as if it is locked
+    ///
+    /// # Errors
+    /// If `path` exists, but its flags cannot be read or set
+    pub fn unlock(path: &Path) -> Result<(), io::Error> {
+        let flags = match fs::metadata(path) {
+            Ok(metadata) => metadata.st_flags(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if flags & (UF_IMMUTABLE | UF_APPEND) == 0 {
+            return Ok(());
+        }
+        chflags(path, flags & !(UF_IMMUTABLE | UF_APPEND))
+    }
+}
+
+/// Files have no flags or Finder metadata on other platforms, so nothing is copied
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    pub fn copy_finder_info(_src: &Path, _dest: &Path) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    pub fn copy_file_flags(_src: &Path, _dest: &Path) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    pub fn unlock(_path: &Path) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(all(test, target_os = "macos"))]
+mod test_file_flags {
+    use super::*;
+    use std::fs;
+    use std::os::macos::fs::MetadataExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Flags of hidden and locked files, from sys/stat.h
+    const UF_HIDDEN: u32 = 0x0000_8000;
+    const UF_IMMUTABLE: u32 = 0x0000_0002;
+
+    #[test]
+    fn copy_and_unlock() {
+        const TEST_DIR: &str = "test_file_flags_copy_and_unlock";
+        let src = [TEST_DIR, "src"].join("/");
+        let dest = [TEST_DIR, "dest"].join("/");
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&src, b"src").unwrap();
+        fs::write(&dest, b"dest").unwrap();
+        Command::new("chflags")
+            .args(&["hidden,uchg", &src])
+            .output()
+            .unwrap();
+
+        copy_file_flags(Path::new(&src), Path::new(&dest)).unwrap();
+        let flags = fs::metadata(&dest).unwrap().st_flags();
+        assert_eq!(flags & UF_HIDDEN != 0, true);
+        assert_eq!(flags & UF_IMMUTABLE != 0, true);
+
+        unlock(Path::new(&src)).unwrap();
+        unlock(Path::new(&dest)).unwrap();
+        let flags = fs::metadata(&dest).unwrap().st_flags();
+        assert_eq!(flags & UF_HIDDEN != 0, true);
+        assert_eq!(flags & UF_IMMUTABLE != 0, false);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn finder_info() {
+        const TEST_DIR: &str = "test_file_flags_finder_info";
+        const TAGS: &str = "com.apple.metadata:_kMDItemUserTags";
+        let src = [TEST_DIR, "src"].join("/");
+        let dest = [TEST_DIR, "dest"].join("/");
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write(&src, b"src").unwrap();
+        fs::write(&dest, b"dest").unwrap();
+        Command::new("xattr")
+            .args(&["-w", TAGS, "tags", &src])
+            .output()
+            .unwrap();
+
+        copy_finder_info(Path::new(&src), Path::new(&dest)).unwrap();
+        let output = Command::new("xattr")
+            .args(&["-p", TAGS, &dest])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "tags");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
@@ -1,1597 +1,5737 @@
 //! Contains utilities for copying, deleting, sorting, hashing files.
 
 use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::marker::Sync;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 use std::{fs, io};
 
 use blake2::{Blake2b, Digest};
 use hashbrown::HashSet;
-use log::{error, info};
+use indicatif::ProgressBar;
+use log::{error, info, warn};
+use memmap2::Mmap;
 use rayon::prelude::*;
-use seahash;
-
-use crate::lumins::parse::Flag;
-use crate::progress::PROGRESS_BAR;
+use seahash::{self, SeaHasher};
+use sha2::Sha256;
+use twox_hash::xxh3;
+
+use crate::lumins::cache;
+use crate::lumins::conflict::{ConflictAction, ConflictInfo};
+use crate::lumins::file_flags;
+use crate::lumins::interrupt;
+use crate::lumins::parse::{Flag, Fsync, HashAlgo, Options, Reflink};
+use crate::lumins::stats::{self, SyncStats};
+use crate::progress;
+use crate::retry;
+use crate::throttle::Throttle;
+use crate::xattrs;
+
+/// Size of the buffer that files are read into while they are streamed into a hash function
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+/// Size of the blocks that files are read in while they are compared byte by byte
+const COMPARE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Marks the temporary files that files are copied into,
+/// which are named `<name>.lms-tmp.<pid>.<counter>`
+const TEMP_FILE_MARKER: &str = ".lms-tmp";
+
+/// Number of temporary files named so far, which keeps their names unique within the process
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// Default suffix that overwritten files are renamed with when they are backed up
+pub const DEFAULT_BACKUP_SUFFIX: &str = "~";
+
+/// Gets the path in `options.backup_dir` that the entry at `path` is moved to, if it is set
+///
+/// # Arguments
+/// * `path`: path of the entry relative to the base directory it is in, which is kept
+///   in the backup dir
+/// * `options`: values of options
+fn backup_dir_target(path: &Path, options: &Options) -> Option<PathBuf> {
+    options.backup_dir.as_ref().map(|dir| dir.join(path))
+}
 
-/// Interface for all file structs to perform common operations
+/// Gives `dest` the owner and group in `options.chown`, if any, without following symlinks
 ///
-/// Ensures that all files (file, dir, symlink) have
-/// a way of obtaining their path, copying, and deleting
-pub trait FileOps {
-    fn path(&self) -> &PathBuf;
-    fn remove(&self, path: &PathBuf);
-    fn copy(&self, src: &PathBuf, dest: &PathBuf);
+/// # Errors
+/// If the owner or group cannot be set
+#[cfg(target_family = "unix")]
+fn chown_copied(dest: &Path, options: &Options) -> Result<(), io::Error> {
+    match options.chown {
+        Some((uid, gid)) => std::os::unix::fs::lchown(dest, uid, gid),
+        None => Ok(()),
+    }
 }
 
-/// A struct that represents a single file
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub struct File {
-    path: PathBuf,
-    size: u64,
+/// Gives `dest` the owner and group in `options.chown`, which is not supported on this platform,
+/// so nothing is changed
+#[cfg(not(target_family = "unix"))]
+fn chown_copied(_dest: &Path, _options: &Options) -> Result<(), io::Error> {
+    Ok(())
 }
 
-impl FileOps for File {
-    fn path(&self) -> &PathBuf {
-        &self.path
-    }
-    fn remove(&self, path: &PathBuf) {
-        match fs::remove_file(&path) {
-            Ok(_) => info!("Deleting file {:?}", path),
-            Err(e) => error!("Error -- Deleting file {:?}: {}", path, e),
-        }
-    }
-    fn copy(&self, src: &PathBuf, dest: &PathBuf) {
-        match fs::copy(&src, &dest) {
-            Ok(_) => info!("Copying file {:?} -> {:?}", src, dest),
-            Err(e) => error!("Error -- Copying file {:?}: {}", src, e),
+/// Gets the permissions that a copy of a file with `metadata` is given,
+/// which are those of the file changed by `options.chmod`, if set
+#[cfg(target_family = "unix")]
+fn chmod_permissions(metadata: &fs::Metadata, options: &Options) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+
+    let permissions = metadata.permissions();
+    match &options.chmod {
+        Some(chmod) => {
+            fs::Permissions::from_mode(chmod.apply(permissions.mode(), metadata.is_dir()))
         }
+        None => permissions,
     }
 }
 
-impl File {
-    pub fn from(path: &str, size: u64) -> Self {
-        File {
-            path: PathBuf::from(path),
-            size,
-        }
+/// Gets the permissions that a copy of a file with `metadata` is given,
+/// which are always those of the file on this platform
+#[cfg(not(target_family = "unix"))]
+fn chmod_permissions(metadata: &fs::Metadata, _options: &Options) -> fs::Permissions {
+    metadata.permissions()
+}
+
+/// Changes the permissions of the copied file `dest` as set by `options.chmod`, if set
+///
+/// # Errors
+/// If the permissions cannot be read or set
+fn chmod_copied(dest: &Path, options: &Options) -> Result<(), io::Error> {
+    if options.chmod.is_none() {
+        return Ok(());
     }
+    fs::set_permissions(dest, chmod_permissions(&fs::metadata(dest)?, options))
+}
 
-    #[allow(unused)]
-    #[allow(clippy::unused_io_amount)]
-    fn diff_copy(src: &PathBuf, dest: &PathBuf) -> Result<(), io::Error> {
-        if !Path::new(&dest).exists() {
-            fs::copy(&src, &dest)?;
+/// Checks whether files in `src` can be reflinked into `dest`, which is when both are on the
+/// same file system, and a probe file can be reflinked in `dest`
+///
+/// # Arguments
+/// * `src`: directory to reflink files from
+/// * `dest`: directory to reflink files into, where the probe files are removed again
+pub fn is_reflink_supported(src: &Path, dest: &Path) -> bool {
+    let same_device = match (fs::metadata(src), fs::metadata(dest)) {
+        (Ok(src_metadata), Ok(dest_metadata)) => {
+            device_id(&src_metadata) == device_id(&dest_metadata)
         }
+        _ => false,
+    };
+    if !same_device {
+        return false;
+    }
 
-        const CHUNK_SIZE: usize = 10000;
-
-        let src_file = fs::File::open(&src)?;
-        let mut src_reader = BufReader::with_capacity(CHUNK_SIZE, &src_file);
-        let dest_file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(&dest)?;
-        dest_file.set_len(src_file.metadata()?.len())?;
-        let mut dest_reader = BufReader::with_capacity(CHUNK_SIZE, &dest_file);
-        let mut dest_writer = BufWriter::with_capacity(CHUNK_SIZE, &dest_file);
-
-        loop {
-            let mut src_buffer = [0; CHUNK_SIZE];
-            let mut dest_buffer = [0; CHUNK_SIZE];
-
-            if src_reader.read(&mut src_buffer)? == 0 {
-                break;
-            }
-            dest_reader.read(&mut dest_buffer)?;
+    let probe = File::temp_path(&dest.join("reflink-probe"));
+    let clone = File::temp_path(&dest.join("reflink-clone"));
+    let supported = fs::write(&probe, b"lms")
+        .and_then(|_| File::reflink(&probe, &clone))
+        .is_ok();
+    fs::remove_file(&probe).ok();
+    fs::remove_file(&clone).ok();
+    supported
+}
 
-            if seahash::hash(&src_buffer) != seahash::hash(&dest_buffer) {
-                dest_writer.write(&src_buffer)?;
-            } else {
-                dest_writer.seek(SeekFrom::Current(CHUNK_SIZE as i64));
-            }
-        }
+/// Default number of bytes at the start and end of files hashed by `Flag::QUICK_HASH`
+pub const DEFAULT_QUICK_HASH_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Size of the blocks that are left as holes in copies if they only contain zeros
+const SPARSE_BLOCK_SIZE: usize = 4096;
+
+/// Size of the buffer that files are copied through when they are not copied with `fs::copy`
+const DEFAULT_COPY_BUFFER_SIZE: usize = 16 * SPARSE_BLOCK_SIZE;
+
+/// How `File::copy_file_contents` copies the contents of a file
+struct ContentOptions<'a> {
+    /// Size of the buffer that the file is read into and written from
+    buffer_size: usize,
+    /// Whether blocks of zeros are left as holes instead of written
+    sparse: bool,
+    /// Whether `dest` is truncated when it is opened, rather than overwritten and truncated
+    /// to the size copied once copying is done
+    truncate: bool,
+    /// Progress bar that is advanced by the bytes copied, if any
+    file_bar: Option<&'a ProgressBar>,
+    /// Limits the rate that the file is copied at, if any
+    throttle: Option<&'a Throttle>,
+    /// Number of bytes at the start of the destination that were already copied,
+    /// which are kept rather than copied again
+    resume_from: u64,
+}
 
-        Ok(())
+/// Flushes the directory that `path` is in to disk, so that its entry for `path` is kept
+///
+/// # Errors
+/// If the directory cannot be opened or flushed
+#[cfg(target_family = "unix")]
+fn sync_parent_dir(path: &Path) -> Result<(), io::Error> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::File::open(dir)?.sync_all(),
+        _ => fs::File::open(".")?.sync_all(),
     }
 }
 
-/// A struct that represents a single directory
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub struct Dir {
-    path: PathBuf,
+/// Flushes the directory that `path` is in to disk, which is not possible on this platform,
+/// where directories are flushed along with the files in them
+#[cfg(not(target_family = "unix"))]
+fn sync_parent_dir(_path: &Path) -> Result<(), io::Error> {
+    Ok(())
 }
 
-impl FileOps for Dir {
-    fn path(&self) -> &PathBuf {
-        &self.path
-    }
-    fn remove(&self, path: &PathBuf) {
-        match fs::remove_dir(&path) {
-            Ok(_) => info!("Deleting dir {:?}", path),
-            Err(e) => error!("Error -- Deleting dir {:?}: {}", path, e),
-        }
-    }
-    fn copy(&self, _src: &PathBuf, dest: &PathBuf) {
-        match fs::create_dir_all(&dest) {
-            Ok(_) => info!("Creating dir {:?}", dest),
-            Err(e) => error!("Error -- Creating dir {:?}: {}", dest, e),
-        }
+/// Flushes everything written to the file system that `path` is on to disk
+///
+/// # Errors
+/// If the file system cannot be flushed
+#[cfg(target_os = "linux")]
+pub fn sync_file_system(path: &Path) -> Result<(), io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = fs::File::open(path)?;
+    if unsafe { libc::syncfs(file.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
 }
 
-impl Dir {
-    pub fn from(dir: &str) -> Self {
-        Dir {
-            path: PathBuf::from(dir),
-        }
-    }
+/// Flushes everything written to the file system that `path` is on to disk,
+/// which flushes all file systems, since they cannot be flushed separately on this platform
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+pub fn sync_file_system(path: &Path) -> Result<(), io::Error> {
+    fs::metadata(path)?;
+    unsafe { libc::sync() };
+    Ok(())
 }
 
-/// A struct that represents a single symbolic link
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub struct Symlink {
-    path: PathBuf,
-    target: PathBuf,
+/// Flushes everything written to the file system that `path` is on to disk,
+/// which is not supported on this platform
+#[cfg(not(target_family = "unix"))]
+pub fn sync_file_system(_path: &Path) -> Result<(), io::Error> {
+    Err(io::Error::other(
+        "Flushing the file system is only supported on Unix",
+    ))
 }
 
-impl FileOps for Symlink {
-    fn path(&self) -> &PathBuf {
-        &self.path
+/// Deletes the entry at `path` with `delete`, or moves it into `options.backup_dir` or to
+/// the trash instead if either is set, retrying any of them if it fails with a transient error
+///
+/// # Arguments
+/// * `path`: path of the entry
+/// * `relative`: path of the entry relative to the base directory it is in
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+/// * `delete`: deletes the entry
+///
+/// # Errors
+/// If the entry cannot be deleted, backed up, or moved to the trash
+fn delete_or_trash<F>(
+    path: &Path,
+    relative: &Path,
+    flags: Flag,
+    options: &Options,
+    mut delete: F,
+) -> Result<(), io::Error>
+where
+    F: FnMut() -> Result<(), io::Error>,
+{
+    let backup = backup_dir_target(relative, options);
+    retry::retry(options.retries, path, || match &backup {
+        Some(backup) => move_to_backup(path, backup, &mut delete),
+        None if flags.contains(Flag::TRASH) => trash_path(path),
+        None => delete(),
+    })
+}
+
+/// Moves the entry at `path` to `backup`, creating the directories it is in
+///
+/// Directories are only created in the backup and then deleted with `delete`, since the entries
+/// in them are moved on their own, and entries are copied and then deleted with `delete` if they
+/// cannot be renamed across file systems
+///
+/// # Errors
+/// If the entry cannot be moved or copied to `backup`, or cannot be deleted afterwards
+fn move_to_backup<F>(path: &Path, backup: &Path, mut delete: F) -> Result<(), io::Error>
+where
+    F: FnMut() -> Result<(), io::Error>,
+{
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        fs::create_dir_all(backup)?;
+        return delete();
     }
-    fn remove(&self, path: &PathBuf) {
-        match fs::remove_file(&path) {
-            Ok(_) => info!("Deleting symlink {:?}", path),
-            Err(e) => error!("Error -- Deleting symlink {:?}: {}", path, e),
-        }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent)?;
     }
-    #[cfg(target_family = "unix")]
-    fn copy(&self, _src: &PathBuf, dest: &PathBuf) {
-        use std::os::unix::fs;
 
-        match fs::symlink(&self.target, &dest) {
-            Ok(_) => info!("Creating symlink {:?} -> {:?}", dest, self.target),
-            Err(e) => error!("Error -- Creating symlink {:?}: {}", dest, e),
-        }
-    }
-    #[cfg(target_family = "windows")]
-    fn copy(&self, _src: &PathBuf, dest: &PathBuf) {
-        use std::os::windows::fs;
-        if self.target.is_file() {
-            match fs::symlink_file(&self.target, &dest) {
-                Ok(_) => info!("Creating symlink file {:?} -> {:?}", dest, self.target),
-                Err(e) => error!("Error -- Creating symlink file{:?}: {}", dest, e),
-            }
+    match fs::rename(path, backup) {
+        Ok(_) => {
+            info!("Backing up {:?} to {:?}", path, backup);
+            Ok(())
         }
-        if self.target.is_dir() {
-            match fs::symlink_dir(&self.target, &dest) {
-                Ok(_) => info!("Creating symlink dir {:?} -> {:?}", dest, self.target),
-                Err(e) => error!("Error -- Creating symlink dir {:?}: {}", dest, e),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            if metadata.file_type().is_symlink() {
+                copy_symlink_to(path, backup)?;
+            } else {
+                fs::copy(path, backup)?;
             }
+            info!("Backing up {:?} to {:?} (copied)", path, backup);
+            delete()
         }
+        Err(e) => Err(e),
     }
 }
 
-impl Symlink {
-    pub fn from(path: &str, target: &str) -> Self {
-        Symlink {
-            path: PathBuf::from(path),
-            target: PathBuf::from(target),
-        }
-    }
+/// Creates a symlink at `dest` with the same target as the symlink at `path`
+///
+/// # Errors
+/// If the target cannot be read, or the symlink cannot be created
+#[cfg(target_family = "unix")]
+fn copy_symlink_to(path: &Path, dest: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(fs::read_link(path)?, dest)
 }
 
-/// A struct that represents sets of different types of files
-#[derive(Eq, PartialEq, Debug)]
-pub struct FileSets {
-    files: HashSet<File>,
-    dirs: HashSet<Dir>,
-    symlinks: HashSet<Symlink>,
+/// Creates a symlink at `dest` with the same target as the symlink at `path`,
+/// which is not supported on this platform
+#[cfg(not(target_family = "unix"))]
+fn copy_symlink_to(_path: &Path, _dest: &Path) -> Result<(), io::Error> {
+    Err(io::Error::other(
+        "Copying symlinks across file systems is only supported on Unix",
+    ))
 }
 
-impl FileSets {
-    /// Initializes FileSets with the given sets
-    ///
-    /// # Arguments
-    /// * `files`: a set of files
-    /// * `dirs`: a set of dirs
-    /// * `symlinks`: a set of symlinks
-    ///
-    /// # Returns
-    /// A newly created FileSets struct
-    pub fn with(files: HashSet<File>, dirs: HashSet<Dir>, symlinks: HashSet<Symlink>) -> Self {
-        FileSets {
-            files,
-            dirs,
-            symlinks,
-        }
+/// Moves the entry at `path` to the trash
+///
+/// # Errors
+/// If there is no trash that the entry can be moved to
+#[cfg(any(windows, target_os = "macos", target_os = "linux"))]
+fn trash_path(path: &Path) -> Result<(), io::Error> {
+    trash::delete(path).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Moves the entry at `path` to the trash, which is not supported on this platform,
+/// so the entry is kept rather than deleted
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn trash_path(_path: &Path) -> Result<(), io::Error> {
+    Err(io::Error::other(
+        "Moving to the trash is not supported on this platform",
+    ))
+}
+
+/// Interface for all file structs to perform common operations
+///
+/// Ensures that all files (file, dir, symlink) have
+/// a way of obtaining their path, copying, and deleting,
+/// where copying and deleting return the counts of what was changed
+pub trait FileOps {
+    fn path(&self) -> &PathBuf;
+    fn remove(&self, path: &PathBuf, flags: Flag, options: &Options) -> SyncStats;
+    fn copy(&self, src: &PathBuf, dest: &PathBuf, flags: Flag, options: &Options) -> SyncStats;
+    /// Gets the size of the contents, in bytes, which is 0 for anything but files
+    fn size(&self) -> u64 {
+        0
     }
-    /// Gets the set of files
-    ///
-    /// # Returns
-    /// The FileSets set of files
-    pub fn files(&self) -> &HashSet<File> {
-        &self.files
+    /// Gets the modification time found while traversing, which is None for anything but files
+    fn modified(&self) -> Option<SystemTime> {
+        None
     }
-    /// Gets the set of dirs
+}
+
+/// Kinds of entries that a path can be classified as when traversing a directory
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Classification {
+    File,
+    Dir,
+    Symlink,
+    /// A directory that is traversed, but only kept if anything inside it is kept
+    PassThrough,
+    /// A directory that is kept, but not traversed
+    EmptyDir,
+    /// Left out of the traversal, for paths that are skipped or handled by the classifier itself
+    Other,
+}
+
+/// Interface for deciding how each path found while traversing a directory is categorized
+///
+/// Implementing this allows custom handling of paths, by classifying them as
+/// `Classification::Other` and collecting them into a custom type that implements `FileOps`,
+/// which can then be copied with `copy_files`
+pub trait Classifier: Sync {
+    /// Classifies `path`, which is relative to the traversed directory
+    fn classify(&self, path: &Path, metadata: &fs::Metadata) -> Classification;
+
+    /// Called before traversing the directory `path`, which is relative to the traversed
+    /// directory, to allow classifying everything inside it differently
     ///
     /// # Returns
-    /// The FileSets set of dirs
-    pub fn dirs(&self) -> &HashSet<Dir> {
-        &self.dirs
+    /// * Some: The classifier to use for everything inside `path`
+    /// * None: If the current classifier should be used
+    fn enter(&self, _path: &Path) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
     }
-    /// Gets the set of symlinks
-    ///
-    /// # Returns
-    /// The FileSets set of symlinks
-    pub fn symlinks(&self) -> &HashSet<Symlink> {
-        &self.symlinks
+
+    /// Whether symlinks are classified and traversed as what they link to,
+    /// instead of as symlinks
+    fn follow_symlinks(&self) -> bool {
+        false
     }
 }
 
-/// Compares all files in `files_to_compare` in `src` with all files in `files_to_compare` in `dest`
-/// and copies them over if they are different, in parallel
-///
-/// # Arguments
-/// * `files_to_compare`: files to compare
-/// * `src`: base directory of the files to copy from, such that for all `file` in
-/// `files_to_compare`, `src + file.path()` is the absolute path of the source file
-/// * `dest`: base directory of the files to copy to, such that for all `file` in
-/// `files_to_compare`, `dest + file.path()` is the absolute path of the destination file
-/// * `flags`: set for Flag's
-pub fn compare_and_copy_files<'a, T, S>(files_to_compare: T, src: &str, dest: &str, flags: Flag)
-where
-    T: ParallelIterator<Item = &'a S>,
-    S: FileOps + Sync + 'a,
-{
-    files_to_compare.for_each(|file| {
-        compare_and_copy_file(file, src, dest, flags);
-        PROGRESS_BAR.inc(2);
-    });
+/// Classifies paths by their file type, which is how `get_all_files` traverses directories
+pub struct DefaultClassifier;
+
+impl Classifier for DefaultClassifier {
+    fn classify(&self, _path: &Path, metadata: &fs::Metadata) -> Classification {
+        if metadata.is_dir() {
+            Classification::Dir
+        } else if metadata.is_file() {
+            Classification::File
+        } else {
+            // If not a file nor dir, must be a symlink
+            Classification::Symlink
+        }
+    }
 }
 
-/// Compares the given file and copies the src file over if it differs from the dest file
+/// Classifies paths with another classifier, but does not traverse directories
+/// on a different file system than the traversed directory
 ///
-/// # Arguments
-/// * `file_to_compare`: file to compare
-/// * `src`: base directory of the file to copy from, such that `src + file.path()`
-/// is the absolute path of the source file
-/// * `dest`: base directory of the files to copy to, such that `dest + file.path()`
-/// is the absolute path of the destination file
-/// * `flags`: set for Flag's
-fn compare_and_copy_file<S>(file_to_compare: &S, src: &str, dest: &str, flags: Flag)
+/// Directories on a different file system are kept as empty directories,
+/// so that they are still created in the destination
+pub struct OneFileSystem<C> {
+    classifier: C,
+    device: Option<u64>,
+}
+
+impl<C> OneFileSystem<C>
 where
-    S: FileOps,
+    C: Classifier,
 {
-    if flags.contains(Flag::SECURE) {
-        let src_file_hash_secure = hash_file_secure(file_to_compare, &src);
-
-        if src_file_hash_secure.is_none() {
-            copy_file(file_to_compare, &src, &dest);
-            return;
+    /// Creates a classifier that stays on the file system of `root`
+    ///
+    /// # Arguments
+    /// * `root`: directory to be traversed
+    /// * `classifier`: classifier to use for all paths on the file system of `root`
+    ///
+    /// # Errors
+    /// This function will return an error if the metadata of `root` cannot be read
+    pub fn new(root: &str, classifier: C) -> Result<Self, io::Error> {
+        let device = device_id(&fs::metadata(root)?);
+        if device.is_none() {
+            warn!("Warning -- Staying on one file system is not supported on this platform");
         }
 
-        let dest_file_hash_secure = hash_file_secure(file_to_compare, &dest);
+        Ok(OneFileSystem { classifier, device })
+    }
+}
 
-        if src_file_hash_secure != dest_file_hash_secure {
-            copy_file(file_to_compare, &src, &dest);
+impl<C> Classifier for OneFileSystem<C>
+where
+    C: Classifier,
+{
+    fn classify(&self, path: &Path, metadata: &fs::Metadata) -> Classification {
+        let classification = self.classifier.classify(path, metadata);
+        if self.device.is_none() || device_id(metadata) == self.device {
+            return classification;
         }
-    } else {
-        let src_file_hash = hash_file(file_to_compare, &src);
 
-        if src_file_hash.is_none() {
-            copy_file(file_to_compare, &src, &dest);
-            return;
+        match classification {
+            Classification::Dir => {
+                info!("Skipping {:?} on another file system", path);
+                Classification::EmptyDir
+            }
+            Classification::PassThrough => Classification::Other,
+            classification => classification,
         }
+    }
 
-        let dest_file_hash = hash_file(file_to_compare, &dest);
+    fn enter(&self, path: &Path) -> Option<Self> {
+        self.classifier.enter(path).map(|classifier| OneFileSystem {
+            classifier,
+            device: self.device,
+        })
+    }
 
-        if src_file_hash != dest_file_hash {
-            copy_file(file_to_compare, &src, &dest);
-        }
+    fn follow_symlinks(&self) -> bool {
+        self.classifier.follow_symlinks()
     }
 }
 
-/// Copies all given files from `src` to `dest` in parallel
-///
-/// # Arguments
-/// * `files_to_copy`: files to copy
-/// * `src`: base directory of the files to copy from, such that for all `file` in
-/// `files_to_copy`, `src + file.path()` is the absolute path of the source file
-/// * `dest`: base directory of the files to copy to, such that for all `file` in
-/// `files_to_copy`, `dest + file.path()` is the absolute path of the destination file
-pub fn copy_files<'a, T, S>(files_to_copy: T, src: &str, dest: &str)
+/// Classifies paths with another classifier, but classifies and traverses symlinks
+/// as the files or directories that they link to
+pub struct FollowSymlinks<C>(pub C);
+
+impl<C> Classifier for FollowSymlinks<C>
 where
-    T: ParallelIterator<Item = &'a S>,
-    S: FileOps + Sync + 'a,
+    C: Classifier,
 {
-    files_to_copy.for_each(|file| {
-        copy_file(file, &src, &dest);
-        PROGRESS_BAR.inc(1);
-    });
+    fn classify(&self, path: &Path, metadata: &fs::Metadata) -> Classification {
+        self.0.classify(path, metadata)
+    }
+
+    fn enter(&self, path: &Path) -> Option<Self> {
+        self.0.enter(path).map(FollowSymlinks)
+    }
+
+    fn follow_symlinks(&self) -> bool {
+        true
+    }
 }
 
-/// Copies a single file from `src` to `dest`
+/// Gets the device and inode of a file, if more than one path is hard linked to it
 ///
-/// # Arguments
-/// * `files_to_copy`: file to copy
-/// * `src`: base directory of the files to copy from, such that `src + file_to_copy.path()`
-/// is the absolute path of the source file
-/// * `dest`: base directory of the files to copy to, such that `dest + file.path()`
-/// is the absolute path of the destination file
-fn copy_file<S>(file_to_copy: &S, src: &str, dest: &str)
-where
-    S: FileOps,
-{
-    let src_file = [&PathBuf::from(&src), file_to_copy.path()].iter().collect();
-    let dest_file = [&PathBuf::from(&dest), file_to_copy.path()]
-        .iter()
-        .collect();
-
-    file_to_copy.copy(&src_file, &dest_file);
+/// # Returns
+/// * Some: The device and inode
+/// * None: If the file has only one link, or inodes are not supported on this platform
+#[cfg(target_family = "unix")]
+fn link_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
 }
 
-/// Deletes all given files in parallel
+/// Gets the device and inode of a file, if more than one path is hard linked to it
 ///
-/// There is no guarantee that this function will delete the files in the given order
-///
-/// # Arguments
-/// `files_to_delete`: files to delete
-/// * `location`: base directory of the files to delete, such that for all `file` in
-/// `files_to_delete`, `location + file.path()` is the absolute path of the file
-pub fn delete_files<'a, T, S>(files_to_delete: T, location: &str)
-where
-    T: ParallelIterator<Item = &'a S>,
-    S: FileOps + Sync + 'a,
-{
-    files_to_delete.for_each(|file| {
-        let path = [&PathBuf::from(&location), file.path()].iter().collect();
-        file.remove(&path);
-        PROGRESS_BAR.inc(1);
-    });
+/// # Returns
+/// * Some: The device and inode
+/// * None: If the file has only one link, or inodes are not supported on this platform
+#[cfg(not(target_family = "unix"))]
+fn link_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
 }
 
-/// Deletes all given files sequentially
-///
-/// This function ensures that the files are deleted in the exact order given
+/// Gets the id of the device that a file is on
 ///
-/// # Arguments
-/// * `files_to_delete`: files to delete, or sorted empty directories
-/// * `location`: base directory of the files to delete, such that for all `file` in
-/// `files_to_delete`, `location + file.path()` is the absolute path of the file
-pub fn delete_files_sequential<'a, T, S>(files_to_delete: T, location: &str)
-where
-    T: IntoIterator<Item = &'a S>,
-    S: FileOps + 'a,
-{
-    for file in files_to_delete {
-        let path = [&PathBuf::from(&location), file.path()].iter().collect();
-        file.remove(&path);
-        PROGRESS_BAR.inc(1);
-    }
+/// # Returns
+/// * Some: The device id
+/// * None: If device ids are not supported on this platform
+#[cfg(target_family = "unix")]
+fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
 }
 
-/// Sorts (unstable) file paths in descending order by number of components, in parallel
-///
-/// # Arguments
-/// `files_to_sort`: files to sort
+/// Gets the id of the device that a file is on
 ///
 /// # Returns
-/// A vector of file paths in descending order by number of components
-///
-/// # Examples
-/// ["a", "a/b", "a/b/c"] becomes ["a/b/c", "a/b", "a"]
-/// ["/usr", "/", "/usr/bin", "/etc"] becomes ["/usr/bin", "/usr", "/etc", "/"]
-pub fn sort_files<'a, T, S>(files_to_sort: T) -> Vec<&'a S>
-where
-    T: ParallelIterator<Item = &'a S>,
-    S: FileOps + Sync + 'a,
-{
-    let mut files_to_sort = Vec::from_par_iter(files_to_sort);
-    files_to_sort.par_sort_unstable_by(|a, b| {
-        b.path()
-            .components()
-            .count()
-            .cmp(&a.path().components().count())
-    });
-    files_to_sort
+/// * Some: The device id
+/// * None: If device ids are not supported on this platform
+#[cfg(not(target_family = "unix"))]
+fn device_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
 }
 
-/// Generates a hash of the given file, using the Seahash non-cryptographic hash function
-///
-/// # Arguments
-/// * `file_to_hash`: file object to hash
-/// * `location`: base directory of the file to hash, such that
-/// `location + file_to_hash.path()` is the absolute path of the file
+/// Sets the owner and group of `dest` to those of `src`, without following `dest` if it is a
+/// symlink, and only if they differ so that users who cannot change owners can still sync
 ///
 /// # Returns
-/// * Some: The hash of the given file
-/// * Err: If the given file cannot be hashed
-pub fn hash_file<S>(file_to_hash: &S, location: &str) -> Option<u64>
-where
-    S: FileOps,
-{
-    let file: PathBuf = [&PathBuf::from(&location), file_to_hash.path()]
-        .iter()
-        .collect();
-
-    match fs::read(file) {
-        Ok(contents) => Some(seahash::hash(&contents)),
-        Err(_) => None,
+/// * Ok(true): If the owner or group was changed
+/// * Ok(false): If they were already the same
+/// * Err: If they cannot be read or set
+#[cfg(target_family = "unix")]
+fn copy_owner(src: &Path, dest: &Path) -> Result<bool, io::Error> {
+    use std::os::unix::fs::{lchown, MetadataExt};
+
+    let dest_metadata = fs::symlink_metadata(dest)?;
+    let src_metadata = if dest_metadata.file_type().is_symlink() {
+        fs::symlink_metadata(src)?
+    } else {
+        fs::metadata(src)?
+    };
+    if (src_metadata.uid(), src_metadata.gid()) == (dest_metadata.uid(), dest_metadata.gid()) {
+        return Ok(false);
     }
+
+    lchown(dest, Some(src_metadata.uid()), Some(src_metadata.gid()))?;
+    Ok(true)
 }
 
-/// Generates a hash of the given file, using the BLAKE2b cryptographic hash function
-///
-/// # Arguments
-/// * `file_to_hash`: file object to hash
-/// * `location`: base directory of the file to hash, such that
-/// `location + file_to_hash.path()` is the absolute path of the file
-///
-/// # Returns
-/// * Some: The hash of the given file
-/// * Err: If the given file cannot be hashed
-pub fn hash_file_secure<S>(file_to_hash: &S, location: &str) -> Option<Vec<u8>>
-where
-    S: FileOps,
-{
-    let file: PathBuf = [&PathBuf::from(&location), file_to_hash.path()]
-        .iter()
-        .collect();
+/// Sets the owner and group of `dest` to those of `src`, which is not supported on this
+/// platform, so nothing is changed
+#[cfg(not(target_family = "unix"))]
+fn copy_owner(_src: &Path, _dest: &Path) -> Result<bool, io::Error> {
+    Ok(false)
+}
 
-    match &mut fs::File::open(&file) {
-        Ok(file) => {
-            let mut hasher = Blake2b::new();
+/// Opens `path` only to set its times, which requires owning it rather than being able to write
+/// to it, since copies already have the permissions of their source, which may be read only
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> Result<fs::File, io::Error> {
+    fs::File::open(path)
+}
 
-            match io::copy(file, &mut hasher) {
-                Ok(_) => Some(hasher.finalize().to_vec()),
-                Err(e) => {
-                    error!("Error -- Hashing: {:?}: {}", file_to_hash.path(), e);
-                    None
-                }
-            }
-        }
-        Err(e) => {
-            error!("Error -- Opening File: {:?}: {}", file_to_hash.path(), e);
-            None
-        }
-    }
+/// Opens `path` only to set its times, with the one access right that is needed for it,
+/// which is still granted if `path` is read only
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> Result<fs::File, io::Error> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .open(path)
 }
 
-/// Recursively traverses a directory and all its subdirectories and returns
-/// a FileSets that contains all files and all directories
-///
-/// # Arguments
-/// * `src`: directory to traverse
+/// Sets the creation time of `dest` to that of `src`
 ///
-/// # Returns
-/// * Ok: A `FileSets` containing a set of files a set of directories
-/// * Error: If `src` is an invalid directory
-pub fn get_all_files(src: &str) -> Result<FileSets, io::Error> {
-    get_all_files_helper(&PathBuf::from(&src), &src)
+/// # Errors
+/// If the creation time of `src` cannot be read, or that of `dest` cannot be set
+#[cfg(any(windows, target_os = "macos"))]
+fn copy_created(src: &Path, dest: &Path) -> Result<(), io::Error> {
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
+
+    let created = fs::metadata(src)?.created()?;
+    open_for_times(dest)?.set_times(fs::FileTimes::new().set_created(created))
 }
 
-/// Recursive helper for `get_all_files`
-///
-/// # Arguments
-/// * `src`: directory to traverse
-/// * `base`: directory to traverse, used for recursive calls
+/// Sets the creation time of `dest` to that of `src`, which cannot be set on this platform,
+/// so nothing is changed
+#[cfg(not(any(windows, target_os = "macos")))]
+fn copy_created(_src: &Path, _dest: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// A struct that represents a single file
 ///
-/// # Returns
-/// * Ok: A `FileSets` containing a set of files a set of directories
-/// * Error: If `src` is an invalid directory
-fn get_all_files_helper(src: &PathBuf, base: &str) -> Result<FileSets, io::Error> {
-    let dir = src.read_dir()?;
+/// Files are equal if they have the same path and size, regardless of their modification time
+#[derive(Debug, Clone)]
+pub struct File {
+    path: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+    /// Device and inode of the file, only if other paths are hard linked to it
+    hard_link: Option<(u64, u64)>,
+}
 
-    let mut files = HashSet::new();
-    let mut dirs = HashSet::new();
-    let mut symlinks = HashSet::new();
+impl PartialEq for File {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.size == other.size
+    }
+}
 
-    for file in dir {
-        if file.is_err() {
-            error!("{}", file.err().unwrap());
-            continue;
-        }
+impl Eq for File {}
 
-        let file = file.unwrap();
-        let metadata = file.metadata();
+impl Hash for File {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.size.hash(state);
+    }
+}
 
-        if metadata.is_err() {
-            error!(
-                "Error -- Reading metadata of {:?} {}",
-                file.path(),
-                metadata.err().unwrap()
-            );
-            continue;
+impl FileOps for File {
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+    fn remove(&self, path: &PathBuf, flags: Flag, options: &Options) -> SyncStats {
+        match delete_or_trash(path, &self.path, flags, options, || fs::remove_file(path)) {
+            Ok(_) => {
+                info!("Deleting file {:?}", path);
+                SyncStats {
+                    files_deleted: 1,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                error!("Error -- Deleting file {:?}: {}", path, e);
+                stats::failure_record(path, e);
+                SyncStats {
+                    errors: 1,
+                    ..SyncStats::default()
+                }
+            }
+        }
+    }
+    fn copy(&self, src: &PathBuf, dest: &PathBuf, flags: Flag, options: &Options) -> SyncStats {
+        // Copy into a temporary file that then replaces `dest`, so that `dest` is never left
+        // partially written, and other hard links to `dest` such as from `--link-dest` are kept
+        let inplace = flags.contains(Flag::INPLACE);
+        let fsync = options.fsync == Fsync::Each;
+        let preallocate = flags.contains(Flag::PREALLOCATE);
+        let size = self.size;
+        let temp = if inplace {
+            dest.clone()
+        } else {
+            File::temp_path(dest)
+        };
+        let file_bar = progress::progress_file_bar(src, self.size);
+        let reflink = options.reflink;
+
+        // A partially copied file that was moved out of the way, or that was left behind
+        // by a copy that was stopped, is moved back to resume it
+        let partial = flags.contains(Flag::PARTIAL);
+        let partial_path = options
+            .partial_dir
+            .as_ref()
+            .map(|dir| File::partial_path(dest, dir));
+        let leftover = match &partial_path {
+            Some(partial_path) => Some(partial_path.clone()),
+            None if partial && !inplace => File::leftover_temp_path(dest),
+            None => None,
+        };
+        if let Some(leftover) = &leftover {
+            if fs::metadata(&temp).is_err() {
+                fs::rename(leftover, &temp).ok();
+            }
         }
 
-        let metadata = metadata.unwrap();
+        let copy = || {
+            retry::retry(options.retries, src, || {
+                let resume_from = if partial {
+                    File::resumable_len(src, &temp)
+                } else {
+                    0
+                };
+                if resume_from > 0 {
+                    info!("Resuming file {:?} from byte {}", src, resume_from);
+                }
 
-        let path = file.path();
-        // This is safe to unwrap, since `get_all_files` always calls this helper
-        // with `base` equal to `src`
-        let relative_path = path.strip_prefix(base).unwrap();
+                // Reflinking takes no time, so it is tried before anything else,
+                // except when it would replace a partially copied file that can be resumed
+                match reflink {
+                    Reflink::Always => return File::reflink(src, &temp),
+                    Reflink::Auto if resume_from == 0 => {
+                        if let Ok(bytes) = File::reflink(src, &temp) {
+                            return Ok(bytes);
+                        }
+                    }
+                    Reflink::Auto | Reflink::Never => (),
+                }
 
-        if metadata.is_dir() {
-            dirs.insert(Dir {
-                path: relative_path.to_path_buf(),
-            });
+                // Sparse copies leave holes instead of allocating space
+                let sparse = flags.contains(Flag::SPARSE);
+                if preallocate && !sparse && resume_from == 0 {
+                    File::preallocate(&temp, size)?;
+                }
 
-            // Recursively call `get_all_files_helper` on the subdirectory
-            match get_all_files_helper(&file.path(), base) {
-                Ok(file_sets) => {
-                    // Add subdirectory subdirectories and files to sets
-                    files.extend(file_sets.files);
-                    dirs.extend(file_sets.dirs);
-                    symlinks.extend(file_sets.symlinks);
+                // fs::copy may share data on copy-on-write file systems, but reading does not,
+                // and it truncates the file first instead of overwriting it in place
+                // or keeping the space allocated for it. It also cannot be throttled, tracked,
+                // or given a buffer size, so the contents are copied by hand for any of those
+                let buffer_size = options.buffer_size;
+                if sparse
+                    || options.throttle.is_limited()
+                    || file_bar.is_some()
+                    || buffer_size.is_some()
+                    || reflink == Reflink::Never
+                    || inplace
+                    || preallocate
+                    || resume_from > 0
+                {
+                    let options = ContentOptions {
+                        buffer_size: buffer_size.unwrap_or(DEFAULT_COPY_BUFFER_SIZE),
+                        sparse,
+                        truncate: !inplace && !preallocate,
+                        file_bar: file_bar.as_ref(),
+                        throttle: Some(&options.throttle),
+                        resume_from,
+                    };
+                    File::copy_file_contents(src, &temp, &options)
+                } else {
+                    fs::copy(src, &temp)
                 }
-                Err(e) => {
-                    error!("Error - Retrieving files: {}", e);
-                    continue;
+            })
+        };
+
+        // Copies that don't match the source when read back are copied once more
+        let mut copied = copy();
+        let mut verify_failed = false;
+        let verify_hash = flags.contains(Flag::VERIFY_COPIES).then_some(options.hash);
+        if let (Ok(_), Some(algo)) = (&copied, verify_hash) {
+            if !File::is_copy_verified(src, &temp, flags, algo) {
+                warn!(
+                    "Warning -- Verifying file {:?} failed, copying it again",
+                    src
+                );
+                // The copy is not resumed, since what was copied is what does not match
+                if partial {
+                    fs::remove_file(&temp).ok();
+                }
+                copied = copy();
+                if copied.is_ok() && !File::is_copy_verified(src, &temp, flags, algo) {
+                    verify_failed = true;
+                    copied = Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Copy does not match the source when read back",
+                    ));
                 }
             }
-        } else if metadata.is_file() {
-            files.insert(File {
-                path: relative_path.to_path_buf(),
-                size: metadata.len(),
+        }
+        if let Some(file_bar) = file_bar {
+            file_bar.finish_and_clear();
+        }
+
+        let copied = copied
+            .and_then(|bytes| File::copy_modified(src, &temp).map(|_| bytes))
+            .and_then(|bytes| {
+                if flags.contains(Flag::PRESERVE_CRTIMES) {
+                    copy_created(src, &temp)?;
+                }
+                if flags.contains(Flag::PRESERVE_XATTRS) {
+                    xattrs::copy_xattrs(src, &temp)?;
+                }
+                chown_copied(&temp, options)?;
+                chmod_copied(&temp, options)?;
+                if fsync {
+                    fs::File::open(&temp)?.sync_all()?;
+                }
+                Ok(bytes)
+            })
+            .and_then(|bytes| File::replace(src, &temp, dest, flags).map(|_| bytes))
+            .and_then(|bytes| {
+                // The rename is only kept once the directory it is in is flushed too
+                if fsync && !inplace {
+                    sync_parent_dir(dest)?;
+                }
+                Ok(bytes)
             });
-        } else {
-            // If not a file nor dir, must be a symlink
-            match fs::read_link(&path) {
-                Ok(target) => {
-                    symlinks.insert(Symlink {
-                        path: relative_path.to_path_buf(),
-                        target,
-                    });
+
+        match copied {
+            Ok(bytes) => {
+                info!("Copying file {:?} -> {:?}", src, dest);
+                // The directory that the file was kept in is only removed once nothing is left
+                if let Some(dir) = partial_path.as_ref().and_then(|path| path.parent()) {
+                    fs::remove_dir(dir).ok();
                 }
-                Err(e) => {
-                    error!("Error - Reading symlink: {}", e);
-                    continue;
+                SyncStats {
+                    files_copied: 1,
+                    bytes_copied: bytes,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                // The temporary file may not exist if it could not be created
+                if inplace {
+                    error!(
+                        "Error -- Copying file {:?} in place, so {:?} may be partially written",
+                        src, dest
+                    );
+                } else if partial && !verify_failed {
+                    File::keep_partial(&temp, partial_path.as_deref());
+                } else {
+                    fs::remove_file(&temp).ok();
+                }
+                error!("Error -- Copying file {:?}: {}", src, e);
+                stats::failure_record(src, e);
+                SyncStats {
+                    errors: 1,
+                    verify_failures: verify_failed as u64,
+                    ..SyncStats::default()
                 }
             }
         }
     }
-
-    Ok(FileSets::with(files, dirs, symlinks))
 }
 
-///////////////////////////////////////////////////////////////////////////////////////////////////
-// Tests
-///////////////////////////////////////////////////////////////////////////////////////////////////
+impl File {
+    pub fn from(path: &str, size: u64) -> Self {
+        File {
+            path: PathBuf::from(path),
+            size,
+            modified: None,
+            hard_link: None,
+        }
+    }
 
-#[cfg(test)]
-mod test_file_ops {
-    use super::*;
+    /// Gets the device and inode that the file shares with the other paths hard linked to it,
+    /// if there are any
+    pub fn hard_link(&self) -> Option<(u64, u64)> {
+        self.hard_link
+    }
 
-    #[test]
-    fn create_dir() {
-        assert_eq!(
-            Dir::from("."),
-            Dir {
-                path: PathBuf::from("."),
+    /// Copies the contents of `src` to `dest` like `fs::copy`, by reading `src` into a buffer
+    /// of `options.buffer_size` bytes and writing it to `dest` one buffer at a time,
+    /// which is only done once the bandwidth limit allows it
+    ///
+    /// If `options.sparse` is set, blocks of SPARSE_BLOCK_SIZE that only contain zeros are
+    /// seeked past instead of written, so that they are left as holes in `dest` on file systems
+    /// that support sparse files
+    ///
+    /// If `options.resume_from` is set, that many bytes at the start of `dest` are kept,
+    /// and only the rest of `src` after them is copied
+    ///
+    /// # Returns
+    /// * Ok: The number of bytes copied, including those of holes
+    /// * Err: If `src` cannot be read or `dest` cannot be written
+    fn copy_file_contents(
+        src: &PathBuf,
+        dest: &PathBuf,
+        options: &ContentOptions,
+    ) -> Result<u64, io::Error> {
+        if let Some(file_bar) = options.file_bar {
+            file_bar.set_position(options.resume_from);
+        }
+
+        // Holes are only left where nothing was written, so sparse copies always truncate `dest`,
+        // unless they resume a partial copy, which is continued after what was already copied
+        let mut src_file = fs::File::open(src)?;
+        let mut dest_file = if options.resume_from > 0 {
+            OpenOptions::new().write(true).open(dest)?
+        } else if options.sparse {
+            fs::File::create(dest)?
+        } else {
+            File::create_dest(dest, options.truncate)?
+        };
+        src_file.seek(SeekFrom::Start(options.resume_from))?;
+        dest_file.seek(SeekFrom::Start(options.resume_from))?;
+        let mut buffer = vec![0; options.buffer_size];
+        let mut bytes = options.resume_from;
+
+        loop {
+            let read = read_block(&mut src_file, &mut buffer)?;
+            if read == 0 {
+                break;
             }
-        )
-    }
 
-    #[test]
-    fn create_file() {
-        assert_eq!(
-            File::from(".", 10),
-            File {
-                path: PathBuf::from("."),
-                size: 10,
+            if let Some(throttle) = options.throttle {
+                throttle.throttle(read as u64);
             }
-        )
+            if options.sparse {
+                for block in buffer[..read].chunks(SPARSE_BLOCK_SIZE) {
+                    if block.iter().all(|&byte| byte == 0) {
+                        dest_file.seek(SeekFrom::Current(block.len() as i64))?;
+                    } else {
+                        dest_file.write_all(block)?;
+                    }
+                }
+            } else {
+                dest_file.write_all(&buffer[..read])?;
+            }
+            bytes += read as u64;
+            if let Some(file_bar) = options.file_bar {
+                file_bar.inc(read as u64);
+            }
+        }
+
+        // A hole at the end is only kept if the size is set, since nothing is written after it,
+        // and files that were overwritten rather than truncated may have been longer
+        dest_file.set_len(bytes)?;
+        fs::set_permissions(dest, src_file.metadata()?.permissions())?;
+        Ok(bytes)
     }
 
-    #[test]
-    fn create_symlink() {
-        assert_eq!(
-            Symlink::from(".", "file"),
-            Symlink {
-                path: PathBuf::from("."),
-                target: PathBuf::from("file"),
-            }
-        )
+    /// Opens `dest` for writing, creating it if it doesn't exist. When copying in place or
+    /// preallocating, `dest` is overwritten rather than truncated, so `truncate` is not set
+    /// and it must be truncated to the size copied once copying is done
+    fn create_dest(dest: &Path, truncate: bool) -> Result<fs::File, io::Error> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .open(dest)
     }
-}
 
-#[cfg(test)]
-mod test_get_all_files {
-    use super::*;
-    use std::process::Command;
+    /// Creates `dest` as a reflink of `src`, which shares the data of `src` until either is
+    /// changed, and gives it the permissions of `src`
+    ///
+    /// # Returns
+    /// * Ok: The number of bytes reflinked
+    /// * Err: If `src` and `dest` are not on the same copy-on-write file system
+    #[cfg(target_os = "linux")]
+    fn reflink(src: &Path, dest: &Path) -> Result<u64, io::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let src_file = fs::File::open(src)?;
+        let metadata = src_file.metadata()?;
+        let dest_file = fs::File::create(dest)?;
+        if unsafe { libc::ioctl(dest_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Reflinking is not possible: {}", e),
+            ));
+        }
 
-    #[test]
-    fn invalid_dir() {
-        assert_eq!(get_all_files("/?").is_err(), true);
+        fs::set_permissions(dest, metadata.permissions())?;
+        Ok(metadata.len())
     }
 
-    #[cfg(target_family = "unix")]
-    #[test]
-    fn dir_insufficient_permissions() {
-        assert_eq!(get_all_files("/root").is_err(), true);
-    }
-
-    #[test]
-    fn empty_dir() {
-        const TEST_DIR: &str = "test_get_all_files_empty_dir";
-
-        fs::create_dir(TEST_DIR).unwrap();
-
-        let file_sets = get_all_files(TEST_DIR).unwrap();
-
-        assert_eq!(file_sets.files(), &HashSet::new());
-        assert_eq!(file_sets.dirs(), &HashSet::new());
+    /// Creates `dest` as a clone of `src` with `clonefile`, which shares the data of `src`
+    /// until either is changed, and also copies its permissions
+    ///
+    /// # Returns
+    /// * Ok: The number of bytes cloned
+    /// * Err: If `src` and `dest` are not on the same APFS volume
+    #[cfg(target_os = "macos")]
+    fn reflink(src: &Path, dest: &Path) -> Result<u64, io::Error> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        };
+        let (src_path, dest_path) = (c_path(src)?, c_path(dest)?);
+        let len = fs::metadata(src)?.len();
 
-        fs::remove_dir(TEST_DIR).unwrap();
+        // clonefile only creates new files, so a temporary file left from before is removed
+        match fs::remove_file(dest) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+        if unsafe { libc::clonefile(src_path.as_ptr(), dest_path.as_ptr(), 0) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Reflinking is not possible: {}", e),
+            ));
+        }
+        Ok(len)
     }
 
-    #[test]
-    fn single_dir() {
-        const TEST_DIR: &str = "test_get_all_files_single_dir";
-        const TEST_SUB_DIR: &str = "test";
-
-        fs::create_dir_all([TEST_DIR, TEST_SUB_DIR].join("/")).unwrap();
-
-        let file_sets = get_all_files(&TEST_DIR).unwrap();
-        let mut dir_set = HashSet::new();
-        dir_set.insert(Dir {
-            path: PathBuf::from(&TEST_SUB_DIR),
-        });
+    /// Creates `dest` as a reflink of `src`, which is not supported on this platform
+    ///
+    /// # Errors
+    /// Always, since files cannot be reflinked
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn reflink(_src: &Path, _dest: &Path) -> Result<u64, io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Reflinking is only supported on Linux and macOS",
+        ))
+    }
 
-        assert_eq!(file_sets.files(), &HashSet::new());
-        assert_eq!(file_sets.dirs(), &dir_set);
+    /// Creates `dest` with `size` bytes allocated for it, so that copying fails right away
+    /// if there is not enough space, and the file is less fragmented
+    ///
+    /// File systems that cannot allocate space up front are left to allocate it while copying
+    ///
+    /// # Errors
+    /// If `dest` cannot be created, or there is not enough space for `size` bytes
+    #[cfg(target_os = "linux")]
+    fn preallocate(dest: &Path, size: u64) -> Result<(), io::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let dest_file = File::create_dest(dest, false)?;
+        if size == 0 {
+            return Ok(());
+        }
+        let fd = dest_file.as_raw_fd();
+        if unsafe { libc::fallocate(fd, libc::FALLOC_FL_KEEP_SIZE, 0, size as libc::off_t) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
 
-        fs::remove_dir_all(&TEST_DIR).unwrap();
+    /// Creates `dest` with `size` bytes allocated for it with `F_PREALLOCATE`, so that copying
+    /// fails right away if there is not enough space, and the file is less fragmented
+    ///
+    /// # Errors
+    /// If `dest` cannot be created, or there is not enough space for `size` bytes
+    #[cfg(target_os = "macos")]
+    fn preallocate(dest: &Path, size: u64) -> Result<(), io::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let dest_file = File::create_dest(dest, false)?;
+        if size == 0 {
+            return Ok(());
+        }
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATEALL,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: size as libc::off_t,
+            fst_bytesalloc: 0,
+        };
+        if unsafe { libc::fcntl(dest_file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ENOTSUP) {
+                return Err(e);
+            }
+        }
+        Ok(())
     }
 
-    #[test]
-    fn single_file() {
-        const TEST_DIR: &str = "test_get_all_files_single_file";
-        const TEST_FILE: &str = "file.txt";
+    /// Creates `dest` with its size set to `size` bytes, for which NTFS allocates the space,
+    /// so that copying fails right away if there is not enough space
+    ///
+    /// # Errors
+    /// If `dest` cannot be created, or there is not enough space for `size` bytes
+    #[cfg(windows)]
+    fn preallocate(dest: &Path, size: u64) -> Result<(), io::Error> {
+        File::create_dest(dest, false)?.set_len(size)
+    }
 
-        fs::create_dir_all(TEST_DIR).unwrap();
+    /// Creates `dest` without allocating any space for it,
+    /// since that is not supported on this platform
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    fn preallocate(dest: &Path, _size: u64) -> Result<(), io::Error> {
+        File::create_dest(dest, false).map(|_| ())
+    }
 
-        fs::File::create([TEST_DIR, TEST_FILE].join("/")).unwrap();
-        fs::write([TEST_DIR, TEST_FILE].join("/"), b"1234").unwrap();
+    /// Checks whether `copy` has the same contents as `src`, by flushing `copy` to disk,
+    /// so that errors writing it are found, and then reading both back and hashing them
+    ///
+    /// Data that was flushed may still be read back from the cache of the operating system,
+    /// rather than from the disk itself
+    ///
+    /// # Returns
+    /// Whether both files have the same hash, which is false if either cannot be read
+    fn is_copy_verified(src: &Path, copy: &Path, flags: Flag, algo: HashAlgo) -> bool {
+        let hashes = fs::File::open(copy)
+            .and_then(|copy_file| copy_file.sync_all())
+            .and_then(|_| Ok((hash_path(src, flags, algo)?, hash_path(copy, flags, algo)?)));
 
-        let file_sets = get_all_files(TEST_DIR).unwrap();
-        let mut file_set = HashSet::new();
-        file_set.insert(File {
-            path: PathBuf::from(TEST_FILE),
-            size: 4,
-        });
+        matches!(hashes, Ok((src_hash, copy_hash)) if src_hash == copy_hash)
+    }
 
-        assert_eq!(file_sets.files(), &file_set);
-        assert_eq!(file_sets.dirs(), &HashSet::new());
+    /// Gets a new path of a temporary file that `dest` is copied into before
+    /// it is renamed into place, which is in the same directory as `dest`
+    ///
+    /// The path is unique, so that copies into `dest` by other processes are never overwritten
+    fn temp_path(dest: &Path) -> PathBuf {
+        let mut file_name = dest.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(
+            "{}.{}.{}",
+            TEMP_FILE_MARKER,
+            process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        dest.with_file_name(file_name)
+    }
 
-        fs::remove_dir_all(TEST_DIR).unwrap();
+    /// Checks whether `path` is a temporary file that a file is copied into,
+    /// which is only left behind if a previous copy was stopped
+    pub fn is_temp_path(path: &Path) -> bool {
+        File::temp_target(path).is_some()
     }
 
-    #[cfg(target_family = "unix")]
-    #[test]
-    fn single_symlink() {
-        use std::os::unix::fs::symlink;
-        const TEST_DIR: &str = "test_get_all_files_single_symlink";
-        const TEST_LINK: &str = "test_get_all_files_single_symlink/file";
-        const TEST_FILE: &str = "test_get_all_files_single_symlink/test.txt";
+    /// Gets the path of the file that the temporary file at `path` is copied into,
+    /// if it is named like a temporary file
+    pub fn temp_target(path: &Path) -> Option<PathBuf> {
+        let file_name = path.file_name()?.to_str()?;
+        let (file_name, counter) = file_name.rsplit_once('.')?;
+        let (file_name, pid) = file_name.rsplit_once('.')?;
+        let target = file_name.strip_suffix(TEMP_FILE_MARKER)?;
+        if target.is_empty() || pid.parse::<u32>().is_err() || counter.parse::<u64>().is_err() {
+            return None;
+        }
+        Some(path.with_file_name(target))
+    }
 
-        fs::create_dir_all(TEST_DIR).unwrap();
-        symlink(TEST_FILE, TEST_LINK).unwrap();
+    /// Finds a temporary file that a previous copy into `dest` left behind,
+    /// which is in the same directory as `dest`
+    fn leftover_temp_path(dest: &Path) -> Option<PathBuf> {
+        let file_name = dest.file_name()?;
+        let dir = match dest.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name())
+            .find(|name| {
+                File::temp_target(Path::new(name)).is_some_and(|target| target == file_name)
+            })
+            .map(|name| dest.with_file_name(name))
+    }
 
-        let mut symlink_set = HashSet::new();
-        symlink_set.insert(Symlink {
-            path: PathBuf::from("file"),
-            target: PathBuf::from(TEST_FILE),
-        });
+    /// Gets the path that a partially copied `dest` is kept at,
+    /// which is in the directory called `dir` next to `dest`
+    fn partial_path(dest: &Path, dir: &str) -> PathBuf {
+        let file_name = dest.file_name().unwrap_or_default();
+        dest.with_file_name(dir).join(file_name)
+    }
 
-        let file_sets = get_all_files(TEST_DIR).unwrap();
+    /// Gets how many bytes of `src` were already copied into `partial` by an earlier copy,
+    /// by comparing `partial` with the start of `src` block by block
+    ///
+    /// # Returns
+    /// The length of `partial`, or 0 if it doesn't exist, cannot be read,
+    /// or is not the start of `src`, such as when `src` changed since it was copied
+    fn resumable_len(src: &Path, partial: &Path) -> u64 {
+        let compare = || -> Result<u64, io::Error> {
+            let mut partial_file = fs::File::open(partial)?;
+            let mut src_file = fs::File::open(src)?;
+            let len = partial_file.metadata()?.len();
+            if len > src_file.metadata()?.len() {
+                return Ok(0);
+            }
 
-        assert_eq!(
-            file_sets,
-            FileSets {
-                files: HashSet::new(),
-                dirs: HashSet::new(),
-                symlinks: symlink_set,
+            let mut partial_buffer = vec![0; DEFAULT_COPY_BUFFER_SIZE];
+            let mut src_buffer = vec![0; DEFAULT_COPY_BUFFER_SIZE];
+            loop {
+                let read = read_block(&mut partial_file, &mut partial_buffer)?;
+                if read == 0 {
+                    return Ok(len);
+                }
+                if read_block(&mut src_file, &mut src_buffer[..read])? != read
+                    || partial_buffer[..read] != src_buffer[..read]
+                {
+                    return Ok(0);
+                }
             }
-        );
+        };
 
-        fs::remove_dir_all(TEST_DIR).unwrap();
+        compare().unwrap_or(0)
     }
 
-    #[test]
-    fn multi_level() {
-        const TEST_DIR: &str = "test_get_all_files_multi_level";
-        const SUB_DIRS: [&str; 2] = ["dir1", "dir1/dir2"];
-        const TEST_FILES: [&str; 3] = ["file.txt", "dir1/file.txt", "dir1/dir2/file2.txt"];
-        const TEST_DATA: [&[u8]; 3] = [b"1", b"", b"1234567890"];
-
-        fs::create_dir_all([TEST_DIR, SUB_DIRS[1]].join("/")).unwrap();
-
-        for i in 0..TEST_FILES.len() {
-            let path = [TEST_DIR, TEST_FILES[i]].join("/");
-            fs::File::create(&path).unwrap();
-            fs::write(&path, TEST_DATA[i]).unwrap();
+    /// Keeps the partially copied file `temp` to resume copying it later,
+    /// moving it to `partial_path` if given, or removing it if it cannot be moved there
+    fn keep_partial(temp: &Path, partial_path: Option<&Path>) {
+        // The temporary file may not exist if it could not be created
+        if fs::metadata(temp).is_err() {
+            return;
         }
+        let partial_path = match partial_path {
+            Some(partial_path) => partial_path,
+            None => {
+                info!("Keeping partial file {:?}", temp);
+                return;
+            }
+        };
 
-        let file_sets = get_all_files(TEST_DIR).unwrap();
-        let mut file_set = HashSet::new();
-        let mut dir_set = HashSet::new();
-
-        for i in 0..TEST_FILES.len() {
-            file_set.insert(File {
-                path: PathBuf::from(TEST_FILES[i]),
-                size: TEST_DATA[i].len() as u64,
-            });
+        let moved = partial_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::rename(temp, partial_path));
+        match moved {
+            Ok(_) => info!("Keeping partial file {:?}", partial_path),
+            Err(e) => {
+                warn!("Warning -- Keeping partial file {:?}: {}", partial_path, e);
+                fs::remove_file(temp).ok();
+            }
         }
+    }
 
-        for i in 0..SUB_DIRS.len() {
-            dir_set.insert(Dir {
-                path: PathBuf::from(SUB_DIRS[i]),
-            });
+    /// Renames `temp` to `dest`, along with the flags and Finder metadata of `src` if they are
+    /// preserved, for which `dest` is first unlocked so that it can be replaced.
+    /// If `temp` is `dest`, which is when copying in place, only the flags are set
+    fn replace(src: &Path, temp: &Path, dest: &Path, flags: Flag) -> Result<(), io::Error> {
+        if !flags.contains(Flag::PRESERVE_FLAGS) {
+            return if temp == dest {
+                Ok(())
+            } else {
+                fs::rename(temp, dest)
+            };
+        }
+        if temp == dest {
+            file_flags::copy_finder_info(src, dest)?;
+            return file_flags::copy_file_flags(src, dest);
         }
 
-        assert_eq!(file_sets.files(), &file_set);
-        assert_eq!(file_sets.dirs(), &dir_set);
-
-        fs::remove_dir_all(TEST_DIR).unwrap();
+        // Locked files cannot be renamed, so flags are only set once `temp` is in place
+        file_flags::copy_finder_info(src, temp)?;
+        file_flags::unlock(dest)?;
+        fs::rename(temp, dest)?;
+        file_flags::copy_file_flags(src, dest)
     }
 
-    #[cfg(target_family = "unix")]
-    #[test]
-    fn multi_level_insufficient_permissions() {
-        const TEST_DIR: &str = "test_get_all_files_multi_level_insufficient_permissions";
-        const SUB_DIR: &str = "dir";
-        const TEST_FILE: &str = "file.txt";
+    /// Sets the modification time of `dest` to that of `src`,
+    /// so that a quick check finds them equal after copying
+    fn copy_modified(src: &Path, dest: &Path) -> Result<(), io::Error> {
+        let modified = fs::metadata(src)?.modified()?;
+        open_for_times(dest)?.set_modified(modified)
+    }
 
-        let file_path = [TEST_DIR, TEST_FILE].join("/");
-        let dir_path = [TEST_DIR, SUB_DIR].join("/");
+    #[allow(unused)]
+    #[allow(clippy::unused_io_amount)]
+    fn diff_copy(src: &PathBuf, dest: &PathBuf) -> Result<(), io::Error> {
+        if !Path::new(&dest).exists() {
+            fs::copy(&src, &dest)?;
+        }
 
-        fs::create_dir_all(&dir_path).unwrap();
-        fs::File::create(&file_path).unwrap();
+        const CHUNK_SIZE: usize = 10000;
 
-        Command::new("chmod")
-            .args(&["000", &file_path])
-            .output()
-            .unwrap();
-        Command::new("chmod")
-            .args(&["000", &dir_path])
-            .output()
-            .unwrap();
+        let src_file = fs::File::open(&src)?;
+        let mut src_reader = BufReader::with_capacity(CHUNK_SIZE, &src_file);
+        let dest_file = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .create(true)
+            .open(&dest)?;
+        dest_file.set_len(src_file.metadata()?.len())?;
+        let mut dest_reader = BufReader::with_capacity(CHUNK_SIZE, &dest_file);
+        let mut dest_writer = BufWriter::with_capacity(CHUNK_SIZE, &dest_file);
 
-        let file_sets = get_all_files(TEST_DIR).unwrap();
+        loop {
+            let mut src_buffer = [0; CHUNK_SIZE];
+            let mut dest_buffer = [0; CHUNK_SIZE];
 
-        let mut file_set = HashSet::new();
-        file_set.insert(File {
-            path: PathBuf::from(&TEST_FILE),
-            size: 0,
-        });
-        let mut dir_set = HashSet::new();
-        dir_set.insert(Dir {
-            path: PathBuf::from(&SUB_DIR),
-        });
+            if src_reader.read(&mut src_buffer)? == 0 {
+                break;
+            }
+            dest_reader.read(&mut dest_buffer)?;
 
-        assert_eq!(file_sets.files(), &file_set);
-        assert_eq!(file_sets.dirs(), &dir_set);
+            if seahash::hash(&src_buffer) != seahash::hash(&dest_buffer) {
+                dest_writer.write(&src_buffer)?;
+            } else {
+                dest_writer.seek(SeekFrom::Current(CHUNK_SIZE as i64));
+            }
+        }
 
-        Command::new("chmod")
-            .arg("777")
-            .args(&["777", &dir_path])
-            .output()
-            .unwrap();
-        fs::remove_dir_all(TEST_DIR).unwrap();
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod test_sort_files {
-    use super::*;
+/// A struct that represents a single directory
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+pub struct Dir {
+    path: PathBuf,
+}
 
-    #[test]
-    fn no_dir() {
-        let no_dir: HashSet<Dir> = HashSet::new();
-        assert_eq!(sort_files(no_dir.par_iter()), Vec::<&Dir>::new());
+impl FileOps for Dir {
+    fn path(&self) -> &PathBuf {
+        &self.path
     }
-
-    #[test]
-    fn single_dir() {
-        let mut single_dir: HashSet<Dir> = HashSet::new();
-        let dir = Dir {
-            path: PathBuf::from("/"),
-        };
-        single_dir.insert(dir.clone());
-        let expected: Vec<&Dir> = vec![&dir];
-
-        assert_eq!(sort_files(single_dir.par_iter()), expected);
+    fn remove(&self, path: &PathBuf, flags: Flag, options: &Options) -> SyncStats {
+        match delete_or_trash(path, &self.path, flags, options, || fs::remove_dir(path)) {
+            Ok(_) => {
+                info!("Deleting dir {:?}", path);
+                SyncStats {
+                    dirs_deleted: 1,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                error!("Error -- Deleting dir {:?}: {}", path, e);
+                stats::failure_record(path, e);
+                SyncStats {
+                    errors: 1,
+                    ..SyncStats::default()
+                }
+            }
+        }
+    }
+    fn copy(&self, _src: &PathBuf, dest: &PathBuf, _flags: Flag, options: &Options) -> SyncStats {
+        match fs::create_dir_all(dest).and_then(|_| chown_copied(dest, options)) {
+            Ok(_) => {
+                info!("Creating dir {:?}", dest);
+                SyncStats {
+                    dirs_created: 1,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                error!("Error -- Creating dir {:?}: {}", dest, e);
+                stats::failure_record(dest, e);
+                SyncStats {
+                    errors: 1,
+                    ..SyncStats::default()
+                }
+            }
+        }
     }
+}
 
-    #[test]
-    fn multi_dir_unique() {
-        let mut multi_dir: HashSet<Dir> = HashSet::new();
-        let dir1 = Dir {
+impl Dir {
+    pub fn from(dir: &str) -> Self {
+        Dir {
+            path: PathBuf::from(dir),
+        }
+    }
+}
+
+/// A struct that represents a single symbolic link
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+pub struct Symlink {
+    path: PathBuf,
+    target: PathBuf,
+}
+
+impl FileOps for Symlink {
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    fn remove(&self, path: &PathBuf, flags: Flag, options: &Options) -> SyncStats {
+        match delete_or_trash(path, &self.path, flags, options, || fs::remove_file(path)) {
+            Ok(_) => {
+                info!("Deleting symlink {:?}", path);
+                SyncStats {
+                    symlinks_deleted: 1,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                error!("Error -- Deleting symlink {:?}: {}", path, e);
+                stats::failure_record(path, e);
+                SyncStats {
+                    errors: 1,
+                    ..SyncStats::default()
+                }
+            }
+        }
+    }
+    #[cfg(target_family = "unix")]
+    fn copy(&self, _src: &PathBuf, dest: &PathBuf, _flags: Flag, options: &Options) -> SyncStats {
+        use std::os::unix::fs;
+
+        match fs::symlink(&self.target, dest).and_then(|_| chown_copied(dest, options)) {
+            Ok(_) => {
+                info!("Creating symlink {:?} -> {:?}", dest, self.target);
+                SyncStats {
+                    symlinks_copied: 1,
+                    ..SyncStats::default()
+                }
+            }
+            Err(e) => {
+                error!("Error -- Creating symlink {:?}: {}", dest, e);
+                stats::failure_record(dest, e);
+                SyncStats {
+                    errors: 1,
+                    ..SyncStats::default()
+                }
+            }
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn copy(&self, _src: &PathBuf, dest: &PathBuf, _flags: Flag, _options: &Options) -> SyncStats {
+        use std::os::windows::fs;
+
+        let copied = SyncStats {
+            symlinks_copied: 1,
+            ..SyncStats::default()
+        };
+
+        if self.target.is_file() {
+            match fs::symlink_file(&self.target, &dest) {
+                Ok(_) => {
+                    info!("Creating symlink file {:?} -> {:?}", dest, self.target);
+                    return copied;
+                }
+                Err(e) => {
+                    error!("Error -- Creating symlink file {:?}: {}", dest, e);
+                    stats::failure_record(dest, e);
+                }
+            }
+        }
+        if self.target.is_dir() {
+            match fs::symlink_dir(&self.target, &dest) {
+                Ok(_) => {
+                    info!("Creating symlink dir {:?} -> {:?}", dest, self.target);
+                    return copied;
+                }
+                Err(e) => {
+                    error!("Error -- Creating symlink dir {:?}: {}", dest, e);
+                    stats::failure_record(dest, e);
+                }
+            }
+        }
+        SyncStats {
+            errors: 1,
+            ..SyncStats::default()
+        }
+    }
+}
+
+impl Symlink {
+    pub fn from(path: &str, target: &str) -> Self {
+        Symlink {
+            path: PathBuf::from(path),
+            target: PathBuf::from(target),
+        }
+    }
+}
+
+/// A struct that represents sets of different types of files
+#[derive(Eq, PartialEq, Debug, Default)]
+pub struct FileSets {
+    files: HashSet<File>,
+    dirs: HashSet<Dir>,
+    symlinks: HashSet<Symlink>,
+}
+
+impl FileSets {
+    /// Initializes FileSets with the given sets
+    ///
+    /// # Arguments
+    /// * `files`: a set of files
+    /// * `dirs`: a set of dirs
+    /// * `symlinks`: a set of symlinks
+    ///
+    /// # Returns
+    /// A newly created FileSets struct
+    pub fn with(files: HashSet<File>, dirs: HashSet<Dir>, symlinks: HashSet<Symlink>) -> Self {
+        FileSets {
+            files,
+            dirs,
+            symlinks,
+        }
+    }
+    /// Gets the set of files
+    ///
+    /// # Returns
+    /// The FileSets set of files
+    pub fn files(&self) -> &HashSet<File> {
+        &self.files
+    }
+    /// Gets the set of dirs
+    ///
+    /// # Returns
+    /// The FileSets set of dirs
+    pub fn dirs(&self) -> &HashSet<Dir> {
+        &self.dirs
+    }
+    /// Gets the set of symlinks
+    ///
+    /// # Returns
+    /// The FileSets set of symlinks
+    pub fn symlinks(&self) -> &HashSet<Symlink> {
+        &self.symlinks
+    }
+    /// Checks whether there are no files, dirs, or symlinks
+    ///
+    /// # Returns
+    /// Whether all sets are empty
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty() && self.symlinks.is_empty()
+    }
+    /// Counts the files, dirs, and symlinks
+    ///
+    /// # Returns
+    /// The total size of all sets
+    pub fn len(&self) -> usize {
+        self.files.len() + self.dirs.len() + self.symlinks.len()
+    }
+    /// Moves all files, dirs, and symlinks of `other` into this FileSets
+    ///
+    /// # Arguments
+    /// * `other`: the FileSets with the entries to add
+    pub fn extend(&mut self, mut other: FileSets) {
+        // Moving the smaller sets into the larger ones avoids rehashing most entries
+        if self.len() < other.len() {
+            std::mem::swap(self, &mut other);
+        }
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+        self.symlinks.extend(other.symlinks);
+    }
+    /// Removes all files, dirs, and symlinks that are also in `other`
+    ///
+    /// # Arguments
+    /// * `other`: the FileSets with the entries to remove
+    pub fn remove_all(&mut self, other: &FileSets) {
+        self.files.retain(|file| !other.files.contains(file));
+        self.dirs.retain(|dir| !other.dirs.contains(dir));
+        self.symlinks
+            .retain(|symlink| !other.symlinks.contains(symlink));
+    }
+    /// Removes all files, dirs, and symlinks inside `dir`, along with `dir` itself
+    ///
+    /// # Arguments
+    /// * `dir`: path of the directory, relative to the base directory of the entries
+    pub fn remove_within(&mut self, dir: &Path) {
+        self.files.retain(|file| !file.path().starts_with(dir));
+        self.dirs.retain(|d| !d.path().starts_with(dir));
+        self.symlinks
+            .retain(|symlink| !symlink.path().starts_with(dir));
+    }
+    /// Removes all files for which `keep` returns false
+    ///
+    /// # Returns
+    /// The set of removed files
+    pub fn retain_files<F>(&mut self, keep: F) -> HashSet<File>
+    where
+        F: Fn(&File) -> bool,
+    {
+        let (kept, removed) = self.files.drain().partition(|file| keep(file));
+        self.files = kept;
+        removed
+    }
+    /// Removes all dirs that do not contain any files or symlinks,
+    /// directly or in any of their subdirectories
+    ///
+    /// # Returns
+    /// The set of removed dirs
+    pub fn prune_empty_dirs(&mut self) -> HashSet<Dir> {
+        let non_empty_dirs: HashSet<&Path> = self
+            .files
+            .iter()
+            .map(FileOps::path)
+            .chain(self.symlinks.iter().map(FileOps::path))
+            .flat_map(|path| path.ancestors().skip(1))
+            .collect();
+
+        let (kept, removed) = self
+            .dirs
+            .drain()
+            .partition(|dir| non_empty_dirs.contains(dir.path().as_path()));
+        self.dirs = kept;
+        removed
+    }
+    /// Gets the paths of all files, dirs, and symlinks
+    ///
+    /// # Returns
+    /// The set of all paths in the FileSets
+    pub fn paths(&self) -> HashSet<&PathBuf> {
+        self.files
+            .iter()
+            .map(FileOps::path)
+            .chain(self.dirs.iter().map(FileOps::path))
+            .chain(self.symlinks.iter().map(FileOps::path))
+            .collect()
+    }
+}
+
+/// Compares all files in `files_to_compare` in `src` with all files in `files_to_compare` in `dest`
+/// and copies them over if they are different, in parallel
+///
+/// If `options.conflict_handler` is given, it decides whether each file that differs is copied over,
+/// kept as it is, or stops all further copying
+///
+/// With Flag::FORCE_COPY, all files are treated as different and copied over without comparing
+///
+/// With Flag::NEWER, files that differ are only copied over if the src file was modified
+/// after the dest file
+///
+/// # Arguments
+/// * `files_to_compare`: files to compare
+/// * `src`: base directory of the files to copy from, such that for all `file` in
+/// `files_to_compare`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to copy to, such that for all `file` in
+/// `files_to_compare`, `dest + file.path()` is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options, where `options.conflict_handler` decides what happens
+///   to files that differ, which are all copied if None
+///
+/// # Returns
+/// * Ok: The counts of the files that were updated
+/// * Err: If `options.conflict_handler` aborted
+pub fn compare_and_copy_files<'a, T, S>(
+    files_to_compare: T,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> Result<SyncStats, io::Error>
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    let aborted = AtomicBool::new(false);
+
+    let stats = files_to_compare
+        .map(|file| {
+            if aborted.load(Ordering::Relaxed) || interrupt::is_interrupted() {
+                return SyncStats::default();
+            }
+            progress::progress_set_dir(file.path());
+            let stats =
+                compare_and_copy_file(file, src, dest, flags, options).unwrap_or_else(|| {
+                    aborted.store(true, Ordering::Relaxed);
+                    SyncStats::default()
+                });
+            stats::report_record(file.path(), &stats);
+            progress::progress_inc_bytes(file.size());
+            progress::progress_inc(2);
+            stats
+        })
+        .sum();
+
+    if aborted.load(Ordering::Relaxed) {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "Aborted by the conflict handler",
+        ));
+    }
+    Ok(stats)
+}
+
+/// Compares the given file and copies the src file over if it differs from the dest file
+///
+/// If `Flag::PRESERVE_PERMS` is set, the permissions of a dest file that is the same
+/// are updated if they differ from those of the src file, instead of copying it again
+///
+/// A dest file that is overwritten is first renamed with `options.backup_suffix`,
+/// or moved into `options.backup_dir`, if either is set
+///
+/// # Arguments
+/// * `file_to_compare`: file to compare
+/// * `src`: base directory of the file to copy from, such that `src + file.path()`
+/// is the absolute path of the source file
+/// * `dest`: base directory of the files to copy to, such that `dest + file.path()`
+/// is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options, where `options.conflict_handler` decides what happens
+///   to the file if it differs, which is copied if None
+///
+/// # Returns
+/// * Some: The counts of the file, if it was updated
+/// * None: If `options.conflict_handler` aborted
+fn compare_and_copy_file<S>(
+    file_to_compare: &S,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> Option<SyncStats>
+where
+    S: FileOps,
+{
+    // Files are not compared at all when forced to be copied
+    if !flags.contains(Flag::FORCE_COPY) && is_same_file(file_to_compare, src, dest, flags, options)
+    {
+        if flags.contains(Flag::PRESERVE_PERMS) {
+            return Some(update_permissions(file_to_compare, src, dest, options));
+        }
+        return Some(SyncStats::default());
+    }
+
+    // Whichever file was modified last wins, so a dest file that is as new is kept
+    if flags.contains(Flag::NEWER) && !is_newer_in_src(file_to_compare, src, dest) {
+        info!(
+            "File {:?} skipped (not newer in the source)",
+            file_to_compare.path()
+        );
+        return Some(SyncStats::default());
+    }
+
+    if let Some(conflict_handler) = &options.conflict_handler {
+        let conflict_info = conflict_info(file_to_compare, src, dest, flags, options);
+        match conflict_handler.resolve(&conflict_info) {
+            ConflictAction::Overwrite => (),
+            ConflictAction::Skip => {
+                info!(
+                    "File {:?} skipped (kept by the conflict handler)",
+                    conflict_info.path
+                );
+                return Some(SyncStats::default());
+            }
+            ConflictAction::Abort => return None,
+        }
+    }
+
+    // The old dest file is kept under the backup suffix or in the backup dir,
+    // rather than overwritten
+    let dest_file = Path::new(dest).join(file_to_compare.path());
+    if let Err(e) = back_up_overwritten(&dest_file, file_to_compare.path(), options) {
+        error!("Error -- Backing up {:?}: {}", dest_file, e);
+        stats::failure_record(&dest_file, e);
+        return Some(SyncStats {
+            errors: 1,
+            ..SyncStats::default()
+        });
+    }
+
+    // The file is in both `src` and `dest`, so it is updated rather than copied
+    let stats = copy_file(file_to_compare, src, dest, flags, options);
+    if stats.files_copied > 0 {
+        cache_copied_hash(file_to_compare, src, dest, options.hash);
+    }
+    Some(SyncStats {
+        files_copied: 0,
+        files_updated: stats.files_copied,
+        ..stats
+    })
+}
+
+/// Keeps the dest file at `path` that is about to be overwritten, by renaming it with
+/// `options.backup_suffix`, or moving it into `options.backup_dir`, if either is set
+///
+/// # Arguments
+/// * `path`: path of the dest file
+/// * `relative`: path of the dest file relative to the dest directory
+/// * `options`: values of options
+///
+/// # Errors
+/// If `path` exists but cannot be backed up
+fn back_up_overwritten(path: &Path, relative: &Path, options: &Options) -> Result<(), io::Error> {
+    if let Some(suffix) = &options.backup_suffix {
+        return backup_file(path, suffix);
+    }
+    let backup = match backup_dir_target(relative, options) {
+        Some(backup) => backup,
+        None => return Ok(()),
+    };
+
+    match retry::retry(options.retries, path, || {
+        move_to_backup(path, &backup, || fs::remove_file(path))
+    }) {
+        // There is nothing to back up if the dest file is already gone
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Renames `path` to `path` with `suffix` appended, replacing any older backup of it
+///
+/// # Errors
+/// If `path` exists but cannot be renamed
+fn backup_file(path: &Path, suffix: &str) -> Result<(), io::Error> {
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(suffix);
+
+    match fs::rename(path, &backup) {
+        Ok(_) => {
+            info!("Backing up {:?} to {:?}", path, backup);
+            Ok(())
+        }
+        // There is nothing to back up if the dest file is already gone
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Describes a file that differs between `src` and `dest` for a conflict handler,
+/// hashing both files only if they have the same size
+///
+/// # Arguments
+/// * `file`: file that differs
+/// * `src`: base directory of the source file, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+fn conflict_info<S>(file: &S, src: &str, dest: &str, flags: Flag, options: &Options) -> ConflictInfo
+where
+    S: FileOps,
+{
+    let dest_size = fs::metadata(Path::new(dest).join(file.path())).map_or(0, |m| m.len());
+    let (src_hash, dest_hash) = if dest_size == file.size() {
+        (
+            hash_file_cached(file, src, flags, options.hash, options.retries),
+            hash_file_cached(file, dest, flags, options.hash, options.retries),
+        )
+    } else {
+        (None, None)
+    };
+
+    ConflictInfo {
+        path: file.path().clone(),
+        src_size: file.size(),
+        dest_size,
+        src_hash,
+        dest_hash,
+    }
+}
+
+/// Sets the permissions of the dest file to those of the src file, if they differ
+///
+/// # Arguments
+/// * `file`: file to update the permissions of
+/// * `src`: base directory of the source file, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the file, if its permissions were updated
+fn update_permissions<S>(file: &S, src: &str, dest: &str, options: &Options) -> SyncStats
+where
+    S: FileOps,
+{
+    let src_file = Path::new(src).join(file.path());
+    let dest_file = Path::new(dest).join(file.path());
+
+    let updated = copy_permission(&src_file, &dest_file, options);
+
+    match updated {
+        Ok(true) => {
+            info!("Updating permissions of {:?}", dest_file);
+            SyncStats {
+                perms_updated: 1,
+                ..SyncStats::default()
+            }
+        }
+        Ok(false) => SyncStats::default(),
+        Err(e) => {
+            error!("Error -- Setting permissions of {:?}: {}", dest_file, e);
+            stats::failure_record(&dest_file, e);
+            SyncStats {
+                errors: 1,
+                ..SyncStats::default()
+            }
+        }
+    }
+}
+
+/// Checks whether the src and dest file are the same, by comparing their hashes,
+/// or their size and modification time if `Flag::QUICK_CHECK` is set and
+/// the modification times are available, unless `Flag::CHECKSUM` is set
+///
+/// Files of different sizes are never hashed, since their contents always differ
+///
+/// If `Flag::QUICK_HASH` is set, only the start and end of the files are hashed,
+/// so files that only differ in between are treated as the same, which trades a small risk
+/// of missing changes for not reading whole files. If `Flag::CHECKSUM` is also set,
+/// files whose start and end are the same are then hashed fully
+///
+/// If `Flag::COMPARE_BYTES` is set, the files are compared byte by byte instead of hashed
+///
+/// If the hash cache is loaded, files that were hashed before and have not changed since
+/// are not hashed again
+///
+/// # Arguments
+/// * `file_to_compare`: file to compare
+/// * `src`: base directory of the source file, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// Whether the files are the same, which is false if the source file cannot be read
+pub fn is_same_file<S>(
+    file_to_compare: &S,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> bool
+where
+    S: FileOps,
+{
+    if flags.contains(Flag::QUICK_CHECK) && !flags.contains(Flag::CHECKSUM) {
+        if let Some(same) = quick_check(file_to_compare, src, dest) {
+            return same;
+        }
+    }
+
+    // Files missing from `dest`, or of a different size than found while traversing `src`,
+    // can't be the same, so they are never hashed
+    let dest_file = Path::new(dest).join(file_to_compare.path());
+    match fs::metadata(&dest_file) {
+        Ok(metadata) if metadata.len() == file_to_compare.size() => (),
+        _ => return false,
+    }
+
+    if flags.contains(Flag::COMPARE_BYTES) {
+        let src_file = Path::new(src).join(file_to_compare.path());
+        return match files_identical(&src_file, &dest_file) {
+            Ok(is_same) => is_same,
+            Err(e) => {
+                error!("Error -- Comparing: {:?}: {}", file_to_compare.path(), e);
+                stats::failure_record(&src_file, e);
+                false
+            }
+        };
+    }
+
+    if flags.contains(Flag::QUICK_HASH) {
+        let size = options.quick_hash_size.unwrap_or(DEFAULT_QUICK_HASH_SIZE);
+        let src_file_hash =
+            hash_file_partial(file_to_compare, src, options.hash, size, options.retries);
+        let is_same = src_file_hash.is_some()
+            && src_file_hash
+                == hash_file_partial(file_to_compare, dest, options.hash, size, options.retries);
+        if !is_same || !flags.contains(Flag::CHECKSUM) {
+            return is_same;
+        }
+    }
+
+    let src_file_hash =
+        hash_file_cached(file_to_compare, src, flags, options.hash, options.retries);
+    src_file_hash.is_some()
+        && src_file_hash
+            == hash_file_cached(file_to_compare, dest, flags, options.hash, options.retries)
+}
+
+/// Checks whether two files have the same contents by reading and comparing them block by block,
+/// which stops at the first block that differs
+///
+/// # Arguments
+/// * `src`: path of the source file
+/// * `dest`: path of the destination file
+///
+/// # Returns
+/// * Ok: Whether the files have the same contents
+/// * Err: If either file cannot be read
+pub fn files_identical(src: &Path, dest: &Path) -> Result<bool, io::Error> {
+    let mut src_file = fs::File::open(src)?;
+    let mut dest_file = fs::File::open(dest)?;
+    if src_file.metadata()?.len() != dest_file.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut src_buffer = vec![0; COMPARE_BUFFER_SIZE];
+    let mut dest_buffer = vec![0; COMPARE_BUFFER_SIZE];
+
+    loop {
+        let len = read_block(&mut src_file, &mut src_buffer)?;
+        if len != read_block(&mut dest_file, &mut dest_buffer)?
+            || src_buffer[..len] != dest_buffer[..len]
+        {
+            return Ok(false);
+        }
+        if len == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads from `file` until `buffer` is full or the end of the file is reached
+///
+/// # Returns
+/// * Ok: The number of bytes read, which is less than the size of `buffer` only at the end
+/// * Err: If the file cannot be read
+fn read_block(file: &mut fs::File, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let mut len = 0;
+
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Checks whether the src and dest file have the same size and modification time,
+/// without reading their contents
+///
+/// The size and modification time of the source file are taken from `file_to_check`
+/// if they were found while traversing, so that only the destination file is read
+///
+/// # Arguments
+/// * `file_to_check`: file to check
+/// * `src`: base directory of the source file, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + file.path()`
+///   is the absolute path of the destination file
+///
+/// # Returns
+/// * Some: Whether both files exist and have the same size and modification time
+/// * None: If either modification time is not available, so the contents must be compared
+fn quick_check<S>(file_to_check: &S, src: &str, dest: &str) -> Option<bool>
+where
+    S: FileOps,
+{
+    let dest_file: PathBuf = [&PathBuf::from(dest), file_to_check.path()]
+        .iter()
+        .collect();
+    let dest_metadata = match fs::metadata(dest_file) {
+        Ok(metadata) => metadata,
+        Err(_) => return Some(false),
+    };
+
+    let (src_len, src_modified) = match file_to_check.modified() {
+        Some(modified) => (file_to_check.size(), Ok(modified)),
+        None => {
+            let src_file: PathBuf = [&PathBuf::from(src), file_to_check.path()].iter().collect();
+            match fs::metadata(src_file) {
+                Ok(metadata) => (metadata.len(), metadata.modified()),
+                Err(_) => return Some(false),
+            }
+        }
+    };
+
+    if src_len != dest_metadata.len() {
+        return Some(false);
+    }
+    match (src_modified, dest_metadata.modified()) {
+        (Ok(src_modified), Ok(dest_modified)) => Some(src_modified == dest_modified),
+        _ => None,
+    }
+}
+
+/// Checks whether the dest file was modified more than `window` after the src file
+///
+/// The modification times are taken from the files if they were found while traversing,
+/// and are read from `src` and `dest` otherwise
+///
+/// # Arguments
+/// * `src_file`: file in the source
+/// * `src`: base directory of the source file, such that `src + src_file.path()`
+///   is the absolute path of the source file
+/// * `dest_file`: file of the same path in the destination
+/// * `dest`: base directory of the destination file, such that `dest + dest_file.path()`
+///   is the absolute path of the destination file
+/// * `window`: difference in modification times that is still treated as the same time
+///
+/// # Returns
+/// Whether the dest file is newer, which is false if either modification time is not available
+pub fn is_newer_in_dest<S>(
+    src_file: &S,
+    src: &str,
+    dest_file: &S,
+    dest: &str,
+    window: Duration,
+) -> bool
+where
+    S: FileOps,
+{
+    match (modified_time(src_file, src), modified_time(dest_file, dest)) {
+        (Some(src_modified), Some(dest_modified)) => dest_modified
+            .duration_since(src_modified)
+            .is_ok_and(|newer_by| newer_by > window),
+        _ => false,
+    }
+}
+
+/// Checks whether the src file was modified after the dest file of the same path
+///
+/// # Arguments
+/// * `src_file`: file in the source, whose modification time is taken from it if it was
+///   found while traversing
+/// * `src`: base directory of the source file, such that `src + src_file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + src_file.path()`
+///   is the absolute path of the destination file
+///
+/// # Returns
+/// Whether the src file is newer, which is true if either modification time is not available,
+/// so that the src file is copied like it is without Flag::NEWER
+fn is_newer_in_src<S>(src_file: &S, src: &str, dest: &str) -> bool
+where
+    S: FileOps,
+{
+    let dest_modified = fs::metadata(Path::new(dest).join(src_file.path()))
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    match (modified_time(src_file, src), dest_modified) {
+        (Some(src_modified), Some(dest_modified)) => src_modified > dest_modified,
+        _ => true,
+    }
+}
+
+/// Gets the modification time of `file` found while traversing, or reads it from `base + file.path()`
+fn modified_time<S>(file: &S, base: &str) -> Option<SystemTime>
+where
+    S: FileOps,
+{
+    file.modified().or_else(|| {
+        let path: PathBuf = [&PathBuf::from(base), file.path()].iter().collect();
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+}
+
+/// Copies all given files from `src` to `dest` in parallel
+///
+/// # Arguments
+/// * `files_to_copy`: files to copy
+/// * `src`: base directory of the files to copy from, such that for all `file` in
+/// `files_to_copy`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to copy to, such that for all `file` in
+/// `files_to_copy`, `dest + file.path()` is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the files that were copied
+pub fn copy_files<'a, T, S>(
+    files_to_copy: T,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files_to_copy
+        .map(|file| {
+            if interrupt::is_interrupted() {
+                return SyncStats::default();
+            }
+            progress::progress_set_dir(file.path());
+            let stats = copy_file(file, src, dest, flags, options);
+            stats::report_record(file.path(), &stats);
+            progress::progress_inc_bytes(file.size());
+            progress::progress_inc(1);
+            stats
+        })
+        .sum()
+}
+
+/// Checks whether `file_to_check` was already copied from `src` to `dest`, which is when
+/// the file in `dest` has the same size and modification time
+///
+/// # Arguments
+/// * `file_to_check`: file to check
+/// * `src`: base directory of the file in the source, such that
+///   `src + file_to_check.path()` is the absolute path of the source file
+/// * `dest`: base directory of the file in the destination, such that
+///   `dest + file_to_check.path()` is the absolute path of the destination file
+///
+/// # Returns
+/// Whether the file was copied, which is false if modification times are not available
+pub fn is_copied<S>(file_to_check: &S, src: &str, dest: &str) -> bool
+where
+    S: FileOps,
+{
+    quick_check(file_to_check, src, dest) == Some(true)
+}
+
+/// Skips all given files, which are kept as they are in the destination, without touching them
+///
+/// # Arguments
+/// * `files_to_skip`: files to skip
+/// * `reason`: why the files are skipped, which is logged with each file
+pub fn skip_files<'a, T, S>(files_to_skip: T, reason: &str)
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files_to_skip.for_each(|file| {
+        info!("File {:?} skipped ({})", file.path(), reason);
+        progress::progress_inc_bytes(file.size());
+        progress::progress_inc(1);
+    });
+}
+
+/// Removes all given temporary files left behind by copies that were stopped,
+/// without counting them as deleted
+///
+/// # Arguments
+/// * `temp_files`: temporary files to remove
+/// * `location`: base directory of the files, such that for all `file` in
+///   `temp_files`, `location + file.path()` is the absolute path of the file
+pub fn remove_temp_files<'a, T, S>(temp_files: T, location: &str)
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    temp_files.for_each(|file| {
+        let path = Path::new(location).join(file.path());
+        match fs::remove_file(&path) {
+            Ok(_) => info!("Removing temporary file {:?}", path),
+            Err(e) => warn!("Warning -- Removing temporary file {:?}: {}", path, e),
+        }
+    });
+}
+
+/// Hard links all given files to `dest` in parallel from the first directory in
+/// `options.link_dest` where they are the same as in `src`, and copies them from `src` to `dest`
+/// otherwise, or if they cannot be linked
+///
+/// # Arguments
+/// * `files_to_copy`: files to link or copy
+/// * `src`: base directory of the files to copy from, such that for all `file` in
+///   `files_to_copy`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to copy to, such that for all `file` in
+///   `files_to_copy`, `dest + file.path()` is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options, where `options.link_dest` are the base directories of
+///   the files to link from, tried in order, such that for all `file` in `files_to_copy`,
+///   `link_dest + file.path()` is the absolute path of a reference file
+///
+/// # Returns
+/// The counts of the files that were linked or copied
+pub fn link_or_copy_files<'a, T, S>(
+    files_to_copy: T,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files_to_copy
+        .map(|file| {
+            if interrupt::is_interrupted() {
+                return SyncStats::default();
+            }
+            progress::progress_set_dir(file.path());
+            let stats = link_or_copy_file(file, src, dest, flags, options);
+            stats::report_record(file.path(), &stats);
+            progress::progress_inc_bytes(file.size());
+            progress::progress_inc(1);
+            stats
+        })
+        .sum()
+}
+
+/// Hard links a single file to `dest` from the first directory in `options.link_dest` where it is
+/// the same as in `src`, and copies it from `src` to `dest` otherwise, or if it cannot be linked
+///
+/// # Arguments
+/// * `file_to_copy`: file to link or copy
+/// * `src`: base directory of the file to copy from, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the file to copy to, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the file, if it was linked or copied
+fn link_or_copy_file<S>(
+    file_to_copy: &S,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    S: FileOps,
+{
+    let dest_file: PathBuf = [&PathBuf::from(dest), file_to_copy.path()].iter().collect();
+
+    for link_dest in &options.link_dest {
+        if !is_same_file(file_to_copy, src, link_dest, flags, options) {
+            continue;
+        }
+
+        let link_file: PathBuf = [&PathBuf::from(link_dest), file_to_copy.path()]
+            .iter()
+            .collect();
+
+        match fs::hard_link(&link_file, &dest_file) {
+            Ok(_) => {
+                info!("Linking file {:?} -> {:?}", link_file, dest_file);
+                return SyncStats {
+                    files_linked: 1,
+                    ..SyncStats::default()
+                };
+            }
+            // Links cannot cross devices, among other reasons, in which case the next directory
+            // is tried, and the file is copied if it cannot be linked from any of them
+            Err(e) => warn!("Linking file {:?} failed: {}", link_file, e),
+        }
+    }
+
+    copy_file(file_to_copy, src, dest, flags, options)
+}
+
+/// Hard links files in `dest` to the copies of files with the same contents, which must
+/// already be copied, and copies them from `src` instead if they cannot be linked
+///
+/// # Arguments
+/// * `duplicates`: files to link, each with the file with the same contents to link it to
+/// * `src`: base directory of the files to copy from, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the files to link, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the files that were linked or copied
+pub fn link_duplicates<'a, T>(
+    duplicates: T,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    T: ParallelIterator<Item = (&'a File, &'a File)>,
+{
+    duplicates
+        .map(|(file, original)| {
+            if interrupt::is_interrupted() {
+                return SyncStats::default();
+            }
+            progress::progress_set_dir(file.path());
+            let stats = link_duplicate(file, original, src, dest, flags, options);
+            stats::report_record(file.path(), &stats);
+            progress::progress_inc_bytes(file.size());
+            progress::progress_inc(1);
+            stats
+        })
+        .sum()
+}
+
+/// Hard links a single file in `dest` to the copy of `original` in `dest`,
+/// and copies it from `src` instead if it cannot be linked
+///
+/// # Arguments
+/// * `file`: file to link
+/// * `original`: file with the same contents to link to
+/// * `src`: base directory of the file to copy from
+/// * `dest`: base directory of both files in the destination
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the file, if it was linked or copied
+fn link_duplicate(
+    file: &File,
+    original: &File,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats {
+    let link_file: PathBuf = [&PathBuf::from(dest), original.path()].iter().collect();
+    let dest_file: PathBuf = [&PathBuf::from(dest), file.path()].iter().collect();
+
+    // The link is made next to the destination file and renamed over it,
+    // so that a destination file that already exists is replaced
+    let temp = File::temp_path(&dest_file);
+    fs::remove_file(&temp).ok();
+
+    match fs::hard_link(&link_file, &temp).and_then(|_| fs::rename(&temp, &dest_file)) {
+        Ok(_) => {
+            info!("Linking file {:?} -> {:?}", link_file, dest_file);
+            SyncStats {
+                files_linked: 1,
+                ..SyncStats::default()
+            }
+        }
+        Err(e) => {
+            fs::remove_file(&temp).ok();
+            warn!(
+                "Linking file {:?} failed, copying instead: {}",
+                link_file, e
+            );
+            copy_file(file, src, dest, flags, options)
+        }
+    }
+}
+
+/// Copies a single file from `src` to `dest`
+///
+/// # Arguments
+/// * `files_to_copy`: file to copy
+/// * `src`: base directory of the files to copy from, such that `src + file_to_copy.path()`
+/// is the absolute path of the source file
+/// * `dest`: base directory of the files to copy to, such that `dest + file.path()`
+/// is the absolute path of the destination file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the file, if it was copied
+fn copy_file<S>(
+    file_to_copy: &S,
+    src: &str,
+    dest: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    S: FileOps,
+{
+    let src_file = [&PathBuf::from(&src), file_to_copy.path()].iter().collect();
+    let dest_file = [&PathBuf::from(&dest), file_to_copy.path()]
+        .iter()
+        .collect();
+
+    file_to_copy.copy(&src_file, &dest_file, flags, options)
+}
+
+/// Deletes all given files in parallel
+///
+/// There is no guarantee that this function will delete the files in the given order
+///
+/// # Arguments
+/// `files_to_delete`: files to delete
+/// * `location`: base directory of the files to delete, such that for all `file` in
+/// `files_to_delete`, `location + file.path()` is the absolute path of the file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the files that were deleted
+pub fn delete_files<'a, T, S>(
+    files_to_delete: T,
+    location: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files_to_delete
+        .map(|file| {
+            if interrupt::is_interrupted() {
+                return SyncStats::default();
+            }
+            let path = [&PathBuf::from(&location), file.path()].iter().collect();
+            let stats = file.remove(&path, flags, options);
+            stats::report_record(file.path(), &stats);
+            progress::progress_inc(1);
+            stats
+        })
+        .sum()
+}
+
+/// Deletes all given files sequentially
+///
+/// This function ensures that the files are deleted in the exact order given
+///
+/// # Arguments
+/// * `files_to_delete`: files to delete, or sorted empty directories
+/// * `location`: base directory of the files to delete, such that for all `file` in
+/// `files_to_delete`, `location + file.path()` is the absolute path of the file
+/// * `flags`: set for Flag's
+/// * `options`: values of options
+///
+/// # Returns
+/// The counts of the files that were deleted
+pub fn delete_files_sequential<'a, T, S>(
+    files_to_delete: T,
+    location: &str,
+    flags: Flag,
+    options: &Options,
+) -> SyncStats
+where
+    T: IntoIterator<Item = &'a S>,
+    S: FileOps + 'a,
+{
+    let mut stats = SyncStats::default();
+    for file in files_to_delete {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        let path = [&PathBuf::from(&location), file.path()].iter().collect();
+        let file_stats = file.remove(&path, flags, options);
+        stats::report_record(file.path(), &file_stats);
+        stats += file_stats;
+        progress::progress_inc(1);
+    }
+    stats
+}
+
+/// Sets the permissions of all given files in `dest` to those of the same files in `src`,
+/// if they differ
+///
+/// Files are handled in the given order, so directories should be sorted deepest first,
+/// such that restricting a directory does not prevent handling those inside it
+///
+/// # Arguments
+/// * `files`: files to set the permissions of, usually sorted directories
+/// * `src`: base directory of the files to read the permissions of, such that for all `file`
+///   in `files`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to set the permissions of, such that for all `file`
+///   in `files`, `dest + file.path()` is the absolute path of the destination file
+/// * `options`: values of options
+pub fn copy_permissions<'a, T, S>(files: T, src: &str, dest: &str, options: &Options)
+where
+    T: IntoIterator<Item = &'a S>,
+    S: FileOps + 'a,
+{
+    for file in files {
+        let src_file = Path::new(src).join(file.path());
+        let dest_file = Path::new(dest).join(file.path());
+
+        let updated = copy_permission(&src_file, &dest_file, options);
+
+        match updated {
+            Ok(true) => info!("Setting permissions of {:?}", dest_file),
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error -- Setting permissions of {:?}: {}", dest_file, e);
+                stats::failure_record(&dest_file, e);
+            }
+        }
+    }
+}
+
+/// Sets the permissions of `dest` to those of `src`, changed by `options.chmod` if set
+///
+/// # Returns
+/// * Ok(true): If the permissions were changed
+/// * Ok(false): If they were already the same
+/// * Err: If they cannot be read or set
+fn copy_permission(src: &Path, dest: &Path, options: &Options) -> Result<bool, io::Error> {
+    let permissions = chmod_permissions(&fs::metadata(src)?, options);
+    if fs::metadata(dest)?.permissions() == permissions {
+        return Ok(false);
+    }
+    fs::set_permissions(dest, permissions).map(|_| true)
+}
+
+/// Sets the owner and group of all given files in `dest` to those of the same files in `src`,
+/// in parallel
+///
+/// Failing to change an owner, such as when not running as root, is logged without stopping
+///
+/// # Arguments
+/// * `files`: files to set the owner and group of
+/// * `src`: base directory of the files to read the owners of, such that for all `file`
+///   in `files`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to set the owners of, such that for all `file`
+///   in `files`, `dest + file.path()` is the absolute path of the destination file
+pub fn copy_owners<'a, T, S>(files: T, src: &str, dest: &str)
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files.for_each(|file| {
+        let src_file = Path::new(src).join(file.path());
+        let dest_file = Path::new(dest).join(file.path());
+
+        match copy_owner(&src_file, &dest_file) {
+            Ok(true) => info!("Setting owner of {:?}", dest_file),
+            Ok(false) => (),
+            Err(e) => {
+                error!("Error -- Setting owner of {:?}: {}", dest_file, e);
+                stats::failure_record(&dest_file, e);
+            }
+        }
+    });
+}
+
+/// Copies the extended attributes of all given files in `src` to the same files in `dest`,
+/// in parallel
+///
+/// # Arguments
+/// * `files`: files to copy the extended attributes of
+/// * `src`: base directory of the files to read the attributes of, such that for all `file`
+///   in `files`, `src + file.path()` is the absolute path of the source file
+/// * `dest`: base directory of the files to set the attributes of, such that for all `file`
+///   in `files`, `dest + file.path()` is the absolute path of the destination file
+pub fn copy_all_xattrs<'a, T, S>(files: T, src: &str, dest: &str)
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    files.for_each(|file| {
+        let src_file = Path::new(src).join(file.path());
+        let dest_file = Path::new(dest).join(file.path());
+
+        if let Err(e) = xattrs::copy_xattrs(&src_file, &dest_file) {
+            error!(
+                "Error -- Copying extended attributes of {:?}: {}",
+                dest_file, e
+            );
+            stats::failure_record(&dest_file, e);
+        }
+    });
+}
+
+/// Sorts (unstable) file paths in descending order by number of components, in parallel
+///
+/// # Arguments
+/// `files_to_sort`: files to sort
+///
+/// # Returns
+/// A vector of file paths in descending order by number of components
+///
+/// # Examples
+/// ["a", "a/b", "a/b/c"] becomes ["a/b/c", "a/b", "a"]
+/// ["/usr", "/", "/usr/bin", "/etc"] becomes ["/usr/bin", "/usr", "/etc", "/"]
+pub fn sort_files<'a, T, S>(files_to_sort: T) -> Vec<&'a S>
+where
+    T: ParallelIterator<Item = &'a S>,
+    S: FileOps + Sync + 'a,
+{
+    let mut files_to_sort = Vec::from_par_iter(files_to_sort);
+    files_to_sort.par_sort_unstable_by(|a, b| {
+        b.path()
+            .components()
+            .count()
+            .cmp(&a.path().components().count())
+    });
+    files_to_sort
+}
+
+/// Generates a hash of the given file, using the hash function `algo`
+///
+/// # Arguments
+/// * `file_to_hash`: file object to hash
+/// * `location`: base directory of the file to hash, such that
+/// `location + file_to_hash.path()` is the absolute path of the file
+/// * `flags`: set for Flag's
+/// * `algo`: hash function to use
+/// * `retries`: number of times to retry hashing the file if it fails with a transient error
+///
+/// # Returns
+/// * Some: The hash of the given file
+/// * Err: If the given file cannot be hashed
+pub fn hash_file<S>(
+    file_to_hash: &S,
+    location: &str,
+    flags: Flag,
+    algo: HashAlgo,
+    retries: u32,
+) -> Option<Vec<u8>>
+where
+    S: FileOps,
+{
+    let file: PathBuf = [&PathBuf::from(&location), file_to_hash.path()]
+        .iter()
+        .collect();
+
+    match retry::retry(retries, &file, || hash_path(&file, flags, algo)) {
+        Ok(hash) => Some(hash),
+        Err(e) => {
+            error!("Error -- Hashing: {:?}: {}", file_to_hash.path(), e);
+            stats::failure_record(&file, e);
+            None
+        }
+    }
+}
+
+/// Generates a hash of the file at `path`, using the hash function `algo`,
+/// by mapping it into memory first if `Flag::MMAP` is set
+///
+/// # Returns
+/// * Ok: The hash of the file
+/// * Err: If the file cannot be read
+fn hash_path(path: &Path, flags: Flag, algo: HashAlgo) -> Result<Vec<u8>, io::Error> {
+    if flags.contains(Flag::MMAP) {
+        if let Some(hash) = hash_mapped(path, algo) {
+            return Ok(hash);
+        }
+    }
+
+    match algo {
+        HashAlgo::Seahash => hash_stream(path, SeaHasher::new()),
+        // The streaming XXH3 hasher doesn't match `xxh3::hash64` for large files,
+        // so the whole file is read to keep hashes the same
+        HashAlgo::Xxhash => {
+            fs::read(path).map(|contents| xxh3::hash64(&contents).to_le_bytes().to_vec())
+        }
+        HashAlgo::Blake2 => hash_digest::<Blake2b>(path),
+        HashAlgo::Sha256 => hash_digest::<Sha256>(path),
+        HashAlgo::Blake3 => hash_blake3(path),
+        HashAlgo::Crc32 => hash_crc32(path),
+    }
+}
+
+/// Generates a hash of the given file like `hash_file`, looking it up in the hash cache first
+/// and adding it to the cache once it is hashed, if the cache is loaded
+///
+/// # Arguments
+/// * `file_to_hash`: file object to hash
+/// * `location`: base directory of the file to hash, such that
+///   `location + file_to_hash.path()` is the absolute path of the file
+/// * `flags`: set for Flag's
+/// * `algo`: hash function to use
+/// * `retries`: number of times to retry hashing the file if it fails with a transient error
+///
+/// # Returns
+/// * Some: The hash of the given file
+/// * None: If the given file cannot be hashed
+fn hash_file_cached<S>(
+    file_to_hash: &S,
+    location: &str,
+    flags: Flag,
+    algo: HashAlgo,
+    retries: u32,
+) -> Option<Vec<u8>>
+where
+    S: FileOps,
+{
+    if !cache::cache_enabled() {
+        return hash_file(file_to_hash, location, flags, algo, retries);
+    }
+
+    // The metadata is read before hashing, so that the hash is invalid if the file changes
+    // while it is hashed
+    let file = Path::new(location).join(file_to_hash.path());
+    let metadata = match fs::metadata(&file) {
+        Ok(metadata) => metadata,
+        Err(_) => return hash_file(file_to_hash, location, flags, algo, retries),
+    };
+    if let Some(hash) = cache::cache_get(&file, algo, &metadata) {
+        return Some(hash);
+    }
+
+    let hash = hash_file(file_to_hash, location, flags, algo, retries)?;
+    cache::cache_insert(&file, algo, &metadata, &hash);
+    Some(hash)
+}
+
+/// Adds the hash of the src file to the hash cache as the hash of the dest file,
+/// once the src file is copied over it, if the src file is in the cache
+///
+/// # Arguments
+/// * `file`: file that was copied
+/// * `src`: base directory of the source file, such that `src + file.path()`
+///   is the absolute path of the source file
+/// * `dest`: base directory of the destination file, such that `dest + file.path()`
+///   is the absolute path of the destination file
+/// * `algo`: hash function of the hash
+fn cache_copied_hash<S>(file: &S, src: &str, dest: &str, algo: HashAlgo)
+where
+    S: FileOps,
+{
+    if !cache::cache_enabled() {
+        return;
+    }
+
+    let src_file = Path::new(src).join(file.path());
+    let dest_file = Path::new(dest).join(file.path());
+    if let (Ok(src_metadata), Ok(dest_metadata)) =
+        (fs::metadata(&src_file), fs::metadata(&dest_file))
+    {
+        if let Some(hash) = cache::cache_get(&src_file, algo, &src_metadata) {
+            cache::cache_insert(&dest_file, algo, &dest_metadata, &hash);
+        }
+    }
+}
+
+/// Generates a hash of only the length and the first and last `size` bytes of the given file,
+/// using the hash function `algo`, which seeks past the rest of the file without reading it
+///
+/// # Arguments
+/// * `file_to_hash`: file object to hash
+/// * `location`: base directory of the file to hash, such that
+///   `location + file_to_hash.path()` is the absolute path of the file
+/// * `algo`: hash function to use
+/// * `size`: number of bytes to hash at the start and end of the file
+/// * `retries`: number of times to retry hashing the file if it fails with a transient error
+///
+/// # Returns
+/// * Some: The hash of the given file, which is of the whole file if it is at most `2 * size` bytes
+/// * None: If the given file cannot be hashed
+pub fn hash_file_partial<S>(
+    file_to_hash: &S,
+    location: &str,
+    algo: HashAlgo,
+    size: u64,
+    retries: u32,
+) -> Option<Vec<u8>>
+where
+    S: FileOps,
+{
+    let file: PathBuf = [&PathBuf::from(&location), file_to_hash.path()]
+        .iter()
+        .collect();
+
+    let read_ends = || -> Result<Vec<u8>, io::Error> {
+        let mut file = fs::File::open(&file)?;
+        let len = file.metadata()?.len();
+        let mut contents = len.to_le_bytes().to_vec();
+
+        if len <= 2 * size {
+            file.read_to_end(&mut contents)?;
+        } else {
+            let mut block = vec![0; size as usize];
+            file.read_exact(&mut block)?;
+            contents.extend_from_slice(&block);
+            file.seek(SeekFrom::End(-(size as i64)))?;
+            file.read_exact(&mut block)?;
+            contents.extend_from_slice(&block);
+        }
+        Ok(contents)
+    };
+
+    match retry::retry(retries, &file, read_ends) {
+        Ok(contents) => Some(hash_bytes(&contents, algo)),
+        Err(e) => {
+            error!("Error -- Hashing: {:?}: {}", file_to_hash.path(), e);
+            stats::failure_record(&file, e);
+            None
+        }
+    }
+}
+
+/// Generates a hash of `contents` using the hash function `algo`
+fn hash_bytes(contents: &[u8], algo: HashAlgo) -> Vec<u8> {
+    match algo {
+        HashAlgo::Seahash => seahash::hash(contents).to_le_bytes().to_vec(),
+        HashAlgo::Xxhash => xxh3::hash64(contents).to_le_bytes().to_vec(),
+        HashAlgo::Blake2 => Blake2b::digest(contents).to_vec(),
+        HashAlgo::Sha256 => Sha256::digest(contents).to_vec(),
+        HashAlgo::Blake3 => blake3::hash(contents).as_bytes().to_vec(),
+        // Big endian, so that the hex digits are the same as those of other tools
+        HashAlgo::Crc32 => crc32fast::hash(contents).to_be_bytes().to_vec(),
+    }
+}
+
+/// Generates a hash of the file at `path` with the cryptographic hash function `D`,
+/// without reading the whole file into memory
+///
+/// # Arguments
+/// * `path`: path of the file to hash
+///
+/// # Returns
+/// * Ok: The hash of the file
+/// * Err: If the file cannot be read
+fn hash_digest<D>(path: &Path) -> Result<Vec<u8>, io::Error>
+where
+    D: Digest + Write,
+{
+    let mut hasher = D::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Generates a hash of the file at `path` by mapping it into memory, which is the same as
+/// the hash from `hash_file` without mapping
+///
+/// The file is hashed again by reading it if its size changes while it is mapped. Note that
+/// some systems still stop the process with SIGBUS if the file is truncated while it is hashed
+///
+/// # Arguments
+/// * `path`: path of the file to hash
+/// * `algo`: hash function to use
+///
+/// # Returns
+/// * Some: The hash of the file
+/// * None: If the file cannot be mapped, or changed while it was hashed
+fn hash_mapped(path: &Path, algo: HashAlgo) -> Option<Vec<u8>> {
+    let file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+
+    // Empty files can't be mapped
+    if len == 0 {
+        return Some(hash_bytes(&[], algo));
+    }
+
+    // This is safe as long as the file isn't changed while it is mapped,
+    // which is checked after hashing
+    let mapped = match unsafe { Mmap::map(&file) } {
+        Ok(mapped) => mapped,
+        Err(e) => {
+            info!("Reading {:?}, since it can't be mapped: {}", path, e);
+            return None;
+        }
+    };
+    let hash = hash_bytes(&mapped, algo);
+
+    match file.metadata() {
+        Ok(metadata) if metadata.len() == len => Some(hash),
+        _ => {
+            warn!(
+                "Reading {:?} again, since it changed while it was mapped",
+                path
+            );
+            None
+        }
+    }
+}
+
+/// Generates a hash of the file at `path` with the streaming hash function `hasher`,
+/// reading it in blocks of `HASH_BUFFER_SIZE` bytes instead of all at once
+///
+/// The hash is the same as hashing the whole contents of the file in one buffer
+///
+/// # Arguments
+/// * `path`: path of the file to hash
+/// * `hasher`: newly created hasher
+///
+/// # Returns
+/// * Ok: The hash of the file
+/// * Err: If the file cannot be read
+fn hash_stream<H>(path: &Path, mut hasher: H) -> Result<Vec<u8>, io::Error>
+where
+    H: Hasher,
+{
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => hasher.write(&buffer[..len]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finish().to_le_bytes().to_vec())
+}
+
+/// Generates a BLAKE3 hash of the file at `path`, without reading the whole file into memory
+///
+/// # Arguments
+/// * `path`: path of the file to hash
+///
+/// # Returns
+/// * Ok: The hash of the file
+/// * Err: If the file cannot be read
+fn hash_blake3(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
+
+/// Generates a CRC-32 checksum of the file at `path`, reading it in blocks of
+/// `HASH_BUFFER_SIZE` bytes instead of all at once
+///
+/// # Arguments
+/// * `path`: path of the file to hash
+///
+/// # Returns
+/// * Ok: The checksum of the file
+/// * Err: If the file cannot be read
+fn hash_crc32(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    let mut hasher = crc32fast::Hasher::new();
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => hasher.update(&buffer[..len]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize().to_be_bytes().to_vec())
+}
+
+/// Recursively traverses a directory and all its subdirectories and returns
+/// a FileSets that contains all files and all directories
+///
+/// # Arguments
+/// * `src`: directory to traverse
+///
+/// # Returns
+/// * Ok: A `FileSets` containing a set of files a set of directories
+/// * Error: If `src` is an invalid directory
+pub fn get_all_files(src: &str) -> Result<FileSets, io::Error> {
+    get_all_files_with(src, &DefaultClassifier)
+}
+
+/// Recursively traverses a directory and all its subdirectories and returns
+/// a FileSets that contains all paths, as categorized by `classifier`
+///
+/// # Arguments
+/// * `src`: directory to traverse
+/// * `classifier`: decides the category of each path
+///
+/// # Returns
+/// * Ok: A `FileSets` containing a set of files a set of directories
+/// * Error: If `src` is an invalid directory
+pub fn get_all_files_with<C>(src: &str, classifier: &C) -> Result<FileSets, io::Error>
+where
+    C: Classifier,
+{
+    get_all_files_helper(&PathBuf::from(&src), src, classifier)
+}
+
+/// Builds a FileSets from the given paths in `src`, instead of traversing all of `src`
+///
+/// Directories in `paths` are traversed recursively, and the parent directories of
+/// all paths are included so that they can be created in the destination
+///
+/// # Arguments
+/// * `src`: base directory of the paths
+/// * `paths`: paths relative to `src`
+/// * `classifier`: decides the category of each path
+///
+/// # Returns
+/// * Ok: A `FileSets` containing the given files, directories, and symlinks
+/// * Error: If `src` is an invalid directory
+pub fn get_listed_files<C>(
+    src: &str,
+    paths: &[PathBuf],
+    classifier: &C,
+) -> Result<FileSets, io::Error>
+where
+    C: Classifier,
+{
+    let base = PathBuf::from(&src);
+    base.read_dir()?;
+
+    let mut file_sets = FileSets::with(HashSet::new(), HashSet::new(), HashSet::new());
+
+    for path in paths {
+        let full_path = base.join(path);
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                error!("Error -- Reading metadata of {:?} {}", full_path, e);
+                // Listed paths that don't exist have nothing to copy, so they don't fail the sync
+                if e.kind() != io::ErrorKind::NotFound {
+                    stats::failure_record(&full_path, e);
+                }
+                continue;
+            }
+        };
+
+        // Include all parent directories of the path
+        for parent in path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() {
+                break;
+            }
+            file_sets.dirs.insert(Dir {
+                path: parent.to_path_buf(),
+            });
+        }
+
+        insert_file(&mut file_sets, &full_path, path, &metadata, src, classifier);
+    }
+
+    Ok(file_sets)
+}
+
+/// Recursive helper for `get_all_files`
+///
+/// # Arguments
+/// * `src`: directory to traverse
+/// * `base`: directory to traverse, used for recursive calls
+/// * `classifier`: decides the category of each path
+///
+/// # Returns
+/// * Ok: A `FileSets` containing a set of files a set of directories
+/// * Error: If `src` is an invalid directory
+fn get_all_files_helper<C>(src: &PathBuf, base: &str, classifier: &C) -> Result<FileSets, io::Error>
+where
+    C: Classifier,
+{
+    let dir = src.read_dir()?;
+
+    // Classify everything inside `src` with the classifier scoped to it, if there is one.
+    // This is safe to unwrap, since `src` is always `base` or one of its subdirectories
+    let scoped_classifier = classifier.enter(src.strip_prefix(base).unwrap());
+    let classifier = scoped_classifier.as_ref().unwrap_or(classifier);
+
+    // Entries, and the subdirectories among them, are handled in parallel,
+    // with each thread inserting into its own sets before they are merged
+    let entries: Vec<io::Result<fs::DirEntry>> = dir.collect();
+    let file_sets = entries
+        .into_par_iter()
+        .fold(FileSets::default, |mut file_sets, file| {
+            let file = match file {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("{}", e);
+                    return file_sets;
+                }
+            };
+
+            let metadata = match file.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    error!("Error -- Reading metadata of {:?} {}", file.path(), e);
+                    stats::failure_record(&file.path(), e);
+                    return file_sets;
+                }
+            };
+
+            let path = file.path();
+            // This is safe to unwrap, since `get_all_files` always calls this helper
+            // with `base` equal to `src`
+            let relative_path = path.strip_prefix(base).unwrap();
+
+            insert_file(
+                &mut file_sets,
+                &path,
+                relative_path,
+                &metadata,
+                base,
+                classifier,
+            );
+            file_sets
+        })
+        .reduce(FileSets::default, |mut file_sets, other| {
+            file_sets.extend(other);
+            file_sets
+        });
+
+    Ok(file_sets)
+}
+
+/// Classifies a single path and inserts it into `file_sets`, recursing into directories
+///
+/// # Arguments
+/// * `file_sets`: sets to insert into
+/// * `path`: path of the file
+/// * `relative_path`: path of the file relative to `base`
+/// * `metadata`: metadata of the file, not following symlinks
+/// * `base`: directory being traversed
+/// * `classifier`: decides the category of each path
+fn insert_file<C>(
+    file_sets: &mut FileSets,
+    path: &Path,
+    relative_path: &Path,
+    metadata: &fs::Metadata,
+    base: &str,
+    classifier: &C,
+) where
+    C: Classifier,
+{
+    // Classify symlinks as what they link to if they should be followed
+    let target_metadata;
+    let metadata = if metadata.file_type().is_symlink() && classifier.follow_symlinks() {
+        match follow_symlink(path, relative_path, base) {
+            Ok(metadata) => {
+                target_metadata = metadata;
+                &target_metadata
+            }
+            Err(e) => {
+                error!("Error -- Following symlink {:?}: {}", path, e);
+                stats::failure_record(path, e);
+                return;
+            }
+        }
+    } else {
+        metadata
+    };
+
+    match classifier.classify(relative_path, metadata) {
+        Classification::Dir => {
+            file_sets.dirs.insert(Dir {
+                path: relative_path.to_path_buf(),
+            });
+
+            // Recursively call `get_all_files_helper` on the subdirectory
+            match get_all_files_helper(&path.to_path_buf(), base, classifier) {
+                // Add subdirectory subdirectories and files to sets
+                Ok(sub_file_sets) => file_sets.extend(sub_file_sets),
+                Err(e) => {
+                    error!("Error - Retrieving files: {}", e);
+                    stats::failure_record(path, e);
+                }
+            }
+        }
+        Classification::File => {
+            file_sets.files.insert(File {
+                path: relative_path.to_path_buf(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                hard_link: link_id(metadata),
+            });
+        }
+        Classification::PassThrough => {
+            match get_all_files_helper(&path.to_path_buf(), base, classifier) {
+                Ok(sub_file_sets) => {
+                    // Only keep the directory if anything inside it was kept
+                    if !sub_file_sets.is_empty() {
+                        file_sets.dirs.insert(Dir {
+                            path: relative_path.to_path_buf(),
+                        });
+                        file_sets.extend(sub_file_sets);
+                    }
+                }
+                Err(e) => {
+                    error!("Error - Retrieving files: {}", e);
+                    stats::failure_record(path, e);
+                }
+            }
+        }
+        Classification::EmptyDir => {
+            file_sets.dirs.insert(Dir {
+                path: relative_path.to_path_buf(),
+            });
+        }
+        Classification::Symlink => match fs::read_link(path) {
+            Ok(target) => {
+                file_sets.symlinks.insert(Symlink {
+                    path: relative_path.to_path_buf(),
+                    target,
+                });
+            }
+            Err(e) => {
+                error!("Error - Reading symlink: {}", e);
+                stats::failure_record(path, e);
+            }
+        },
+        Classification::Other => (),
+    }
+}
+
+/// Reads the metadata of what the symlink `path` links to
+///
+/// # Arguments
+/// * `path`: path of the symlink
+/// * `relative_path`: path of the symlink relative to `base`
+/// * `base`: directory being traversed
+///
+/// # Returns
+/// * Ok: The metadata of the file or directory that `path` links to
+/// * Error: If `path` links to nothing, or to a directory that it is in
+fn follow_symlink(
+    path: &Path,
+    relative_path: &Path,
+    base: &str,
+) -> Result<fs::Metadata, io::Error> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata);
+    }
+
+    // `path` is only reached by traversing the directories that its parents resolve to,
+    // so linking to any of them, or to a directory containing them, is a loop
+    let target = fs::canonicalize(path)?;
+    for parent in relative_path.ancestors().skip(1) {
+        if fs::canonicalize(Path::new(base).join(parent))?.starts_with(&target) {
+            return Err(io::Error::other(format!("Symlink loop to {:?}", target)));
+        }
+    }
+
+    Ok(metadata)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_file_ops {
+    use super::*;
+
+    #[test]
+    fn create_dir() {
+        assert_eq!(
+            Dir::from("."),
+            Dir {
+                path: PathBuf::from("."),
+            }
+        )
+    }
+
+    #[test]
+    fn create_file() {
+        assert_eq!(
+            File::from(".", 10),
+            File {
+                path: PathBuf::from("."),
+                size: 10,
+                modified: None,
+                hard_link: None,
+            }
+        )
+    }
+
+    #[test]
+    fn create_symlink() {
+        assert_eq!(
+            Symlink::from(".", "file"),
+            Symlink {
+                path: PathBuf::from("."),
+                target: PathBuf::from("file"),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_get_all_files {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn invalid_dir() {
+        assert_eq!(get_all_files("/?").is_err(), true);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn dir_insufficient_permissions() {
+        assert_eq!(get_all_files("/root").is_err(), true);
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        const TEST_DIR: &str = "test_get_all_files_same_as_sequential";
+
+        for i in 0..8 {
+            let dir = format!("{}/{}/{}", TEST_DIR, i, i * 2);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(format!("{}/file.txt", dir), vec![0; i]).unwrap();
+            fs::write(format!("{}/{}/file.txt", TEST_DIR, i), b"").unwrap();
+        }
+
+        let sequential = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| get_all_files(TEST_DIR).unwrap());
+        let parallel = get_all_files(TEST_DIR).unwrap();
+
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel.files().len(), 16);
+        assert_eq!(parallel.dirs().len(), 16);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn empty_dir() {
+        const TEST_DIR: &str = "test_get_all_files_empty_dir";
+
+        fs::create_dir(TEST_DIR).unwrap();
+
+        let file_sets = get_all_files(TEST_DIR).unwrap();
+
+        assert_eq!(file_sets.files(), &HashSet::new());
+        assert_eq!(file_sets.dirs(), &HashSet::new());
+
+        fs::remove_dir(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn single_dir() {
+        const TEST_DIR: &str = "test_get_all_files_single_dir";
+        const TEST_SUB_DIR: &str = "test";
+
+        fs::create_dir_all([TEST_DIR, TEST_SUB_DIR].join("/")).unwrap();
+
+        let file_sets = get_all_files(&TEST_DIR).unwrap();
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir {
+            path: PathBuf::from(&TEST_SUB_DIR),
+        });
+
+        assert_eq!(file_sets.files(), &HashSet::new());
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        fs::remove_dir_all(&TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn single_file() {
+        const TEST_DIR: &str = "test_get_all_files_single_file";
+        const TEST_FILE: &str = "file.txt";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        fs::File::create([TEST_DIR, TEST_FILE].join("/")).unwrap();
+        fs::write([TEST_DIR, TEST_FILE].join("/"), b"1234").unwrap();
+
+        let file_sets = get_all_files(TEST_DIR).unwrap();
+        let mut file_set = HashSet::new();
+        file_set.insert(File {
+            path: PathBuf::from(TEST_FILE),
+            size: 4,
+            modified: None,
+            hard_link: None,
+        });
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &HashSet::new());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn single_symlink() {
+        use std::os::unix::fs::symlink;
+        const TEST_DIR: &str = "test_get_all_files_single_symlink";
+        const TEST_LINK: &str = "test_get_all_files_single_symlink/file";
+        const TEST_FILE: &str = "test_get_all_files_single_symlink/test.txt";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        symlink(TEST_FILE, TEST_LINK).unwrap();
+
+        let mut symlink_set = HashSet::new();
+        symlink_set.insert(Symlink {
+            path: PathBuf::from("file"),
+            target: PathBuf::from(TEST_FILE),
+        });
+
+        let file_sets = get_all_files(TEST_DIR).unwrap();
+
+        assert_eq!(
+            file_sets,
+            FileSets {
+                files: HashSet::new(),
+                dirs: HashSet::new(),
+                symlinks: symlink_set,
+            }
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn multi_level() {
+        const TEST_DIR: &str = "test_get_all_files_multi_level";
+        const SUB_DIRS: [&str; 2] = ["dir1", "dir1/dir2"];
+        const TEST_FILES: [&str; 3] = ["file.txt", "dir1/file.txt", "dir1/dir2/file2.txt"];
+        const TEST_DATA: [&[u8]; 3] = [b"1", b"", b"1234567890"];
+
+        fs::create_dir_all([TEST_DIR, SUB_DIRS[1]].join("/")).unwrap();
+
+        for i in 0..TEST_FILES.len() {
+            let path = [TEST_DIR, TEST_FILES[i]].join("/");
+            fs::File::create(&path).unwrap();
+            fs::write(&path, TEST_DATA[i]).unwrap();
+        }
+
+        let file_sets = get_all_files(TEST_DIR).unwrap();
+        let mut file_set = HashSet::new();
+        let mut dir_set = HashSet::new();
+
+        for i in 0..TEST_FILES.len() {
+            file_set.insert(File {
+                path: PathBuf::from(TEST_FILES[i]),
+                size: TEST_DATA[i].len() as u64,
+                modified: None,
+                hard_link: None,
+            });
+        }
+
+        for i in 0..SUB_DIRS.len() {
+            dir_set.insert(Dir {
+                path: PathBuf::from(SUB_DIRS[i]),
+            });
+        }
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn multi_level_insufficient_permissions() {
+        const TEST_DIR: &str = "test_get_all_files_multi_level_insufficient_permissions";
+        const SUB_DIR: &str = "dir";
+        const TEST_FILE: &str = "file.txt";
+
+        let file_path = [TEST_DIR, TEST_FILE].join("/");
+        let dir_path = [TEST_DIR, SUB_DIR].join("/");
+
+        fs::create_dir_all(&dir_path).unwrap();
+        fs::File::create(&file_path).unwrap();
+
+        Command::new("chmod")
+            .args(&["000", &file_path])
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .args(&["000", &dir_path])
+            .output()
+            .unwrap();
+
+        let file_sets = get_all_files(TEST_DIR).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File {
+            path: PathBuf::from(&TEST_FILE),
+            size: 0,
+            modified: None,
+            hard_link: None,
+        });
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir {
+            path: PathBuf::from(&SUB_DIR),
+        });
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        Command::new("chmod")
+            .arg("777")
+            .args(&["777", &dir_path])
+            .output()
+            .unwrap();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_get_all_files_with {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Example classifier that takes over `.gpg` files so that they can be copied in a custom way
+    struct GpgClassifier {
+        gpg_files: Mutex<Vec<GpgFile>>,
+    }
+
+    impl Classifier for GpgClassifier {
+        fn classify(&self, path: &Path, metadata: &fs::Metadata) -> Classification {
+            if path.extension() == Some("gpg".as_ref()) {
+                self.gpg_files.lock().unwrap().push(GpgFile {
+                    path: path.to_path_buf(),
+                });
+                return Classification::Other;
+            }
+            DefaultClassifier.classify(path, metadata)
+        }
+    }
+
+    /// Example file that is "decrypted" when copied, by reversing its contents
+    /// and removing its `.gpg` extension
+    struct GpgFile {
+        path: PathBuf,
+    }
+
+    impl FileOps for GpgFile {
+        fn path(&self) -> &PathBuf {
+            &self.path
+        }
+        fn remove(&self, path: &PathBuf, _flags: Flag, _options: &Options) -> SyncStats {
+            fs::remove_file(path).unwrap();
+            SyncStats {
+                files_deleted: 1,
+                ..SyncStats::default()
+            }
+        }
+        fn copy(
+            &self,
+            src: &PathBuf,
+            dest: &PathBuf,
+            _flags: Flag,
+            _options: &Options,
+        ) -> SyncStats {
+            let mut contents = fs::read(src).unwrap();
+            contents.reverse();
+            fs::write(dest.with_extension(""), contents).unwrap();
+            SyncStats {
+                files_copied: 1,
+                ..SyncStats::default()
+            }
+        }
+    }
+
+    #[test]
+    fn default_classifier() {
+        assert_eq!(
+            get_all_files_with("src", &DefaultClassifier).unwrap(),
+            get_all_files("src").unwrap()
+        );
+    }
+
+    #[test]
+    fn custom_classifier() {
+        const TEST_DIR: &str = "test_get_all_files_with_custom_classifier";
+        const TEST_DIR_OUT: &str = "test_get_all_files_with_custom_classifier_out";
+
+        fs::create_dir_all([TEST_DIR, "a"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR_OUT, "a"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a/file.txt"].join("/"), b"plain").unwrap();
+        fs::write([TEST_DIR, "a/secret.txt.gpg"].join("/"), b"terces").unwrap();
+
+        let classifier = GpgClassifier {
+            gpg_files: Mutex::new(Vec::new()),
+        };
+        let file_sets = get_all_files_with(TEST_DIR, &classifier).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File::from("a/file.txt", 5));
+
+        assert_eq!(file_sets.files(), &file_set);
+
+        let gpg_files = classifier.gpg_files.lock().unwrap();
+        copy_files(
+            gpg_files.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            fs::read([TEST_DIR_OUT, "a/secret.txt"].join("/")).unwrap(),
+            b"secret"
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn one_file_system_same_device() {
+        assert_eq!(
+            get_all_files_with(
+                "src",
+                &OneFileSystem::new("src", DefaultClassifier).unwrap()
+            )
+            .unwrap(),
+            get_all_files("src").unwrap()
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn one_file_system_other_device() {
+        const TEST_DIR: &str = "test_get_all_files_with_one_file_system_other_device";
+
+        fs::create_dir_all([TEST_DIR, "mount/a"].join("/")).unwrap();
+        fs::write([TEST_DIR, "file.txt"].join("/"), b"file").unwrap();
+        fs::write([TEST_DIR, "mount/a/file.txt"].join("/"), b"file").unwrap();
+
+        // Pretend that the traversed directory is on a device that nothing inside it is on
+        let classifier = OneFileSystem {
+            classifier: DefaultClassifier,
+            device: Some(u64::MAX),
+        };
+        let file_sets = get_all_files_with(TEST_DIR, &classifier).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File::from("file.txt", 4));
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir::from("mount"));
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn follow_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        const TEST_DIR: &str = "test_get_all_files_with_follow_symlinks";
+
+        fs::create_dir_all([TEST_DIR, "a"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a/file.txt"].join("/"), b"file").unwrap();
+        symlink("a/file.txt", [TEST_DIR, "file_link"].join("/")).unwrap();
+        symlink("a", [TEST_DIR, "dir_link"].join("/")).unwrap();
+        symlink("..", [TEST_DIR, "a/loop"].join("/")).unwrap();
+        symlink("self", [TEST_DIR, "self"].join("/")).unwrap();
+
+        let file_sets = get_all_files_with(TEST_DIR, &FollowSymlinks(DefaultClassifier)).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File::from("a/file.txt", 4));
+        file_set.insert(File::from("file_link", 4));
+        file_set.insert(File::from("dir_link/file.txt", 4));
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir::from("a"));
+        dir_set.insert(Dir::from("dir_link"));
+
+        // The loops are left out instead of being traversed forever
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+        assert!(file_sets.symlinks().is_empty());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_get_listed_files {
+    use super::*;
+
+    #[test]
+    fn invalid_dir() {
+        assert!(get_listed_files("/?", &[], &DefaultClassifier).is_err());
+    }
+
+    #[test]
+    fn missing_file() {
+        let file_sets =
+            get_listed_files("src", &[PathBuf::from("missing.rs")], &DefaultClassifier).unwrap();
+
+        assert_eq!(file_sets.files(), &HashSet::new());
+        assert_eq!(file_sets.dirs(), &HashSet::new());
+        assert_eq!(file_sets.symlinks(), &HashSet::new());
+    }
+
+    #[test]
+    fn nested_file() {
+        const TEST_DIR: &str = "test_get_listed_files_nested_file";
+        const TEST_FILE: &str = "a/b/file.txt";
+
+        fs::create_dir_all([TEST_DIR, "a/b"].join("/")).unwrap();
+        fs::write([TEST_DIR, TEST_FILE].join("/"), b"1234").unwrap();
+        fs::write([TEST_DIR, "a/other.txt"].join("/"), b"1234").unwrap();
+
+        let file_sets =
+            get_listed_files(TEST_DIR, &[PathBuf::from(TEST_FILE)], &DefaultClassifier).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File::from(TEST_FILE, 4));
+
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir::from("a"));
+        dir_set.insert(Dir::from("a/b"));
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn dir() {
+        const TEST_DIR: &str = "test_get_listed_files_dir";
+
+        fs::create_dir_all([TEST_DIR, "a/b"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a/b/file.txt"].join("/"), b"1234").unwrap();
+        fs::write([TEST_DIR, "other.txt"].join("/"), b"1234").unwrap();
+
+        let file_sets =
+            get_listed_files(TEST_DIR, &[PathBuf::from("a")], &DefaultClassifier).unwrap();
+
+        let mut file_set = HashSet::new();
+        file_set.insert(File::from("a/b/file.txt", 4));
+
+        let mut dir_set = HashSet::new();
+        dir_set.insert(Dir::from("a"));
+        dir_set.insert(Dir::from("a/b"));
+
+        assert_eq!(file_sets.files(), &file_set);
+        assert_eq!(file_sets.dirs(), &dir_set);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn symlink() {
+        const TEST_DIR: &str = "test_get_listed_files_symlink";
+
+        fs::create_dir_all([TEST_DIR, "a"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a/file.txt"].join("/"), b"1234").unwrap();
+        std::os::unix::fs::symlink("a", [TEST_DIR, "link"].join("/")).unwrap();
+
+        let file_sets =
+            get_listed_files(TEST_DIR, &[PathBuf::from("link")], &DefaultClassifier).unwrap();
+
+        let mut symlink_set = HashSet::new();
+        symlink_set.insert(Symlink::from("link", "a"));
+
+        // Listed symlinks are kept as symlinks, without listing what they link to
+        assert_eq!(file_sets.files(), &HashSet::new());
+        assert_eq!(file_sets.dirs(), &HashSet::new());
+        assert_eq!(file_sets.symlinks(), &symlink_set);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_file_sets {
+    use super::*;
+
+    #[test]
+    fn prune_empty_dirs() {
+        let files: HashSet<File> = vec![File::from("a/b/c.txt", 1)].into_iter().collect();
+        let symlinks: HashSet<Symlink> = vec![Symlink::from("d/link", "target")]
+            .into_iter()
+            .collect();
+        let dirs: HashSet<Dir> = vec!["a", "a/b", "a/e", "d", "f", "f/g"]
+            .into_iter()
+            .map(Dir::from)
+            .collect();
+        let mut file_sets = FileSets::with(files, dirs, symlinks);
+
+        let pruned = file_sets.prune_empty_dirs();
+
+        let expected_kept: HashSet<Dir> =
+            vec!["a", "a/b", "d"].into_iter().map(Dir::from).collect();
+        let expected_pruned: HashSet<Dir> =
+            vec!["a/e", "f", "f/g"].into_iter().map(Dir::from).collect();
+        assert_eq!(file_sets.dirs(), &expected_kept);
+        assert_eq!(pruned, expected_pruned);
+    }
+}
+
+#[cfg(test)]
+mod test_sort_files {
+    use super::*;
+
+    #[test]
+    fn no_dir() {
+        let no_dir: HashSet<Dir> = HashSet::new();
+        assert_eq!(sort_files(no_dir.par_iter()), Vec::<&Dir>::new());
+    }
+
+    #[test]
+    fn single_dir() {
+        let mut single_dir: HashSet<Dir> = HashSet::new();
+        let dir = Dir {
+            path: PathBuf::from("/"),
+        };
+        single_dir.insert(dir.clone());
+        let expected: Vec<&Dir> = vec![&dir];
+
+        assert_eq!(sort_files(single_dir.par_iter()), expected);
+    }
+
+    #[test]
+    fn multi_dir_unique() {
+        let mut multi_dir: HashSet<Dir> = HashSet::new();
+        let dir1 = Dir {
+            path: PathBuf::from("/"),
+        };
+        let dir2 = Dir {
+            path: PathBuf::from("/a"),
+        };
+        let dir3 = Dir {
+            path: PathBuf::from("/a/b"),
+        };
+        multi_dir.insert(dir1.clone());
+        multi_dir.insert(dir2.clone());
+        multi_dir.insert(dir3.clone());
+        let expected: Vec<&Dir> = vec![&dir3, &dir2, &dir1];
+
+        assert_eq!(sort_files(multi_dir.par_iter()), expected);
+    }
+
+    #[test]
+    fn multi_dir() {
+        let mut multi_dir: HashSet<Dir> = HashSet::new();
+        let dir1 = Dir {
             path: PathBuf::from("/"),
         };
-        let dir2 = Dir {
-            path: PathBuf::from("/a"),
+        let dir2 = Dir {
+            path: PathBuf::from("/a/c"),
+        };
+        let dir3 = Dir {
+            path: PathBuf::from("/a/b"),
+        };
+        multi_dir.insert(dir1.clone());
+        multi_dir.insert(dir2.clone());
+        multi_dir.insert(dir3.clone());
+        let expected: Vec<&Dir> = vec![&dir2, &dir3, &dir1];
+
+        assert_eq!(
+            sort_files(multi_dir.par_iter()).get(2).unwrap(),
+            &expected[2]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_hash_file {
+    use super::*;
+
+    const HASH_ALGOS: [HashAlgo; 6] = [
+        HashAlgo::Seahash,
+        HashAlgo::Blake2,
+        HashAlgo::Sha256,
+        HashAlgo::Xxhash,
+        HashAlgo::Blake3,
+        HashAlgo::Crc32,
+    ];
+
+    #[test]
+    fn invalid_file() {
+        for &algo in &HASH_ALGOS {
+            assert_eq!(
+                hash_file(
+                    &File {
+                        path: PathBuf::from("test"),
+                        size: 0,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    ".",
+                    Flag::empty(),
+                    algo,
+                    0
+                ),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn streamed() {
+        const TEST_FILE: &str = "test_hash_file_streamed.txt";
+
+        // Spans several reads and ends partway through both a read and an 8 byte word
+        let contents: Vec<u8> = (0..2 * HASH_BUFFER_SIZE + 13)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(TEST_FILE, &contents).unwrap();
+
+        for &algo in &HASH_ALGOS {
+            assert_eq!(
+                hash_file(
+                    &File {
+                        path: PathBuf::from(TEST_FILE),
+                        size: contents.len() as u64,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    ".",
+                    Flag::empty(),
+                    algo,
+                    0
+                ),
+                Some(hash_bytes(&contents, algo))
+            );
+        }
+
+        fs::remove_file(TEST_FILE).unwrap();
+    }
+
+    #[test]
+    fn crc32() {
+        const TEST_FILE: &str = "test_hash_file_crc32.txt";
+
+        fs::write(TEST_FILE, "123456789").unwrap();
+
+        assert_eq!(
+            hash_path(Path::new(TEST_FILE), Flag::empty(), HashAlgo::Crc32).unwrap(),
+            vec![0xcb, 0xf4, 0x39, 0x26]
+        );
+
+        fs::remove_file(TEST_FILE).unwrap();
+    }
+
+    #[test]
+    fn mapped() {
+        const TEST_FILE: &str = "test_hash_file_mapped.txt";
+        const TEST_EMPTY_FILE: &str = "test_hash_file_mapped_empty.txt";
+
+        let contents: Vec<u8> = (0..HASH_BUFFER_SIZE + 13)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(TEST_FILE, &contents).unwrap();
+        fs::File::create(TEST_EMPTY_FILE).unwrap();
+
+        for &algo in &HASH_ALGOS {
+            assert_eq!(
+                hash_mapped(Path::new(TEST_FILE), algo),
+                Some(hash_bytes(&contents, algo))
+            );
+            assert_eq!(
+                hash_mapped(Path::new(TEST_EMPTY_FILE), algo),
+                Some(hash_bytes(&[], algo))
+            );
+        }
+        assert_eq!(hash_mapped(Path::new("test"), HashAlgo::Seahash), None);
+
+        fs::remove_file(TEST_FILE).unwrap();
+        fs::remove_file(TEST_EMPTY_FILE).unwrap();
+    }
+
+    #[test]
+    fn empty_file() {
+        const TEST_FILE1: &str = "test_hash_file_empty_file1.txt";
+        const TEST_FILE2: &str = "test_hash_file_empty_file2.txt";
+
+        fs::File::create(TEST_FILE1).unwrap();
+        fs::File::create(TEST_FILE2).unwrap();
+
+        for &algo in &HASH_ALGOS {
+            assert_eq!(
+                hash_file(
+                    &File {
+                        path: PathBuf::from(TEST_FILE1),
+                        size: 0,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    ".",
+                    Flag::empty(),
+                    algo,
+                    0
+                ),
+                hash_file(
+                    &File {
+                        path: PathBuf::from(TEST_FILE2),
+                        size: 0,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    ".",
+                    Flag::empty(),
+                    algo,
+                    0
+                )
+            );
+        }
+
+        fs::remove_file(TEST_FILE1).unwrap();
+        fs::remove_file(TEST_FILE2).unwrap();
+    }
+
+    #[test]
+    fn equal_files() {
+        const TEST_DIR: &str = "test_hash_file_equal_files";
+        const TEST_FILE1: &str = "file1.txt";
+        const TEST_FILE2: &str = "file2.txt";
+
+        let path1 = [TEST_DIR, TEST_FILE1].join("/");
+        let path2 = [TEST_DIR, TEST_FILE2].join("/");
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::File::create(&path1).unwrap();
+        fs::File::create(&path2).unwrap();
+        fs::write(path1, b"1234567890").unwrap();
+        fs::write(path2, b"1234567890").unwrap();
+
+        for &algo in &HASH_ALGOS {
+            let hash = hash_file(
+                &File {
+                    path: PathBuf::from(TEST_FILE1),
+                    size: 10,
+                    modified: None,
+                    hard_link: None,
+                },
+                TEST_DIR,
+                Flag::empty(),
+                algo,
+                0,
+            );
+
+            assert!(hash.is_some());
+            assert_eq!(
+                hash,
+                hash_file(
+                    &File {
+                        path: PathBuf::from(TEST_FILE2),
+                        size: 10,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    TEST_DIR,
+                    Flag::empty(),
+                    algo,
+                    0
+                )
+            );
+        }
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn different_files() {
+        for &algo in &HASH_ALGOS {
+            assert_ne!(
+                hash_file(
+                    &File {
+                        path: PathBuf::from("lumins/file_ops.rs"),
+                        size: 0,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    "src",
+                    Flag::empty(),
+                    algo,
+                    0
+                ),
+                hash_file(
+                    &File {
+                        path: PathBuf::from("main.rs"),
+                        size: 0,
+                        modified: None,
+                        hard_link: None,
+                    },
+                    "src",
+                    Flag::empty(),
+                    algo,
+                    0
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn sha256() {
+        const TEST_FILE: &str = "test_hash_file_sha256.txt";
+
+        fs::write(TEST_FILE, b"abc").unwrap();
+
+        let hash = hash_file(
+            &File {
+                path: PathBuf::from(TEST_FILE),
+                size: 3,
+                modified: None,
+                hard_link: None,
+            },
+            ".",
+            Flag::empty(),
+            HashAlgo::Sha256,
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(
+            hash.iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        fs::remove_file(TEST_FILE).unwrap();
+    }
+
+    #[test]
+    fn partial() {
+        const TEST_DIR: &str = "test_hash_file_partial";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"head middle tail").unwrap();
+        fs::write([TEST_DIR, "b.txt"].join("/"), b"head MIDDLE tail").unwrap();
+        fs::write([TEST_DIR, "c.txt"].join("/"), b"head middle TAIL").unwrap();
+
+        let hash = |name: &str, size: u64, algo: HashAlgo| {
+            hash_file_partial(&File::from(name, 16), TEST_DIR, algo, size, 0)
+        };
+
+        for &algo in &HASH_ALGOS {
+            // Only the first and last 4 bytes are hashed
+            assert_eq!(hash("a.txt", 4, algo), hash("b.txt", 4, algo));
+            assert_ne!(hash("a.txt", 4, algo), hash("c.txt", 4, algo));
+            // The whole file is hashed if it is not larger than both ends
+            assert_ne!(hash("a.txt", 8, algo), hash("b.txt", 8, algo));
+            assert!(hash("a.txt", 4, algo).is_some());
+            assert_eq!(hash("missing.txt", 4, algo), None);
+        }
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_delete_files {
+    use super::*;
+
+    #[test]
+    fn delete_no_files() {
+        const TEST_DIR: &str = "test_delete_files_delete_no_files";
+        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        let files_to_delete: HashSet<File> = HashSet::new();
+        let files_to_delete_sequential: Vec<&File> = Vec::new();
+        let mut file_set = HashSet::new();
+
+        for i in 0..TEST_FILES.len() {
+            fs::File::create([TEST_DIR, TEST_FILES[i]].join("/")).unwrap();
+            let file = File {
+                path: PathBuf::from(TEST_FILES[i]),
+                size: 0,
+                modified: None,
+                hard_link: None,
+            };
+            file_set.insert(file);
+        }
+
+        delete_files(
+            files_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            files_to_delete_sequential,
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            get_all_files(TEST_DIR).unwrap(),
+            FileSets {
+                files: file_set,
+                dirs: HashSet::new(),
+                symlinks: HashSet::new(),
+            }
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn delete_invalid_file_and_link() {
+        use std::os::unix::fs::symlink;
+
+        const TEST_DIR: &str = "test_delete_files_delete_invalid_file_and_link";
+        const TEST_DIR_SEQ: &str = "test_delete_files_delete_invalid_file_and_link_seq";
+        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_SEQ).unwrap();
+
+        let mut files_to_delete: HashSet<File> = HashSet::new();
+        let mut files_to_delete_sequential: Vec<&File> = Vec::new();
+        let mut file_set = HashSet::new();
+
+        fs::File::create([TEST_DIR, TEST_FILES[0]].join("/")).unwrap();
+        fs::File::create([TEST_DIR_SEQ, TEST_FILES[0]].join("/")).unwrap();
+        let file = File {
+            path: PathBuf::from([TEST_FILES[0], "a"].join("/")),
+            size: 0,
+            modified: None,
+            hard_link: None,
         };
-        let dir3 = Dir {
-            path: PathBuf::from("/a/b"),
+        let expected_file = File {
+            path: PathBuf::from(TEST_FILES[0]),
+            size: 0,
+            modified: None,
+            hard_link: None,
         };
-        multi_dir.insert(dir1.clone());
-        multi_dir.insert(dir2.clone());
-        multi_dir.insert(dir3.clone());
-        let expected: Vec<&Dir> = vec![&dir3, &dir2, &dir1];
+        file_set.insert(expected_file);
+        files_to_delete.insert(file.clone());
+        files_to_delete_sequential.push(&file);
 
-        assert_eq!(sort_files(multi_dir.par_iter()), expected);
+        let mut links_to_delete: HashSet<Symlink> = HashSet::new();
+        let mut links_to_delete_sequential: Vec<&Symlink> = Vec::new();
+        let mut link_set = HashSet::new();
+
+        symlink(TEST_FILES[1], [TEST_DIR, "file"].join("/")).unwrap();
+        symlink(TEST_FILES[1], [TEST_DIR_SEQ, "file"].join("/")).unwrap();
+        let link = Symlink {
+            path: PathBuf::from("filea"),
+            target: PathBuf::from(TEST_FILES[1]),
+        };
+        let expected_link = Symlink {
+            path: PathBuf::from("file"),
+            target: PathBuf::from(TEST_FILES[1]),
+        };
+        link_set.insert(expected_link);
+        links_to_delete.insert(link.clone());
+        links_to_delete_sequential.push(&link);
+
+        delete_files(
+            files_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            files_to_delete_sequential,
+            TEST_DIR_SEQ,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files(
+            links_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            links_to_delete_sequential,
+            TEST_DIR_SEQ,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            get_all_files(TEST_DIR).unwrap(),
+            FileSets {
+                files: file_set.clone(),
+                dirs: HashSet::new(),
+                symlinks: link_set.clone(),
+            }
+        );
+        assert_eq!(
+            get_all_files(TEST_DIR_SEQ).unwrap(),
+            FileSets {
+                files: file_set,
+                dirs: HashSet::new(),
+                symlinks: link_set,
+            }
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
     }
 
+    #[cfg(target_family = "unix")]
     #[test]
-    fn multi_dir() {
-        let mut multi_dir: HashSet<Dir> = HashSet::new();
-        let dir1 = Dir {
-            path: PathBuf::from("/"),
+    fn delete_file_and_link() {
+        use std::os::unix::fs::symlink;
+
+        const TEST_DIR: &str = "test_delete_files_delete_file_and_link";
+        const TEST_DIR_SEQ: &str = "test_delete_files_delete_file_and_link_seq";
+        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_SEQ).unwrap();
+
+        let mut files_to_delete: HashSet<File> = HashSet::new();
+        let mut files_to_delete_sequential: Vec<&File> = Vec::new();
+        let mut file_set = HashSet::new();
+
+        fs::File::create([TEST_DIR, TEST_FILES[0]].join("/")).unwrap();
+        fs::File::create([TEST_DIR_SEQ, TEST_FILES[0]].join("/")).unwrap();
+        let file = File {
+            path: PathBuf::from(TEST_FILES[0]),
+            size: 0,
+            modified: None,
+            hard_link: None,
+        };
+        file_set.insert(file.clone());
+        files_to_delete.insert(file.clone());
+        files_to_delete_sequential.push(&file);
+
+        let mut links_to_delete: HashSet<Symlink> = HashSet::new();
+        let mut links_to_delete_sequential: Vec<&Symlink> = Vec::new();
+        let mut link_set = HashSet::new();
+
+        symlink(TEST_FILES[1], [TEST_DIR, "file"].join("/")).unwrap();
+        symlink(TEST_FILES[1], [TEST_DIR_SEQ, "file"].join("/")).unwrap();
+        let link = Symlink {
+            path: PathBuf::from("file"),
+            target: PathBuf::from(TEST_FILES[1]),
+        };
+        link_set.insert(link.clone());
+        links_to_delete.insert(link.clone());
+        links_to_delete_sequential.push(&link);
+
+        delete_files(
+            files_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            files_to_delete_sequential,
+            TEST_DIR_SEQ,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files(
+            links_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            links_to_delete_sequential,
+            TEST_DIR_SEQ,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            get_all_files(TEST_DIR).unwrap(),
+            FileSets {
+                files: HashSet::new(),
+                dirs: HashSet::new(),
+                symlinks: HashSet::new(),
+            }
+        );
+        assert_eq!(
+            get_all_files(TEST_DIR_SEQ).unwrap(),
+            FileSets {
+                files: HashSet::new(),
+                dirs: HashSet::new(),
+                symlinks: HashSet::new(),
+            }
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
+    }
+
+    #[test]
+    fn delete_partial_dirs() {
+        const TEST_DIR: &str = "test_delete_files_delete_partial_dirs";
+        const TEST_DIR_SEQ: &str = "test_delete_files_delete_partial_dirs_seq";
+        const TEST_SUB_DIRS: [&str; 3] = ["dir0", "dir1", "dir2"];
+
+        fs::create_dir_all([TEST_DIR, TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR_SEQ, TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR, TEST_SUB_DIRS[2]].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR_SEQ, TEST_SUB_DIRS[2]].join("/")).unwrap();
+
+        let mut dirs_to_delete: HashSet<Dir> = HashSet::new();
+        let mut dirs_to_delete_sequential: Vec<&Dir> = Vec::new();
+        let mut file_set: HashSet<Dir> = HashSet::new();
+
+        let dir0 = Dir {
+            path: PathBuf::from(TEST_SUB_DIRS[0]),
         };
         let dir2 = Dir {
-            path: PathBuf::from("/a/c"),
-        };
-        let dir3 = Dir {
-            path: PathBuf::from("/a/b"),
+            path: PathBuf::from(TEST_SUB_DIRS[2]),
         };
-        multi_dir.insert(dir1.clone());
-        multi_dir.insert(dir2.clone());
-        multi_dir.insert(dir3.clone());
-        let expected: Vec<&Dir> = vec![&dir2, &dir3, &dir1];
+
+        dirs_to_delete.insert(dir0.clone());
+        dirs_to_delete.insert(dir2.clone());
+        dirs_to_delete_sequential.push(&dir0);
+        dirs_to_delete_sequential.push(&dir2);
+
+        delete_files(
+            dirs_to_delete.par_iter(),
+            TEST_DIR,
+            Flag::empty(),
+            &Options::default(),
+        );
+        delete_files_sequential(
+            dirs_to_delete_sequential,
+            TEST_DIR_SEQ,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        file_set.insert(Dir {
+            path: PathBuf::from(TEST_SUB_DIRS[0]),
+        });
+        file_set.insert(Dir {
+            path: PathBuf::from([TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")),
+        });
 
         assert_eq!(
-            sort_files(multi_dir.par_iter()).get(2).unwrap(),
-            &expected[2]
+            get_all_files(TEST_DIR).unwrap(),
+            FileSets {
+                files: HashSet::new(),
+                dirs: file_set.clone(),
+                symlinks: HashSet::new(),
+            }
+        );
+        assert_eq!(
+            get_all_files(TEST_DIR_SEQ).unwrap(),
+            FileSets {
+                files: HashSet::new(),
+                dirs: file_set,
+                symlinks: HashSet::new(),
+            }
         );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
     }
 }
 
 #[cfg(test)]
-mod test_hash_file {
+mod test_copy_files {
     use super::*;
+    use std::process::Command;
+
+    /// Removes the given directories when dropped, after giving back the permissions that were
+    /// taken away from them, so that nothing is left behind even if an assertion fails
+    #[cfg(target_family = "unix")]
+    struct RemoveOnDrop(&'static [&'static str]);
+
+    #[cfg(target_family = "unix")]
+    impl Drop for RemoveOnDrop {
+        fn drop(&mut self) {
+            for dir in self.0 {
+                let _ = Command::new("chmod").args(["-R", "u+rwx", dir]).output();
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
 
     #[test]
-    fn invalid_file() {
+    fn copy_file_contents() {
+        const TEST_FILE_OUT: &str = "test_copy_files_copy_file_contents.rs";
+
+        let src = PathBuf::from("src/lumins/file_ops.rs");
+        let dest = PathBuf::from(TEST_FILE_OUT);
+
+        // Buffers that don't divide the file evenly still copy all of it
+        for &buffer_size in &[1000, DEFAULT_COPY_BUFFER_SIZE, 8 * 1024 * 1024] {
+            let options = ContentOptions {
+                buffer_size,
+                sparse: false,
+                truncate: true,
+                file_bar: None,
+                throttle: None,
+                resume_from: 0,
+            };
+            assert_eq!(
+                File::copy_file_contents(&src, &dest, &options).unwrap(),
+                fs::metadata(&src).unwrap().len()
+            );
+            assert_eq!(fs::read(&dest).unwrap(), fs::read(&src).unwrap());
+            assert_eq!(
+                fs::metadata(&dest).unwrap().permissions(),
+                fs::metadata(&src).unwrap().permissions()
+            );
+        }
+
+        fs::remove_file(TEST_FILE_OUT).unwrap();
+    }
+
+    #[test]
+    fn resume_partial_copy() {
+        const TEST_DIR: &str = "test_copy_files_resume_partial_copy";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        let src = PathBuf::from("src/lumins/file_ops.rs");
+        let partial = PathBuf::from([TEST_DIR, "partial"].join("/"));
+        let contents = fs::read(&src).unwrap();
+
+        // Only partial files that are the start of the source are resumed
+        assert_eq!(File::resumable_len(&src, &partial), 0);
+        fs::write(&partial, &contents[..1000]).unwrap();
+        assert_eq!(File::resumable_len(&src, &partial), 1000);
+        fs::write(&partial, b"changed").unwrap();
+        assert_eq!(File::resumable_len(&src, &partial), 0);
+        fs::write(&partial, [&contents[..], b"longer"].concat()).unwrap();
+        assert_eq!(File::resumable_len(&src, &partial), 0);
+
+        // The rest of the source is copied after the start that is kept
+        fs::write(&partial, &contents[..1000]).unwrap();
+        let options = ContentOptions {
+            buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            sparse: false,
+            truncate: true,
+            file_bar: None,
+            throttle: None,
+            resume_from: 1000,
+        };
         assert_eq!(
-            hash_file(
-                &File {
-                    path: PathBuf::from("test"),
-                    size: 0,
-                },
-                "."
-            ),
-            None
+            File::copy_file_contents(&src, &partial, &options).unwrap(),
+            contents.len() as u64
         );
+        assert_eq!(fs::read(&partial).unwrap(), contents);
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
     }
 
     #[test]
-    fn empty_file() {
-        const TEST_FILE1: &str = "test_hash_file_empty_file1.txt";
-        const TEST_FILE2: &str = "test_hash_file_empty_file2.txt";
+    fn keep_partial() {
+        const TEST_DIR: &str = "test_copy_files_keep_partial";
 
-        fs::File::create(TEST_FILE1).unwrap();
-        fs::File::create(TEST_FILE2).unwrap();
+        fs::create_dir_all(TEST_DIR).unwrap();
+        let dest = PathBuf::from([TEST_DIR, "file.txt"].join("/"));
+        let temp = File::temp_path(&dest);
+        let partial_path = File::partial_path(&dest, ".partial");
 
+        assert_ne!(File::temp_path(&dest), temp);
+        assert_eq!(File::temp_target(&temp), Some(dest.clone()));
+        assert_eq!(File::temp_target(&dest), None);
         assert_eq!(
-            hash_file(
-                &File {
-                    path: PathBuf::from(TEST_FILE1),
-                    size: 0,
-                },
-                "."
-            ),
-            hash_file(
-                &File {
-                    path: PathBuf::from(TEST_FILE2),
-                    size: 0,
-                },
-                "."
-            )
+            File::temp_target(&dest.with_file_name("file.txt.lms-tmp")),
+            None
         );
         assert_eq!(
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from(TEST_FILE1),
-                    size: 0,
-                },
-                "."
-            ),
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from(TEST_FILE2),
-                    size: 0,
-                },
-                "."
-            )
+            File::temp_target(&dest.with_file_name("file.txt.lms-tmp.1.x")),
+            None
+        );
+        assert_eq!(
+            partial_path,
+            PathBuf::from([TEST_DIR, ".partial/file.txt"].join("/"))
         );
 
-        fs::remove_file(TEST_FILE1).unwrap();
-        fs::remove_file(TEST_FILE2).unwrap();
+        fs::write(&temp, b"partial").unwrap();
+        assert_eq!(File::leftover_temp_path(&dest), Some(temp.clone()));
+        File::keep_partial(&temp, Some(&partial_path));
+        assert_eq!(File::leftover_temp_path(&dest), None);
+        assert!(!temp.exists());
+        assert_eq!(fs::read(&partial_path).unwrap(), b"partial");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
     }
 
     #[test]
-    fn equal_files() {
-        const TEST_DIR: &str = "test_hash_file_equal_files";
-        const TEST_FILE1: &str = "file1.txt";
-        const TEST_FILE2: &str = "file2.txt";
-
-        let path1 = [TEST_DIR, TEST_FILE1].join("/");
-        let path2 = [TEST_DIR, TEST_FILE2].join("/");
+    fn reflink_supported() {
+        const TEST_DIR: &str = "test_copy_files_reflink_supported";
 
         fs::create_dir_all(TEST_DIR).unwrap();
-        fs::File::create(&path1).unwrap();
-        fs::File::create(&path2).unwrap();
-        fs::write(path1, b"1234567890").unwrap();
-        fs::write(path2, b"1234567890").unwrap();
 
-        assert_eq!(
-            hash_file(
-                &File {
-                    path: PathBuf::from(TEST_FILE1),
-                    size: 10,
-                },
-                "."
-            ),
-            hash_file(
-                &File {
-                    path: PathBuf::from(TEST_FILE2),
-                    size: 10,
-                },
-                "."
+        // Only copy-on-write file systems support reflinks, but the probe files never remain
+        let supported = is_reflink_supported(Path::new("src"), Path::new(TEST_DIR));
+        assert_eq!(fs::read_dir(TEST_DIR).unwrap().count(), 0);
+        if supported {
+            assert!(File::reflink(
+                Path::new("src/main.rs"),
+                &Path::new(TEST_DIR).join("main.rs")
             )
-        );
+            .is_ok());
+        }
+        assert!(!is_reflink_supported(Path::new("/?"), Path::new(TEST_DIR)));
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn reflink() {
+        const TEST_FILE_OUT: &str = "test_copy_files_reflink.rs";
+
+        let src = PathBuf::from("src/lumins/file_ops.rs");
+        let dest = PathBuf::from(TEST_FILE_OUT);
+
+        // Only copy-on-write file systems such as Btrfs and XFS support reflinks
+        match File::reflink(&src, &dest) {
+            Ok(bytes) => {
+                assert_eq!(bytes, fs::metadata(&src).unwrap().len());
+                assert_eq!(fs::read(&dest).unwrap(), fs::read(&src).unwrap());
+            }
+            Err(e) => assert!(e.to_string().starts_with("Reflinking is not possible")),
+        }
+
+        fs::remove_file(TEST_FILE_OUT).ok();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn preallocate() {
+        use std::os::unix::fs::MetadataExt;
+
+        const TEST_FILE_OUT: &str = "test_copy_files_preallocate.rs";
+        const SIZE: u64 = 1024 * 1024;
+
+        let dest = PathBuf::from(TEST_FILE_OUT);
+
+        // The size is only allocated, so the file is still empty
+        File::preallocate(&dest, SIZE).unwrap();
+        let metadata = fs::metadata(&dest).unwrap();
+        assert_eq!(metadata.len(), 0);
+        assert!(metadata.blocks() * 512 >= SIZE);
+
+        // Too much space to allocate fails right away
+        assert!(File::preallocate(&dest, i64::MAX as u64).is_err());
+
+        fs::remove_file(TEST_FILE_OUT).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(windows, target_os = "macos"))]
+    fn copy_creation_time() {
+        const TEST_FILE_OUT: &str = "test_copy_files_copy_creation_time.rs";
+
+        let src = PathBuf::from("src/lumins/file_ops.rs");
+        let dest = PathBuf::from(TEST_FILE_OUT);
+
+        fs::copy(&src, &dest).unwrap();
+        copy_created(&src, &dest).unwrap();
         assert_eq!(
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from(TEST_FILE1),
-                    size: 10,
-                },
-                "."
-            ),
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from(TEST_FILE2),
-                    size: 10,
-                },
-                "."
-            )
+            fs::metadata(&dest).unwrap().created().unwrap(),
+            fs::metadata(&src).unwrap().created().unwrap()
         );
 
-        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_file(TEST_FILE_OUT).unwrap();
     }
 
     #[test]
-    fn different_files() {
-        assert_ne!(
-            hash_file(
-                &File {
-                    path: PathBuf::from("lumins/file_ops.rs"),
-                    size: 0,
-                },
-                "src"
-            ),
-            hash_file(
-                &File {
-                    path: PathBuf::from("main.rs"),
-                    size: 0,
-                },
-                "src"
-            )
+    fn tracked_copy() {
+        const TEST_FILE_OUT: &str = "test_copy_files_tracked_copy.rs";
+
+        let src = PathBuf::from("src/lumins/file_ops.rs");
+        let dest = PathBuf::from(TEST_FILE_OUT);
+        let file_bar = ProgressBar::hidden();
+        let options = ContentOptions {
+            buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            sparse: false,
+            truncate: true,
+            file_bar: Some(&file_bar),
+            throttle: None,
+            resume_from: 0,
+        };
+
+        assert_eq!(
+            File::copy_file_contents(&src, &dest, &options).unwrap(),
+            fs::metadata(&src).unwrap().len()
         );
-        assert_ne!(
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from("lumins/file_ops.rs"),
-                    size: 0,
-                },
-                "src"
-            ),
-            hash_file_secure(
-                &File {
-                    path: PathBuf::from("main.rs"),
-                    size: 0,
-                },
-                "src"
-            )
+        assert_eq!(file_bar.position(), fs::metadata(&src).unwrap().len());
+        assert_eq!(fs::read(&dest).unwrap(), fs::read(&src).unwrap());
+        assert_eq!(
+            fs::metadata(&dest).unwrap().permissions(),
+            fs::metadata(&src).unwrap().permissions()
         );
-    }
-}
 
-#[cfg(test)]
-mod test_delete_files {
-    use super::*;
+        fs::remove_file(TEST_FILE_OUT).unwrap();
+    }
 
     #[test]
-    fn delete_no_files() {
-        const TEST_DIR: &str = "test_delete_files_delete_no_files";
-        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+    fn copy_verified() {
+        const TEST_DIR: &str = "test_copy_files_copy_verified";
 
         fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write([TEST_DIR, "src"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR, "same"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR, "different"].join("/"), b"abd").unwrap();
+
+        let path = |name: &str| PathBuf::from([TEST_DIR, name].join("/"));
+        for &algo in &[HashAlgo::Seahash, HashAlgo::Blake2] {
+            assert!(File::is_copy_verified(
+                &path("src"),
+                &path("same"),
+                Flag::empty(),
+                algo
+            ));
+            assert!(!File::is_copy_verified(
+                &path("src"),
+                &path("different"),
+                Flag::empty(),
+                algo
+            ));
+            assert!(!File::is_copy_verified(
+                &path("src"),
+                &path("missing"),
+                Flag::empty(),
+                algo
+            ));
+        }
 
-        let files_to_delete: HashSet<File> = HashSet::new();
-        let files_to_delete_sequential: Vec<&File> = Vec::new();
-        let mut file_set = HashSet::new();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
 
-        for i in 0..TEST_FILES.len() {
-            fs::File::create([TEST_DIR, TEST_FILES[i]].join("/")).unwrap();
-            let file = File {
-                path: PathBuf::from(TEST_FILES[i]),
-                size: 0,
-            };
-            file_set.insert(file);
-        }
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn sparse_copy() {
+        use std::os::unix::fs::MetadataExt;
 
-        delete_files(files_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(files_to_delete_sequential.into_iter(), TEST_DIR);
+        const TEST_DIR: &str = "test_copy_files_sparse_copy";
+        const ZEROS: usize = 1024 * 1024;
 
+        fs::create_dir_all(TEST_DIR).unwrap();
+        let mut contents = vec![0; ZEROS];
+        contents.extend_from_slice(b"abc");
+        contents.extend_from_slice(&vec![0; ZEROS]);
+        fs::write([TEST_DIR, "src"].join("/"), &contents).unwrap();
+
+        let src = PathBuf::from([TEST_DIR, "src"].join("/"));
+        let dest = PathBuf::from([TEST_DIR, "dest"].join("/"));
+        let options = ContentOptions {
+            buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            sparse: true,
+            truncate: true,
+            file_bar: None,
+            throttle: None,
+            resume_from: 0,
+        };
         assert_eq!(
-            get_all_files(TEST_DIR).unwrap(),
-            FileSets {
-                files: file_set,
-                dirs: HashSet::new(),
-                symlinks: HashSet::new(),
-            }
+            File::copy_file_contents(&src, &dest, &options).unwrap(),
+            contents.len() as u64
         );
 
+        assert_eq!(fs::read(&dest).unwrap(), contents);
+        // Only the block with data takes up space
+        assert!(fs::metadata(&dest).unwrap().blocks() * 512 < ZEROS as u64);
+
         fs::remove_dir_all(TEST_DIR).unwrap();
     }
 
-    #[cfg(target_family = "unix")]
     #[test]
-    fn delete_invalid_file_and_link() {
-        use std::os::unix::fs::symlink;
-
-        const TEST_DIR: &str = "test_delete_files_delete_invalid_file_and_link";
-        const TEST_DIR_SEQ: &str = "test_delete_files_delete_invalid_file_and_link_seq";
-        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+    fn temp_file_renamed() {
+        const TEST_DIR: &str = "test_copy_files_temp_file_renamed";
+        const TEST_DIR_OUT: &str = "test_copy_files_temp_file_renamed_out";
 
         fs::create_dir_all(TEST_DIR).unwrap();
-        fs::create_dir_all(TEST_DIR_SEQ).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "file"].join("/"), b"new").unwrap();
+        fs::write([TEST_DIR_OUT, "link"].join("/"), b"old").unwrap();
+        fs::hard_link(
+            [TEST_DIR_OUT, "link"].join("/"),
+            [TEST_DIR_OUT, "file"].join("/"),
+        )
+        .unwrap();
 
-        let mut files_to_delete: HashSet<File> = HashSet::new();
-        let mut files_to_delete_sequential: Vec<&File> = Vec::new();
-        let mut file_set = HashSet::new();
+        let file = File::from("file", 3);
+        let stats = file.copy(
+            &PathBuf::from([TEST_DIR, "file"].join("/")),
+            &PathBuf::from([TEST_DIR_OUT, "file"].join("/")),
+            Flag::empty(),
+            &Options::default(),
+        );
 
-        fs::File::create([TEST_DIR, TEST_FILES[0]].join("/")).unwrap();
-        fs::File::create([TEST_DIR_SEQ, TEST_FILES[0]].join("/")).unwrap();
-        let file = File {
-            path: PathBuf::from([TEST_FILES[0], "a"].join("/")),
-            size: 0,
-        };
-        let expected_file = File {
-            path: PathBuf::from(TEST_FILES[0]),
-            size: 0,
-        };
-        file_set.insert(expected_file);
-        files_to_delete.insert(file.clone());
-        files_to_delete_sequential.push(&file);
+        assert_eq!(stats.files_copied, 1);
+        assert_eq!(fs::read([TEST_DIR_OUT, "file"].join("/")).unwrap(), b"new");
+        assert_eq!(fs::read([TEST_DIR_OUT, "link"].join("/")).unwrap(), b"old");
+        assert!(!fs::read_dir(TEST_DIR_OUT)
+            .unwrap()
+            .any(|entry| File::is_temp_path(&entry.unwrap().path())));
 
-        let mut links_to_delete: HashSet<Symlink> = HashSet::new();
-        let mut links_to_delete_sequential: Vec<&Symlink> = Vec::new();
-        let mut link_set = HashSet::new();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
 
-        symlink(TEST_FILES[1], [TEST_DIR, "file"].join("/")).unwrap();
-        symlink(TEST_FILES[1], [TEST_DIR_SEQ, "file"].join("/")).unwrap();
-        let link = Symlink {
-            path: PathBuf::from("filea"),
-            target: PathBuf::from(TEST_FILES[1]),
-        };
-        let expected_link = Symlink {
-            path: PathBuf::from("file"),
-            target: PathBuf::from(TEST_FILES[1]),
-        };
-        link_set.insert(expected_link);
-        links_to_delete.insert(link.clone());
-        links_to_delete_sequential.push(&link);
+    #[test]
+    fn temp_file_removed_on_error() {
+        const TEST_DIR: &str = "test_copy_files_temp_file_removed_on_error";
+        const TEST_DIR_OUT: &str = "test_copy_files_temp_file_removed_on_error_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all([TEST_DIR_OUT, "file", "dir"].join("/")).unwrap();
+        fs::write([TEST_DIR, "file"].join("/"), b"new").unwrap();
+
+        let file = File::from("file", 3);
+        let stats = file.copy(
+            &PathBuf::from([TEST_DIR, "file"].join("/")),
+            &PathBuf::from([TEST_DIR_OUT, "file"].join("/")),
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(stats.errors, 1);
+        assert!(!fs::read_dir(TEST_DIR_OUT)
+            .unwrap()
+            .any(|entry| File::is_temp_path(&entry.unwrap().path())));
 
-        delete_files(files_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(files_to_delete_sequential.into_iter(), TEST_DIR_SEQ);
-        delete_files(links_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(links_to_delete_sequential.into_iter(), TEST_DIR_SEQ);
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
 
-        assert_eq!(
-            get_all_files(TEST_DIR).unwrap(),
-            FileSets {
-                files: file_set.clone(),
-                dirs: HashSet::new(),
-                symlinks: link_set.clone(),
-            }
+    #[test]
+    fn no_files() {
+        const TEST_DIR: &str = "test_copy_files_no_files";
+        const TEST_DIR_OUT: &str = "test_copy_files_no_files_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+
+        copy_files(
+            HashSet::<File>::new().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
         );
+
         assert_eq!(
-            get_all_files(TEST_DIR_SEQ).unwrap(),
+            get_all_files(TEST_DIR_OUT).unwrap(),
             FileSets {
-                files: file_set,
+                files: HashSet::new(),
                 dirs: HashSet::new(),
-                symlinks: link_set,
+                symlinks: HashSet::new(),
             }
         );
 
         fs::remove_dir_all(TEST_DIR).unwrap();
-        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 
-    #[cfg(target_family = "unix")]
     #[test]
-    fn delete_file_and_link() {
-        use std::os::unix::fs::symlink;
+    fn regular_files_dirs() {
+        const TEST_DIR: &str = "src";
+        const TEST_DIR_OUT: &str = "test_copy_files_regular_files_dirs_out";
 
-        const TEST_DIR: &str = "test_delete_files_delete_file_and_link";
-        const TEST_DIR_SEQ: &str = "test_delete_files_delete_file_and_link_seq";
-        const TEST_FILES: [&str; 2] = ["file1.txt", "file2.txt"];
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            get_all_files(TEST_DIR_OUT).unwrap(),
+            get_all_files(TEST_DIR).unwrap()
+        );
+
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn read_only_source() {
+        use std::os::unix::fs::PermissionsExt;
+
+        const TEST_DIR: &str = "test_copy_files_read_only_source";
+        const TEST_DIR_OUT: &str = "test_copy_files_read_only_source_out";
+        let _cleanup = RemoveOnDrop(&[TEST_DIR, TEST_DIR_OUT]);
 
         fs::create_dir_all(TEST_DIR).unwrap();
-        fs::create_dir_all(TEST_DIR_SEQ).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        let src = [TEST_DIR, "read_only.txt"].join("/");
+        fs::write(&src, b"read only").unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o444)).unwrap();
 
-        let mut files_to_delete: HashSet<File> = HashSet::new();
-        let mut files_to_delete_sequential: Vec<&File> = Vec::new();
-        let mut file_set = HashSet::new();
+        // The copy already has the read only permissions when its modification time is set
+        let stats = copy_files(
+            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+        assert_eq!(stats.files_copied, 1);
 
-        fs::File::create([TEST_DIR, TEST_FILES[0]].join("/")).unwrap();
-        fs::File::create([TEST_DIR_SEQ, TEST_FILES[0]].join("/")).unwrap();
-        let file = File {
-            path: PathBuf::from(TEST_FILES[0]),
-            size: 0,
-        };
-        file_set.insert(file.clone());
-        files_to_delete.insert(file.clone());
-        files_to_delete_sequential.push(&file);
+        let dest = [TEST_DIR_OUT, "read_only.txt"].join("/");
+        let metadata = fs::metadata(&dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"read only");
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o444);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            fs::metadata(&src).unwrap().modified().unwrap()
+        );
+    }
 
-        let mut links_to_delete: HashSet<Symlink> = HashSet::new();
-        let mut links_to_delete_sequential: Vec<&Symlink> = Vec::new();
-        let mut link_set = HashSet::new();
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn insufficient_output_permissions() {
+        const TEST_DIR: &str = "src";
+        const TEST_DIR_OUT: &str = "test_copy_files_insufficient_output_permissions_out";
+        const SUB_DIR: &str = "lumins";
+        let _cleanup = RemoveOnDrop(&[TEST_DIR_OUT]);
 
-        symlink(TEST_FILES[1], [TEST_DIR, "file"].join("/")).unwrap();
-        symlink(TEST_FILES[1], [TEST_DIR_SEQ, "file"].join("/")).unwrap();
-        let link = Symlink {
-            path: PathBuf::from("file"),
-            target: PathBuf::from(TEST_FILES[1]),
-        };
-        link_set.insert(link.clone());
-        links_to_delete.insert(link.clone());
-        links_to_delete_sequential.push(&link);
+        fs::create_dir_all([TEST_DIR_OUT, SUB_DIR].join("/")).unwrap();
+        fs::File::create([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
+        fs::File::create([TEST_DIR_OUT, "cli.yml"].join("/")).unwrap();
+        fs::File::create([TEST_DIR_OUT, "lib.rs"].join("/")).unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR_OUT, SUB_DIR].join("/"))
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR_OUT, "main.rs"].join("/"))
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR_OUT, "cli.yml"].join("/"))
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR_OUT, "lib.rs"].join("/"))
+            .output()
+            .unwrap();
+
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
 
-        delete_files(files_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(files_to_delete_sequential.into_iter(), TEST_DIR_SEQ);
-        delete_files(links_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(links_to_delete_sequential.into_iter(), TEST_DIR_SEQ);
+        let mut files = HashSet::new();
+        files.insert(File {
+            path: PathBuf::from("main.rs"),
+            size: 0,
+            modified: None,
+            hard_link: None,
+        });
+        files.insert(File {
+            path: PathBuf::from("cli.yml"),
+            size: 0,
+            modified: None,
+            hard_link: None,
+        });
+        files.insert(File {
+            path: PathBuf::from("lib.rs"),
+            size: 0,
+            modified: None,
+            hard_link: None,
+        });
+        let mut dirs = HashSet::new();
+        dirs.insert(Dir {
+            path: PathBuf::from("lumins"),
+        });
 
         assert_eq!(
-            get_all_files(TEST_DIR).unwrap(),
+            get_all_files(TEST_DIR_OUT).unwrap(),
             FileSets {
-                files: HashSet::new(),
-                dirs: HashSet::new(),
+                files: files.clone(),
+                dirs: dirs.clone(),
                 symlinks: HashSet::new(),
             }
         );
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn insufficient_input_permissions() {
+        const TEST_DIR: &str = "test_copy_files_insufficient_input_permissions";
+        const TEST_DIR_OUT: &str = "test_copy_files_insufficient_input_permissions_out";
+        let _cleanup = RemoveOnDrop(&[TEST_DIR, TEST_DIR_OUT]);
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+
+        Command::new("cp")
+            .args(&["-r", "src/lumins", TEST_DIR])
+            .output()
+            .unwrap();
+        Command::new("cp")
+            .args(&["src/main.rs", TEST_DIR])
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR, "lumins"].join("/"))
+            .output()
+            .unwrap();
+        Command::new("chmod")
+            .arg("000")
+            .arg([TEST_DIR, "main.rs"].join("/"))
+            .output()
+            .unwrap();
+
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
+
+        let files = HashSet::new();
+        let mut dirs = HashSet::new();
+        dirs.insert(Dir {
+            path: PathBuf::from("lumins"),
+        });
+
         assert_eq!(
-            get_all_files(TEST_DIR_SEQ).unwrap(),
+            get_all_files(TEST_DIR_OUT).unwrap(),
             FileSets {
-                files: HashSet::new(),
-                dirs: HashSet::new(),
+                files: files.clone(),
+                dirs: dirs.clone(),
                 symlinks: HashSet::new(),
             }
         );
-
-        fs::remove_dir_all(TEST_DIR).unwrap();
-        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
     }
 
     #[test]
-    fn delete_partial_dirs() {
-        const TEST_DIR: &str = "test_delete_files_delete_partial_dirs";
-        const TEST_DIR_SEQ: &str = "test_delete_files_delete_partial_dirs_seq";
-        const TEST_SUB_DIRS: [&str; 3] = ["dir0", "dir1", "dir2"];
-
-        fs::create_dir_all([TEST_DIR, TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")).unwrap();
-        fs::create_dir_all([TEST_DIR_SEQ, TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")).unwrap();
-        fs::create_dir_all([TEST_DIR, TEST_SUB_DIRS[2]].join("/")).unwrap();
-        fs::create_dir_all([TEST_DIR_SEQ, TEST_SUB_DIRS[2]].join("/")).unwrap();
-
-        let mut dirs_to_delete: HashSet<Dir> = HashSet::new();
-        let mut dirs_to_delete_sequential: Vec<&Dir> = Vec::new();
-        let mut file_set: HashSet<Dir> = HashSet::new();
-
-        let dir0 = Dir {
-            path: PathBuf::from(TEST_SUB_DIRS[0]),
-        };
-        let dir2 = Dir {
-            path: PathBuf::from(TEST_SUB_DIRS[2]),
-        };
+    #[cfg(target_family = "unix")]
+    fn copy_symlink() {
+        use std::os::unix::fs::symlink;
+        const TEST_DIR: &str = "test_copy_files_copy_symlink";
+        const TEST_DIR_OUT: &str = "test_copy_files_copy_symlink_out_seq";
 
-        dirs_to_delete.insert(dir0.clone());
-        dirs_to_delete.insert(dir2.clone());
-        dirs_to_delete_sequential.push(&dir0);
-        dirs_to_delete_sequential.push(&dir2);
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        symlink("src/main.rs", [TEST_DIR, "file"].join("/")).unwrap();
 
-        delete_files(dirs_to_delete.par_iter(), TEST_DIR);
-        delete_files_sequential(dirs_to_delete_sequential.into_iter(), TEST_DIR_SEQ);
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().symlinks().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
 
-        file_set.insert(Dir {
-            path: PathBuf::from(TEST_SUB_DIRS[0]),
-        });
-        file_set.insert(Dir {
-            path: PathBuf::from([TEST_SUB_DIRS[0], TEST_SUB_DIRS[1]].join("/")),
+        let mut links_set = HashSet::new();
+        links_set.insert(Symlink {
+            path: PathBuf::from("file"),
+            target: PathBuf::from("src/main.rs"),
         });
 
         assert_eq!(
-            get_all_files(TEST_DIR).unwrap(),
-            FileSets {
-                files: HashSet::new(),
-                dirs: file_set.clone(),
-                symlinks: HashSet::new(),
-            }
-        );
-        assert_eq!(
-            get_all_files(TEST_DIR_SEQ).unwrap(),
+            get_all_files(TEST_DIR_OUT).unwrap(),
             FileSets {
                 files: HashSet::new(),
-                dirs: file_set,
-                symlinks: HashSet::new(),
+                dirs: HashSet::new(),
+                symlinks: links_set.clone(),
             }
         );
 
         fs::remove_dir_all(TEST_DIR).unwrap();
-        fs::remove_dir_all(TEST_DIR_SEQ).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
-}
-
-#[cfg(test)]
-mod test_copy_files {
-    use super::*;
-    use std::process::Command;
 
     #[test]
-    fn no_files() {
-        const TEST_DIR: &str = "test_copy_files_no_files";
-        const TEST_DIR_OUT: &str = "test_copy_files_no_files_out";
+    #[cfg(target_family = "windows")]
+    fn copy_symlink() {
+        use std::env;
+        use std::os::windows::fs as wfs;
+        const TEST_DIR: &str = "test_copy_files_copy_symlink";
+        const TEST_DIR_OUT: &str = "test_copy_files_copy_symlink_out_seq";
+        let CURRENT_PATH: PathBuf = env::current_dir().unwrap();
 
         fs::create_dir_all(TEST_DIR).unwrap();
         fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        wfs::symlink_file("src/main.rs", [TEST_DIR, "file"].join("/")).unwrap();
+        wfs::symlink_dir("src", [TEST_DIR, "dir"].join("/")).unwrap();
+
+        copy_files(
+            get_all_files(TEST_DIR).unwrap().symlinks().par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        );
 
-        copy_files(HashSet::<File>::new().par_iter(), TEST_DIR, TEST_DIR_OUT);
+        let mut links_set = HashSet::new();
+        links_set.insert(Symlink {
+            path: PathBuf::from("file"),
+            target: PathBuf::from("src/main.rs"),
+        });
+
+        links_set.insert(Symlink {
+            path: PathBuf::from("dir"),
+            target: PathBuf::from("src/"),
+        });
 
         assert_eq!(
             get_all_files(TEST_DIR_OUT).unwrap(),
             FileSets {
                 files: HashSet::new(),
                 dirs: HashSet::new(),
-                symlinks: HashSet::new(),
+                symlinks: links_set.clone(),
             }
         );
 
         fs::remove_dir_all(TEST_DIR).unwrap();
         fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
+}
+
+#[cfg(test)]
+mod test_compare_and_copy_files {
+    use super::*;
+    use crate::lumins::conflict::ConflictHandler;
+    use std::sync::{Arc, Mutex};
 
     #[test]
-    fn regular_files_dirs() {
+    fn single_same() {
         const TEST_DIR: &str = "src";
-        const TEST_DIR_OUT: &str = "test_copy_files_regular_files_dirs_out";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_single_same_out";
 
         fs::create_dir_all(TEST_DIR_OUT).unwrap();
 
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+        fs::copy(
+            [TEST_DIR, "main.rs"].join("/"),
+            [TEST_DIR_OUT, "main.rs"].join("/"),
+        )
+        .unwrap();
+
+        let file_to_compare = File {
+            path: PathBuf::from("main.rs"),
+            size: fs::metadata([TEST_DIR, "main.rs"].join("/")).unwrap().len(),
+            modified: None,
+            hard_link: None,
+        };
+
+        let mut files_to_compare = HashSet::new();
+        files_to_compare.insert(file_to_compare.clone());
+
+        compare_and_copy_files(
+            files_to_compare.clone().par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            Flag::empty(),
+            &Options::default(),
+        )
+        .unwrap();
+
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
+            Flag::empty(),
+            &Options {
+                hash: HashAlgo::Blake2,
+                ..Options::default()
+            },
+        )
+        .unwrap();
 
-        assert_eq!(
-            get_all_files(TEST_DIR_OUT).unwrap(),
-            get_all_files(TEST_DIR).unwrap()
-        );
+        let actual = fs::read([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
+        let expected = fs::read([TEST_DIR, "main.rs"].join("/")).unwrap();
+        assert_eq!(actual, expected);
 
         fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 
     #[test]
-    #[cfg(target_family = "unix")]
-    fn insufficient_output_permissions() {
+    fn single_different() {
         const TEST_DIR: &str = "src";
-        const TEST_DIR_OUT: &str = "test_copy_files_insufficient_output_permissions_out";
-        const SUB_DIR: &str = "lumins";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_single_different_out";
 
-        fs::create_dir_all([TEST_DIR_OUT, SUB_DIR].join("/")).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
         fs::File::create([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
-        fs::File::create([TEST_DIR_OUT, "cli.yml"].join("/")).unwrap();
-        fs::File::create([TEST_DIR_OUT, "lib.rs"].join("/")).unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR_OUT, SUB_DIR].join("/"))
-            .output()
+
+        let file_to_compare = File {
+            path: PathBuf::from("main.rs"),
+            size: fs::metadata([TEST_DIR, "main.rs"].join("/")).unwrap().len(),
+            modified: None,
+            hard_link: None,
+        };
+        let mut files_to_compare = HashSet::new();
+        files_to_compare.insert(file_to_compare.clone());
+
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options::default(),
+        )
+        .unwrap();
+
+        let actual = fs::read([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
+        let expected = fs::read([TEST_DIR, "main.rs"].join("/")).unwrap();
+
+        assert_eq!(actual, expected);
+
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    fn missing_dest() {
+        const TEST_DIR: &str = "src";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_missing_dest_out";
+
+        let file_to_compare = File {
+            path: PathBuf::from("main.rs"),
+            size: fs::metadata([TEST_DIR, "main.rs"].join("/")).unwrap().len(),
+            modified: None,
+            hard_link: None,
+        };
+
+        assert!(!is_same_file(
+            &file_to_compare,
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options {
+                hash: HashAlgo::Blake2,
+                ..Options::default()
+            }
+        ));
+
+        // Nothing is hashed, so nothing fails
+        assert!(!stats::failures_take()
+            .iter()
+            .any(|(path, _)| path.starts_with(TEST_DIR_OUT)));
+    }
+
+    #[test]
+    fn quick_check_same() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_quick_check_same";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_quick_check_same_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"xyz").unwrap();
+
+        let modified = fs::metadata([TEST_DIR, "a.txt"].join("/"))
+            .unwrap()
+            .modified()
             .unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR_OUT, "main.rs"].join("/"))
-            .output()
+        OpenOptions::new()
+            .write(true)
+            .open([TEST_DIR_OUT, "a.txt"].join("/"))
+            .unwrap()
+            .set_modified(modified)
             .unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR_OUT, "cli.yml"].join("/"))
-            .output()
+
+        let mut files_to_compare = HashSet::new();
+        files_to_compare.insert(File::from("a.txt", 3));
+
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::QUICK_CHECK,
+            &Options::default(),
+        )
+        .unwrap();
+
+        // Contents are never read, so the files are considered equal
+        assert_eq!(fs::read([TEST_DIR_OUT, "a.txt"].join("/")).unwrap(), b"xyz");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    fn checksum() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_checksum";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_checksum_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"xyz").unwrap();
+
+        let modified = fs::metadata([TEST_DIR, "a.txt"].join("/"))
+            .unwrap()
+            .modified()
             .unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR_OUT, "lib.rs"].join("/"))
-            .output()
+        OpenOptions::new()
+            .write(true)
+            .open([TEST_DIR_OUT, "a.txt"].join("/"))
+            .unwrap()
+            .set_modified(modified)
             .unwrap();
 
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+        let mut files_to_compare = HashSet::new();
+        files_to_compare.insert(File::from("a.txt", 3));
+
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            Flag::QUICK_CHECK | Flag::CHECKSUM,
+            &Options::default(),
+        )
+        .unwrap();
+
+        // The contents are hashed even though the size and modification time are the same
+        assert_eq!(fs::read([TEST_DIR_OUT, "a.txt"].join("/")).unwrap(), b"abc");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    fn quick_hash() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_quick_hash";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_quick_hash_out";
+
+        let head_and_tail = vec![b'a'; DEFAULT_QUICK_HASH_SIZE as usize];
+        let contents = |middle: &[u8]| [&head_and_tail[..], middle, &head_and_tail[..]].concat();
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "a.bin"].join("/"), contents(b"new")).unwrap();
+        fs::write([TEST_DIR_OUT, "a.bin"].join("/"), contents(b"old")).unwrap();
+
+        let files_to_compare = get_all_files(TEST_DIR).unwrap().files().clone();
+
+        // Changes between the start and end are missed
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
+            Flag::QUICK_HASH,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read([TEST_DIR_OUT, "a.bin"].join("/")).unwrap(),
+            contents(b"old")
         );
 
-        let mut files = HashSet::new();
-        files.insert(File {
-            path: PathBuf::from("main.rs"),
-            size: 0,
-        });
-        files.insert(File {
-            path: PathBuf::from("cli.yml"),
-            size: 0,
-        });
-        files.insert(File {
-            path: PathBuf::from("lib.rs"),
-            size: 0,
-        });
-        let mut dirs = HashSet::new();
-        dirs.insert(Dir {
-            path: PathBuf::from("lumins"),
-        });
-
+        // unless the files are then hashed fully
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::QUICK_HASH | Flag::CHECKSUM,
+            &Options::default(),
+        )
+        .unwrap();
         assert_eq!(
-            get_all_files(TEST_DIR_OUT).unwrap(),
-            FileSets {
-                files: files.clone(),
-                dirs: dirs.clone(),
-                symlinks: HashSet::new(),
-            }
+            fs::read([TEST_DIR_OUT, "a.bin"].join("/")).unwrap(),
+            contents(b"new")
         );
 
-        Command::new("rm")
-            .arg("-rf")
-            .arg(TEST_DIR_OUT)
-            .output()
-            .unwrap();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 
     #[test]
-    #[cfg(target_family = "unix")]
-    fn insufficient_input_permissions() {
-        const TEST_DIR: &str = "test_copy_files_insufficient_input_permissions";
-        const TEST_DIR_OUT: &str = "test_copy_files_insufficient_input_permissions_out";
+    fn compare_bytes() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_compare_bytes";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_compare_bytes_out";
 
         fs::create_dir_all(TEST_DIR).unwrap();
         fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR, "b.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "b.txt"].join("/"), b"xyz").unwrap();
 
-        Command::new("cp")
-            .args(&["-r", "src/lumins", TEST_DIR])
-            .output()
-            .unwrap();
-        Command::new("cp")
-            .args(&["src/main.rs", TEST_DIR])
-            .output()
-            .unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR, "lumins"].join("/"))
-            .output()
-            .unwrap();
-        Command::new("chmod")
-            .arg("000")
-            .arg([TEST_DIR, "main.rs"].join("/"))
-            .output()
-            .unwrap();
+        let files_to_compare = get_all_files(TEST_DIR).unwrap().files().clone();
 
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().dirs().par_iter(),
+        let stats = compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().files().par_iter(),
+            Flag::COMPARE_BYTES,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(stats.files_updated, 1);
+        assert_eq!(fs::read([TEST_DIR_OUT, "b.txt"].join("/")).unwrap(), b"abc");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    fn force_copy() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_force_copy";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_force_copy_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR, "b.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "b.txt"].join("/"), b"xyz").unwrap();
+
+        let files_to_compare = get_all_files(TEST_DIR).unwrap().files().clone();
+
+        let stats = compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
+            Flag::FORCE_COPY,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(stats.files_updated, 2);
+        assert_eq!(fs::read([TEST_DIR_OUT, "b.txt"].join("/")).unwrap(), b"abc");
 
-        let files = HashSet::new();
-        let mut dirs = HashSet::new();
-        dirs.insert(Dir {
-            path: PathBuf::from("lumins"),
-        });
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    }
+
+    #[test]
+    fn conflict_handler() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_conflict_handler";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_conflict_handler_out";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+        fs::write([TEST_DIR, "overwrite.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DIR, "skip.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DIR_OUT, "overwrite.txt"].join("/"), b"old").unwrap();
+        fs::write([TEST_DIR_OUT, "skip.txt"].join("/"), b"older").unwrap();
+
+        let files_to_compare = get_all_files(TEST_DIR).unwrap().files().clone();
+        let conflicts = Arc::new(Mutex::new(Vec::new()));
+        let conflict_handler = {
+            let conflicts = Arc::clone(&conflicts);
+            ConflictHandler::new(move |info| {
+                conflicts.lock().unwrap().push(info.clone());
+                if info.path == Path::new("skip.txt") {
+                    ConflictAction::Skip
+                } else {
+                    ConflictAction::Overwrite
+                }
+            })
+        };
 
+        let stats = compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options {
+                conflict_handler: Some(conflict_handler),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.files_updated, 1);
         assert_eq!(
-            get_all_files(TEST_DIR_OUT).unwrap(),
-            FileSets {
-                files: files.clone(),
-                dirs: dirs.clone(),
-                symlinks: HashSet::new(),
+            fs::read([TEST_DIR_OUT, "overwrite.txt"].join("/")).unwrap(),
+            b"new"
+        );
+        assert_eq!(
+            fs::read([TEST_DIR_OUT, "skip.txt"].join("/")).unwrap(),
+            b"older"
+        );
+
+        // Files of the same size are hashed for the handler, but others are not
+        let mut conflicts = conflicts.lock().unwrap().clone();
+        conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts[0].src_hash.is_some());
+        assert_ne!(conflicts[0].src_hash, conflicts[0].dest_hash);
+        assert_eq!((conflicts[1].src_size, conflicts[1].dest_size), (3, 5));
+        assert_eq!(conflicts[1].src_hash, None);
+
+        let abort_handler = ConflictHandler::new(|_| ConflictAction::Abort);
+        assert!(compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::empty(),
+            &Options {
+                conflict_handler: Some(abort_handler),
+                ..Options::default()
             }
+        )
+        .is_err());
+        assert_eq!(
+            fs::read([TEST_DIR_OUT, "skip.txt"].join("/")).unwrap(),
+            b"older"
         );
 
-        Command::new("chmod")
-            .arg("777")
-            .arg([TEST_DIR, "lumins"].join("/"))
-            .output()
-            .unwrap();
-        Command::new("rm")
-            .args(&["-rf", TEST_DIR])
-            .output()
-            .unwrap();
-        Command::new("rm")
-            .args(&["-rf", TEST_DIR_OUT])
-            .output()
-            .unwrap();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 
     #[test]
-    #[cfg(target_family = "unix")]
-    fn copy_symlink() {
-        use std::os::unix::fs::symlink;
-        const TEST_DIR: &str = "test_copy_files_copy_symlink";
-        const TEST_DIR_OUT: &str = "test_copy_files_copy_symlink_out_seq";
+    fn quick_check_traversed() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_quick_check_traversed";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_quick_check_traversed_out";
 
         fs::create_dir_all(TEST_DIR).unwrap();
         fs::create_dir_all(TEST_DIR_OUT).unwrap();
-        symlink("src/main.rs", [TEST_DIR, "file"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"xyz").unwrap();
 
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().symlinks().par_iter(),
-            TEST_DIR,
-            TEST_DIR_OUT,
+        let files_to_compare = get_all_files(TEST_DIR).unwrap().files().clone();
+        let modified = files_to_compare.iter().next().unwrap().modified().unwrap();
+        assert_eq!(
+            modified,
+            fs::metadata([TEST_DIR, "a.txt"].join("/"))
+                .unwrap()
+                .modified()
+                .unwrap()
         );
+        OpenOptions::new()
+            .write(true)
+            .open([TEST_DIR_OUT, "a.txt"].join("/"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
 
-        let mut links_set = HashSet::new();
-        links_set.insert(Symlink {
-            path: PathBuf::from("file"),
-            target: PathBuf::from("src/main.rs"),
-        });
+        // The source is only read while traversing, so later changes to it are not seen
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"def").unwrap();
 
-        assert_eq!(
-            get_all_files(TEST_DIR_OUT).unwrap(),
-            FileSets {
-                files: HashSet::new(),
-                dirs: HashSet::new(),
-                symlinks: links_set.clone(),
-            }
-        );
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
+            TEST_DIR,
+            TEST_DIR_OUT,
+            Flag::QUICK_CHECK,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read([TEST_DIR_OUT, "a.txt"].join("/")).unwrap(), b"xyz");
 
         fs::remove_dir_all(TEST_DIR).unwrap();
         fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 
     #[test]
-    #[cfg(target_family = "windows")]
-    fn copy_symlink() {
-        use std::os::windows::fs as wfs;
-        use std::env;
-        const TEST_DIR: &str = "test_copy_files_copy_symlink";
-        const TEST_DIR_OUT: &str = "test_copy_files_copy_symlink_out_seq";
-        let CURRENT_PATH: PathBuf = env::current_dir().unwrap();
+    fn quick_check_different() {
+        const TEST_DIR: &str = "test_compare_and_copy_files_quick_check_different";
+        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_quick_check_different_out";
 
         fs::create_dir_all(TEST_DIR).unwrap();
         fs::create_dir_all(TEST_DIR_OUT).unwrap();
-        wfs::symlink_file("src/main.rs", [TEST_DIR, "file"].join("/")).unwrap();
-        wfs::symlink_dir("src", [TEST_DIR, "dir"].join("/")).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"abc").unwrap();
+        fs::write([TEST_DIR_OUT, "a.txt"].join("/"), b"xyz").unwrap();
 
-        copy_files(
-            get_all_files(TEST_DIR).unwrap().symlinks().par_iter(),
+        OpenOptions::new()
+            .write(true)
+            .open([TEST_DIR_OUT, "a.txt"].join("/"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let mut files_to_compare = HashSet::new();
+        files_to_compare.insert(File::from("a.txt", 3));
+
+        compare_and_copy_files(
+            files_to_compare.par_iter(),
             TEST_DIR,
             TEST_DIR_OUT,
-        );
-
-        let mut links_set = HashSet::new();
-        links_set.insert(Symlink {
-            path: PathBuf::from("file"),
-            target: PathBuf::from("src/main.rs"),
-        });
+            Flag::QUICK_CHECK,
+            &Options::default(),
+        )
+        .unwrap();
 
-        links_set.insert(Symlink {
-            path: PathBuf::from("dir"),
-            target: PathBuf::from("src/"),
-        });
+        let src_metadata = fs::metadata([TEST_DIR, "a.txt"].join("/")).unwrap();
+        let dest_metadata = fs::metadata([TEST_DIR_OUT, "a.txt"].join("/")).unwrap();
 
+        assert_eq!(fs::read([TEST_DIR_OUT, "a.txt"].join("/")).unwrap(), b"abc");
         assert_eq!(
-            get_all_files(TEST_DIR_OUT).unwrap(),
-            FileSets {
-                files: HashSet::new(),
-                dirs: HashSet::new(),
-                symlinks: links_set.clone(),
-            }
+            src_metadata.modified().unwrap(),
+            dest_metadata.modified().unwrap()
         );
 
-       fs::remove_dir_all(TEST_DIR).unwrap();
-       fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
     }
 }
 
 #[cfg(test)]
-mod test_compare_and_copy_files {
+mod test_files_identical {
     use super::*;
 
-    #[test]
-    fn single_same() {
-        const TEST_DIR: &str = "src";
-        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_single_same_out";
+    const TEST_DIR: &str = "test_files_identical";
 
-        fs::create_dir_all(TEST_DIR_OUT).unwrap();
+    fn files_identical_with(name: &str, src: &[u8], dest: &[u8]) -> bool {
+        let dir = [TEST_DIR, name].join("_");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write([&dir, "src"].join("/"), src).unwrap();
+        fs::write([&dir, "dest"].join("/"), dest).unwrap();
 
-        fs::copy(
-            [TEST_DIR, "main.rs"].join("/"),
-            [TEST_DIR_OUT, "main.rs"].join("/"),
+        let identical = files_identical(
+            Path::new(&[&dir, "src"].join("/")),
+            Path::new(&[&dir, "dest"].join("/")),
         )
         .unwrap();
 
-        let file_to_compare = File {
-            path: PathBuf::from("main.rs"),
-            size: fs::metadata([TEST_DIR, "main.rs"].join("/")).unwrap().len(),
-        };
+        fs::remove_dir_all(&dir).unwrap();
+        identical
+    }
 
-        let mut files_to_compare = HashSet::new();
-        files_to_compare.insert(file_to_compare.clone());
+    #[test]
+    fn identical() {
+        let contents = vec![b'a'; 3 * COMPARE_BUFFER_SIZE + 1];
+        assert!(files_identical_with("identical", &contents, &contents));
+        assert!(files_identical_with("identical_empty", b"", b""));
+    }
 
-        let mut flags = Flag::empty();
-        flags |= Flag::SECURE;
+    #[test]
+    fn early_difference() {
+        let src = vec![b'a'; 3 * COMPARE_BUFFER_SIZE];
+        let mut dest = src.clone();
+        dest[0] = b'b';
+        assert!(!files_identical_with("early_difference", &src, &dest));
+    }
 
-        compare_and_copy_files(
-            files_to_compare.clone().par_iter(),
-            TEST_DIR,
-            TEST_DIR_OUT,
-            Flag::empty(),
-        );
+    #[test]
+    fn late_difference() {
+        let src = vec![b'a'; 3 * COMPARE_BUFFER_SIZE];
+        let mut dest = src.clone();
+        dest[3 * COMPARE_BUFFER_SIZE - 1] = b'b';
+        assert!(!files_identical_with("late_difference", &src, &dest));
+    }
+
+    #[test]
+    fn unequal_lengths() {
+        assert!(!files_identical_with("unequal_lengths", b"abc", b"abcd"));
+        assert!(!files_identical_with("unequal_lengths_empty", b"", b"a"));
+    }
 
-        compare_and_copy_files(files_to_compare.par_iter(), TEST_DIR, TEST_DIR_OUT, flags);
+    #[test]
+    fn missing_file() {
+        assert!(files_identical(
+            Path::new("src/main.rs"),
+            Path::new(&[TEST_DIR, "missing"].join("/"))
+        )
+        .is_err());
+    }
+}
 
-        let actual = fs::read([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
-        let expected = fs::read([TEST_DIR, "main.rs"].join("/")).unwrap();
-        assert_eq!(actual, expected);
+#[cfg(test)]
+mod test_backup_file {
+    use super::*;
 
-        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+    #[test]
+    fn renamed() {
+        const TEST_DIR: &str = "test_backup_file_renamed";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DIR, "a.txt.bak"].join("/"), b"old").unwrap();
+
+        backup_file(Path::new(&[TEST_DIR, "a.txt"].join("/")), ".bak").unwrap();
+
+        assert!(fs::metadata([TEST_DIR, "a.txt"].join("/")).is_err());
+        assert_eq!(fs::read([TEST_DIR, "a.txt.bak"].join("/")).unwrap(), b"new");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
     }
 
     #[test]
-    fn single_different() {
-        const TEST_DIR: &str = "src";
-        const TEST_DIR_OUT: &str = "test_compare_and_copy_files_single_different_out";
+    fn moved_to_backup() {
+        const TEST_DIR: &str = "test_backup_file_moved_to_backup";
 
-        fs::create_dir_all(TEST_DIR_OUT).unwrap();
-        fs::File::create([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
+        fs::create_dir_all([TEST_DIR, "dir"].join("/")).unwrap();
+        fs::write([TEST_DIR, "dir/a.txt"].join("/"), b"old").unwrap();
 
-        let file_to_compare = File {
-            path: PathBuf::from("main.rs"),
-            size: fs::metadata([TEST_DIR, "main.rs"].join("/")).unwrap().len(),
-        };
-        let mut files_to_compare = HashSet::new();
-        files_to_compare.insert(file_to_compare.clone());
+        let path = PathBuf::from([TEST_DIR, "dir/a.txt"].join("/"));
+        let backup = PathBuf::from([TEST_DIR, "backup/dir/a.txt"].join("/"));
+        move_to_backup(&path, &backup, || fs::remove_file(&path)).unwrap();
 
-        compare_and_copy_files(
-            files_to_compare.par_iter(),
-            TEST_DIR,
-            TEST_DIR_OUT,
-            Flag::empty(),
-        );
+        assert!(fs::metadata(&path).is_err());
+        assert_eq!(fs::read(&backup).unwrap(), b"old");
 
-        let actual = fs::read([TEST_DIR_OUT, "main.rs"].join("/")).unwrap();
-        let expected = fs::read([TEST_DIR, "main.rs"].join("/")).unwrap();
+        // Directories are only created in the backup, since their entries are moved on their own
+        let path = PathBuf::from([TEST_DIR, "dir"].join("/"));
+        let backup = PathBuf::from([TEST_DIR, "backup/dir"].join("/"));
+        move_to_backup(&path, &backup, || fs::remove_dir(&path)).unwrap();
 
-        assert_eq!(actual, expected);
+        assert!(fs::metadata(&path).is_err());
+        assert!(fs::metadata(&backup).unwrap().is_dir());
 
-        fs::remove_dir_all(TEST_DIR_OUT).unwrap();
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn missing() {
+        const TEST_DIR: &str = "test_backup_file_missing";
+
+        fs::create_dir_all(TEST_DIR).unwrap();
+
+        assert!(backup_file(Path::new(&[TEST_DIR, "a.txt"].join("/")), "~").is_ok());
+        assert!(fs::metadata([TEST_DIR, "a.txt~"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
     }
 }
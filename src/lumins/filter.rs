@@ -0,0 +1,747 @@
+//! Contains filters for choosing which files are copied, synchronized, or deleted
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern, PatternError};
+use log::error;
+use regex::Regex;
+
+use crate::lumins::file_ops::{Classification, Classifier, DefaultClassifier};
+
+/// Options for matching patterns, such that `*` and `?` do not match `/`, but `**` does
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Name of the files that list patterns to ignore in their directory and below
+pub const IGNORE_FILE_NAME: &str = ".lmsignore";
+
+/// Patterns of files that operating systems and editors leave behind
+pub const JUNK_PATTERNS: [&str; 9] = [
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "*.swp",
+    "*.swo",
+    "*~",
+    ".#*",
+];
+
+/// What to do with paths that match a rule
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Action {
+    Include,
+    Exclude,
+}
+
+/// What a rule matches paths with
+#[derive(Debug, Clone)]
+enum Matcher {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+/// A single glob pattern or regex to match paths against
+///
+/// Patterns ending in `/` only match directories, and patterns ending in `/***` match
+/// a directory and everything inside it, as in rsync. Patterns containing a `/` are matched
+/// against the end of the path relative to the source, or the whole path if they start
+/// with a `/`, and all other patterns are matched against the name of the file.
+/// Regexes can match any part of the path relative to the source
+#[derive(Debug, Clone)]
+pub struct Rule {
+    action: Action,
+    matcher: Matcher,
+    base: PathBuf,
+    dir_only: bool,
+    recursive: bool,
+    whole_path: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    /// Compiles `pattern` into a rule
+    ///
+    /// # Errors
+    /// If `pattern` is not a valid glob
+    pub fn new(action: Action, pattern: &str) -> Result<Self, PatternError> {
+        let whole_path = pattern.trim_end_matches('/').contains('/');
+        let recursive = pattern.ends_with("/***");
+        let pattern = pattern.strip_suffix("/***").unwrap_or(pattern);
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.starts_with('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        Ok(Rule {
+            action,
+            matcher: Matcher::Glob(Pattern::new(pattern)?),
+            base: PathBuf::new(),
+            dir_only,
+            recursive,
+            whole_path,
+            anchored,
+        })
+    }
+
+    /// Compiles `regex` into a rule
+    ///
+    /// # Errors
+    /// If `regex` is not a valid regex
+    pub fn new_regex(action: Action, regex: &str) -> Result<Self, regex::Error> {
+        Ok(Rule {
+            action,
+            matcher: Matcher::Regex(Regex::new(regex)?),
+            base: PathBuf::new(),
+            dir_only: false,
+            recursive: false,
+            whole_path: true,
+            anchored: false,
+        })
+    }
+
+    /// Gets the action of the rule
+    ///
+    /// # Returns
+    /// What to do with matching paths
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    /// Checks whether the rule matches `path`
+    ///
+    /// # Arguments
+    /// * `path`: path relative to the source
+    /// * `is_dir`: whether `path` is a directory
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        // Rules from ignore files only apply inside the directory of the file
+        let path = match path.strip_prefix(&self.base) {
+            Ok(path) if path.as_os_str().is_empty() => return false,
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+
+        if self.recursive {
+            // The matching directory can be `path` itself or any directory that it is in
+            return path
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty())
+                .enumerate()
+                .any(|(i, dir)| (i > 0 || is_dir) && self.matches_pattern(dir));
+        }
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        self.matches_pattern(path)
+    }
+
+    /// Checks whether the pattern or regex of the rule matches `path`,
+    /// regardless of what kind of file it is
+    ///
+    /// # Arguments
+    /// * `path`: path relative to the base of the rule
+    fn matches_pattern(&self, path: &Path) -> bool {
+        let pattern = match &self.matcher {
+            Matcher::Glob(pattern) => pattern,
+            // Paths that are not valid UTF-8 are matched with their invalid parts replaced
+            Matcher::Regex(regex) => return regex.is_match(&path.to_string_lossy()),
+        };
+
+        if self.anchored {
+            pattern.matches_path_with(path, MATCH_OPTIONS)
+        } else if self.whole_path {
+            // Unanchored patterns can match any trailing part of the path
+            (0..path.iter().count()).any(|i| {
+                let suffix = path.iter().skip(i).collect::<PathBuf>();
+                pattern.matches_path_with(&suffix, MATCH_OPTIONS)
+            })
+        } else {
+            match path.file_name() {
+                Some(name) => pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS),
+                None => false,
+            }
+        }
+    }
+}
+
+/// An ordered list of rules that decides which paths are left out
+///
+/// The first rule that matches a path decides whether it is included or excluded.
+/// Paths that match no rule take the action of the closest parent directory that matches
+/// a rule, and are included if there is none. Excluded directories are only traversed if
+/// there are include rules that could match something inside them
+///
+/// Rules from ignore files apply after all other rules, with rules from deeper directories,
+/// and later lines of the same file, applying first
+///
+/// Directories with an excluded name are always excluded, along with everything inside them,
+/// before any rule is applied
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Vec<Rule>,
+    ignore_rules: Vec<Rule>,
+    ignore_root: Option<PathBuf>,
+    excluded_dir_names: HashSet<OsString>,
+}
+
+impl Filter {
+    /// Adds a rule after all existing rules
+    ///
+    /// # Errors
+    /// If `pattern` is not a valid glob
+    pub fn add(&mut self, action: Action, pattern: &str) -> Result<(), PatternError> {
+        self.rules.push(Rule::new(action, pattern)?);
+        Ok(())
+    }
+
+    /// Adds a pattern of paths to include, even if a later rule excludes them
+    ///
+    /// # Errors
+    /// If `pattern` is not a valid glob
+    pub fn include(&mut self, pattern: &str) -> Result<(), PatternError> {
+        self.add(Action::Include, pattern)
+    }
+
+    /// Adds a pattern of paths to exclude
+    ///
+    /// # Errors
+    /// If `pattern` is not a valid glob
+    pub fn exclude(&mut self, pattern: &str) -> Result<(), PatternError> {
+        self.add(Action::Exclude, pattern)
+    }
+
+    /// Adds a regex rule after all existing rules
+    ///
+    /// # Errors
+    /// If `regex` is not a valid regex
+    pub fn add_regex(&mut self, action: Action, regex: &str) -> Result<(), regex::Error> {
+        self.rules.push(Rule::new_regex(action, regex)?);
+        Ok(())
+    }
+
+    /// Adds a regex of paths to include, even if a later rule excludes them
+    ///
+    /// # Errors
+    /// If `regex` is not a valid regex
+    pub fn include_regex(&mut self, regex: &str) -> Result<(), regex::Error> {
+        self.add_regex(Action::Include, regex)
+    }
+
+    /// Adds a regex of paths to exclude
+    ///
+    /// # Errors
+    /// If `regex` is not a valid regex
+    pub fn exclude_regex(&mut self, regex: &str) -> Result<(), regex::Error> {
+        self.add_regex(Action::Exclude, regex)
+    }
+
+    /// Adds all `JUNK_PATTERNS` to exclude, after all existing rules
+    pub fn exclude_junk(&mut self) {
+        for pattern in &JUNK_PATTERNS {
+            // This is safe to unwrap, since all junk patterns are valid globs
+            self.exclude(pattern).unwrap();
+        }
+    }
+
+    /// Excludes all directories called `name`, wherever they are, without traversing them
+    pub fn exclude_dir(&mut self, name: &str) {
+        self.excluded_dir_names.insert(OsString::from(name));
+    }
+
+    /// Adds the rules listed in the contents of a filter file, after all existing rules
+    ///
+    /// Each line holds one pattern, which is included if it starts with `+ `, and excluded
+    /// otherwise, with an optional `- ` prefix. Blank lines and lines starting with `#` are skipped
+    ///
+    /// # Errors
+    /// The line number and error of the first invalid pattern
+    pub fn add_lines(&mut self, contents: &str) -> Result<(), (usize, PatternError)> {
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (action, pattern) = if let Some(pattern) = line.strip_prefix("+ ") {
+                (Action::Include, pattern)
+            } else if let Some(pattern) = line.strip_prefix("- ") {
+                (Action::Exclude, pattern)
+            } else {
+                (Action::Exclude, line)
+            };
+
+            self.add(action, pattern).map_err(|e| (i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Excludes the patterns listed in the contents of an exclude file, after all existing rules
+    ///
+    /// Each line holds one pattern, which is always excluded, even if it starts with `+ `.
+    /// Blank lines and lines starting with `#` are skipped
+    ///
+    /// # Errors
+    /// The line number and error of the first invalid pattern
+    pub fn exclude_lines(&mut self, contents: &str) -> Result<(), (usize, PatternError)> {
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.exclude(line).map_err(|e| (i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Reads ignore files in the directories of `root` while traversing,
+    /// even if another directory is being traversed
+    ///
+    /// Each line of an ignore file holds one pattern relative to the directory of the file,
+    /// which is included if it starts with `!`, and excluded otherwise.
+    /// Blank lines and lines starting with `#` are skipped
+    ///
+    /// # Arguments
+    /// * `root`: the directory to find ignore files in, usually the source
+    pub fn use_ignore_files(&mut self, root: &Path) {
+        self.ignore_root = Some(root.to_path_buf());
+    }
+
+    /// Gets the rules, in the order they are applied
+    ///
+    /// # Returns
+    /// The rules of the Filter
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Checks whether `path` is excluded
+    ///
+    /// # Arguments
+    /// * `path`: path relative to the source
+    /// * `is_dir`: whether `path` is a directory
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if self.in_excluded_dir(path, is_dir) {
+            return true;
+        }
+
+        let action = self.action(path, is_dir).or_else(|| {
+            path.ancestors()
+                .skip(1)
+                .take_while(|parent| !parent.as_os_str().is_empty())
+                .find_map(|parent| self.action(parent, true))
+        });
+
+        action == Some(Action::Exclude)
+    }
+
+    /// Finds the action of the first rule that matches `path`
+    ///
+    /// # Arguments
+    /// * `path`: path relative to the source
+    /// * `is_dir`: whether `path` is a directory
+    ///
+    /// # Returns
+    /// * Some: The action of the first matching rule
+    /// * None: If no rule matches
+    fn action(&self, path: &Path, is_dir: bool) -> Option<Action> {
+        self.rules
+            .iter()
+            .chain(&self.ignore_rules)
+            .find(|rule| rule.matches(path, is_dir))
+            .map(Rule::action)
+    }
+
+    /// Checks whether `path`, or any directory that it is in, has an excluded name
+    ///
+    /// # Arguments
+    /// * `path`: path relative to the source
+    /// * `is_dir`: whether `path` is a directory
+    fn in_excluded_dir(&self, path: &Path, is_dir: bool) -> bool {
+        if self.excluded_dir_names.is_empty() {
+            return false;
+        }
+
+        let dirs = if is_dir {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        dirs.iter()
+            .any(|name| self.excluded_dir_names.contains(name))
+    }
+
+    /// Checks whether there are any include rules
+    fn has_includes(&self) -> bool {
+        self.rules
+            .iter()
+            .chain(&self.ignore_rules)
+            .any(|rule| rule.action == Action::Include)
+    }
+}
+
+/// Parses the contents of the ignore file in `base`
+///
+/// # Returns
+/// * Ok: The rules of the file, with the last line first
+/// * Err: The line number and error of the first invalid pattern
+fn parse_ignore_file(contents: &str, base: &Path) -> Result<Vec<Rule>, (usize, PatternError)> {
+    let mut rules = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (action, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (Action::Include, pattern),
+            None => (Action::Exclude, line),
+        };
+
+        let mut rule = Rule::new(action, pattern).map_err(|e| (i + 1, e))?;
+        rule.base = base.to_path_buf();
+        rules.push(rule);
+    }
+
+    // Later lines take precedence over earlier ones
+    rules.reverse();
+    Ok(rules)
+}
+
+impl Classifier for Filter {
+    fn classify(&self, path: &Path, metadata: &fs::Metadata) -> Classification {
+        // Directories with an excluded name are never traversed, even if there are includes.
+        // Only the name of `path` needs to be checked, since its parents were already traversed
+        if metadata.is_dir()
+            && path
+                .file_name()
+                .is_some_and(|name| self.excluded_dir_names.contains(name))
+        {
+            return Classification::Other;
+        }
+
+        if self.is_excluded(path, metadata.is_dir()) {
+            // Excluded directories may still contain included paths
+            if metadata.is_dir() && self.has_includes() {
+                return Classification::PassThrough;
+            }
+            return Classification::Other;
+        }
+        DefaultClassifier.classify(path, metadata)
+    }
+
+    fn enter(&self, path: &Path) -> Option<Self> {
+        let ignore_file = self.ignore_root.as_ref()?.join(path).join(IGNORE_FILE_NAME);
+        let contents = fs::read_to_string(&ignore_file).ok()?;
+
+        match parse_ignore_file(&contents, path) {
+            Ok(mut ignore_rules) => {
+                let mut filter = self.clone();
+                ignore_rules.append(&mut filter.ignore_rules);
+                filter.ignore_rules = ignore_rules;
+                Some(filter)
+            }
+            Err((line, e)) => {
+                error!("Error -- Reading {:?}:{}: {}", ignore_file, line, e);
+                None
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_rule {
+    use super::*;
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(Rule::new(Action::Exclude, "a[").is_err());
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Rule::new_regex(Action::Exclude, "a(").is_err());
+    }
+
+    #[test]
+    fn regex() {
+        let rule = Rule::new_regex(Action::Exclude, r"(^|/)build-[0-9]{8}$").unwrap();
+
+        assert!(rule.matches(Path::new("build-20200101"), true));
+        assert!(rule.matches(Path::new("a/build-20200101"), true));
+        assert!(!rule.matches(Path::new("a/build-2020"), true));
+        assert!(!rule.matches(Path::new("a/build-20200101/b"), false));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn regex_invalid_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let rule = Rule::new_regex(Action::Exclude, r"^a/.*\.txt$").unwrap();
+        let path = Path::new("a").join(OsStr::from_bytes(b"b\xff.txt"));
+
+        assert!(rule.matches(&path, false));
+    }
+
+    #[test]
+    fn file_name() {
+        let rule = Rule::new(Action::Exclude, "*.iso").unwrap();
+
+        assert!(rule.matches(Path::new("a.iso"), false));
+        assert!(rule.matches(Path::new("a/b/c.iso"), false));
+        assert!(!rule.matches(Path::new("a.iso/b"), false));
+        assert!(!rule.matches(Path::new("a.txt"), false));
+    }
+
+    #[test]
+    fn dir_only() {
+        let rule = Rule::new(Action::Exclude, "target/").unwrap();
+
+        assert!(rule.matches(Path::new("target"), true));
+        assert!(rule.matches(Path::new("a/target"), true));
+        assert!(!rule.matches(Path::new("target"), false));
+    }
+
+    #[test]
+    fn whole_path() {
+        let rule = Rule::new(Action::Exclude, "a/*.txt").unwrap();
+
+        assert!(rule.matches(Path::new("a/b.txt"), false));
+        assert!(!rule.matches(Path::new("a/b/c.txt"), false));
+        assert!(rule.matches(Path::new("b/a/b.txt"), false));
+        assert!(!rule.matches(Path::new("b/ca/b.txt"), false));
+    }
+
+    #[test]
+    fn anchored() {
+        let rule = Rule::new(Action::Exclude, "/a?c").unwrap();
+
+        assert!(rule.matches(Path::new("abc"), false));
+        assert!(!rule.matches(Path::new("b/abc"), false));
+
+        let rule = Rule::new(Action::Exclude, "/a/*.txt").unwrap();
+
+        assert!(rule.matches(Path::new("a/b.txt"), false));
+        assert!(!rule.matches(Path::new("b/a/b.txt"), false));
+    }
+
+    #[test]
+    fn dir_and_contents() {
+        let rule = Rule::new(Action::Include, "a/b/***").unwrap();
+
+        assert!(rule.matches(Path::new("a/b"), true));
+        assert!(rule.matches(Path::new("a/b/c.txt"), false));
+        assert!(rule.matches(Path::new("a/b/c/d.txt"), false));
+        assert!(rule.matches(Path::new("c/a/b/d.txt"), false));
+        assert!(!rule.matches(Path::new("a/b"), false));
+        assert!(!rule.matches(Path::new("a/bc/d.txt"), false));
+        assert!(!rule.matches(Path::new("a"), true));
+
+        let rule = Rule::new(Action::Include, "/b/***").unwrap();
+
+        assert!(rule.matches(Path::new("b/c.txt"), false));
+        assert!(!rule.matches(Path::new("a/b/c.txt"), false));
+    }
+
+    #[test]
+    fn recursive_wildcard() {
+        let rule = Rule::new(Action::Exclude, "a/**/*.txt").unwrap();
+
+        assert!(rule.matches(Path::new("a/b.txt"), false));
+        assert!(rule.matches(Path::new("a/b/c/d.txt"), false));
+        assert!(!rule.matches(Path::new("b/c.txt"), false));
+    }
+}
+
+#[cfg(test)]
+mod test_filter {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let filter = Filter::default();
+
+        assert!(!filter.is_excluded(Path::new("a"), false));
+    }
+
+    #[test]
+    fn excludes() {
+        let mut filter = Filter::default();
+        filter.exclude("node_modules/").unwrap();
+        filter.exclude("*.iso").unwrap();
+
+        assert!(filter.is_excluded(Path::new("a/node_modules"), true));
+        assert!(filter.is_excluded(Path::new("a/b.iso"), false));
+        assert!(!filter.is_excluded(Path::new("a/b.txt"), false));
+    }
+
+    #[test]
+    fn first_match() {
+        let mut filter = Filter::default();
+        filter.include("*.json").unwrap();
+        filter.exclude("*").unwrap();
+
+        assert!(!filter.is_excluded(Path::new("a.json"), false));
+        assert!(filter.is_excluded(Path::new("a.txt"), false));
+
+        let mut filter = Filter::default();
+        filter.exclude("*").unwrap();
+        filter.include("*.json").unwrap();
+
+        assert!(filter.is_excluded(Path::new("a.json"), false));
+        assert_eq!(filter.rules().len(), 2);
+        assert_eq!(filter.rules()[0].action(), Action::Exclude);
+    }
+
+    #[test]
+    fn dir_and_contents() {
+        let mut filter = Filter::default();
+        filter.include("photos/***").unwrap();
+        filter.exclude("*").unwrap();
+
+        assert!(!filter.is_excluded(Path::new("photos"), true));
+        assert!(!filter.is_excluded(Path::new("photos/a.jpg"), false));
+        assert!(!filter.is_excluded(Path::new("photos/2020/a.jpg"), false));
+        assert!(filter.is_excluded(Path::new("a.jpg"), false));
+    }
+
+    #[test]
+    fn parent_dir() {
+        let mut filter = Filter::default();
+        filter.include("*.json").unwrap();
+        filter.include("keep/").unwrap();
+        filter.exclude("build/").unwrap();
+
+        assert!(filter.is_excluded(Path::new("build"), true));
+        assert!(filter.is_excluded(Path::new("build/a.txt"), false));
+        assert!(filter.is_excluded(Path::new("build/a/b.txt"), false));
+        assert!(!filter.is_excluded(Path::new("build/a/b.json"), false));
+        assert!(!filter.is_excluded(Path::new("build/keep/a.txt"), false));
+        assert!(!filter.is_excluded(Path::new("a/b.txt"), false));
+    }
+
+    #[test]
+    fn add_lines() {
+        let mut filter = Filter::default();
+        let lines = "# Comment\n\n+ keep.txt\n- build/\n*.iso\n";
+
+        assert!(filter.add_lines(lines).is_ok());
+        assert_eq!(filter.rules().len(), 3);
+        assert_eq!(filter.rules()[0].action(), Action::Include);
+        assert_eq!(filter.rules()[1].action(), Action::Exclude);
+        assert_eq!(filter.rules()[2].action(), Action::Exclude);
+
+        assert!(!filter.is_excluded(Path::new("build/keep.txt"), false));
+        assert!(filter.is_excluded(Path::new("build/a.txt"), false));
+        assert!(filter.is_excluded(Path::new("a.iso"), false));
+    }
+
+    #[test]
+    fn add_lines_invalid() {
+        let mut filter = Filter::default();
+
+        assert_eq!(filter.add_lines("a\n\nb[\n").unwrap_err().0, 3);
+    }
+
+    #[test]
+    fn exclude_lines() {
+        let mut filter = Filter::default();
+        let lines = "# Comment\n\nbuild/\n+ keep.txt\n*.iso  \n";
+
+        assert!(filter.exclude_lines(lines).is_ok());
+        assert_eq!(filter.rules().len(), 3);
+        assert!(filter
+            .rules()
+            .iter()
+            .all(|rule| rule.action() == Action::Exclude));
+
+        assert!(filter.is_excluded(Path::new("build/a.txt"), false));
+        assert!(filter.is_excluded(Path::new("+ keep.txt"), false));
+        assert!(!filter.is_excluded(Path::new("keep.txt"), false));
+        assert!(filter.is_excluded(Path::new("a.iso"), false));
+
+        assert_eq!(filter.exclude_lines("a\n# b[\nc[\n").unwrap_err().0, 3);
+    }
+
+    #[test]
+    fn exclude_dir() {
+        let mut filter = Filter::default();
+        filter.include("*.json").unwrap();
+        filter.exclude_dir(".git");
+
+        assert!(filter.is_excluded(Path::new("a/.git"), true));
+        assert!(filter.is_excluded(Path::new("a/.git/b.json"), false));
+        assert!(!filter.is_excluded(Path::new("a/.git.json"), false));
+        assert!(!filter.is_excluded(Path::new(".git"), false));
+    }
+
+    #[test]
+    fn exclude_junk() {
+        let mut filter = Filter::default();
+        filter.include("keep~").unwrap();
+        filter.exclude_junk();
+
+        assert_eq!(filter.rules().len(), JUNK_PATTERNS.len() + 1);
+        assert!(filter.is_excluded(Path::new("a/.DS_Store"), false));
+        assert!(filter.is_excluded(Path::new("Thumbs.db"), false));
+        assert!(filter.is_excluded(Path::new("a/.b.txt.swp"), false));
+        assert!(filter.is_excluded(Path::new("a/b.txt~"), false));
+        assert!(!filter.is_excluded(Path::new("a/keep~"), false));
+        assert!(!filter.is_excluded(Path::new("a/b.txt"), false));
+    }
+
+    #[test]
+    fn regexes() {
+        let mut filter = Filter::default();
+        filter.include_regex(r"\.json$").unwrap();
+        filter.exclude_regex(r"^build-[0-9]{8}$").unwrap();
+
+        assert!(filter.is_excluded(Path::new("build-20200101"), true));
+        assert!(filter.is_excluded(Path::new("build-20200101/a.txt"), false));
+        assert!(!filter.is_excluded(Path::new("build-20200101/a.json"), false));
+        assert!(!filter.is_excluded(Path::new("a/build-20200101"), true));
+    }
+
+    #[test]
+    fn ignore_files() {
+        const TEST_DIR: &str = "test_filter_ignore_files";
+
+        fs::create_dir_all([TEST_DIR, "a/b"].join("/")).unwrap();
+        fs::write(
+            [TEST_DIR, "a", IGNORE_FILE_NAME].join("/"),
+            "*.log\n!keep.log\n",
+        )
+        .unwrap();
+        fs::write([TEST_DIR, "a/b", IGNORE_FILE_NAME].join("/"), "!*.log\n").unwrap();
+
+        let mut filter = Filter::default();
+        filter.use_ignore_files(Path::new(TEST_DIR));
+
+        assert!(filter.enter(Path::new("")).is_none());
+
+        let filter_a = filter.enter(Path::new("a")).unwrap();
+        let filter_b = filter_a.enter(Path::new("a/b")).unwrap();
+
+        assert!(filter_a.is_excluded(Path::new("a/x.log"), false));
+        assert!(!filter_a.is_excluded(Path::new("a/keep.log"), false));
+        assert!(filter_a.is_excluded(Path::new("a/c/x.log"), false));
+        assert!(!filter_a.is_excluded(Path::new("c/x.log"), false));
+        assert!(!filter_b.is_excluded(Path::new("a/b/x.log"), false));
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
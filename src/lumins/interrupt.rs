@@ -0,0 +1,26 @@
+//! Stops starting new file operations once the program is interrupted, such as by Ctrl-C,
+//! so that those already running can finish without leaving anything half-written
+
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code after being interrupted, which is 128 + SIGINT like most shells use
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Whether the program was interrupted, set by `interrupt`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Interrupts the program, so that no new file operations are started
+///
+/// Interrupting it a second time exits immediately, without waiting for running operations
+pub fn interrupt() {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    eprintln!("Interrupted -- Finishing the files in progress, interrupt again to stop now");
+}
+
+/// Checks whether the program was interrupted, after which no new file operations are started
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
@@ -1,4 +1,14 @@
+pub mod cache;
+pub mod chmod;
+pub mod conflict;
 pub mod core;
+pub mod file_flags;
 pub mod file_ops;
+pub mod filter;
+pub mod interrupt;
 pub mod parse;
 pub mod progress;
+pub mod retry;
+pub mod stats;
+pub mod throttle;
+pub mod xattrs;
@@ -2,31 +2,163 @@
 
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Read};
+use std::path::{self, Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use bitflags::bitflags;
 use clap::ArgMatches;
 use env_logger::Builder;
+use glob::Pattern;
+use indicatif::ProgressDrawTarget;
 use log::LevelFilter;
+use serde::{Deserialize, Serialize};
 
-use crate::progress::PROGRESS_BAR;
+use crate::lumins::cache;
+use crate::lumins::chmod::Chmod;
+use crate::lumins::conflict::{self, ConflictHandler};
+use crate::lumins::file_ops;
+use crate::lumins::filter::{Action, Filter};
+use crate::lumins::stats;
+use crate::lumins::throttle::Throttle;
+use crate::progress::{self, PROGRESS_BAR};
+
+/// Name of the config file that is read from the source, unless another one is given
+pub const CONFIG_FILE_NAME: &str = "lms.toml";
 
 bitflags! {
     /// Enum to represent command line flags
-    pub struct Flag: u32 {
-        const NO_DELETE     = 0x1;
-        const SECURE        = 0x2;
-        const VERBOSE       = 0x4;
-        const SEQUENTIAL    = 0x8;
+    pub struct Flag: u64 {
+        const NO_DELETE         = 0x1;
+        /// Same as `HashAlgo::Blake2` in `Options::hash`, which it is mapped to while parsing
+        #[deprecated(note = "use HashAlgo::Blake2 in Options::hash instead")]
+        const SECURE            = 0x2;
+        const VERBOSE           = 0x4;
+        const SEQUENTIAL        = 0x8;
+        /// Deletes everything before copying, which frees space in the destination first
+        const DELETE_BEFORE     = 0x10;
+        /// Deletes files and symlinks before copying, and directories after copying,
+        /// which is the default
+        const DELETE_DURING     = 0x20;
+        /// Only deletes entries replaced by a source entry of the same path before copying,
+        /// and everything else after copying, so the destination keeps as much as possible
+        const DELETE_AFTER      = 0x40;
+        const QUICK_CHECK       = 0x80;
+        /// Deletes the paths left out by the filter, size, or time range from the destination,
+        /// even if `NO_DELETE` is set
+        const DELETE_EXCLUDED   = 0x100;
+        const JSON              = 0x200;
+        const ONE_FILE_SYSTEM   = 0x400;
+        const FOLLOW_SYMLINKS   = 0x800;
+        const PRESERVE_PERMS    = 0x1000;
+        /// Leaves out source directories without any files or symlinks left in them,
+        /// and deletes them from the destination if they are left empty there
+        const PRUNE_EMPTY_DIRS  = 0x2000;
+        const QUIET             = 0x4000;
+        /// Neither updates nor replaces files already in the destination, even if their size
+        /// differs, so only new files are copied
+        const IGNORE_EXISTING   = 0x8000;
+        const DETAILED_PROGRESS = 0x10000;
+        /// Neither updates nor replaces files modified in the destination more than
+        /// `Options::modify_window` after the source file
+        const UPDATE            = 0x20000;
+        /// Gives everything in the destination the owner and group of the source
+        const PRESERVE_OWNER    = 0x40000;
+        const CHECKSUM          = 0x80000;
+        const QUICK_HASH        = 0x100000;
+        const MMAP              = 0x200000;
+        const COMPARE_BYTES     = 0x400000;
+        const INTERACTIVE       = 0x800000;
+        const PLAIN_PROGRESS    = 0x1000000;
+        const HASH_CACHE        = 0x2000000;
+        const VERIFY_COPIES     = 0x4000000;
+        const FORCE_COPY        = 0x8000000;
+        const BACKUP            = 0x10000000;
+        const SPARSE            = 0x20000000;
+        const PRESERVE_FLAGS    = 0x40000000;
+        const PRESERVE_CRTIMES  = 0x80000000;
+        const PRESERVE_XATTRS   = 0x100000000;
+        /// Only updates files that differ if the source file was modified after the
+        /// destination file, so whichever is newer is kept
+        const NEWER             = 0x200000000;
+        const RESUME            = 0x400000000;
+        const INPLACE           = 0x800000000;
+        const PARTIAL           = 0x1000000000;
+        const STATS_ONLY        = 0x2000000000;
+        const TRASH             = 0x4000000000;
+        const PREALLOCATE       = 0x8000000000;
+        const DEDUP             = 0x10000000000;
+        const HARD_LINKS        = 0x20000000000;
     }
 }
 
+/// Enum to represent the hash function used for comparing files
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum HashAlgo {
+    /// Seahash, a fast non-cryptographic hash function
+    #[default]
+    Seahash,
+    /// BLAKE2b, a cryptographic hash function
+    Blake2,
+    /// SHA-256, a cryptographic hash function
+    Sha256,
+    /// XXH3, a very fast non-cryptographic hash function
+    Xxhash,
+    /// BLAKE3, a cryptographic hash function that is much faster than BLAKE2b
+    Blake3,
+    /// CRC-32, a checksum that many other tools also produce
+    Crc32,
+}
+
+/// Enum to represent whether copied files are reflinks, which share their data with the source
+/// on copy-on-write file systems until either is changed
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub enum Reflink {
+    /// Reflinks files where possible, and copies them otherwise
+    #[default]
+    Auto,
+    /// Reflinks all files, failing those that cannot be
+    Always,
+    /// Copies all files, without sharing any of their data
+    Never,
+}
+
+/// Enum to represent when copied files are flushed to disk
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub enum Fsync {
+    /// Leaves flushing to the operating system
+    #[default]
+    Never,
+    /// Flushes each copied file, and its directory once it is renamed into place
+    Each,
+    /// Flushes the whole file system of the destination once everything is copied
+    AtEnd,
+}
+
+/// Defaults read from a config file, which arguments take precedence over
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    /// Names of flags to set, as given in arguments without the leading dashes
+    flags: Vec<String>,
+    /// Name of the hash function, as given to `--hash`
+    hash: Option<String>,
+    /// Patterns of paths to include, even if an exclude pattern of the config matches them
+    include: Vec<String>,
+    /// Patterns of paths to exclude
+    exclude: Vec<String>,
+}
+
 /// Enum to represent subcommand type
 #[derive(Eq, PartialEq, Clone)]
 pub enum SubCommandType {
     Copy,
+    Move,
     Synchronize,
     Remove,
+    Verify,
+    Diff,
+    Checksum,
 }
 
 /// Struct to represent subcommands
@@ -36,10 +168,117 @@ pub struct SubCommand<'a> {
     pub sub_command_type: SubCommandType,
 }
 
+/// Struct to represent options that carry a value
+#[derive(Default, Debug)]
+pub struct Options {
+    /// Paths relative to the source to use instead of traversing the whole source,
+    /// which should be used with `Flag::NO_DELETE` so that nothing else is deleted
+    pub files: Option<Vec<PathBuf>>,
+    /// Rules for leaving out paths in both the source and destination
+    pub filter: Filter,
+    /// Files smaller than this many bytes are treated as if they are not in the source
+    pub min_size: Option<u64>,
+    /// Files larger than this many bytes are treated as if they are not in the source
+    pub max_size: Option<u64>,
+    /// Files modified before this time are treated as if they are not in the source
+    pub newer_than: Option<SystemTime>,
+    /// Files modified after this time are treated as if they are not in the source
+    pub older_than: Option<SystemTime>,
+    /// Hash function used for comparing files in the source and destination
+    pub hash: HashAlgo,
+    /// Directories to hard link files from if they are the same there as in the source,
+    /// tried in order, which falls back to copying them if they cannot be linked
+    pub link_dest: Vec<String>,
+    /// Largest difference in modification times that is still treated as the same time
+    pub modify_window: Duration,
+    /// Decides what happens to files that differ in the source and destination,
+    /// which are all overwritten if None
+    pub conflict_handler: Option<ConflictHandler>,
+    /// File to keep the hashes of files in across runs, if any
+    pub cache_file: Option<PathBuf>,
+    /// Suffix that overwritten files in the destination are renamed with, if they are kept
+    pub backup_suffix: Option<String>,
+    /// Directory that overwritten and deleted entries in the destination are moved into,
+    /// keeping their paths in the destination, if they are kept.
+    /// It is left out of the destination if it is inside it
+    pub backup_dir: Option<PathBuf>,
+    /// User and group ids that everything copied is given, instead of those of the source
+    pub chown: Option<(Option<u32>, Option<u32>)>,
+    /// Changes to the permissions of everything copied, instead of keeping those of the source
+    pub chmod: Option<Chmod>,
+    /// Whether copied files are reflinks of the source files
+    pub reflink: Reflink,
+    /// Name of the directories next to partially copied files that they are kept in, if any
+    pub partial_dir: Option<String>,
+    /// When copied files are flushed to disk
+    pub fsync: Fsync,
+    /// Size of the buffer that files are copied through, if they are copied by hand
+    pub buffer_size: Option<usize>,
+    /// Maximum number of bytes copied per second, if limited
+    pub bwlimit: Option<u64>,
+    /// Number of times to retry an operation on a file that failed with a transient error
+    pub retries: u32,
+    /// Number of bytes at the start and end of files hashed by `Flag::QUICK_HASH`,
+    /// or `file_ops::DEFAULT_QUICK_HASH_SIZE` if None
+    pub quick_hash_size: Option<u64>,
+    /// Limits the combined rate of all copies to `bwlimit`, once copying starts
+    pub throttle: Throttle,
+}
+
+impl Options {
+    /// Checks whether a file of `size` bytes is within `min_size` and `max_size`
+    pub fn in_size_range(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min_size| size >= min_size)
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+
+    /// Gets a pattern that matches the cache file if it is in `dest`, to leave it out of `dest`
+    pub fn cache_file_pattern(&self, dest: &str) -> Option<String> {
+        let path = self.cache_file.as_ref()?.strip_prefix(dest).ok()?;
+        Some(["/", &Pattern::escape(&path.to_string_lossy())].concat())
+    }
+
+    /// Checks whether there is a limit on the modification time of files
+    pub fn has_time_range(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+
+    /// Checks whether a file modified at `modified` is within `newer_than` and `older_than`
+    pub fn in_time_range(&self, modified: SystemTime) -> bool {
+        self.newer_than
+            .is_none_or(|newer_than| modified >= newer_than)
+            && self
+                .older_than
+                .is_none_or(|older_than| modified <= older_than)
+    }
+}
+
 /// Struct to represent the result of parsing args
 pub struct ParseResult<'a> {
     pub sub_command: SubCommand<'a>,
     pub flags: Flag,
+    pub options: Options,
+    pub eta_smoothing: f64,
+    /// Minimum time between two redraws of the progress bar, if it is only redrawn on a timer
+    pub progress_refresh: Option<Duration>,
+    /// Number of threads to copy, compare, and delete files with, if limited
+    pub threads: Option<usize>,
+}
+
+/// Why the command line arguments could not be parsed
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ParseError {
+    /// An argument or option has an invalid value
+    Argument,
+    /// The source or destination is not a valid directory, or could not be created
+    Path,
+}
+
+/// Parsing the value of a single option only fails if the value is invalid
+impl From<()> for ParseError {
+    fn from(_: ()) -> Self {
+        ParseError::Argument
+    }
 }
 
 /// Parses command line arguments for source and destination folders and
@@ -49,13 +288,58 @@ pub struct ParseResult<'a> {
 /// This function will return an error in the following situations,
 /// but is not limited to just these cases:
 /// * The source folder is not a valid directory
+/// * The source glob of cp does not match anything
 /// * The destination folder could not be created
-pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
+/// * An option has an invalid value
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ParseError> {
     // These are safe to unwrap since subcommands are required
     let sub_command_name = args.subcommand_name().unwrap();
     let args = args.subcommand_matches(sub_command_name).unwrap();
 
-    const FLAG_NAMES: [&str; 4] = ["nodelete", "secure", "verbose", "sequential"];
+    const FLAG_NAMES: [&str; 42] = [
+        "nodelete",
+        "secure",
+        "verbose",
+        "sequential",
+        "delete-before",
+        "delete-during",
+        "delete-after",
+        "quick-check",
+        "delete-excluded",
+        "json",
+        "one-file-system",
+        "copy-links",
+        "perms",
+        "prune-empty-dirs",
+        "quiet",
+        "ignore-existing",
+        "progress-per-file",
+        "update",
+        "owner",
+        "checksum",
+        "quick-hash",
+        "mmap",
+        "compare-bytes",
+        "interactive",
+        "plain",
+        "cache",
+        "verify",
+        "force-copy",
+        "backup",
+        "sparse",
+        "flags",
+        "crtimes",
+        "xattrs",
+        "newer",
+        "resume",
+        "inplace",
+        "partial",
+        "stats-only",
+        "trash",
+        "preallocate",
+        "dedup",
+        "hard-links",
+    ];
 
     // Parse for flags
     let mut flags = Flag::empty();
@@ -65,6 +349,117 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
         }
     }
 
+    // Archive mode implies the flags for everything rsync's -a preserves that is not always kept
+    if args.is_present("archive") {
+        flags |= archive_flags();
+        if !flags.contains(Flag::QUIET) {
+            for warning in archive_warnings() {
+                eprintln!("Argument Warning -- archive {}", warning);
+            }
+        }
+    }
+
+    // Warn about metadata that cannot be preserved on this platform
+    if !flags.contains(Flag::QUIET) {
+        if flags.contains(Flag::PRESERVE_FLAGS) && !cfg!(target_os = "macos") {
+            eprintln!("Argument Warning -- flags are only preserved on macOS");
+        }
+        if flags.contains(Flag::PRESERVE_XATTRS) && !cfg!(unix) {
+            eprintln!("Argument Warning -- xattrs are only preserved on Unix");
+        }
+    }
+
+    // Parse for the ETA smoothing factor
+    let eta_smoothing = match args.value_of("eta-smoothing") {
+        Some(value) => match value.parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor <= 1.0 => factor,
+            _ => {
+                eprintln!(
+                    "Argument Error -- eta-smoothing {} is not a number in (0, 1]",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => progress::DEFAULT_ETA_SMOOTHING,
+    };
+
+    // Parse for the time between redraws of the progress bar, which is given in milliseconds
+    let progress_refresh = match args.value_of("progress-refresh") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => {
+                eprintln!(
+                    "Argument Error -- progress-refresh {} is not a positive number of milliseconds",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => None,
+    };
+
+    // Parse for the bandwidth limit, which is given in KB per second
+    let bwlimit = match args.value_of("bwlimit") {
+        Some(value) => match value.parse::<f64>() {
+            Ok(kbps) if kbps * 1024.0 >= 1.0 => Some((kbps * 1024.0) as u64),
+            _ => {
+                eprintln!(
+                    "Argument Error -- bwlimit {} is not a positive number of KB/s",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => None,
+    };
+
+    // Parse for the number of times to retry failed copies
+    let retries = match args.value_of("retries") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(retries) => retries,
+            Err(_) => {
+                eprintln!(
+                    "Argument Error -- retries {} is not a non-negative integer",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => 0,
+    };
+
+    // Parse for the size of the blocks hashed by quick-hash, which is given in MiB
+    let quick_hash_size = match args.value_of("quick-hash-size") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(mib) if mib > 0 => Some(mib * 1024 * 1024),
+            _ => {
+                eprintln!(
+                    "Argument Error -- quick-hash-size {} is not a positive integer",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => None,
+    };
+
+    // Parse for the number of threads, where 0 leaves it to Rayon to use all cores
+    let threads = match args.value_of("threads") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(0) => None,
+            Ok(threads) => Some(threads),
+            Err(_) => {
+                eprintln!(
+                    "Argument Error -- threads {} is not a non-negative integer",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        },
+        None => None,
+    };
+
     // These values are safe to unwrap since the args are required
     let mut sub_command = match sub_command_name {
         "cp" => SubCommand {
@@ -72,6 +467,11 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
             dest: vec![args.value_of("DESTINATION").unwrap().to_string()],
             sub_command_type: SubCommandType::Copy,
         },
+        "mv" => SubCommand {
+            src: Some(args.value_of("SOURCE").unwrap()),
+            dest: vec![args.value_of("DESTINATION").unwrap().to_string()],
+            sub_command_type: SubCommandType::Move,
+        },
         "rm" => SubCommand {
             src: None,
             dest: args
@@ -86,8 +486,313 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
             dest: vec![args.value_of("DESTINATION").unwrap().to_string()],
             sub_command_type: SubCommandType::Synchronize,
         },
-        _ => return Err(()),
+        "verify" => SubCommand {
+            src: Some(args.value_of("SOURCE").unwrap()),
+            dest: vec![args.value_of("DESTINATION").unwrap().to_string()],
+            sub_command_type: SubCommandType::Verify,
+        },
+        "diff" => SubCommand {
+            src: Some(args.value_of("SOURCE").unwrap()),
+            dest: vec![args.value_of("DESTINATION").unwrap().to_string()],
+            sub_command_type: SubCommandType::Diff,
+        },
+        "checksum" => SubCommand {
+            src: Some(args.value_of("DIRECTORY").unwrap()),
+            dest: Vec::new(),
+            sub_command_type: SubCommandType::Checksum,
+        },
+        _ => return Err(ParseError::Argument),
+    };
+
+    let mut options = Options {
+        bwlimit,
+        retries,
+        quick_hash_size,
+        ..Options::default()
+    };
+
+    // Expand a glob in the cp source into the list of files to copy,
+    // unless it is the name of something that exists, which is copied as it is
+    if sub_command.sub_command_type == SubCommandType::Copy
+        && is_glob(sub_command.src.unwrap())
+        && fs::symlink_metadata(sub_command.src.unwrap()).is_err()
+    {
+        let (base, files) = expand_glob(sub_command.src.unwrap()).map_err(|_| ParseError::Path)?;
+        sub_command.src = Some(base);
+        options.files = Some(files);
+    }
+
+    // Read the list of paths to copy, without traversing the rest of the source or deleting anything
+    if let Some(path) = args.value_of("files-from") {
+        if options.files.is_some() {
+            eprintln!("Argument Error -- files-from cannot be used with a glob source");
+            return Err(ParseError::Argument);
+        }
+        options.files = Some(parse_files_from(path)?);
+        flags |= Flag::NO_DELETE;
+    }
+
+    // Parse for include and exclude patterns, and the files they are read from, in the order given
+    let mut rules = Vec::new();
+    for &name in &[
+        "include",
+        "exclude",
+        "include-regex",
+        "exclude-regex",
+        "filter-file",
+        "exclude-from",
+    ] {
+        if let (Some(indices), Some(values)) = (args.indices_of(name), args.values_of(name)) {
+            rules.extend(indices.zip(values).map(|(i, value)| (i, name, value)));
+        }
+    }
+    rules.sort_by_key(|&(i, ..)| i);
+
+    for (_, name, value) in rules {
+        let result = match name {
+            "include" => options.filter.include(value).map_err(|e| e.to_string()),
+            "exclude" => options.filter.exclude(value).map_err(|e| e.to_string()),
+            "include-regex" => options
+                .filter
+                .include_regex(value)
+                .map_err(|e| e.to_string()),
+            "exclude-regex" => options
+                .filter
+                .exclude_regex(value)
+                .map_err(|e| e.to_string()),
+            "filter-file" => {
+                parse_filter_file(value, &mut options.filter)?;
+                continue;
+            }
+            _ => {
+                parse_exclude_from(value, &mut options.filter)?;
+                continue;
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("Argument Error -- {} {}: {}", name, value, e);
+            return Err(ParseError::Argument);
+        }
+    }
+
+    // Read defaults from the config file given, or else from the one in the source if there is
+    // one. Its flags are added to the flags given, and its rules apply after the rules given.
+    // Checksums leave it out, so that its hash doesn't replace CRC-32
+    let config_path = match (args.value_of("config"), sub_command.src) {
+        (Some(path), _) => Some(PathBuf::from(path)),
+        (None, Some(src))
+            if sub_command.sub_command_type != SubCommandType::Move
+                && sub_command.sub_command_type != SubCommandType::Checksum =>
+        {
+            Some(Path::new(src).join(CONFIG_FILE_NAME)).filter(|path| path.is_file())
+        }
+        _ => None,
     };
+    let config = match config_path {
+        Some(path) => parse_config(&path)?,
+        None => Config::default(),
+    };
+
+    for name in &config.flags {
+        match FLAG_NAMES.iter().position(|flag_name| flag_name == name) {
+            Some(i) => flags |= Flag::from_bits_truncate(1 << i),
+            None => {
+                eprintln!("Config Error -- {} is not a flag", name);
+                return Err(ParseError::Argument);
+            }
+        }
+    }
+
+    let config_rules = [
+        ("include", Action::Include, &config.include),
+        ("exclude", Action::Exclude, &config.exclude),
+    ];
+    for &(name, action, patterns) in &config_rules {
+        for pattern in patterns {
+            if let Err(e) = options.filter.add(action, pattern) {
+                eprintln!("Config Error -- {} {}: {}", name, pattern, e);
+                return Err(ParseError::Argument);
+            }
+        }
+    }
+
+    // Exclude junk files after all other rules, so that they can still be included
+    if args.is_present("exclude-junk") {
+        options.filter.exclude_junk();
+    }
+
+    // Parse for names of directories to exclude anywhere
+    if let Some(names) = args.values_of("exclude-dir") {
+        for name in names {
+            if name.is_empty() || name.contains(path::is_separator) || name == "." || name == ".." {
+                eprintln!(
+                    "Argument Error -- exclude-dir {} is not a directory name",
+                    name
+                );
+                return Err(ParseError::Argument);
+            }
+            options.filter.exclude_dir(name);
+        }
+    }
+
+    // Parse for size limits
+    options.min_size = parse_size_arg(args, "min-size")?;
+    options.max_size = parse_size_arg(args, "max-size")?;
+
+    if let (Some(min_size), Some(max_size)) = (options.min_size, options.max_size) {
+        if min_size > max_size {
+            eprintln!("Argument Error -- min-size is larger than max-size");
+            return Err(ParseError::Argument);
+        }
+    }
+
+    // Parse for modification time limits
+    options.newer_than = parse_time_arg(args, "newer-than")?;
+    options.older_than = parse_time_arg(args, "older-than")?;
+
+    // Parse for the tolerance of modification times, which is given in seconds
+    if let Some(value) = args.value_of("modify-window") {
+        match value.parse::<u64>() {
+            Ok(secs) => options.modify_window = Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!(
+                    "Argument Error -- modify-window {} is not a non-negative integer",
+                    value
+                );
+                return Err(ParseError::Argument);
+            }
+        }
+    }
+
+    // Parse for the hash function, where secure is short for blake2
+    #[allow(deprecated)]
+    let secure = flags.contains(Flag::SECURE);
+    if secure {
+        options.hash = HashAlgo::Blake2;
+    } else if let Some(name) = args.value_of("hash").or(config.hash.as_deref()) {
+        options.hash = parse_hash_algo(name)?;
+    } else if sub_command.sub_command_type == SubCommandType::Checksum {
+        // Checksums default to CRC-32 so that they can be compared with those of other tools
+        options.hash = HashAlgo::Crc32;
+    }
+
+    // Keep hashes across runs in the given cache file, or else in the destination,
+    // where the cache file is left out
+    if let Some(path) = args.value_of("cache-file") {
+        options.cache_file = Some(PathBuf::from(path));
+    } else if flags.contains(Flag::HASH_CACHE) {
+        if let Some(dest) = sub_command.dest.first() {
+            options.cache_file = Some(Path::new(dest).join(cache::CACHE_FILE_NAME));
+        }
+    }
+    if let Some(pattern) = sub_command
+        .dest
+        .first()
+        .and_then(|dest| options.cache_file_pattern(dest))
+    {
+        // This is safe to unwrap, since the path of the cache file is escaped
+        options.filter.exclude(&pattern).unwrap();
+    }
+
+    // Ask on stdin what happens to each file that differs
+    if flags.contains(Flag::INTERACTIVE) {
+        options.conflict_handler = Some(conflict::prompt_handler());
+    }
+
+    // Keep overwritten files under the given suffix
+    if flags.contains(Flag::BACKUP) {
+        let suffix = args
+            .value_of("suffix")
+            .unwrap_or(file_ops::DEFAULT_BACKUP_SUFFIX);
+        if suffix.is_empty() {
+            eprintln!("Argument Error -- suffix must not be empty");
+            return Err(ParseError::Argument);
+        }
+        options.backup_suffix = Some(suffix.to_string());
+    }
+
+    // Move overwritten and deleted entries into a directory, under their paths in the destination
+    if let Some(dir) = args.value_of("backup-dir") {
+        if fs::metadata(dir).is_ok_and(|m| !m.is_dir()) {
+            eprintln!("Argument Error -- backup-dir {} is not a directory", dir);
+            return Err(ParseError::Argument);
+        }
+        options.backup_dir = Some(PathBuf::from(dir));
+    }
+
+    // Give everything copied the same owner and group, instead of preserving those of the source
+    if let Some(value) = args.value_of("chown") {
+        options.chown = Some(parse_chown(value)?);
+        flags.remove(Flag::PRESERVE_OWNER);
+    }
+
+    // Share the data of copied files with the source, if possible
+    if let Some(value) = args.value_of("reflink") {
+        options.reflink = parse_reflink(value)?;
+    }
+
+    // Keep files that fail partway to resume them, in a directory next to them if it is given,
+    // which is left out like --exclude-dir so that it is neither copied nor deleted
+    if let Some(name) = args.value_of("partial-dir") {
+        if name.is_empty() || name.contains(path::is_separator) || name == "." || name == ".." {
+            eprintln!(
+                "Argument Error -- partial-dir {} is not a directory name",
+                name
+            );
+            return Err(ParseError::Argument);
+        }
+        options.filter.exclude_dir(name);
+        options.partial_dir = Some(name.to_string());
+    }
+    if options.partial_dir.is_some() {
+        flags |= Flag::PARTIAL;
+    }
+
+    // Copy files through a buffer of a fixed size, instead of however the platform copies them
+    options.buffer_size = match parse_size_arg(args, "buffer-size")? {
+        Some(size) if size == 0 || size > MAX_BUFFER_SIZE => {
+            eprintln!("Argument Error -- buffer-size must be between 1 byte and 1G");
+            return Err(ParseError::Argument);
+        }
+        size => size.map(|size| size as usize),
+    };
+
+    // Move removed entries to the trash, which is never replaced by deleting them permanently
+    if flags.contains(Flag::TRASH) && !cfg!(any(windows, target_os = "macos", target_os = "linux"))
+    {
+        eprintln!("Argument Error -- trash is not supported on this platform");
+        return Err(ParseError::Argument);
+    }
+
+    // Flush copied files to disk, either each one or all of them once everything is copied
+    if args.is_present("fsync") {
+        options.fsync = Fsync::Each;
+    } else if args.is_present("fsync-at-end") {
+        options.fsync = Fsync::AtEnd;
+    }
+
+    // Change the permissions of everything copied
+    if let Some(value) = args.value_of("chmod") {
+        if !cfg!(unix) {
+            eprintln!("Argument Error -- chmod is only supported on Unix");
+            return Err(ParseError::Argument);
+        }
+        match Chmod::new(value) {
+            Ok(chmod) => options.chmod = Some(chmod),
+            Err(e) => {
+                eprintln!("Argument Error -- chmod {}", e);
+                return Err(ParseError::Argument);
+            }
+        }
+    }
+
+    // Read ignore files from the source while traversing
+    if args.is_present("use-ignore-files") {
+        if let Some(src) = sub_command.src {
+            options.filter.use_ignore_files(Path::new(src));
+        }
+    }
 
     // Validate directories
     match sub_command.sub_command_type {
@@ -109,10 +814,31 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
             });
 
             if sub_command.dest.is_empty() {
-                return Err(());
+                return Err(ParseError::Path);
+            }
+        }
+        SubCommandType::Verify | SubCommandType::Diff | SubCommandType::Checksum => {
+            // All directories must be valid, and are never created
+            let mut dirs = vec![("Source", sub_command.src.unwrap())];
+            if let Some(dest) = sub_command.dest.first() {
+                dirs.push(("Destination", dest.as_str()));
+            }
+            for &(name, dir) in &dirs {
+                match fs::metadata(dir) {
+                    Ok(m) => {
+                        if !m.is_dir() {
+                            eprintln!("{} Error -- {} is not a directory", name, dir);
+                            return Err(ParseError::Path);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{} Error -- {}: {}", name, dir, e);
+                        return Err(ParseError::Path);
+                    }
+                }
             }
         }
-        SubCommandType::Copy | SubCommandType::Synchronize => {
+        SubCommandType::Copy | SubCommandType::Move | SubCommandType::Synchronize => {
             // Check if src is valid
             match fs::metadata(sub_command.src.unwrap()) {
                 Ok(m) => {
@@ -121,18 +847,55 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
                             "Source Error -- {} is not a directory",
                             sub_command.src.unwrap()
                         );
-                        return Err(());
+                        return Err(ParseError::Path);
                     }
                 }
                 Err(e) => {
                     eprintln!("Source Error -- {}: {}", sub_command.src.unwrap(), e);
-                    return Err(());
+                    return Err(ParseError::Path);
                 }
             };
 
-            // If the directory already exists, then the directory is directory + src name
-            if sub_command.sub_command_type == SubCommandType::Copy
-                && fs::metadata(&sub_command.dest[0]).is_ok()
+            // Check if the directories to link unchanged files from are valid
+            for link_dest in args.values_of("link-dest").into_iter().flatten() {
+                match fs::metadata(link_dest) {
+                    Ok(m) => {
+                        if !m.is_dir() {
+                            eprintln!(
+                                "Argument Error -- link-dest {} is not a directory",
+                                link_dest
+                            );
+                            return Err(ParseError::Argument);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Argument Error -- link-dest {}: {}", link_dest, e);
+                        return Err(ParseError::Argument);
+                    }
+                }
+                options.link_dest.push(link_dest.to_string());
+            }
+
+            // Check if dest is a directory, if it already exists
+            if let Ok(m) = fs::metadata(&sub_command.dest[0]) {
+                if !m.is_dir() {
+                    eprintln!(
+                        "Destination Error -- {} is not a directory",
+                        sub_command.dest[0]
+                    );
+                    return Err(ParseError::Path);
+                }
+            }
+
+            // Like rsync, the source is synchronized into a directory of its own name in the
+            // destination, unless it ends with a separator, so that only its contents are.
+            // When copying or moving, this is only done if the destination already exists, like cp.
+            // Globs and listed paths are always copied directly into the destination
+            let src_contents_only = sub_command.src.unwrap().ends_with(path::is_separator);
+            if !src_contents_only
+                && options.files.is_none()
+                && (sub_command.sub_command_type == SubCommandType::Synchronize
+                    || fs::metadata(&sub_command.dest[0]).is_ok())
             {
                 let mut new_dest = PathBuf::from(&sub_command.dest[0]);
                 let src_name = PathBuf::from(sub_command.src.unwrap());
@@ -142,9 +905,21 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
                 }
             }
 
-            if fs::metadata(&sub_command.dest[0]).is_err() {
+            match fs::metadata(&sub_command.dest[0]) {
+                Ok(m) => {
+                    // The directory of the source's name may also be in the way
+                    if !m.is_dir() {
+                        eprintln!(
+                            "Destination Error -- {} is not a directory",
+                            sub_command.dest[0]
+                        );
+                        return Err(ParseError::Path);
+                    }
+                }
+                // Nothing is created if only the counts of what would be done are found
+                Err(_) if flags.contains(Flag::STATS_ONLY) => (),
                 // Create destination folder if not already existing
-                match fs::create_dir_all(&sub_command.dest[0]) {
+                Err(_) => match fs::create_dir_all(&sub_command.dest[0]) {
                     Ok(_) => {
                         if flags.contains(Flag::VERBOSE) {
                             println!("Creating dir {:?}", sub_command.dest[0]);
@@ -152,32 +927,523 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Result<ParseResult<'a>, ()> {
                     }
                     Err(e) => {
                         eprintln!("Destination Error -- {}: {}", sub_command.dest[0], e);
-                        return Err(());
+                        return Err(ParseError::Path);
                     }
+                },
+            }
+        }
+    }
+
+    Ok(ParseResult {
+        sub_command,
+        flags,
+        options,
+        eta_smoothing,
+        progress_refresh,
+        threads,
+    })
+}
+
+/// Largest buffer that files can be copied through, so that every copying thread can allocate it
+const MAX_BUFFER_SIZE: u64 = 1 << 30;
+
+/// Characters that have a special meaning in globs
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// Checks whether `pattern` contains any glob special characters
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(&GLOB_CHARS[..])
+}
+
+/// Finds the directory part of `pattern` that comes before any glob special characters
+///
+/// # Examples
+/// "src/*.txt" becomes "src"
+/// "src/a*/b" becomes "src"
+/// "*.txt" becomes "."
+fn glob_base(pattern: &str) -> &str {
+    let fixed_len = pattern.find(&GLOB_CHARS[..]).unwrap_or(pattern.len());
+
+    match pattern[..fixed_len].rfind(path::is_separator) {
+        Some(0) => &pattern[..1],
+        Some(i) => &pattern[..i],
+        None => ".",
+    }
+}
+
+/// Expands the glob `pattern` into the paths it matches
+///
+/// # Returns
+/// * Ok: The base directory of `pattern`, and the matched paths relative to it
+/// * Err: If `pattern` is invalid, does not match anything, or matches a path outside of its
+///   base directory
+fn expand_glob(pattern: &str) -> Result<(&str, Vec<PathBuf>), ()> {
+    let base = glob_base(pattern);
+
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Source Error -- {}: {}", pattern, e);
+            return Err(());
+        }
+    };
+
+    let mut files = Vec::new();
+    for path in paths {
+        match path {
+            Ok(path) => match path.strip_prefix(base) {
+                Ok(relative) => files.push(relative.to_path_buf()),
+                // Patterns without a directory match paths that are already relative to it
+                Err(_) if base == "." => files.push(path),
+                Err(_) => {
+                    eprintln!("Source Error -- {} is not inside {}", path.display(), base);
+                    return Err(());
                 }
+            },
+            Err(e) => eprintln!("Source Error -- {}", e),
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("Source Error -- {} does not match any files", pattern);
+        return Err(());
+    }
+
+    Ok((base, files))
+}
+
+/// Parses the value of the size argument `name`, if it was given
+///
+/// # Returns
+/// * Ok: The size in bytes, if given
+/// * Err: If the value is not a valid size
+fn parse_size_arg(args: &ArgMatches, name: &str) -> Result<Option<u64>, ()> {
+    match args.value_of(name) {
+        Some(value) => match parse_size(value) {
+            Some(size) => Ok(Some(size)),
+            None => {
+                eprintln!("Argument Error -- {} {} is not a valid size", name, value);
+                Err(())
             }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses a size with an optional suffix K, M, G, or T, in powers of 1024
+///
+/// # Examples
+/// "100" becomes 100
+/// "1.5K" becomes 1536
+/// "2G" becomes 2147483648
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, suffix) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+
+    let multiplier: u64 = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return None,
+    };
+
+    let number = number.parse::<f64>().ok()?;
+    if number.is_sign_negative() || !number.is_finite() {
+        return None;
+    }
+
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Parses the value of the time argument `name`, if it was given
+///
+/// # Returns
+/// * Ok: The time, if given
+/// * Err: If the value is not a valid time
+fn parse_time_arg(args: &ArgMatches, name: &str) -> Result<Option<SystemTime>, ()> {
+    match args.value_of(name) {
+        Some(value) => match parse_time(value) {
+            Some(time) => Ok(Some(time)),
+            None => {
+                eprintln!(
+                    "Argument Error -- {} {} is not an RFC 3339 timestamp or a duration",
+                    name, value
+                );
+                Err(())
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses an RFC 3339 timestamp, or a duration before now
+///
+/// # Examples
+/// "2020-06-01T12:00:00Z" is that time
+/// "7d" is 7 days ago
+/// "1h 30m" is an hour and a half ago
+fn parse_time(value: &str) -> Option<SystemTime> {
+    if let Ok(time) = humantime::parse_rfc3339_weak(value) {
+        return Some(time);
+    }
+
+    let duration = humantime::parse_duration(value).ok()?;
+    SystemTime::now().checked_sub(duration)
+}
+
+/// Adds the rules listed in the filter file at `path` to `filter`
+///
+/// # Returns
+/// * Ok: If all rules were added
+/// * Err: If the file cannot be read or contains an invalid pattern
+fn parse_filter_file(path: &str, filter: &mut Filter) -> Result<(), ()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Filter File Error -- {}: {}", path, e);
+            return Err(());
+        }
+    };
+
+    if let Err((line, e)) = filter.add_lines(&contents) {
+        eprintln!("Filter File Error -- {}:{}: {}", path, line, e);
+        return Err(());
+    }
+
+    Ok(())
+}
+
+/// Excludes the patterns listed in the exclude file at `path` from `filter`
+///
+/// # Returns
+/// * Ok: If all patterns were excluded
+/// * Err: If the file cannot be read or contains an invalid pattern
+fn parse_exclude_from(path: &str, filter: &mut Filter) -> Result<(), ()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Exclude From Error -- {}: {}", path, e);
+            return Err(());
+        }
+    };
+
+    if let Err((line, e)) = filter.exclude_lines(&contents) {
+        eprintln!("Exclude From Error -- {}:{}: {}", path, line, e);
+        return Err(());
+    }
+
+    Ok(())
+}
+
+/// Reads the config file at `path`
+///
+/// # Returns
+/// * Ok: The defaults in the config file
+/// * Err: If the file cannot be read or is not a valid config
+fn parse_config(path: &Path) -> Result<Config, ()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Config Error -- {}: {}", path.display(), e);
+            return Err(());
+        }
+    };
+
+    match toml::from_str(&contents) {
+        Ok(config) => Ok(config),
+        Err(e) => {
+            eprintln!("Config Error -- {}: {}", path.display(), e);
+            Err(())
+        }
+    }
+}
+
+/// Reads the paths listed in the file at `path`, one per line, or in stdin if `path` is `-`
+///
+/// # Returns
+/// * Ok: The listed paths, relative to the source, with blank lines skipped
+/// * Err: If the list cannot be read, or a path is not relative to the source
+fn parse_files_from(path: &str) -> Result<Vec<PathBuf>, ()> {
+    let mut contents = String::new();
+    let read = if path == "-" {
+        io::stdin().read_to_string(&mut contents).map(|_| ())
+    } else {
+        fs::read_to_string(path).map(|list| contents = list)
+    };
+    if let Err(e) = read {
+        eprintln!("Files From Error -- {}: {}", path, e);
+        return Err(());
+    }
+
+    let mut files = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+
+        // Paths must stay inside the source, and are compared without any `.` components
+        let file = Path::new(line);
+        if file.is_absolute()
+            || file
+                .components()
+                .any(|component| component == path::Component::ParentDir)
+        {
+            eprintln!(
+                "Files From Error -- {}:{}: {} is not a path relative to the source",
+                path,
+                i + 1,
+                line
+            );
+            return Err(());
+        }
+
+        let file: PathBuf = file.components().collect();
+        if !file.as_os_str().is_empty() {
+            files.push(file);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Parses the name of a hash function
+///
+/// # Arguments
+/// * `name`: one of seahash, blake2, blake3, sha256, or xxhash, where xxh3 is short for xxhash
+///
+/// # Returns
+/// * Ok: The hash function
+/// * Err: If `name` is not a supported hash function
+fn parse_hash_algo(name: &str) -> Result<HashAlgo, ()> {
+    match name {
+        "seahash" => Ok(HashAlgo::Seahash),
+        "blake2" => Ok(HashAlgo::Blake2),
+        "blake3" => Ok(HashAlgo::Blake3),
+        "sha256" => Ok(HashAlgo::Sha256),
+        "xxhash" | "xxh3" => Ok(HashAlgo::Xxhash),
+        "crc32" => Ok(HashAlgo::Crc32),
+        _ => {
+            eprintln!(
+                "Argument Error -- hash {} is not a supported algorithm",
+                name
+            );
+            Err(())
         }
     }
+}
 
-    Ok(ParseResult { sub_command, flags })
+/// Gets whether copied files are reflinks from the value of --reflink
+///
+/// # Errors
+/// If `value` is not auto, always, or never
+fn parse_reflink(value: &str) -> Result<Reflink, ()> {
+    match value {
+        "auto" => Ok(Reflink::Auto),
+        "always" => Ok(Reflink::Always),
+        "never" => Ok(Reflink::Never),
+        _ => {
+            eprintln!(
+                "Argument Error -- reflink {} is not auto, always, or never",
+                value
+            );
+            Err(())
+        }
+    }
+}
+
+/// Gets the flags implied by archive mode, which are `Flag::PRESERVE_PERMS`,
+/// and `Flag::PRESERVE_OWNER` when running as root on Unix, like rsync's -a
+///
+/// Modification times, symlinks and recursion need no flags, since they are always kept
+fn archive_flags() -> Flag {
+    if is_root() {
+        Flag::PRESERVE_PERMS | Flag::PRESERVE_OWNER
+    } else {
+        Flag::PRESERVE_PERMS
+    }
+}
+
+/// Gets how archive mode differs from rsync's -a on this platform
+fn archive_warnings() -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    if cfg!(unix) {
+        warnings.push("does not copy devices or special files");
+        if !is_root() {
+            warnings.push("only preserves owners and groups when running as root");
+        }
+    } else {
+        warnings.push("does not preserve owners and groups on this platform");
+    }
+    warnings
+}
+
+/// Checks whether the program is running as root
+#[cfg(unix)]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Checks whether the program is running as root, which is never on this platform
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+/// Parses the owner and group of --chown, given as user, user:group, or :group,
+/// where each is either a name or a numeric id
+///
+/// Names are resolved here, so that nothing is copied if they do not exist
+///
+/// # Returns
+/// * Ok: The user and group ids, where either is None if not given
+/// * Err: If a name does not exist, or the ids cannot be given without running as root
+#[cfg(unix)]
+fn parse_chown(value: &str) -> Result<(Option<u32>, Option<u32>), ()> {
+    let (user, group) = match value.find(':') {
+        Some(index) => (&value[..index], Some(&value[index + 1..])),
+        None => (value, None),
+    };
+    if user.is_empty() && group.is_none_or(str::is_empty) {
+        eprintln!(
+            "Argument Error -- chown {} is not user, user:group, or :group",
+            value
+        );
+        return Err(());
+    }
+
+    let uid = match user {
+        "" => None,
+        user => match resolve_user(user) {
+            Some(uid) => Some(uid),
+            None => {
+                eprintln!("Argument Error -- chown user {} does not exist", user);
+                return Err(());
+            }
+        },
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => match resolve_group(group) {
+            Some(gid) => Some(gid),
+            None => {
+                eprintln!("Argument Error -- chown group {} does not exist", group);
+                return Err(());
+            }
+        },
+    };
+
+    // Without root, every file would fail to change owners, so stop before copying anything
+    let uid_allowed = uid.is_none_or(|uid| uid == unsafe { libc::geteuid() });
+    let gid_allowed = gid.is_none_or(is_in_group);
+    if !(is_root() || uid_allowed && gid_allowed) {
+        eprintln!("Argument Error -- chown {} requires running as root", value);
+        return Err(());
+    }
+
+    Ok((uid, gid))
+}
+
+/// Parses the owner and group of --chown, which are not supported on this platform
+#[cfg(not(unix))]
+fn parse_chown(_value: &str) -> Result<(Option<u32>, Option<u32>), ()> {
+    eprintln!("Argument Error -- chown is only supported on Unix");
+    Err(())
+}
+
+/// Gets the id of the user `name`, which may also be a numeric id
+#[cfg(unix)]
+fn resolve_user(name: &str) -> Option<u32> {
+    if let Ok(uid) = name.parse() {
+        return Some(uid);
+    }
+
+    let name = std::ffi::CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        None
+    } else {
+        Some(unsafe { (*passwd).pw_uid })
+    }
+}
+
+/// Gets the id of the group `name`, which may also be a numeric id
+#[cfg(unix)]
+fn resolve_group(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse() {
+        return Some(gid);
+    }
+
+    let name = std::ffi::CString::new(name).ok()?;
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        None
+    } else {
+        Some(unsafe { (*group).gr_gid })
+    }
+}
+
+/// Checks whether the program is running in the group `gid`,
+/// which allows it to give files that group without root
+#[cfg(unix)]
+fn is_in_group(gid: u32) -> bool {
+    if gid == unsafe { libc::getegid() } {
+        return true;
+    }
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    count > 0 && groups[..count as usize].contains(&gid)
 }
 
 /// Sets up the environment based on given flags
 pub fn set_env(flags: Flag) {
+    let json = flags.contains(Flag::JSON);
+    let hide_progress = json || flags.intersects(Flag::QUIET | Flag::INTERACTIVE);
+
     let mut builder = Builder::new();
     builder.format(|_, record| {
-        PROGRESS_BAR.println(format!("{}", record.args()));
+        // A hidden progress bar drops printed lines, such as when stderr is not a terminal,
+        // so write them to stderr directly
+        if PROGRESS_BAR.is_hidden() {
+            eprintln!("{}", record.args());
+        } else {
+            PROGRESS_BAR.println(format!("{}", record.args()));
+        }
         Ok(())
     });
 
+    // If quiet, printing a JSON report, or asking about conflicts, hide the progress bar,
+    // or else print it as plain lines if requested or if it can't be drawn,
+    // or else show the bytes copied of large files below it if requested
+    if hide_progress {
+        PROGRESS_BAR.set_draw_target(ProgressDrawTarget::hidden());
+    } else if flags.contains(Flag::PLAIN_PROGRESS) || !io::stderr().is_terminal() {
+        progress::progress_enable_plain();
+    } else if flags.contains(Flag::DETAILED_PROGRESS) {
+        progress::progress_enable_file_bars();
+    }
+
+    // If printing a JSON report, keep stdout for the report
+    if json {
+        stats::report_init();
+    }
+
     // If verbose, enable info logging
-    if flags.contains(Flag::VERBOSE) {
+    if flags.contains(Flag::VERBOSE) && !json {
         env::set_var("RUST_LOG", "info");
         builder.filter(None, LevelFilter::Info).init();
     } else {
-        // or else enable only error logging
-        env::set_var("RUST_LOG", "error");
-        builder.filter(None, LevelFilter::Error).init();
+        // or else enable only warning and error logging, such as for unsupported flags
+        env::set_var("RUST_LOG", "warn");
+        builder.filter(None, LevelFilter::Warn).init();
     }
 
     // If sequential, set Rayon to use only 1 thread
@@ -1,26 +1,503 @@
 //! Keeps track of LuminS' progress
 
-use indicatif::{ProgressBar, ProgressStyle};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
 use lazy_static::lazy_static;
 
+/// Default weight given to the newest throughput sample when smoothing the ETA
+pub const DEFAULT_ETA_SMOOTHING: f64 = 0.1;
+
+/// Minimum time between two throughput samples
+const ETA_SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of bytes of files copied or compared so far, shown along with PROGRESS_BAR
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of bytes of files to copy or compare
+static TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Minimum size of a file, in bytes, for it to get its own bar while it is copied
+pub const FILE_BAR_THRESHOLD: u64 = 50 * 1024 * 1024;
+
+/// Whether large files get their own bar below PROGRESS_BAR, set by `progress_enable_file_bars`
+static FILE_BARS: AtomicBool = AtomicBool::new(false);
+
+/// Number of lines printed for each progress bar when progress is printed as plain lines
+const PLAIN_LINES: u64 = 100;
+
+/// Whether progress is printed as plain lines instead of drawn, set by `progress_enable_plain`
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Position of PROGRESS_BAR counted separately, so that each plain line is printed exactly once
+static PLAIN_POSITION: AtomicU64 = AtomicU64::new(0);
+
+/// Milliseconds between redraws of PROGRESS_BAR, set by `progress_set_refresh`,
+/// or 0 if it is redrawn each time another thousandth is done
+static REFRESH_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Minimum time between two changes of the directory shown by PROGRESS_BAR, so that it
+/// doesn't flicker between the directories of files that are processed in parallel
+const DIR_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum number of characters of the directory shown by PROGRESS_BAR,
+/// beyond which the start of the directory is cut off
+const MAX_DIR_LEN: usize = 40;
+
 lazy_static! {
     /// Provides a bar that shows the number of files
-    /// copied, synchronized, or deleted, out of the total number of files
+    /// copied, synchronized, or deleted, out of the total number of files,
+    /// with the number of bytes and transfer speed in its message,
+    /// and the directory currently being processed in its prefix
     pub static ref PROGRESS_BAR: ProgressBar = {
         let progress_bar = ProgressBar::new(0);
         progress_bar.set_style(
             ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] [{bar:40.green/blue}] {pos}/{len} ({eta})"),
+                .template("[{elapsed_precise}] [{bar:40.green/blue}] {pos}/{len} ({msg}) {prefix}"),
         );
         progress_bar
     };
+
+    /// Time when the directory shown by PROGRESS_BAR last changed, if it has been shown
+    static ref DIR_INSTANT: Mutex<Option<Instant>> = Mutex::new(None);
+
+    /// Keeps the smoothed throughput used to compute the ETA shown by PROGRESS_BAR
+    static ref ETA_ESTIMATOR: Mutex<EtaEstimator> = Mutex::new(EtaEstimator::new());
+
+    /// Draws PROGRESS_BAR together with the bars of large files, if they are enabled
+    static ref MULTI_PROGRESS: MultiProgress = MultiProgress::new();
+
+    /// Draws MULTI_PROGRESS until PROGRESS_BAR and all the bars of files are finished
+    static ref MULTI_PROGRESS_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+
+/// Estimates the remaining time from an exponentially smoothed throughput,
+/// along with the smoothed transfer speed in bytes
+struct EtaEstimator {
+    smoothing: f64,
+    rate: Option<f64>,
+    byte_rate: Option<f64>,
+    last_instant: Instant,
+    last_position: u64,
+    last_bytes: u64,
 }
 
-/// Initializes PROGRESS_BAR with `length` and sets draw delta
+impl EtaEstimator {
+    fn new() -> Self {
+        EtaEstimator {
+            smoothing: DEFAULT_ETA_SMOOTHING,
+            rate: None,
+            byte_rate: None,
+            last_instant: Instant::now(),
+            last_position: 0,
+            last_bytes: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rate = None;
+        self.byte_rate = None;
+        self.last_instant = Instant::now();
+        self.last_position = 0;
+        self.last_bytes = 0;
+    }
+}
+
+/// Initializes PROGRESS_BAR with `length` and sets draw delta,
+/// or starts redrawing it on a timer if `progress_set_refresh` was called
 /// # Arguments
 /// * `length`: Length fo the bar to set
-pub fn progress_init(length: u64) {
+/// * `total_bytes`: Total size of the files to copy or compare, or 0 to not show bytes
+pub fn progress_init(length: u64, total_bytes: u64) {
     PROGRESS_BAR.set_length(length);
-    PROGRESS_BAR.set_draw_delta(length / 1000);
+    match REFRESH_MS.load(Ordering::Relaxed) {
+        0 => PROGRESS_BAR.set_draw_delta(length / 1000),
+        refresh_ms => {
+            // Changes never draw the bar themselves, so it is only drawn by the ticks,
+            // which stop once it is finished, so they are started again for each new bar
+            PROGRESS_BAR.set_draw_delta(u64::MAX);
+            PROGRESS_BAR.enable_steady_tick(refresh_ms);
+        }
+    }
     PROGRESS_BAR.set_position(0);
+    PROGRESS_BAR.set_message("");
+    PROGRESS_BAR.set_prefix("");
+    BYTES.store(0, Ordering::Relaxed);
+    TOTAL_BYTES.store(total_bytes, Ordering::Relaxed);
+    PLAIN_POSITION.store(0, Ordering::Relaxed);
+
+    if let Ok(mut estimator) = ETA_ESTIMATOR.lock() {
+        estimator.reset();
+    }
+    if let Ok(mut dir_instant) = DIR_INSTANT.lock() {
+        *dir_instant = None;
+    }
+}
+
+/// Shows a bar of the bytes copied below PROGRESS_BAR for each file of at least
+/// FILE_BAR_THRESHOLD bytes, unless PROGRESS_BAR is hidden
+///
+/// `progress_finish` must be called once everything is copied, so that the bars are cleared
+pub fn progress_enable_file_bars() {
+    let mut multi_progress_thread = match MULTI_PROGRESS_THREAD.lock() {
+        Ok(multi_progress_thread) => multi_progress_thread,
+        Err(_) => return,
+    };
+    if multi_progress_thread.is_some() || PROGRESS_BAR.is_hidden() {
+        return;
+    }
+
+    MULTI_PROGRESS.add(PROGRESS_BAR.clone());
+    FILE_BARS.store(true, Ordering::SeqCst);
+    *multi_progress_thread = Some(thread::spawn(|| {
+        MULTI_PROGRESS.join_and_clear().ok();
+    }));
+}
+
+/// Prints the progress to stderr as plain lines, each time another hundredth is done,
+/// instead of drawing PROGRESS_BAR, so that logs are free of cursor control codes
+pub fn progress_enable_plain() {
+    PROGRESS_BAR.set_draw_target(ProgressDrawTarget::hidden());
+    PLAIN.store(true, Ordering::SeqCst);
+}
+
+/// Creates a bar for copying the file at `path` of `size` bytes, which is shown below
+/// PROGRESS_BAR until it is finished
+///
+/// # Returns
+/// * Some: The bar of the file
+/// * None: If bars of files are not enabled, or the file is smaller than FILE_BAR_THRESHOLD
+pub fn progress_file_bar(path: &Path, size: u64) -> Option<ProgressBar> {
+    if !FILE_BARS.load(Ordering::Relaxed) || size < FILE_BAR_THRESHOLD {
+        return None;
+    }
+
+    let file_bar = MULTI_PROGRESS.add(ProgressBar::new(size));
+    file_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("  [{bar:40.cyan/blue}] {bytes}/{total_bytes} {wide_msg}"),
+    );
+    file_bar.set_message(&path.display().to_string());
+    Some(file_bar)
+}
+
+/// Finishes and clears PROGRESS_BAR, along with the bars of files if they are enabled
+pub fn progress_finish() {
+    PROGRESS_BAR.finish_and_clear();
+
+    let multi_progress_thread = match MULTI_PROGRESS_THREAD.lock() {
+        Ok(mut multi_progress_thread) => multi_progress_thread.take(),
+        Err(_) => None,
+    };
+    if let Some(multi_progress_thread) = multi_progress_thread {
+        multi_progress_thread.join().ok();
+        // Nothing draws MULTI_PROGRESS anymore, so lines printed afterwards go to stderr directly
+        FILE_BARS.store(false, Ordering::SeqCst);
+        PROGRESS_BAR.set_draw_target(ProgressDrawTarget::stderr());
+    }
+}
+
+/// Redraws PROGRESS_BAR once every `refresh` from the next `progress_init` on,
+/// instead of each time another thousandth is done, which is far too often for many small files
+/// # Arguments
+/// * `refresh`: Time between redraws, which must not be 0
+pub fn progress_set_refresh(refresh: Duration) {
+    REFRESH_MS.store(refresh.as_millis().max(1) as u64, Ordering::SeqCst);
+
+    // The terminal is otherwise drawn to at most 15 times a second, which would skip some ticks.
+    // Hidden bars, plain lines, and the bars of large files keep their own draw targets
+    if !PROGRESS_BAR.is_hidden() && !FILE_BARS.load(Ordering::Relaxed) {
+        PROGRESS_BAR.set_draw_target(ProgressDrawTarget::stderr_nohz());
+    }
+}
+
+/// Sets the smoothing factor used for the ETA of PROGRESS_BAR
+///
+/// A factor close to 1 follows the current throughput closely, while a factor
+/// close to 0 averages over a longer period and gives a steadier ETA
+/// # Arguments
+/// * `smoothing`: Weight of the newest throughput sample, in (0, 1]
+pub fn progress_set_eta_smoothing(smoothing: f64) {
+    if let Ok(mut estimator) = ETA_ESTIMATOR.lock() {
+        estimator.smoothing = smoothing;
+    }
+}
+
+/// Shows the directory of the file at `path` in PROGRESS_BAR as the directory being processed,
+/// unless it was changed less than DIR_SAMPLE_INTERVAL ago
+/// # Arguments
+/// * `path`: Relative path of the file that is being processed
+pub fn progress_set_dir(path: &Path) {
+    if PROGRESS_BAR.is_hidden() {
+        return;
+    }
+
+    // Another thread is already changing the directory, so this one is skipped
+    let mut dir_instant = match DIR_INSTANT.try_lock() {
+        Ok(dir_instant) => dir_instant,
+        Err(_) => return,
+    };
+    if dir_instant.is_some_and(|instant| instant.elapsed() < DIR_SAMPLE_INTERVAL) {
+        return;
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    PROGRESS_BAR.set_prefix(&dir_prefix(dir));
+    *dir_instant = Some(Instant::now());
+}
+
+/// Formats `dir` as the prefix of PROGRESS_BAR
+///
+/// # Returns
+/// `dir`, where its start is replaced by `...` if it is longer than MAX_DIR_LEN characters,
+/// or `.` if `dir` is empty
+fn dir_prefix(dir: &Path) -> String {
+    let dir = dir.display().to_string();
+    let len = dir.chars().count();
+    if dir.is_empty() {
+        String::from(".")
+    } else if len > MAX_DIR_LEN {
+        let end: String = dir.chars().skip(len - MAX_DIR_LEN + 3).collect();
+        ["...", &end].concat()
+    } else {
+        dir
+    }
+}
+
+/// Adds `bytes` to the bytes shown by PROGRESS_BAR, which are only redrawn by `progress_inc`
+/// # Arguments
+/// * `bytes`: Size of the file that was copied or compared
+pub fn progress_inc_bytes(bytes: u64) {
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Advances PROGRESS_BAR by `delta` and refreshes the ETA and transfer speed
+/// # Arguments
+/// * `delta`: Amount to advance the bar by
+pub fn progress_inc(delta: u64) {
+    PROGRESS_BAR.inc(delta);
+
+    if PLAIN.load(Ordering::Relaxed) {
+        let position = PLAIN_POSITION.fetch_add(delta, Ordering::Relaxed) + delta;
+        let length = PROGRESS_BAR.length();
+        if is_plain_line_due(position - delta, position, length) {
+            eprintln!(
+                "{}",
+                plain_line(
+                    position,
+                    length,
+                    BYTES.load(Ordering::Relaxed),
+                    TOTAL_BYTES.load(Ordering::Relaxed)
+                )
+            );
+        }
+    }
+
+    // Another thread is already sampling, so there is no need to wait for it
+    let mut estimator = match ETA_ESTIMATOR.try_lock() {
+        Ok(estimator) => estimator,
+        Err(_) => return,
+    };
+
+    let elapsed = estimator.last_instant.elapsed();
+    if elapsed < ETA_SAMPLE_INTERVAL {
+        return;
+    }
+
+    let position = PROGRESS_BAR.position();
+    let sample = position.saturating_sub(estimator.last_position) as f64 / elapsed.as_secs_f64();
+    let rate = smooth_rate(estimator.rate, sample, estimator.smoothing);
+
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let byte_sample = bytes.saturating_sub(estimator.last_bytes) as f64 / elapsed.as_secs_f64();
+    let byte_rate = smooth_rate(estimator.byte_rate, byte_sample, estimator.smoothing);
+
+    estimator.rate = Some(rate);
+    estimator.byte_rate = Some(byte_rate);
+    estimator.last_instant = Instant::now();
+    estimator.last_position = position;
+    estimator.last_bytes = bytes;
+
+    let remaining = if rate > 0.0 {
+        let remaining = PROGRESS_BAR.length().saturating_sub(position) as f64 / rate;
+        Some(Duration::from_secs_f64(remaining))
+    } else {
+        None
+    };
+    PROGRESS_BAR.set_message(&progress_message(
+        bytes,
+        TOTAL_BYTES.load(Ordering::Relaxed),
+        byte_rate,
+        remaining,
+    ));
+}
+
+/// Formats the message of PROGRESS_BAR
+/// # Arguments
+/// * `bytes`: Number of bytes copied or compared so far
+/// * `total_bytes`: Total number of bytes, or 0 to leave out the bytes and transfer speed
+/// * `byte_rate`: Transfer speed, in bytes per second
+/// * `remaining`: Estimated remaining time, if any
+///
+/// # Returns
+/// The bytes, transfer speed, and remaining time, separated by commas
+fn progress_message(
+    bytes: u64,
+    total_bytes: u64,
+    byte_rate: f64,
+    remaining: Option<Duration>,
+) -> String {
+    let mut parts = Vec::new();
+    if total_bytes > 0 {
+        parts.push(format!("{}/{}", HumanBytes(bytes), HumanBytes(total_bytes)));
+        parts.push(format!("{}/s", HumanBytes(byte_rate as u64)));
+    }
+    if let Some(remaining) = remaining {
+        parts.push(format!("{}", HumanDuration(remaining)));
+    }
+    parts.join(", ")
+}
+
+/// Checks whether advancing from `previous` to `position` finished another hundredth of `length`,
+/// or everything
+fn is_plain_line_due(previous: u64, position: u64, length: u64) -> bool {
+    let step = (length / PLAIN_LINES).max(1);
+    previous / step != position / step || (previous < length && position >= length)
+}
+
+/// Formats a plain line of progress
+/// # Arguments
+/// * `position`: Number of files done so far
+/// * `length`: Total number of files
+/// * `bytes`: Number of bytes copied or compared so far
+/// * `total_bytes`: Total number of bytes, or 0 to leave out the bytes
+///
+/// # Returns
+/// The files done out of all files, followed by the bytes if there are any
+fn plain_line(position: u64, length: u64, bytes: u64, total_bytes: u64) -> String {
+    if total_bytes > 0 {
+        format!(
+            "{}/{} files ({}/{})",
+            position,
+            length,
+            HumanBytes(bytes),
+            HumanBytes(total_bytes)
+        )
+    } else {
+        format!("{}/{} files", position, length)
+    }
+}
+
+/// Blends a new throughput sample into the previous estimate
+/// # Arguments
+/// * `rate`: Previous estimate, if any
+/// * `sample`: Newest throughput sample
+/// * `smoothing`: Weight of the newest sample, in (0, 1]
+///
+/// # Returns
+/// The new throughput estimate
+fn smooth_rate(rate: Option<f64>, sample: f64, smoothing: f64) -> f64 {
+    match rate {
+        Some(rate) => smoothing * sample + (1.0 - smoothing) * rate,
+        None => sample,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_smooth_rate {
+    use super::*;
+
+    #[test]
+    fn first_sample() {
+        assert_eq!(smooth_rate(None, 50.0, DEFAULT_ETA_SMOOTHING), 50.0);
+    }
+
+    #[test]
+    fn no_smoothing() {
+        assert_eq!(smooth_rate(Some(10.0), 50.0, 1.0), 50.0);
+    }
+
+    #[test]
+    fn smoothing() {
+        assert_eq!(smooth_rate(Some(10.0), 50.0, 0.25), 20.0);
+    }
+}
+
+#[cfg(test)]
+mod test_progress_message {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_eq!(
+            progress_message(1024, 4096, 2048.0, Some(Duration::from_secs(2))),
+            "1.00KB/4.00KB, 2.00KB/s, 2 seconds"
+        );
+    }
+
+    #[test]
+    fn no_bytes() {
+        assert_eq!(
+            progress_message(0, 0, 0.0, Some(Duration::from_secs(2))),
+            "2 seconds"
+        );
+        assert_eq!(progress_message(0, 0, 0.0, None), "");
+    }
+}
+
+#[cfg(test)]
+mod test_dir_prefix {
+    use super::*;
+
+    #[test]
+    fn short() {
+        assert_eq!(dir_prefix(Path::new("a/b")), "a/b");
+        assert_eq!(dir_prefix(Path::new("")), ".");
+    }
+
+    #[test]
+    fn long() {
+        let dir = ["a/", &"b".repeat(MAX_DIR_LEN)].concat();
+        let prefix = dir_prefix(Path::new(&dir));
+        assert_eq!(prefix.chars().count(), MAX_DIR_LEN);
+        assert_eq!(prefix, ["...", &"b".repeat(MAX_DIR_LEN - 3)].concat());
+    }
+}
+
+#[cfg(test)]
+mod test_plain_line {
+    use super::*;
+
+    #[test]
+    fn due_every_hundredth() {
+        assert!(!is_plain_line_due(0, 49, 5000));
+        assert!(is_plain_line_due(49, 50, 5000));
+        assert!(!is_plain_line_due(50, 52, 5000));
+        assert!(is_plain_line_due(98, 101, 5000));
+    }
+
+    #[test]
+    fn due_when_done() {
+        assert!(is_plain_line_due(149, 150, 150));
+        assert!(is_plain_line_due(0, 1, 3));
+    }
+
+    #[test]
+    fn line() {
+        assert_eq!(plain_line(1000, 5000, 0, 0), "1000/5000 files");
+        assert_eq!(
+            plain_line(1000, 5000, 1024, 4096),
+            "1000/5000 files (1.00KB/4.00KB)"
+        );
+    }
 }
@@ -0,0 +1,132 @@
+//! Retries file operations that fail with transient errors, such as over flaky network mounts
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::lumins::interrupt;
+
+/// How many operations succeeded after being retried at least once
+static RETRIED: AtomicU64 = AtomicU64::new(0);
+
+/// How long to wait before the first retry, which doubles with every retry after it
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest time to wait before any retry
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Gets how many operations succeeded only after being retried, which shows how flaky
+/// the file systems that were copied from and to are
+pub fn retried_count() -> u64 {
+    RETRIED.load(Ordering::SeqCst)
+}
+
+/// Checks whether `error` may go away if the operation that caused it is retried
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Gets how long to wait before retry number `attempt`, starting from 0
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(1 << attempt.min(16))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+/// Runs `op` on `path`, retrying it with exponential backoff if it fails with a transient error,
+/// up to `retries` times
+///
+/// # Returns
+/// * Ok: The result of the first successful attempt
+/// * Err: The error of the last attempt, or the first error that is not transient
+pub fn retry<T, F>(retries: u32, path: &Path, mut op: F) -> Result<T, io::Error>
+where
+    F: FnMut() -> Result<T, io::Error>,
+{
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(ref e) if attempt < retries && is_transient(e) && !interrupt::is_interrupted() => {
+                let wait = backoff(attempt);
+                attempt += 1;
+                warn!(
+                    "Retrying {:?} in {:?} ({}/{}): {}",
+                    path, wait, attempt, retries, e
+                );
+                thread::sleep(wait);
+            }
+            Ok(value) => {
+                if attempt > 0 {
+                    RETRIED.fetch_add(1, Ordering::SeqCst);
+                }
+                return Ok(value);
+            }
+            result => return result,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_retry {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(0), Duration::from_millis(100));
+        assert_eq!(backoff(1), Duration::from_millis(200));
+        assert_eq!(backoff(3), Duration::from_millis(800));
+        assert_eq!(backoff(7), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn transient() {
+        let retried = retried_count();
+        let mut attempts = 0;
+        let result = retry(2, Path::new("file"), || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            } else {
+                Ok(attempts)
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+        assert!(retried_count() > retried);
+    }
+
+    #[test]
+    fn too_many_retries() {
+        let mut attempts = 0;
+        let result: Result<(), io::Error> = retry(1, Path::new("file"), || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        });
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn permanent() {
+        let mut attempts = 0;
+        let result: Result<(), io::Error> = retry(5, Path::new("file"), || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,422 @@
+//! Keeps track of what LuminS changed
+
+use std::fmt;
+use std::io;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indicatif::HumanBytes;
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+/// Whether paths changed by file operations are collected into REPORT
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Collects the paths changed by file operations, once reporting is started
+    static ref REPORT: Mutex<SyncReport> = Mutex::new(SyncReport::default());
+    /// Collects the paths that file operations failed on, along with why they failed
+    static ref FAILURES: Mutex<Vec<(PathBuf, io::Error)>> = Mutex::new(Vec::new());
+}
+
+/// Counts of the files, directories, and symlinks that were
+/// copied, synchronized, or deleted
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SyncStats {
+    /// Files copied to the destination that did not exist there before
+    pub files_copied: u64,
+    /// Files in the destination that were overwritten because they differed
+    pub files_updated: u64,
+    /// Files hard linked to the destination from a reference directory, instead of copied
+    pub files_linked: u64,
+    /// Files in the destination that were the same, but had their permissions updated
+    pub perms_updated: u64,
+    pub files_deleted: u64,
+    pub dirs_created: u64,
+    pub dirs_deleted: u64,
+    pub symlinks_copied: u64,
+    pub symlinks_deleted: u64,
+    /// Total size of all copied and updated files
+    pub bytes_copied: u64,
+    /// Copies and deletions that failed, which are logged as errors
+    pub errors: u64,
+    /// Copies that failed because they did not match the source when read back,
+    /// which are also counted in errors
+    pub verify_failures: u64,
+    /// Copies, hashes, and deletions that only succeeded after being retried,
+    /// which are not counted in errors
+    pub retried: u64,
+}
+
+impl Add for SyncStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        SyncStats {
+            files_copied: self.files_copied + other.files_copied,
+            files_updated: self.files_updated + other.files_updated,
+            files_linked: self.files_linked + other.files_linked,
+            perms_updated: self.perms_updated + other.perms_updated,
+            files_deleted: self.files_deleted + other.files_deleted,
+            dirs_created: self.dirs_created + other.dirs_created,
+            dirs_deleted: self.dirs_deleted + other.dirs_deleted,
+            symlinks_copied: self.symlinks_copied + other.symlinks_copied,
+            symlinks_deleted: self.symlinks_deleted + other.symlinks_deleted,
+            bytes_copied: self.bytes_copied + other.bytes_copied,
+            errors: self.errors + other.errors,
+            verify_failures: self.verify_failures + other.verify_failures,
+            retried: self.retried + other.retried,
+        }
+    }
+}
+
+impl AddAssign for SyncStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sum for SyncStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(SyncStats::default(), Add::add)
+    }
+}
+
+impl SyncStats {
+    /// Summarizes the counts in one line for a run that took `elapsed`, such as
+    /// `12 copied (5 linked), 3 updated, 2 deleted, 40.20MB transferred in 1.3s`,
+    /// where linked files and symlinks count as copied, and dirs are left out of copied
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let linked = if self.files_linked > 0 {
+            format!(" ({} linked)", self.files_linked)
+        } else {
+            String::new()
+        };
+        let mut summary = format!(
+            "{} copied{}, {} updated, {} deleted, {} transferred in {:.1}s",
+            self.files_copied + self.files_linked + self.symlinks_copied,
+            linked,
+            self.files_updated,
+            self.files_deleted + self.dirs_deleted + self.symlinks_deleted,
+            HumanBytes(self.bytes_copied),
+            elapsed.as_secs_f64()
+        );
+        if self.errors > 0 {
+            summary.push_str(&format!(", {} errors", self.errors));
+        }
+        if self.retried > 0 {
+            summary.push_str(&format!(", {} retried", self.retried));
+        }
+        summary
+    }
+
+    /// Formats the counts as an estimate of what would be done, for `--stats-only`
+    ///
+    /// # Returns
+    /// The number of entries to copy, update, and delete, along with the bytes to transfer
+    pub fn estimate(&self) -> String {
+        format!(
+            "{} to copy, {} to update, {} to delete, {} to transfer",
+            self.files_copied + self.symlinks_copied,
+            self.files_updated,
+            self.files_deleted + self.dirs_deleted + self.symlinks_deleted,
+            HumanBytes(self.bytes_copied)
+        )
+    }
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} files copied, {} files updated, {} files linked, \
+             {} permissions updated, {} files deleted, {} dirs created, {} dirs deleted, \
+             {} symlinks copied, {} symlinks deleted, {} bytes copied, {} errors, \
+             {} verification failures, {} retried",
+            self.files_copied,
+            self.files_updated,
+            self.files_linked,
+            self.perms_updated,
+            self.files_deleted,
+            self.dirs_created,
+            self.dirs_deleted,
+            self.symlinks_copied,
+            self.symlinks_deleted,
+            self.bytes_copied,
+            self.errors,
+            self.verify_failures,
+            self.retried
+        )
+    }
+}
+
+/// Relative paths that were changed by a copy, synchronization, or removal
+#[derive(Default, Debug, Eq, PartialEq, Serialize)]
+pub struct SyncReport {
+    /// Files, dirs, and symlinks copied or linked to the destination
+    /// that did not exist there before
+    pub copied: Vec<String>,
+    /// Files in the destination that were overwritten because they differed
+    pub updated: Vec<String>,
+    /// Files, dirs, and symlinks deleted from the destination
+    pub deleted: Vec<String>,
+    /// Total size of all copied and updated files
+    pub bytes_copied: u64,
+}
+
+impl SyncReport {
+    /// Adds `path`, which was changed as counted in `stats`
+    fn record(&mut self, path: &Path, stats: &SyncStats) {
+        let path = path.to_string_lossy().to_string();
+
+        if stats.files_updated > 0 {
+            self.updated.push(path);
+        } else if stats.files_copied
+            + stats.files_linked
+            + stats.dirs_created
+            + stats.symlinks_copied
+            > 0
+        {
+            self.copied.push(path);
+        } else if stats.files_deleted + stats.dirs_deleted + stats.symlinks_deleted > 0 {
+            self.deleted.push(path);
+        }
+        self.bytes_copied += stats.bytes_copied;
+    }
+}
+
+/// Starts collecting the paths changed by file operations into a report
+pub fn report_init() {
+    REPORTING.store(true, Ordering::SeqCst);
+}
+
+/// Adds `path`, which was changed as counted in `stats`, to the report if it was started
+/// # Arguments
+/// * `path`: Path relative to the source or destination
+/// * `stats`: Counts of what was changed at `path`
+pub fn report_record(path: &Path, stats: &SyncStats) {
+    if !REPORTING.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Ok(mut report) = REPORT.lock() {
+        report.record(path, stats);
+    }
+}
+
+/// Takes the report collected so far, with all paths sorted
+///
+/// # Returns
+/// The report, which is empty if it was not started
+pub fn report_take() -> SyncReport {
+    let mut report = match REPORT.lock() {
+        Ok(mut report) => std::mem::take(&mut *report),
+        Err(_) => SyncReport::default(),
+    };
+
+    report.copied.sort();
+    report.updated.sort();
+    report.deleted.sort();
+    report
+}
+
+/// Adds `path`, which a file operation failed on, to the failures, so that they can be
+/// reported once everything else is done
+/// # Arguments
+/// * `path`: Path of the file that could not be copied, deleted, hashed, or read
+/// * `error`: Why the operation failed
+pub fn failure_record(path: &Path, error: io::Error) {
+    if let Ok(mut failures) = FAILURES.lock() {
+        failures.push((path.to_path_buf(), error));
+    }
+}
+
+/// Takes the failures collected so far, sorted by path
+///
+/// # Returns
+/// The paths that file operations failed on, along with why they failed
+pub fn failures_take() -> Vec<(PathBuf, io::Error)> {
+    let mut failures = match FAILURES.lock() {
+        Ok(mut failures) => std::mem::take(&mut *failures),
+        Err(_) => Vec::new(),
+    };
+
+    failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+    failures
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_sync_stats {
+    use super::*;
+
+    #[test]
+    fn sum() {
+        let stats = vec![
+            SyncStats {
+                files_copied: 1,
+                bytes_copied: 10,
+                ..SyncStats::default()
+            },
+            SyncStats {
+                files_updated: 1,
+                bytes_copied: 5,
+                ..SyncStats::default()
+            },
+            SyncStats {
+                dirs_deleted: 2,
+                ..SyncStats::default()
+            },
+        ];
+
+        assert_eq!(
+            stats.into_iter().sum::<SyncStats>(),
+            SyncStats {
+                files_copied: 1,
+                files_updated: 1,
+                dirs_deleted: 2,
+                bytes_copied: 15,
+                ..SyncStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let stats = SyncStats {
+            files_copied: 10,
+            files_linked: 1,
+            symlinks_copied: 1,
+            files_updated: 3,
+            files_deleted: 1,
+            dirs_deleted: 1,
+            dirs_created: 4,
+            bytes_copied: 1536,
+            ..SyncStats::default()
+        };
+
+        assert_eq!(
+            stats.summary(Duration::from_millis(1300)),
+            "12 copied (1 linked), 3 updated, 2 deleted, 1.50KB transferred in 1.3s"
+        );
+
+        let stats = SyncStats {
+            errors: 2,
+            ..SyncStats::default()
+        };
+
+        assert_eq!(
+            stats.summary(Duration::from_secs(0)),
+            "0 copied, 0 updated, 0 deleted, 0B transferred in 0.0s, 2 errors"
+        );
+
+        let stats = SyncStats {
+            files_copied: 1,
+            retried: 3,
+            ..SyncStats::default()
+        };
+
+        assert_eq!(
+            stats.summary(Duration::from_secs(0)),
+            "1 copied, 0 updated, 0 deleted, 0B transferred in 0.0s, 3 retried"
+        );
+    }
+
+    #[test]
+    fn estimate() {
+        let stats = SyncStats {
+            files_copied: 10,
+            symlinks_copied: 1,
+            files_updated: 3,
+            files_deleted: 1,
+            dirs_deleted: 1,
+            dirs_created: 4,
+            bytes_copied: 1536,
+            ..SyncStats::default()
+        };
+
+        assert_eq!(
+            stats.estimate(),
+            "11 to copy, 3 to update, 2 to delete, 1.50KB to transfer"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_sync_report {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut report = SyncReport::default();
+        let copied = SyncStats {
+            files_copied: 1,
+            bytes_copied: 10,
+            ..SyncStats::default()
+        };
+        let updated = SyncStats {
+            files_updated: 1,
+            bytes_copied: 5,
+            ..SyncStats::default()
+        };
+        let deleted = SyncStats {
+            dirs_deleted: 1,
+            ..SyncStats::default()
+        };
+
+        report.record(Path::new("a/b.txt"), &copied);
+        report.record(Path::new("c.txt"), &updated);
+        report.record(Path::new("d"), &deleted);
+        report.record(Path::new("e.txt"), &SyncStats::default());
+
+        assert_eq!(
+            report,
+            SyncReport {
+                copied: vec![String::from("a/b.txt")],
+                updated: vec![String::from("c.txt")],
+                deleted: vec![String::from("d")],
+                bytes_copied: 15,
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_failures {
+    use super::*;
+
+    #[test]
+    fn record() {
+        failure_record(
+            Path::new("test_failures/b"),
+            io::Error::from(io::ErrorKind::NotFound),
+        );
+        failure_record(
+            Path::new("test_failures/a"),
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+
+        let failures: Vec<(PathBuf, io::ErrorKind)> = failures_take()
+            .into_iter()
+            .filter(|(path, _)| path.starts_with("test_failures"))
+            .map(|(path, e)| (path, e.kind()))
+            .collect();
+
+        assert_eq!(
+            failures,
+            vec![
+                (
+                    PathBuf::from("test_failures/a"),
+                    io::ErrorKind::PermissionDenied
+                ),
+                (PathBuf::from("test_failures/b"), io::ErrorKind::NotFound),
+            ]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Limits the bandwidth that LuminS uses for copying files
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Hands out bytes at a fixed rate, allowing bursts of up to one second worth of bytes
+///
+/// Taking more bytes than are available puts the bucket in debt, which the next takers
+/// wait out, so any amount can be taken at once, even if it is larger than the rate
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_instant: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        TokenBucket {
+            rate: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            last_instant: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` from the bucket at `now`
+    ///
+    /// # Returns
+    /// How long to wait before using the bytes
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_instant)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_instant = now;
+
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// Limits the combined rate of all copies that share it, once it is started
+#[derive(Default, Debug)]
+pub struct Throttle {
+    /// Shared by all copying threads, so that their combined rate stays under the limit
+    limiter: Mutex<Option<TokenBucket>>,
+    /// Whether copies are limited by `limiter`, which is checked without locking it
+    limited: AtomicBool,
+}
+
+impl Throttle {
+    /// Limits all copies to a combined `bytes_per_sec` from now on
+    /// # Arguments
+    /// * `bytes_per_sec`: Maximum number of bytes copied per second, which must not be 0,
+    ///   or None to not limit copies
+    pub fn start(&self, bytes_per_sec: Option<u64>) {
+        if let Ok(mut limiter) = self.limiter.lock() {
+            *limiter = bytes_per_sec.map(TokenBucket::new);
+            self.limited.store(limiter.is_some(), Ordering::SeqCst);
+        }
+    }
+
+    /// Checks whether copies are limited by `start`
+    pub fn is_limited(&self) -> bool {
+        self.limited.load(Ordering::Relaxed)
+    }
+
+    /// Blocks until `bytes` more can be copied without going over the limit
+    /// # Arguments
+    /// * `bytes`: Number of bytes about to be copied
+    pub fn throttle(&self, bytes: u64) {
+        if !self.is_limited() {
+            return;
+        }
+
+        // Sleep after releasing the lock, so that other threads can queue up behind this one
+        let wait = match self.limiter.lock() {
+            Ok(mut limiter) => match limiter.as_mut() {
+                Some(limiter) => limiter.take(bytes, Instant::now()),
+                None => return,
+            },
+            Err(_) => return,
+        };
+        thread::sleep(wait);
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test_token_bucket {
+    use super::*;
+
+    #[test]
+    fn burst() {
+        let mut bucket = TokenBucket::new(100);
+        let now = bucket.last_instant;
+
+        assert_eq!(bucket.take(100, now), Duration::from_secs(0));
+        assert_eq!(bucket.take(50, now), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn debt() {
+        let mut bucket = TokenBucket::new(100);
+        let now = bucket.last_instant;
+
+        // Larger than the rate, but still allowed after waiting it out
+        assert_eq!(bucket.take(300, now), Duration::from_secs(2));
+        assert_eq!(
+            bucket.take(100, now + Duration::from_secs(2)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn refill() {
+        let mut bucket = TokenBucket::new(100);
+        let now = bucket.last_instant;
+
+        assert_eq!(bucket.take(100, now), Duration::from_secs(0));
+        assert_eq!(
+            bucket.take(100, now + Duration::from_secs(5)),
+            Duration::from_secs(0)
+        );
+        assert_eq!(
+            bucket.take(100, now + Duration::from_secs(5)),
+            Duration::from_secs(1)
+        );
+    }
+}
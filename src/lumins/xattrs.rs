@@ -0,0 +1,93 @@
+//! Preserves the extended attributes of files and directories, such as `user.*` attributes
+//! on Linux and quarantine flags on macOS, which only exist on Unix
+
+pub use platform::copy_xattrs;
+
+#[cfg(unix)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    use log::warn;
+
+    /// Copies all extended attributes of `src` to `dest`, without following symlinks
+    ///
+    /// Attributes that cannot be read or set, such as those in a namespace that needs
+    /// more privileges, are logged and skipped
+    ///
+    /// # Errors
+    /// If the extended attributes of `src` cannot be listed
+    pub fn copy_xattrs(src: &Path, dest: &Path) -> Result<(), io::Error> {
+        for name in xattr::list(src)? {
+            let value = match xattr::get(src, &name) {
+                Ok(Some(value)) => value,
+                // Removed since it was listed
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(
+                        "Warning -- Reading extended attribute {:?} of {:?}: {}",
+                        name, src, e
+                    );
+                    continue;
+                }
+            };
+
+            if let Err(e) = xattr::set(dest, &name, &value) {
+                warn!(
+                    "Warning -- Setting extended attribute {:?} of {:?}: {}",
+                    name, dest, e
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    /// Does nothing, since extended attributes are only supported on Unix
+    pub fn copy_xattrs(_src: &Path, _dest: &Path) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+// Tests
+#[cfg(all(test, target_os = "linux"))]
+mod test_copy_xattrs {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn user_xattrs() {
+        const TEST_SRC: &str = "test_xattrs_user_xattrs_src";
+        const TEST_DEST: &str = "test_xattrs_user_xattrs_dest";
+
+        fs::write(TEST_SRC, "a").unwrap();
+        fs::write(TEST_DEST, "a").unwrap();
+
+        // Not every file system supports user attributes
+        if xattr::set(TEST_SRC, "user.lms_test", b"value").is_ok() {
+            copy_xattrs(Path::new(TEST_SRC), Path::new(TEST_DEST)).unwrap();
+            assert_eq!(
+                xattr::get(TEST_DEST, "user.lms_test").unwrap(),
+                Some(b"value".to_vec())
+            );
+        }
+
+        fs::remove_file(TEST_SRC).unwrap();
+        fs::remove_file(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn missing_src() {
+        assert!(copy_xattrs(
+            Path::new("test_xattrs_missing_src"),
+            Path::new("test_xattrs_missing_dest")
+        )
+        .is_err());
+    }
+}
@@ -1,10 +1,30 @@
+use std::io;
+use std::path::Path;
 use std::process;
+use std::time::Instant;
 
 use clap::{load_yaml, App};
+use log::info;
 
+use lms::cache;
 use lms::core;
-use lms::parse::{self, SubCommandType};
-use lms::progress::PROGRESS_BAR;
+use lms::file_ops;
+use lms::interrupt;
+use lms::parse::{self, Flag, Fsync, Options, ParseError, Reflink, SubCommandType};
+use lms::progress;
+use lms::retry;
+use lms::stats;
+
+/// Exit code after invalid arguments
+const ARGUMENT_EXIT_CODE: i32 = 1;
+/// Exit code when the source or destination is not valid, so nothing was done
+const INVALID_PATH_EXIT_CODE: i32 = 2;
+/// Exit code when some files failed, but everything else was done
+const PARTIAL_FAILURE_EXIT_CODE: i32 = 3;
+/// Exit code when `verify` or `diff` found differences
+const DIFFERENCES_EXIT_CODE: i32 = 4;
+/// Exit code after any other error that is neither about the arguments nor the files
+const OTHER_ERROR_EXIT_CODE: i32 = 5;
 
 fn main() {
     // Parse command args
@@ -12,33 +32,280 @@ fn main() {
     let args = App::from_yaml(yaml).get_matches();
 
     // Determine subcommands and flags from args
-    let (sub_command, flags) = match parse::parse_args(&args) {
-        Ok(f) => (f.sub_command, f.flags),
-        Err(_) => process::exit(1),
-    };
+    let (sub_command, flags, options, eta_smoothing, progress_refresh, threads) =
+        match parse::parse_args(&args) {
+            Ok(f) => (
+                f.sub_command,
+                f.flags,
+                f.options,
+                f.eta_smoothing,
+                f.progress_refresh,
+                f.threads,
+            ),
+            Err(ParseError::Argument) => process::exit(ARGUMENT_EXIT_CODE),
+            Err(ParseError::Path) => process::exit(INVALID_PATH_EXIT_CODE),
+        };
 
     parse::set_env(flags);
+    // Stop starting new copies on Ctrl-C, so that nothing is left half-written
+    if let Err(e) = ctrlc::set_handler(interrupt::interrupt) {
+        eprintln!("{}", e);
+        process::exit(OTHER_ERROR_EXIT_CODE);
+    }
+    // Limit the threads of the global pool before anything runs in parallel
+    if let Some(threads) = threads {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            eprintln!("{}", e);
+            process::exit(OTHER_ERROR_EXIT_CODE);
+        }
+    }
+    progress::progress_set_eta_smoothing(eta_smoothing);
+    if let Some(refresh) = progress_refresh {
+        progress::progress_set_refresh(refresh);
+    }
+    // If verbose, report whether copies take no time or space, since they can be reflinked
+    if let (Some(src), Some(dest)) = (sub_command.src, sub_command.dest.first()) {
+        let copies = matches!(
+            sub_command.sub_command_type,
+            SubCommandType::Copy | SubCommandType::Move | SubCommandType::Synchronize
+        );
+        if copies && flags.contains(Flag::VERBOSE) && options.reflink != Reflink::Never {
+            let supported = file_ops::is_reflink_supported(Path::new(src), Path::new(dest));
+            info!(
+                "Reflinking from {} to {} is {}",
+                src,
+                dest,
+                if supported {
+                    "available"
+                } else {
+                    "not available"
+                }
+            );
+        }
+    }
+    if let Some(cache_file) = &options.cache_file {
+        cache::cache_init(cache_file);
+    }
 
     // Call correct core function depending on subcommand
+    let start = Instant::now();
     let result = match sub_command.sub_command_type {
-        SubCommandType::Copy => core::copy(sub_command.src.unwrap(), &sub_command.dest[0], flags),
+        SubCommandType::Copy => core::copy(
+            sub_command.src.unwrap(),
+            &sub_command.dest[0],
+            flags,
+            &options,
+        ),
+        SubCommandType::Move => core::move_dir(
+            sub_command.src.unwrap(),
+            &sub_command.dest[0],
+            flags,
+            &options,
+        ),
         SubCommandType::Remove => sub_command
             .dest
             .iter()
-            .map(|dest| core::remove(dest, flags))
-            .collect(),
-        SubCommandType::Synchronize => {
-            core::synchronize(sub_command.src.unwrap(), &sub_command.dest[0], flags)
+            .map(|dest| core::remove(dest, flags, &options))
+            .sum(),
+        SubCommandType::Synchronize => core::synchronize(
+            sub_command.src.unwrap(),
+            &sub_command.dest[0],
+            flags,
+            &options,
+        ),
+        SubCommandType::Verify | SubCommandType::Diff => {
+            verify(
+                sub_command.src.unwrap(),
+                &sub_command.dest[0],
+                flags,
+                &options,
+                sub_command.sub_command_type == SubCommandType::Diff,
+            );
+            return;
+        }
+        SubCommandType::Checksum => {
+            checksum(sub_command.src.unwrap(), flags, &options);
+            return;
         }
     };
 
     // End and remove progress bars
-    PROGRESS_BAR.finish_and_clear();
+    progress::progress_finish();
+    save_cache();
+
+    // Whatever was not started before the interrupt is left as it was
+    if interrupt::is_interrupted() {
+        print_failures();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        eprintln!("Interrupted -- Stopped before finishing");
+        process::exit(interrupt::INTERRUPTED_EXIT_CODE);
+    }
+
+    // Flush everything copied to disk before finishing, if it was not flushed along the way
+    if let (Ok(_), Fsync::AtEnd, Some(dest)) = (&result, options.fsync, sub_command.dest.first()) {
+        if let Err(e) = file_ops::sync_file_system(Path::new(dest)) {
+            print_failures();
+            eprintln!("Fsync Error -- {}: {}", dest, e);
+            process::exit(PARTIAL_FAILURE_EXIT_CODE);
+        }
+    }
 
     // If error, print to stderr and exit
-    if let Err(e) = result {
-        eprintln!("{}", e);
-        process::exit(1);
+    match result {
+        Ok(stats) if flags.contains(Flag::STATS_ONLY) => println!("{}", stats.estimate()),
+        Ok(_) if flags.contains(Flag::JSON) => {
+            let report = stats::report_take();
+            match serde_json::to_string(&report) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(OTHER_ERROR_EXIT_CODE);
+                }
+            }
+        }
+        Ok(mut stats) => {
+            // Hashes are retried outside of the operations that count stats, so retries are
+            // counted on their own
+            stats.retried = retry::retried_count();
+            info!("{}", stats);
+            if !flags.contains(Flag::QUIET) {
+                eprintln!(
+                    "{}: {}{}",
+                    summary_verb(&sub_command.sub_command_type),
+                    stats.summary(start.elapsed()),
+                    match options.fsync {
+                        Fsync::Never => "",
+                        Fsync::Each => ", each file flushed to disk",
+                        Fsync::AtEnd => ", flushed to disk at the end",
+                    }
+                );
+            }
+        }
+        Err(e) => exit_with_error(&e),
+    }
+
+    // Anything that failed along the way means the destination is incomplete
+    if print_failures() {
+        process::exit(PARTIAL_FAILURE_EXIT_CODE);
+    }
+}
+
+/// Gets the past tense of `sub_command_type` that starts the summary after it ran
+fn summary_verb(sub_command_type: &SubCommandType) -> &'static str {
+    match sub_command_type {
+        SubCommandType::Copy => "Copied",
+        SubCommandType::Move => "Moved",
+        SubCommandType::Synchronize => "Synced",
+        SubCommandType::Remove => "Removed",
+        SubCommandType::Verify => "Verified",
+        SubCommandType::Diff => "Compared",
+        SubCommandType::Checksum => "Checksummed",
+    }
+}
+
+/// Prints a summary of every path that a file operation failed on
+///
+/// # Returns
+/// Whether anything failed
+fn print_failures() -> bool {
+    let failures = stats::failures_take();
+    for (path, e) in &failures {
+        eprintln!("Failed -- {}: {}", path.display(), e);
+    }
+    !failures.is_empty()
+}
+
+/// Prints the failures and `e`, and exits with the code for the partial failure if any files
+/// failed before `e`, or otherwise with the code for the kind of `e`, which is an invalid
+/// argument if `e` is invalid input, an invalid source or destination if it was not found
+/// or is not a directory, or any other error
+fn exit_with_error(e: &io::Error) -> ! {
+    let exit_code = if print_failures() {
+        PARTIAL_FAILURE_EXIT_CODE
+    } else {
+        match e.kind() {
+            io::ErrorKind::InvalidInput => ARGUMENT_EXIT_CODE,
+            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => INVALID_PATH_EXIT_CODE,
+            _ => OTHER_ERROR_EXIT_CODE,
+        }
+    };
+    eprintln!("{}", e);
+    process::exit(exit_code);
+}
+
+/// Saves the hashes that were cached while comparing files,
+/// only printing an error if they cannot be saved
+fn save_cache() {
+    if let Err(e) = cache::cache_save() {
+        eprintln!("Cache Error -- {}", e);
+    }
+}
+
+/// Verifies that `dest` matches `src`, printing all differences,
+/// and exits with an error if there are any, or with the partial failure code if any files failed
+///
+/// If `as_diff` is set, the differences are printed with `+` for paths only in `src`,
+/// `-` for paths only in `dest`, and `~` for paths in both that differ,
+/// followed by the identical files with `=` if `Flag::VERBOSE` is set
+fn verify(src: &str, dest: &str, flags: Flag, options: &Options, as_diff: bool) {
+    let result = core::verify(src, dest, flags, options);
+    progress::progress_finish();
+    save_cache();
+
+    match result {
+        Ok(differences) => {
+            let (missing, extra, different) = if as_diff {
+                ("+ ", "- ", "~ ")
+            } else {
+                ("Missing -- ", "Extra -- ", "Different -- ")
+            };
+            for path in &differences.missing {
+                println!("{}{}", missing, path.display());
+            }
+            for path in &differences.extra {
+                println!("{}{}", extra, path.display());
+            }
+            for path in &differences.different {
+                println!("{}{}", different, path.display());
+            }
+            if as_diff && flags.contains(Flag::VERBOSE) {
+                for path in &differences.identical {
+                    println!("= {}", path.display());
+                }
+            }
+            if print_failures() {
+                process::exit(PARTIAL_FAILURE_EXIT_CODE);
+            }
+            if !differences.is_empty() {
+                process::exit(DIFFERENCES_EXIT_CODE);
+            }
+        }
+        Err(e) => exit_with_error(&e),
+    }
+}
+
+/// Prints the checksum of every file in `src` in hex followed by its path, sorted by path,
+/// and exits with an error if any file can't be hashed
+fn checksum(src: &str, flags: Flag, options: &Options) {
+    let result = core::checksums(src, flags, options);
+    progress::progress_finish();
+
+    match result {
+        Ok(checksums) => {
+            for (path, hash) in &checksums {
+                let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+                println!("{} {}", hex, path.display());
+            }
+            if print_failures() {
+                process::exit(PARTIAL_FAILURE_EXIT_CODE);
+            }
+        }
+        Err(e) => exit_with_error(&e),
     }
 }
 
@@ -52,10 +319,10 @@ mod test_main {
     use std::process::Command;
 
     #[cfg(debug_assertions)]
-    const BUILD_DIR: &str = "target/debug";
+    const BUILD_DIR: &str = "target/debug/";
 
     #[cfg(not(debug_assertions))]
-    const BUILD_DIR: &str = "target/release";
+    const BUILD_DIR: &str = "target/release/";
 
     #[test]
     fn test_no_args() {
@@ -84,6 +351,27 @@ mod test_main {
         assert_eq!(output.status.success(), false);
     }
 
+    #[test]
+    fn test_dest_not_dir() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "Cargo.toml";
+
+        for args in &[["cp", "src", TEST_DEST], ["sync", "src/", TEST_DEST]] {
+            let output = Command::new("target/release/lms")
+                .args(args)
+                .output()
+                .unwrap();
+
+            assert_eq!(output.status.code(), Some(2));
+            assert!(String::from_utf8_lossy(&output.stderr)
+                .contains("Destination Error -- Cargo.toml is not a directory"));
+        }
+    }
+
     #[test]
     fn test_too_many_args() {
         Command::new("cargo")
@@ -99,6 +387,50 @@ mod test_main {
         assert_eq!(output.status.success(), false);
     }
 
+    #[test]
+    fn test_exit_codes() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SRC: &str = "test_main_test_exit_codes_src";
+        const TEST_DEST: &str = "test_main_test_exit_codes_dest";
+
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "dir/a.txt"].join("/"), b"a").unwrap();
+        fs::write([TEST_SRC, "b.txt"].join("/"), b"b").unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        // A file in the way of a directory only fails the files inside it
+        fs::write([TEST_DEST, "dir"].join("/"), b"file").unwrap();
+
+        let src = [TEST_SRC, ""].join("/");
+        let exit_code = |args: &[&str]| {
+            Command::new("target/release/lms")
+                .args(args)
+                .output()
+                .unwrap()
+                .status
+                .code()
+        };
+
+        assert_eq!(
+            exit_code(&["sync", "--hash", "md5", &src, TEST_DEST]),
+            Some(1)
+        );
+        assert_eq!(exit_code(&["sync", &src, &[&src, "dir"].concat()]), Some(1));
+        assert_eq!(exit_code(&["sync", "/?/", TEST_DEST]), Some(2));
+        assert_eq!(exit_code(&["rm", "/?"]), Some(2));
+        assert_eq!(exit_code(&["cp", &src, TEST_DEST]), Some(3));
+        assert_eq!(fs::read([TEST_DEST, "b.txt"].join("/")).unwrap(), b"b");
+        assert_eq!(exit_code(&["verify", &src, TEST_DEST]), Some(4));
+        assert_eq!(exit_code(&["sync", &src, TEST_DEST]), Some(0));
+        assert_eq!(exit_code(&["diff", &src, TEST_DEST]), Some(0));
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
     #[test]
     fn test_invalid_args() {
         Command::new("cargo")
@@ -114,7 +446,102 @@ mod test_main {
         assert_eq!(output.status.success(), false);
     }
 
+    #[test]
+    fn test_invalid_eta_smoothing() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--eta-smoothing", "2", "src", "dest"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_progress_refresh() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_progress_refresh";
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--progress-refresh", "200", "src/", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            fs::read("src/main.rs").unwrap(),
+            fs::read([TEST_DEST, "main.rs"].join("/")).unwrap()
+        );
+
+        for &refresh in &["0", "-1", "fast"] {
+            let output = Command::new("target/release/lms")
+                .args(["sync", "--progress-refresh", refresh, "src/", TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(!output.status.success());
+        }
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
     #[cfg(target_family = "unix")]
+    #[test]
+    fn test_invalid_size() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--max-size", "2X", "src", "dest"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("not a valid size"));
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--min-size",
+                "2G",
+                "--max-size",
+                "1G",
+                "src",
+                "dest",
+            ])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("larger than max-size"));
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--newer-than", "yesterday", "src", "dest"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("not an RFC 3339 timestamp"));
+    }
+
     #[test]
     fn test_copy() {
         Command::new("cargo")
@@ -140,130 +567,1870 @@ mod test_main {
         fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
-    #[cfg(target_family = "unix")]
     #[test]
-    fn test_secure() {
+    fn test_copy_verify() {
         Command::new("cargo")
-            .args(&["build", "--release"])
+            .args(["build", "--release"])
             .output()
             .unwrap();
 
-        const TEST_SOURCE: &str = BUILD_DIR;
-        const TEST_DEST: &str = "test_main_test_secure";
-        fs::create_dir_all(TEST_DEST).unwrap();
+        const TEST_SOURCE: &str = "src";
+        const TEST_DEST: &str = "test_main_test_copy_verify";
 
-        Command::new("target/release/lms")
-            .args(&["sync", "-s", TEST_SOURCE, TEST_DEST])
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--verify", TEST_SOURCE, TEST_DEST])
             .output()
             .unwrap();
 
+        assert!(output.status.success());
+
         let diff = Command::new("diff")
-            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .args(["-r", TEST_SOURCE, TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
+        assert!(diff.status.success());
 
         fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn test_sequential() {
+    fn test_copy_glob() {
         Command::new("cargo")
-            .args(&["build", "--release"])
+            .args(["build", "--release"])
             .output()
             .unwrap();
 
-        const TEST_SOURCE: &str = BUILD_DIR;
-        const TEST_DEST: &str = "test_main_test_sequential";
+        const TEST_DEST: &str = "test_main_test_copy_glob";
+        const TEST_EXPECTED: &str = "test_main_test_copy_glob_expected";
+        fs::create_dir_all(TEST_EXPECTED).unwrap();
 
-        Command::new("target/release/lms")
-            .args(&["sync", "-S", TEST_SOURCE, TEST_DEST])
+        fs::copy(
+            "src/lumins/conflict.rs",
+            [TEST_EXPECTED, "conflict.rs"].join("/"),
+        )
+        .unwrap();
+        fs::copy("src/lumins/cache.rs", [TEST_EXPECTED, "cache.rs"].join("/")).unwrap();
+        fs::copy("src/lumins/chmod.rs", [TEST_EXPECTED, "chmod.rs"].join("/")).unwrap();
+        fs::copy("src/lumins/core.rs", [TEST_EXPECTED, "core.rs"].join("/")).unwrap();
+        fs::copy("src/lumins/parse.rs", [TEST_EXPECTED, "parse.rs"].join("/")).unwrap();
+        fs::copy(
+            "src/lumins/progress.rs",
+            [TEST_EXPECTED, "progress.rs"].join("/"),
+        )
+        .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "src/lumins/[cp]*.rs", TEST_DEST])
             .output()
             .unwrap();
 
+        assert!(output.status.success());
+
         let diff = Command::new("diff")
-            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .args(["-r", TEST_DEST, TEST_EXPECTED])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
+        assert!(diff.status.success());
 
         fs::remove_dir_all(TEST_DEST).unwrap();
+        fs::remove_dir_all(TEST_EXPECTED).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn test_sequential_copy() {
+    fn test_copy_glob_dir() {
         Command::new("cargo")
-            .args(&["build", "--release"])
+            .args(["build", "--release"])
             .output()
             .unwrap();
 
-        const TEST_SOURCE: &str = BUILD_DIR;
-        const TEST_DEST: &str = "test_main_test_sequential_copy";
+        const TEST_DEST: &str = "test_main_test_copy_glob_dir";
 
-        Command::new("target/release/lms")
-            .args(&["cp", "-S", TEST_SOURCE, TEST_DEST])
+        let output = Command::new("target/release/lms")
+            .args(["cp", "src/*", TEST_DEST])
             .output()
             .unwrap();
 
+        assert!(output.status.success());
+
         let diff = Command::new("diff")
-            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .args(["-r", "src", TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
+        assert!(diff.status.success());
 
         fs::remove_dir_all(TEST_DEST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
     #[test]
-    fn test_no_delete() {
+    fn test_copy_glob_literal() {
         Command::new("cargo")
-            .args(&["build", "--release"])
+            .args(["build", "--release"])
             .output()
             .unwrap();
 
-        const TEST_SOURCE1: &str = "test_main_test_no_delete_source1";
-        const TEST_SOURCE2: &str = "test_main_test_no_delete_source2";
-        const TEST_DEST: &str = "test_main_test_no_delete_out";
-        const TEST_EXPECTED: &str = "test_main_test_no_delete_expected";
-        const TEST_FILE1: &str = "Cargo.toml";
-        const TEST_FILE2: &str = "Cargo.lock";
+        const TEST_SRC: &str = "test_main_test_copy_glob_literal_src[1]";
+        const TEST_DEST: &str = "test_main_test_copy_glob_literal_dest";
 
-        fs::create_dir_all(TEST_SOURCE1).unwrap();
-        fs::create_dir_all(TEST_SOURCE2).unwrap();
-        fs::create_dir_all(TEST_EXPECTED).unwrap();
+        fs::create_dir_all([TEST_SRC, "dir"].join("/")).unwrap();
+        fs::write([TEST_SRC, "dir/a.txt"].join("/"), b"a").unwrap();
 
-        fs::copy(TEST_FILE1, [TEST_SOURCE1, TEST_FILE1].join("/")).unwrap();
-        fs::copy(TEST_FILE2, [TEST_SOURCE2, TEST_FILE2].join("/")).unwrap();
-        fs::copy(TEST_FILE1, [TEST_EXPECTED, TEST_FILE1].join("/")).unwrap();
-        fs::copy(TEST_FILE2, [TEST_EXPECTED, TEST_FILE2].join("/")).unwrap();
+        // A source that exists is copied as it is, even if it would also be a glob
+        let output = Command::new("target/release/lms")
+            .args(["cp", TEST_SRC, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SRC, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_SRC).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_copy_glob_no_match() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_copy_glob_no_match";
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "src/*.none", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(fs::metadata(TEST_DEST).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_verify() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_verify";
 
         Command::new("target/release/lms")
-            .args(&["cp", TEST_SOURCE1, TEST_DEST])
+            .args(["cp", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["verify", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+
+        fs::write([TEST_DEST, "main.rs"].join("/"), b"").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["verify", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("main.rs"));
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_diff() {
+        Command::new("cargo")
+            .args(["build", "--release"])
             .output()
             .unwrap();
 
+        const TEST_DEST: &str = "test_main_test_diff";
+
         Command::new("target/release/lms")
-            .args(&["sync", "-n", TEST_SOURCE2, TEST_DEST])
+            .args(["cp", "src", TEST_DEST])
             .output()
             .unwrap();
 
-        let diff = Command::new("diff")
-            .args(&["-r", TEST_DEST, TEST_EXPECTED])
+        fs::write([TEST_DEST, "main.rs"].join("/"), b"").unwrap();
+        fs::remove_file([TEST_DEST, "cli.yml"].join("/")).unwrap();
+        fs::write([TEST_DEST, "extra.txt"].join("/"), b"").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["diff", "-v", "src", TEST_DEST])
             .output()
             .unwrap();
 
-        assert_eq!(diff.status.success(), true);
+        assert!(!output.status.success());
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert!(lines.contains(&"+ cli.yml"));
+        assert!(lines.contains(&"- extra.txt"));
+        assert!(lines.contains(&"~ main.rs"));
+        assert!(lines.contains(&"= lib.rs"));
+
+        // Nothing is modified
+        assert_eq!(fs::read([TEST_DEST, "main.rs"].join("/")).unwrap(), b"");
 
-        fs::remove_dir_all(TEST_SOURCE1).unwrap();
-        fs::remove_dir_all(TEST_SOURCE2).unwrap();
         fs::remove_dir_all(TEST_DEST).unwrap();
-        fs::remove_dir_all(TEST_EXPECTED).unwrap();
+    }
+
+    #[test]
+    fn test_inplace() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DIR: &str = "test_main_test_inplace";
+        let src = [TEST_DIR, "src/"].join("/");
+        let dest = [TEST_DIR, "dest"].join("/");
+        let link = [TEST_DIR, "link.txt"].join("/");
+
+        for &(inplace, expected) in &[(false, &b"old and longer"[..]), (true, b"new")] {
+            fs::create_dir_all(&src).unwrap();
+            fs::create_dir_all(&dest).unwrap();
+            fs::write([&src, "file.txt"].concat(), b"new").unwrap();
+            fs::write([&dest, "file.txt"].join("/"), b"old and longer").unwrap();
+            fs::hard_link([&dest, "file.txt"].join("/"), &link).unwrap();
+
+            let mut args = vec!["sync", &src, &dest];
+            if inplace {
+                args.insert(1, "--inplace");
+            }
+            let output = Command::new("target/release/lms")
+                .args(&args)
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            assert_eq!(fs::read([&dest, "file.txt"].join("/")).unwrap(), b"new");
+            // Files copied in place are written through other hard links to them,
+            // and truncated to the size of the source once they are overwritten
+            assert_eq!(fs::read(&link).unwrap(), expected);
+
+            fs::remove_dir_all(TEST_DIR).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_partial() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DIR: &str = "test_main_test_partial";
+        let src = [TEST_DIR, "src/"].join("/");
+        let dest = [TEST_DIR, "dest"].join("/");
+        let contents = fs::read("src/main.rs").unwrap();
+
+        // Partial files are kept next to the file, or in the partial dir next to it
+        for &(arg, partial_dir, partial_path) in &[
+            ("--partial", "", "file.rs.lms-tmp.1.0"),
+            ("--partial-dir=.partial", ".partial", ".partial/file.rs"),
+        ] {
+            let partial_path = [&dest, partial_path].join("/");
+            fs::create_dir_all(&src).unwrap();
+            fs::create_dir_all([&dest, partial_dir].join("/")).unwrap();
+            fs::write([&src, "file.rs"].concat(), &contents).unwrap();
+            fs::write(&partial_path, &contents[..1000]).unwrap();
+
+            let output = Command::new("target/release/lms")
+                .args(["sync", "--quiet", "--verbose", arg, &src, &dest])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains("from byte 1000"));
+            assert_eq!(fs::read([&dest, "file.rs"].join("/")).unwrap(), contents);
+            assert!(fs::metadata(&partial_path).is_err());
+            assert!(fs::metadata([&dest, ".partial"].join("/")).is_err());
+
+            fs::remove_dir_all(TEST_DIR).unwrap();
+        }
+
+        // Partial files that are not the start of the source are copied over
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write([&src, "file.rs"].concat(), &contents).unwrap();
+        fs::write([&dest, "file.rs.lms-tmp.1.0"].join("/"), b"changed").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--quiet", "--verbose", "--partial", &src, &dest])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("Resuming file"));
+        assert_eq!(fs::read([&dest, "file.rs"].join("/")).unwrap(), contents);
+
+        // Partial files cannot be kept when copying in place
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--partial", "--inplace", &src, &dest])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_fsync() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_fsync";
+
+        for &(fsync, note) in &[
+            ("--fsync", "each file flushed to disk"),
+            ("--fsync-at-end", "flushed to disk at the end"),
+        ] {
+            let output = Command::new("target/release/lms")
+                .args(["sync", fsync, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            assert!(String::from_utf8_lossy(&output.stderr).contains(note));
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_trash() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DIR: &str = "test_main_test_trash";
+        let target = [TEST_DIR, "target"].join("/");
+        let data = fs::canonicalize(".").unwrap().join(TEST_DIR).join("data");
+        let trash = data.join("Trash/files");
+
+        // The whole target is moved to the trash as it is
+        fs::create_dir_all([&target, "dir"].join("/")).unwrap();
+        fs::create_dir_all(&data).unwrap();
+        fs::write([&target, "dir/file.txt"].join("/"), b"file").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["rm", "--trash", &target])
+            .env("XDG_DATA_HOME", &data)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert!(fs::metadata(&target).is_err());
+        assert_eq!(
+            fs::read(trash.join("target/dir/file.txt")).unwrap(),
+            b"file"
+        );
+
+        // Only the entries in the time range are moved to the trash
+        fs::create_dir_all(&target).unwrap();
+        fs::write([&target, "old.txt"].join("/"), b"old").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["rm", "--trash", "--older-than", "0s", &target])
+            .env("XDG_DATA_HOME", &data)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(fs::read(trash.join("old.txt")).unwrap(), b"old");
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_preallocate() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_preallocate";
+
+        // Files are the same size as the source once copied, including those overwritten
+        for _ in 0..2 {
+            let output = Command::new("target/release/lms")
+                .args(["cp", "--preallocate", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+        }
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_buffer_size";
+
+        for &buffer_size in &["1000", "128K", "8M"] {
+            let output = Command::new("target/release/lms")
+                .args(["sync", "--buffer-size", buffer_size, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+
+        for &buffer_size in &["0", "2G", "big"] {
+            let output = Command::new("target/release/lms")
+                .args(["sync", "--buffer-size", buffer_size, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(!output.status.success());
+        }
+        fs::remove_dir_all(TEST_DEST).ok();
+    }
+
+    #[test]
+    fn test_checksum() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DIR: &str = "test_main_test_checksum";
+
+        fs::create_dir_all([TEST_DIR, "b"].join("/")).unwrap();
+        fs::write([TEST_DIR, "c.txt"].join("/"), b"123456789").unwrap();
+        fs::write([TEST_DIR, "b/a.txt"].join("/"), b"").unwrap();
+        fs::write([TEST_DIR, "a.txt"].join("/"), b"The quick brown fox").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["checksum", TEST_DIR])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "b74574de a.txt\n00000000 b/a.txt\ncbf43926 c.txt\n"
+        );
+
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn test_json() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_json";
+
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_DEST, "extra.txt"].join("/"), b"").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--json", "src/", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let copied = report["copied"].as_array().unwrap();
+
+        assert!(copied.contains(&serde_json::json!("main.rs")));
+        assert_eq!(report["deleted"], serde_json::json!(["extra.txt"]));
+        assert_eq!(report["updated"], serde_json::json!([]));
+        assert!(report["bytes_copied"].as_u64().unwrap() > 0);
+        assert!(!String::from_utf8_lossy(&output.stderr).contains("Synced: "));
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_force_copy() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_force_copy";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        // Files that are the same are still copied over
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--force-copy", "--json", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let updated = report["updated"].as_array().unwrap();
+
+        assert!(updated.contains(&serde_json::json!("main.rs")));
+        assert_eq!(report["copied"], serde_json::json!([]));
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_backup() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_backup";
+        const TEST_FILE: &str = "main.rs";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+        fs::write([TEST_DEST, TEST_FILE].join("/"), b"old").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "-b", "--suffix", ".bak", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            fs::read([TEST_DEST, "main.rs.bak"].join("/")).unwrap(),
+            b"old"
+        );
+
+        // Backups are kept by later syncs, and the other files are the same as in the source
+        Command::new("target/release/lms")
+            .args(["sync", "-b", "--suffix", ".bak", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+        fs::remove_file([TEST_DEST, "main.rs.bak"].join("/")).unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_backup_dir() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_backup_dir";
+        const TEST_BACKUP_DIR: &str = "test_main_test_backup_dir/.backup";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+        fs::write([TEST_DEST, "main.rs"].join("/"), b"old").unwrap();
+        fs::write([TEST_DEST, "extra.txt"].join("/"), b"extra").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--backup-dir",
+                TEST_BACKUP_DIR,
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            fs::read([TEST_BACKUP_DIR, "main.rs"].join("/")).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            fs::read([TEST_BACKUP_DIR, "extra.txt"].join("/")).unwrap(),
+            b"extra"
+        );
+
+        // The backup dir is kept by later syncs, and the other files are the same as in the source
+        Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--backup-dir",
+                TEST_BACKUP_DIR,
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+        assert!(fs::metadata(TEST_BACKUP_DIR).is_ok());
+        fs::remove_dir_all(TEST_BACKUP_DIR).unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_mv() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_mv_src";
+        const TEST_DEST: &str = "test_main_test_mv_dest";
+
+        Command::new("cp")
+            .args(["-r", "src", TEST_SOURCE])
+            .output()
+            .unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["mv", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        // Like mv, the source is moved into an existing destination directory
+        let diff = Command::new("diff")
+            .args(["-r", "src", &[TEST_DEST, TEST_SOURCE].join("/")])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+        assert!(fs::metadata(TEST_SOURCE).is_err());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_plain() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_plain";
+
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--plain", "src/", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        // Progress is printed as lines without any control codes
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.lines().any(|line| line.ends_with(")")));
+        assert!(!stderr.contains('\x1b'));
+
+        // A summary is printed after the run
+        assert!(stderr
+            .lines()
+            .any(|line| line.starts_with("Synced: ") && line.contains(" copied, ")));
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_quiet() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_quiet";
+
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--quiet", "--verbose", "src/", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+
+        // Verbose lines are still logged without the progress bar
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Copying file"));
+        assert!(!stderr.contains("Synced: "));
+        assert_eq!(
+            fs::read("src/main.rs").unwrap(),
+            fs::read([TEST_DEST, "main.rs"].join("/")).unwrap()
+        );
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_secure() {
+        Command::new("cargo")
+            .args(&["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = BUILD_DIR;
+        const TEST_DEST: &str = "test_main_test_secure";
+        fs::create_dir_all(TEST_DEST).unwrap();
+
+        Command::new("target/release/lms")
+            .args(&["sync", "-s", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_hash() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_hash";
+
+        for hash in &["seahash", "blake2", "blake3", "sha256", "xxhash", "xxh3"] {
+            Command::new("target/release/lms")
+                .args(["sync", "--hash", hash, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+        }
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--hash", "md5", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_mmap() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_mmap";
+        const TEST_FILE: &str = "main.rs";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        // A file of the same size is only found to differ by hashing it
+        let mut contents = fs::read([TEST_DEST, TEST_FILE].join("/")).unwrap();
+        contents[0] ^= 1;
+        fs::write([TEST_DEST, TEST_FILE].join("/"), contents).unwrap();
+
+        Command::new("target/release/lms")
+            .args(["sync", "--mmap", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        let output = Command::new("target/release/lms")
+            .args(["verify", "--mmap", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_compare_bytes() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_compare_bytes";
+        const TEST_FILE: &str = "main.rs";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        // A file of the same size is only found to differ by comparing its bytes
+        let mut contents = fs::read([TEST_DEST, TEST_FILE].join("/")).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write([TEST_DEST, TEST_FILE].join("/"), contents).unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["verify", "--compare-bytes", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        Command::new("target/release/lms")
+            .args(["sync", "--compare-bytes", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--compare-bytes",
+                "--secure",
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_cache() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_cache";
+        const TEST_FILE: &str = "main.rs";
+
+        let sync_and_diff = || {
+            let output = Command::new("target/release/lms")
+                .args(["sync", "--cache", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+
+            Command::new("diff")
+                .args(["-r", "-x", ".lms-cache", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap()
+        };
+
+        fs::create_dir_all(TEST_DEST).unwrap();
+        assert!(sync_and_diff().status.success());
+
+        // Both sides are hashed and cached once they are compared
+        assert!(sync_and_diff().status.success());
+        assert!(fs::metadata([TEST_DEST, ".lms-cache"].join("/")).is_ok());
+
+        // A file of the same size that changed is hashed again
+        let mut contents = fs::read([TEST_DEST, TEST_FILE].join("/")).unwrap();
+        contents[0] ^= 1;
+        fs::write([TEST_DEST, TEST_FILE].join("/"), contents).unwrap();
+        assert!(sync_and_diff().status.success());
+
+        // The cache is left out when verifying with it
+        let output = Command::new("target/release/lms")
+            .args(["verify", "--cache", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_interactive() {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_interactive_src/";
+        const TEST_DEST: &str = "test_main_test_interactive_dest";
+
+        fs::create_dir_all(TEST_SOURCE).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SOURCE, "a.txt"].join("/"), b"new").unwrap();
+        fs::write([TEST_DEST, "a.txt"].join("/"), b"old").unwrap();
+
+        let sync_answering = |answer: &str| {
+            let mut child = Command::new("target/release/lms")
+                .args(["sync", "-i", TEST_SOURCE, TEST_DEST])
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(answer.as_bytes())
+                .unwrap();
+            child.wait_with_output().unwrap()
+        };
+
+        let output = sync_answering("n\n");
+        assert!(output.status.success());
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"old");
+
+        let output = sync_answering("a\n");
+        assert!(!output.status.success());
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"old");
+
+        let output = sync_answering("y\n");
+        assert!(output.status.success());
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"new");
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_bwlimit() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_bwlimit";
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--bwlimit", "0", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        Command::new("target/release/lms")
+            .args(["sync", "--bwlimit", "4096", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_cp_update() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_cp_update";
+
+        Command::new("target/release/lms")
+            .args(["cp", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        // A copied file with the same size and modification time is not copied again
+        let copied = [TEST_DEST, "main.rs"].join("/");
+        let contents = fs::read(&copied).unwrap();
+        fs::write(&copied, vec![b'x'; contents.len()]).unwrap();
+        Command::new("touch")
+            .args(["-r", "src/main.rs", &copied])
+            .output()
+            .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "-u", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(fs::read(&copied).unwrap(), vec![b'x'; contents.len()]);
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_sync_nested() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_sync_nested";
+
+        Command::new("target/release/lms")
+            .args(["sync", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", "src", &[TEST_DEST, "src"].join("/")])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_threads() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_threads";
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--threads", "-1", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        Command::new("target/release/lms")
+            .args(["sync", "--threads", "2", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_sparse() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_sparse";
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--sparse", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_archive() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_archive";
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "-a", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("Argument Warning -- archive does not copy devices or special files"));
+
+        let diff = Command::new("diff")
+            .args(["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(diff.status.success());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_xattrs() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_xattrs_source";
+        const TEST_DEST: &str = "test_main_test_xattrs_out";
+        let test_dir = [TEST_SOURCE, "b"].join("/");
+        let test_file = [TEST_SOURCE, "a"].join("/");
+
+        fs::create_dir_all(&test_dir).unwrap();
+        fs::write(&test_file, "a").unwrap();
+
+        // Not every file system supports user attributes
+        if xattr::set(&test_dir, "user.lms_test", b"dir").is_ok() {
+            xattr::set(&test_file, "user.lms_test", b"file").unwrap();
+
+            let output = Command::new("target/release/lms")
+                .args(["cp", "-X", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            assert_eq!(
+                xattr::get([TEST_DEST, "b"].join("/"), "user.lms_test").unwrap(),
+                Some(b"dir".to_vec())
+            );
+            assert_eq!(
+                xattr::get([TEST_DEST, "a"].join("/"), "user.lms_test").unwrap(),
+                Some(b"file".to_vec())
+            );
+
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_chown() {
+        use std::os::unix::fs::MetadataExt;
+
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_chown";
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--chown", "lms_no_such_user", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(!std::path::Path::new(TEST_DEST).exists());
+
+        // Giving files another owner requires root
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--chown", "1234:1234", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        for path in &["lumins", "lumins/file_ops.rs"] {
+            let metadata = fs::metadata([TEST_DEST, path].join("/")).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (1234, 1234));
+        }
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_interrupt() {
+        use std::{thread, time::Duration};
+
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_interrupt_source";
+        const TEST_DEST: &str = "test_main_test_interrupt_out";
+        const NUM_FILES: usize = 200;
+
+        fs::create_dir_all(TEST_SOURCE).unwrap();
+        for i in 0..NUM_FILES {
+            fs::write([TEST_SOURCE, &i.to_string()].join("/"), [b'a'; 100]).unwrap();
+        }
+
+        // Copying is slowed down, so that it is interrupted before it finishes
+        let child = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--bwlimit",
+                "1",
+                &[TEST_SOURCE, "/"].concat(),
+                TEST_DEST,
+            ])
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(1500));
+        Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .output()
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(130));
+
+        let copied: Vec<_> = fs::read_dir(TEST_DEST)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(copied.len() < NUM_FILES);
+        assert!(!copied.iter().any(|name| name.contains(".lms-tmp.")));
+        for name in &copied {
+            assert_eq!(
+                fs::read([TEST_DEST, name].join("/")).unwrap(),
+                fs::read([TEST_SOURCE, name].join("/")).unwrap()
+            );
+        }
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_chmod() {
+        use std::os::unix::fs::PermissionsExt;
+
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_chmod";
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--chmod", "F8", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--chmod", "F600,D750", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        let mode = |path: &str| {
+            fs::metadata([TEST_DEST, path].join("/"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("lumins"), 0o750);
+        assert_eq!(mode("lumins/file_ops.rs"), 0o600);
+        assert_eq!(mode("main.rs"), 0o600);
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_delete_order() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_delete_order";
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--delete-before",
+                "--delete-after",
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        for flag in &["--delete-before", "--delete-during", "--delete-after"] {
+            fs::create_dir_all(TEST_DEST).unwrap();
+            fs::write([TEST_DEST, "extra.txt"].join("/"), b"").unwrap();
+
+            let output = Command::new("target/release/lms")
+                .args(["sync", flag, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reflink() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "src/";
+        const TEST_DEST: &str = "test_main_test_reflink";
+
+        for reflink in &["--reflink=never", "--reflink=auto"] {
+            let output = Command::new("target/release/lms")
+                .args(["cp", reflink, TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+
+            fs::remove_dir_all(TEST_DEST).unwrap();
+        }
+
+        // Whether reflinking is available is reported before copying
+        let output = Command::new("target/release/lms")
+            .args(["cp", "-v", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Reflinking from src/ to "));
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+
+        // Files that cannot be reflinked fail, unless on a copy-on-write file system
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--reflink=always", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        if output.status.success() {
+            let diff = Command::new("diff")
+                .args(["-r", TEST_SOURCE, TEST_DEST])
+                .output()
+                .unwrap();
+
+            assert!(diff.status.success());
+        } else {
+            assert!(String::from_utf8_lossy(&output.stderr).contains("Reflinking is not possible"));
+        }
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_sequential() {
+        Command::new("cargo")
+            .args(&["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = BUILD_DIR;
+        const TEST_DEST: &str = "test_main_test_sequential";
+
+        Command::new("target/release/lms")
+            .args(&["sync", "-S", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_sequential_copy() {
+        Command::new("cargo")
+            .args(&["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = BUILD_DIR;
+        const TEST_DEST: &str = "test_main_test_sequential_copy";
+
+        Command::new("target/release/lms")
+            .args(&["cp", "-S", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(&["-r", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_no_delete() {
+        Command::new("cargo")
+            .args(&["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE1: &str = "test_main_test_no_delete_source1";
+        const TEST_SOURCE2: &str = "test_main_test_no_delete_source2/";
+        const TEST_DEST: &str = "test_main_test_no_delete_out";
+        const TEST_EXPECTED: &str = "test_main_test_no_delete_expected";
+        const TEST_FILE1: &str = "Cargo.toml";
+        const TEST_FILE2: &str = "Cargo.lock";
+
+        fs::create_dir_all(TEST_SOURCE1).unwrap();
+        fs::create_dir_all(TEST_SOURCE2).unwrap();
+        fs::create_dir_all(TEST_EXPECTED).unwrap();
+
+        fs::copy(TEST_FILE1, [TEST_SOURCE1, TEST_FILE1].join("/")).unwrap();
+        fs::copy(TEST_FILE2, [TEST_SOURCE2, TEST_FILE2].join("/")).unwrap();
+        fs::copy(TEST_FILE1, [TEST_EXPECTED, TEST_FILE1].join("/")).unwrap();
+        fs::copy(TEST_FILE2, [TEST_EXPECTED, TEST_FILE2].join("/")).unwrap();
+
+        Command::new("target/release/lms")
+            .args(&["cp", TEST_SOURCE1, TEST_DEST])
+            .output()
+            .unwrap();
+
+        Command::new("target/release/lms")
+            .args(&["sync", "-n", TEST_SOURCE2, TEST_DEST])
+            .output()
+            .unwrap();
+
+        let diff = Command::new("diff")
+            .args(&["-r", TEST_DEST, TEST_EXPECTED])
+            .output()
+            .unwrap();
+
+        assert_eq!(diff.status.success(), true);
+
+        fs::remove_dir_all(TEST_SOURCE1).unwrap();
+        fs::remove_dir_all(TEST_SOURCE2).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+        fs::remove_dir_all(TEST_EXPECTED).unwrap();
+    }
+
+    #[test]
+    fn test_failures() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_failures_source/";
+        const TEST_DEST: &str = "test_main_test_failures_out";
+        const TEST_FILE: &str = "Cargo.toml";
+
+        fs::create_dir_all(TEST_SOURCE).unwrap();
+        fs::create_dir_all([TEST_DEST, TEST_FILE, "dir"].join("/")).unwrap();
+        fs::copy(TEST_FILE, [TEST_SOURCE, TEST_FILE].join("/")).unwrap();
+
+        // A dir in the way of a file can't be overwritten without deleting it
+        let output = Command::new("target/release/lms")
+            .args(["sync", "-n", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("Failed -- {}", [TEST_SOURCE, TEST_FILE].concat())));
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_config() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_config_source/";
+        const TEST_DEST1: &str = "test_main_test_config_out1";
+        const TEST_DEST2: &str = "test_main_test_config_out2";
+        const TEST_CONFIG: &str = "test_main_test_config.toml";
+
+        fs::create_dir_all(TEST_SOURCE).unwrap();
+        fs::write([TEST_SOURCE, "a.txt"].join("/"), b"a").unwrap();
+        fs::write([TEST_SOURCE, "b.log"].join("/"), b"b").unwrap();
+        fs::write(
+            [TEST_SOURCE, "lms.toml"].join("/"),
+            "hash = \"blake3\"\nexclude = [\"*.log\"]\n",
+        )
+        .unwrap();
+        fs::write(TEST_CONFIG, "flags = [\"not-a-flag\"]\n").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", TEST_SOURCE, TEST_DEST1])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert!(fs::metadata([TEST_DEST1, "a.txt"].join("/")).is_ok());
+        assert!(fs::metadata([TEST_DEST1, "b.log"].join("/")).is_err());
+
+        // Patterns given take precedence over those of the config
+        Command::new("target/release/lms")
+            .args(["sync", "--include", "*.log", TEST_SOURCE, TEST_DEST2])
+            .output()
+            .unwrap();
+
+        assert!(fs::metadata([TEST_DEST2, "b.log"].join("/")).is_ok());
+
+        let output = Command::new("target/release/lms")
+            .args(["sync", "--config", TEST_CONFIG, TEST_SOURCE, TEST_DEST2])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST1).unwrap();
+        fs::remove_dir_all(TEST_DEST2).unwrap();
+        fs::remove_file(TEST_CONFIG).unwrap();
+    }
+
+    #[test]
+    fn test_filter_file() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_filter_file_source/";
+        const TEST_DEST: &str = "test_main_test_filter_file_out";
+        const TEST_FILTER_FILE: &str = "test_main_test_filter_file.txt";
+
+        fs::create_dir_all([TEST_SOURCE, "build"].join("/")).unwrap();
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_SOURCE, "a.txt"].join("/"), b"a").unwrap();
+        fs::write([TEST_SOURCE, "build/b.txt"].join("/"), b"b").unwrap();
+        fs::write([TEST_SOURCE, "build/keep.txt"].join("/"), b"keep").unwrap();
+        fs::write(
+            TEST_FILTER_FILE,
+            "# Only keep one file from build\n+ build/keep.txt\n- build/\n",
+        )
+        .unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "sync",
+                "--filter-file",
+                TEST_FILTER_FILE,
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"a");
+        assert_eq!(
+            fs::read([TEST_DEST, "build/keep.txt"].join("/")).unwrap(),
+            b"keep"
+        );
+        assert!(fs::metadata([TEST_DEST, "build/b.txt"].join("/")).is_err());
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+        fs::remove_file(TEST_FILTER_FILE).unwrap();
+    }
+
+    #[test]
+    fn test_exclude_from() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_SOURCE: &str = "test_main_test_exclude_from_source/";
+        const TEST_DEST: &str = "test_main_test_exclude_from_out";
+        const TEST_EXCLUDE_FILE1: &str = "test_main_test_exclude_from1.txt";
+        const TEST_EXCLUDE_FILE2: &str = "test_main_test_exclude_from2.txt";
+
+        fs::create_dir_all([TEST_SOURCE, "build"].join("/")).unwrap();
+        fs::write([TEST_SOURCE, "a.txt"].join("/"), b"a").unwrap();
+        fs::write([TEST_SOURCE, "b.iso"].join("/"), b"b").unwrap();
+        fs::write([TEST_SOURCE, "build/c.txt"].join("/"), b"c").unwrap();
+        fs::write(TEST_EXCLUDE_FILE1, "# Build output\nbuild/\n\n").unwrap();
+        fs::write(TEST_EXCLUDE_FILE2, "*.iso\n").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args([
+                "cp",
+                "--exclude-from",
+                TEST_EXCLUDE_FILE1,
+                "--exclude-from",
+                TEST_EXCLUDE_FILE2,
+                TEST_SOURCE,
+                TEST_DEST,
+            ])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(fs::read([TEST_DEST, "a.txt"].join("/")).unwrap(), b"a");
+        assert!(fs::metadata([TEST_DEST, "b.iso"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "build"].join("/")).is_err());
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--exclude-from", "/?", TEST_SOURCE, TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+
+        fs::remove_dir_all(TEST_SOURCE).unwrap();
+        fs::remove_dir_all(TEST_DEST).unwrap();
+        fs::remove_file(TEST_EXCLUDE_FILE1).unwrap();
+        fs::remove_file(TEST_EXCLUDE_FILE2).unwrap();
+    }
+
+    #[test]
+    fn test_filter_file_missing() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_filter_file_missing_out";
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--filter-file", "/?", "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(fs::metadata(TEST_DEST).is_err());
+    }
+
+    #[test]
+    fn test_files_from() {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_DEST: &str = "test_main_test_files_from";
+
+        fs::create_dir_all(TEST_DEST).unwrap();
+        fs::write([TEST_DEST, "extra.txt"].join("/"), b"").unwrap();
+
+        let mut child = Command::new("target/release/lms")
+            .args(["sync", "--files-from", "-", "src", TEST_DEST])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"main.rs\n\n./lumins/core.rs\nmissing.rs\n")
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("missing.rs"));
+        assert_eq!(
+            fs::read("src/main.rs").unwrap(),
+            fs::read([TEST_DEST, "main.rs"].join("/")).unwrap()
+        );
+        assert_eq!(
+            fs::read("src/lumins/core.rs").unwrap(),
+            fs::read([TEST_DEST, "lumins/core.rs"].join("/")).unwrap()
+        );
+        assert!(fs::metadata([TEST_DEST, "lumins/parse.rs"].join("/")).is_err());
+        assert!(fs::metadata([TEST_DEST, "extra.txt"].join("/")).is_ok());
+
+        fs::remove_dir_all(TEST_DEST).unwrap();
+    }
+
+    #[test]
+    fn test_files_from_outside_src() {
+        Command::new("cargo")
+            .args(["build", "--release"])
+            .output()
+            .unwrap();
+
+        const TEST_LIST: &str = "test_main_test_files_from_outside_src.txt";
+        const TEST_DEST: &str = "test_main_test_files_from_outside_src";
+
+        fs::write(TEST_LIST, b"main.rs\n../Cargo.toml\n").unwrap();
+
+        let output = Command::new("target/release/lms")
+            .args(["cp", "--files-from", TEST_LIST, "src", TEST_DEST])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(fs::metadata(TEST_DEST).is_err());
+
+        fs::remove_file(TEST_LIST).unwrap();
     }
 
     #[cfg(target_family = "unix")]
@@ -0,0 +1,49 @@
+#!/bin/bash
+
+CUR_DIR="$(pwd)"
+
+echo 'Requesting superuser access'
+function refresh_sudo_access {
+    sudo -v
+}
+refresh_sudo_access
+
+echo 'Building dir structure'
+rm -rf $CUR_DIR/test
+mkdir -p $CUR_DIR/test/c1
+
+echo 'Generating directory C'
+for i in {1..48}
+do
+    NAME=$(cat /dev/urandom | tr -dc 'a-zA-Z0-9' | fold -w 32 | head -n 1)
+    dd if=/dev/urandom of=$CUR_DIR/test/c1/$NAME bs=1M count=8 2> /dev/null
+done
+cp -r $CUR_DIR/test/c1 $CUR_DIR/test/c2
+refresh_sudo_access
+
+echo 'Building latest lms binary'
+cargo build --release
+refresh_sudo_access
+
+DROP_CACHE_CMD='sync; echo 3 | sudo tee /proc/sys/vm/drop_caches'
+
+echo 'Starting test runs ...'
+NEW_LMS="$CUR_DIR/target/release/lms"
+
+function run_benchmark {
+    SRC_DIR="$1"
+    DST_DIR="$2"
+
+    for HASH in seahash blake3
+    do
+        echo "Testing verify --hash $HASH $SRC_DIR --> $DST_DIR"
+        hyperfine --prepare "$DROP_CACHE_CMD" \
+            "$NEW_LMS verify --hash $HASH $SRC_DIR $DST_DIR" \
+            "$NEW_LMS verify --hash $HASH --mmap $SRC_DIR $DST_DIR"
+        hyperfine --warmup 1 \
+            "$NEW_LMS verify --hash $HASH $SRC_DIR $DST_DIR" \
+            "$NEW_LMS verify --hash $HASH --mmap $SRC_DIR $DST_DIR"
+    done
+}
+
+run_benchmark "$CUR_DIR/test/c1/" "$CUR_DIR/test/c2/"